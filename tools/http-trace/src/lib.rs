@@ -0,0 +1,420 @@
+//! Sanitized traces of HTTP exchanges, written with `--debug-http <PATH>`.
+//!
+//! Once [`install`]ed, every request sent with [`SendTraced`] is recorded as a pretty
+//! JSON document appended to the trace file: the method, URL, request headers, a
+//! preview of the request body, the response status and headers, and the start of
+//! the response body. Credentials are redacted from URLs and headers, and any value
+//! registered with [`redact`] is scrubbed from the whole document.
+//!
+//! The same requests are held to the rate limits [`limit::install`]ed, if any, and
+//! saved or answered by the [`recording`] in progress, and the bodies they hold in
+//! memory are counted in the [`usage`] of the run.
+
+pub mod limit;
+pub mod recording;
+pub mod usage;
+
+use futures::stream::{self, StreamExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::{Body, Client, Request, RequestBuilder, Response, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+/// How much of a response body is recorded by default.
+pub const DEFAULT_BODY_LIMIT: usize = 4 * 1024;
+
+/// Query parameters whose values are credentials.
+const SECRET_PARAMETERS: [&str; 8] = [
+    "key",
+    "api_key",
+    "apikey",
+    "token",
+    "access_token",
+    "sig",
+    "signature",
+    "x-amz-signature",
+];
+
+/// Headers whose values are credentials.
+const SECRET_HEADERS: [&str; 6] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "x-goog-api-key",
+];
+
+/// Longer strings in JSON request bodies are replaced by their length, so that
+/// encoded images do not end up in the trace.
+const MAX_PREVIEW_STRING: usize = 256;
+
+const REDACTED: &str = "REDACTED";
+
+struct Trace {
+    file: Mutex<File>,
+    body_limit: usize,
+}
+
+static TRACE: OnceLock<Trace> = OnceLock::new();
+
+/// The values scrubbed from traces and recordings, longest first.
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Starts tracing every exchange into `path`, which is truncated first.
+///
+/// The file is written in append mode, so a child process may trace into it as well.
+pub fn install(
+    path: &Path,
+    body_limit: usize,
+) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.set_len(0)?;
+    let trace = Trace {
+        file: Mutex::new(file),
+        body_limit,
+    };
+    TRACE
+        .set(trace)
+        .map_err(|_| io::Error::other("the HTTP trace is already installed"))
+}
+
+/// Registers a secret, such as an API key, to be scrubbed from every trace and
+/// recording.
+pub fn redact(secret: &str) {
+    let mut secrets = SECRETS.lock().unwrap();
+    if !secret.is_empty() && !secrets.iter().any(|known| known == secret) {
+        secrets.push(secret.to_string());
+        // Replace longer secrets first, so that none is left partially visible.
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+    }
+}
+
+/// Replaces every registered secret in `text`.
+fn scrub(mut text: String) -> String {
+    for secret in SECRETS.lock().unwrap().iter() {
+        text = text.replace(secret.as_str(), REDACTED);
+    }
+    text
+}
+
+/// Replaces the start of a registered secret that `text` ends with, having been cut
+/// short in the middle of it, which [`scrub`] would miss.
+fn scrub_cut(mut text: String) -> String {
+    for secret in SECRETS.lock().unwrap().iter() {
+        let cut = (1..secret.len())
+            .rev()
+            .find(|&len| secret.is_char_boundary(len) && text.ends_with(&secret[..len]));
+        if let Some(len) = cut {
+            text.truncate(text.len() - len);
+            text.push_str(REDACTED);
+            break;
+        }
+    }
+    text
+}
+
+#[derive(Serialize)]
+struct Exchange {
+    method: String,
+    url: String,
+    request_headers: Value,
+    request_body: Option<Value>,
+    status: Option<u16>,
+    response_headers: Option<Value>,
+    response_body: Option<String>,
+    error: Option<String>,
+}
+
+/// Sending a request while recording it in the installed trace, if any, and within
+/// the installed rate limits, or answering it from the recording being replayed.
+pub trait SendTraced {
+    /// Sends the request, previewing a bytes body in the trace.
+    fn send_traced(self) -> impl std::future::Future<Output = reqwest::Result<Response>>;
+
+    /// Sends the request, describing its body in the trace with `preview`, for bodies
+    /// that cannot be previewed, like multipart forms.
+    fn send_traced_with(
+        self,
+        preview: Value,
+    ) -> impl std::future::Future<Output = reqwest::Result<Response>>;
+}
+
+impl SendTraced for RequestBuilder {
+    async fn send_traced(self) -> reqwest::Result<Response> {
+        send(self, None).await
+    }
+
+    async fn send_traced_with(
+        self,
+        preview: Value,
+    ) -> reqwest::Result<Response> {
+        send(self, Some(preview)).await
+    }
+}
+
+async fn send(
+    builder: RequestBuilder,
+    preview: Option<Value>,
+) -> reqwest::Result<Response> {
+    let (client, request) = builder.build_split();
+    let request = request?;
+    redact_credentials(&request);
+    if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+        usage::buffer(body.len());
+    }
+    if let Some(response) = recording::replayed(&request) {
+        return Ok(response);
+    }
+    let url = request.url().clone();
+    let pending = recording::is_recording().then(|| {
+        recording::Pending::new(&request, request_preview(&request, preview.clone()))
+    });
+    limit::acquire(&url).await;
+    let response = match TRACE.get() {
+        Some(trace) => send_traced(trace, client, request, preview).await,
+        None => client.execute(request).await,
+    };
+    if let Ok(response) = &response {
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            limit::throttle(&url);
+        }
+    }
+    match (pending, response) {
+        (Some(pending), Ok(response)) => recording::save(pending, response).await,
+        (_, response) => response,
+    }
+}
+
+/// Registers the credentials sent with `request`, so that they are scrubbed wherever
+/// they are echoed back.
+fn redact_credentials(request: &Request) {
+    for (name, value) in request.url().query_pairs() {
+        if SECRET_PARAMETERS.contains(&name.to_ascii_lowercase().as_str()) {
+            redact(&value);
+        }
+    }
+    for name in SECRET_HEADERS {
+        for value in request.headers().get_all(name) {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            redact(&value);
+            if let Some((_scheme, credentials)) = value.split_once(' ') {
+                redact(credentials);
+            }
+        }
+    }
+}
+
+/// The preview of the body of `request`, given or taken from a bytes body.
+fn request_preview(
+    request: &Request,
+    preview: Option<Value>,
+) -> Option<Value> {
+    preview.or_else(|| {
+        request.body().map(|body| match body.as_bytes() {
+            Some(bytes) => preview_body(bytes),
+            None => Value::from("<streamed body>"),
+        })
+    })
+}
+
+async fn send_traced(
+    trace: &Trace,
+    client: Client,
+    request: Request,
+    preview: Option<Value>,
+) -> reqwest::Result<Response> {
+    let mut exchange = Exchange {
+        method: request.method().to_string(),
+        url: redact_url(request.url()),
+        request_headers: headers(request.headers()),
+        request_body: request_preview(&request, preview),
+        status: None,
+        response_headers: None,
+        response_body: None,
+        error: None,
+    };
+
+    let mut response = match client.execute(request).await {
+        Ok(response) => response,
+        Err(error) => {
+            exchange.error = Some(error.to_string());
+            trace.record(&exchange);
+            return Err(error);
+        },
+    };
+    exchange.status = Some(response.status().as_u16());
+    exchange.response_headers = Some(headers(response.headers()));
+
+    // Event streams never end, so their body is left unread.
+    if is_event_stream(&response) {
+        exchange.response_body = Some("<event stream>".to_string());
+        trace.record(&exchange);
+        return Ok(response);
+    }
+
+    // Only the start of the body is read before the response is handed on, and the
+    // rest streams through unbuffered, so that a large model is never held whole.
+    let status = response.status();
+    let version = response.version();
+    let response_headers = response.headers().clone();
+    let total = response.content_length();
+    let mut head = Vec::new();
+    let mut shown = Vec::new();
+    let mut ended = false;
+    while shown.len() < trace.body_limit {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                let take = chunk.len().min(trace.body_limit - shown.len());
+                shown.extend_from_slice(&chunk[..take]);
+                head.push(chunk);
+            },
+            Ok(None) => {
+                ended = true;
+                break;
+            },
+            Err(error) => {
+                exchange.error = Some(error.to_string());
+                trace.record(&exchange);
+                return Err(error);
+            },
+        }
+    }
+    let mut text = String::from_utf8_lossy(&shown).into_owned();
+    if !ended {
+        text = scrub_cut(text);
+    }
+    match total {
+        _ if ended => {},
+        Some(total) if total > shown.len() as u64 => {
+            text.push_str(&format!("... ({} bytes in total)", total))
+        },
+        Some(_) => {},
+        None => text.push_str("... (the rest is not recorded)"),
+    }
+    exchange.response_body = Some(text);
+    trace.record(&exchange);
+
+    if ended {
+        return Ok(rebuild(status, version, response_headers, head.concat()));
+    }
+    let rest = stream::iter(head.into_iter().map(Ok)).chain(response.bytes_stream());
+    Ok(rebuild(
+        status,
+        version,
+        response_headers,
+        Body::wrap_stream(rest),
+    ))
+}
+
+fn is_event_stream(response: &Response) -> bool {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream"))
+}
+
+/// A response with a body already read.
+fn rebuild(
+    status: StatusCode,
+    version: reqwest::Version,
+    headers: HeaderMap,
+    body: impl Into<reqwest::Body>,
+) -> Response {
+    let mut rebuilt = http::Response::new(body);
+    *rebuilt.status_mut() = status;
+    *rebuilt.version_mut() = version;
+    *rebuilt.headers_mut() = headers;
+    Response::from(rebuilt)
+}
+
+impl Trace {
+    fn record(
+        &self,
+        exchange: &Exchange,
+    ) {
+        let Ok(json) = serde_json::to_string_pretty(exchange) else {
+            return;
+        };
+        let mut json = scrub(json);
+        json.push('\n');
+        // A failing trace must not fail the request it describes.
+        let _ = self.file.lock().unwrap().write_all(json.as_bytes());
+    }
+}
+
+fn redact_url(url: &reqwest::Url) -> String {
+    let mut url = url.clone();
+    if url.password().is_some() {
+        let _ = url.set_password(Some(REDACTED));
+    }
+    if url.query().is_some() {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(name, value)| {
+                let secret =
+                    SECRET_PARAMETERS.contains(&name.to_ascii_lowercase().as_str());
+                let value = if secret {
+                    REDACTED.to_string()
+                } else {
+                    value.into_owned()
+                };
+                (name.into_owned(), value)
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+fn headers(headers: &HeaderMap) -> Value {
+    let entries = headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), Value::from(header_text(name, value))));
+    Value::Object(entries.collect())
+}
+
+/// The value of a header as text, redacted if it is a credential.
+fn header_text(
+    name: &HeaderName,
+    value: &HeaderValue,
+) -> String {
+    if SECRET_HEADERS.contains(&name.as_str()) {
+        REDACTED.to_string()
+    } else {
+        String::from_utf8_lossy(value.as_bytes()).into_owned()
+    }
+}
+
+/// Previews a request body: JSON with its long strings elided, or the start of text.
+fn preview_body(bytes: &[u8]) -> Value {
+    match serde_json::from_slice::<Value>(bytes) {
+        Ok(mut json) => {
+            elide_long_strings(&mut json);
+            json
+        },
+        Err(_) => {
+            let shown = &bytes[..bytes.len().min(MAX_PREVIEW_STRING)];
+            let mut text = String::from_utf8_lossy(shown).into_owned();
+            if shown.len() < bytes.len() {
+                text.push_str(&format!("... ({} bytes in total)", bytes.len()));
+            }
+            Value::from(text)
+        },
+    }
+}
+
+fn elide_long_strings(value: &mut Value) {
+    match value {
+        Value::String(text) if text.len() > MAX_PREVIEW_STRING => {
+            *value = Value::from(format!("<{} bytes>", text.len()));
+        },
+        Value::Array(items) => items.iter_mut().for_each(elide_long_strings),
+        Value::Object(fields) => fields.values_mut().for_each(elide_long_strings),
+        _ => {},
+    }
+}
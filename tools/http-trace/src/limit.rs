@@ -0,0 +1,357 @@
+//! Rate limits on the calls to the Gemini API, shared by every pipeline running at
+//! once on the machine.
+//!
+//! Each class of endpoint has its own limit in requests per minute. Requests are
+//! spaced evenly: each reserves the next free slot of its class in a schedule kept
+//! in the temporary directory, locked while it is updated, so concurrent processes
+//! take turns in the order they asked. A 429 response halves the rate of its class
+//! for a minute, down to an eighth when they keep coming.
+
+use fs2::FileExt;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The host of the Gemini API, serving Gemini, Veo, its operations, and Imagen.
+const API_HOST: &str = "generativelanguage.googleapis.com";
+
+/// Name of the schedule shared by the processes, in the temporary directory.
+const SCHEDULE_FILE: &str = "text-to-view-rate-limits.json";
+
+/// How long the rate of a class stays reduced after a 429 response.
+const PENALTY_DURATION: f64 = 60.0;
+
+/// The most the interval between requests is stretched by repeated 429 responses.
+const MAX_PENALTY: f64 = 8.0;
+
+/// Waits shorter than this are not worth a message.
+const QUIET_WAIT: f64 = 1.0;
+
+/// The classes of endpoints, each with its own quota.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EndpointClass {
+    /// Text generation, used to optimize prompts.
+    Gemini,
+    /// Submissions of video generations.
+    Veo,
+    /// Polls of long-running operations.
+    Operations,
+    /// Image generations, used for views generated as stills.
+    Imagen,
+}
+
+/// The most requests per minute for each class of endpoint, unlimited when `None`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RateLimits {
+    pub gemini_rpm: Option<u32>,
+    pub veo_rpm: Option<u32>,
+    pub operations_rpm: Option<u32>,
+    pub imagen_rpm: Option<u32>,
+}
+
+impl RateLimits {
+    fn interval(
+        &self,
+        class: EndpointClass,
+    ) -> Option<f64> {
+        let rpm = match class {
+            EndpointClass::Gemini => self.gemini_rpm,
+            EndpointClass::Veo => self.veo_rpm,
+            EndpointClass::Operations => self.operations_rpm,
+            EndpointClass::Imagen => self.imagen_rpm,
+        }?;
+        (rpm > 0).then(|| 60.0 / rpm as f64)
+    }
+}
+
+/// The next free slot of a class, and how much its interval is stretched.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Slot {
+    /// Seconds since the Unix epoch.
+    next: f64,
+    penalty: f64,
+    penalty_until: f64,
+}
+
+static LIMITS: OnceLock<RateLimits> = OnceLock::new();
+
+/// Starts limiting the rate of the requests sent with [`SendTraced`](crate::SendTraced).
+pub fn install(limits: RateLimits) -> io::Result<()> {
+    LIMITS
+        .set(limits)
+        .map_err(|_| io::Error::other("the rate limits are already installed"))
+}
+
+/// The class of the endpoint at `url`, if it is one of the Gemini API.
+pub fn classify(url: &Url) -> Option<EndpointClass> {
+    if url.host_str() != Some(API_HOST) {
+        return None;
+    }
+    let path = url.path();
+    if path.ends_with(":predictLongRunning") {
+        Some(EndpointClass::Veo)
+    } else if path.ends_with(":predict") {
+        Some(EndpointClass::Imagen)
+    } else if path.contains("/operations/") {
+        Some(EndpointClass::Operations)
+    } else if path.ends_with(":generateContent")
+        || path.ends_with(":streamGenerateContent")
+    {
+        Some(EndpointClass::Gemini)
+    } else {
+        None
+    }
+}
+
+/// Waits for the turn of a request to `url`, if its class is limited.
+pub(crate) async fn acquire(url: &Url) {
+    let Some((class, interval)) = limited(url) else {
+        return;
+    };
+    wait_turn(&schedule_path(), class, interval).await;
+}
+
+/// Reserves the next free slot of `class` in the schedule at `path`, then waits for it.
+async fn wait_turn(
+    path: &Path,
+    class: EndpointClass,
+    interval: f64,
+) {
+    let wait = update(path, |schedule, now| {
+        let slot = schedule.entry(class).or_default();
+        if now >= slot.penalty_until {
+            slot.penalty = 1.0;
+        }
+        let at = slot.next.max(now);
+        slot.next = at + interval * slot.penalty.max(1.0);
+        at - now
+    });
+    // A schedule that cannot be read or written must not stop the pipeline.
+    let Ok(wait) = wait else {
+        return;
+    };
+    if wait >= QUIET_WAIT {
+        eprintln!("Waiting {:.1}s for the {:?} rate limit...", wait, class);
+    }
+    if wait > 0.0 {
+        tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+    }
+}
+
+/// Slows the requests of the class of `url` down after a 429 response.
+pub(crate) fn throttle(url: &Url) {
+    let Some((class, interval)) = limited(url) else {
+        return;
+    };
+    let _ = update(&schedule_path(), |schedule, now| {
+        let slot = schedule.entry(class).or_default();
+        slot.penalty = (slot.penalty.max(1.0) * 2.0).min(MAX_PENALTY);
+        slot.penalty_until = now + PENALTY_DURATION;
+        slot.next = slot.next.max(now + interval * slot.penalty);
+    });
+}
+
+fn limited(url: &Url) -> Option<(EndpointClass, f64)> {
+    let class = classify(url)?;
+    Some((class, LIMITS.get()?.interval(class)?))
+}
+
+/// Applies `change` to the schedule at `path` under an exclusive lock, with the
+/// current time.
+fn update<T>(
+    path: &Path,
+    change: impl FnOnce(&mut BTreeMap<EndpointClass, Slot>, f64) -> T,
+) -> io::Result<T> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    file.lock_exclusive()?;
+    let result = update_locked(&mut file, change);
+    FileExt::unlock(&file)?;
+    result
+}
+
+fn update_locked<T>(
+    file: &mut File,
+    change: impl FnOnce(&mut BTreeMap<EndpointClass, Slot>, f64) -> T,
+) -> io::Result<T> {
+    let mut json = String::new();
+    file.read_to_string(&mut json)?;
+    let mut schedule = serde_json::from_str(&json).unwrap_or_default();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64());
+    let result = change(&mut schedule, now);
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(serde_json::to_string(&schedule)?.as_bytes())?;
+    Ok(result)
+}
+
+fn schedule_path() -> PathBuf {
+    std::env::temp_dir().join(SCHEDULE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::time::Instant;
+
+    /// The interval between requests, long enough to stand out from scheduling noise.
+    const INTERVAL: f64 = 0.2;
+
+    /// How far a turn may come late, for the runtime to wake its task up.
+    const SLACK: f64 = 0.08;
+
+    #[test]
+    fn classifies_the_endpoints_of_the_gemini_api() {
+        let class = |url: &str| classify(&Url::parse(url).unwrap());
+        let models = "https://generativelanguage.googleapis.com/v1beta/models";
+        assert_eq!(
+            class(&format!(
+                "{}/gemini-2.5-flash:generateContent?key=k",
+                models
+            )),
+            Some(EndpointClass::Gemini)
+        );
+        assert_eq!(
+            class(&format!(
+                "{}/gemini-2.5-flash:streamGenerateContent",
+                models
+            )),
+            Some(EndpointClass::Gemini)
+        );
+        assert_eq!(
+            class(&format!(
+                "{}/veo-3.0-generate-001:predictLongRunning",
+                models
+            )),
+            Some(EndpointClass::Veo)
+        );
+        assert_eq!(
+            class(&format!("{}/veo-3.0-generate-001/operations/abc", models)),
+            Some(EndpointClass::Operations)
+        );
+        assert_eq!(
+            class(&format!("{}/imagen-3.0-generate-002:predict?key=k", models)),
+            Some(EndpointClass::Imagen)
+        );
+        // Downloads of the videos, and other hosts, are not limited.
+        assert_eq!(class("https://generativelanguage.googleapis.com/v1beta/files/f:download?alt=media"), None);
+        assert_eq!(class("http://127.0.0.1:8000/imagen:predict"), None);
+    }
+
+    /// Starts `count` acquirers of `class` at once, returning when each got its turn
+    /// in seconds from the start, in the order the acquirers were started.
+    async fn race(
+        path: &Path,
+        class: EndpointClass,
+        count: usize,
+    ) -> Vec<f64> {
+        let path = Arc::new(path.to_path_buf());
+        let start = Instant::now();
+        let tasks: Vec<_> = (0..count)
+            .map(|_| {
+                let path = Arc::clone(&path);
+                tokio::spawn(async move {
+                    wait_turn(&path, class, INTERVAL).await;
+                    start.elapsed().as_secs_f64()
+                })
+            })
+            .collect();
+        let mut turns = Vec::new();
+        for task in tasks {
+            turns.push(task.await.unwrap());
+        }
+        turns
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn spaces_concurrent_acquirers_evenly() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SCHEDULE_FILE);
+        let count = 6;
+        let mut turns = race(&path, EndpointClass::Veo, count).await;
+        turns.sort_by(f64::total_cmp);
+
+        // The first goes at once, and each of the others an interval after the one
+        // before it: no two share a slot, and none is kept waiting past its own.
+        assert!(turns[0] < SLACK, "the first turn came at {:.3}s", turns[0]);
+        for (index, pair) in turns.windows(2).enumerate() {
+            let gap = pair[1] - pair[0];
+            assert!(
+                gap > INTERVAL - SLACK && gap < INTERVAL + SLACK,
+                "turns {} and {} are {:.3}s apart: {:?}",
+                index,
+                index + 1,
+                gap,
+                turns
+            );
+        }
+        let last = turns[count - 1];
+        let expected = INTERVAL * (count - 1) as f64;
+        assert!(
+            (last - expected).abs() < SLACK,
+            "the last turn came at {:.3}s, not {:.3}s",
+            last,
+            expected
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn serves_later_acquirers_after_earlier_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Arc::new(dir.path().join(SCHEDULE_FILE));
+        let start = Instant::now();
+
+        // A second wave arrives while the first is still waiting for its turns.
+        let first = {
+            let path = Arc::clone(&path);
+            tokio::spawn(async move { race(&path, EndpointClass::Gemini, 3).await })
+        };
+        tokio::time::sleep(Duration::from_secs_f64(INTERVAL / 2.0)).await;
+        let offset = start.elapsed().as_secs_f64();
+        let second = race(&path, EndpointClass::Gemini, 3).await;
+        let first = first.await.unwrap();
+
+        let first_last = first.iter().copied().fold(0.0, f64::max);
+        let second_first = second
+            .iter()
+            .map(|turn| turn + offset)
+            .fold(f64::MAX, f64::min);
+        assert!(
+            second_first > first_last + INTERVAL - SLACK,
+            "the second wave started at {:.3}s, before the first ended at {:.3}s",
+            second_first,
+            first_last
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn limits_each_class_on_its_own() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Arc::new(dir.path().join(SCHEDULE_FILE));
+        let veo = {
+            let path = Arc::clone(&path);
+            tokio::spawn(async move { race(&path, EndpointClass::Veo, 3).await })
+        };
+        let operations = race(&path, EndpointClass::Operations, 3).await;
+        let veo = veo.await.unwrap();
+
+        // Both classes take their first turn at once, without waiting for the other.
+        for turns in [veo, operations] {
+            let first = turns.iter().copied().fold(f64::MAX, f64::min);
+            assert!(first < SLACK, "the first turn came at {:.3}s", first);
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Recording the exchanges of a run with `--record <DIR>`, and answering the same
+//! requests from the recording with `--replay <DIR>`, without the network.
+//!
+//! Every exchange sent with [`SendTraced`](crate::SendTraced) is saved as a numbered
+//! JSON document, `0001.json`, holding the method, URL, a preview of the request
+//! body and the response status and headers, next to the raw response body,
+//! `0001.body`. Credentials are redacted as in the trace. On replay, each request is
+//! answered by the first unused exchange with the same method and URL, and a request
+//! with none panics, naming the request.
+
+use crate::{header_text, is_event_stream, rebuild, redact_url, scrub};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Request, Response, StatusCode, Version};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+enum Session {
+    Record {
+        dir: PathBuf,
+        next: AtomicUsize,
+    },
+    Replay {
+        dir: PathBuf,
+        /// The recorded exchanges in order, each taken when it answers a request.
+        exchanges: Mutex<Vec<Option<Recorded>>>,
+    },
+}
+
+static SESSION: OnceLock<Session> = OnceLock::new();
+
+#[derive(Serialize, Deserialize)]
+struct Recorded {
+    method: String,
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_body: Option<Value>,
+    status: u16,
+    headers: Vec<(String, String)>,
+    /// The file holding the response body, missing for event streams, which are not
+    /// recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+}
+
+/// Starts saving every exchange into `dir`, which is created if needed.
+///
+/// The numbering continues after the exchanges already in `dir`, so that runs
+/// recording into the same directory do not overwrite each other.
+pub fn record(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let next = recorded_files(dir)?
+        .last()
+        .map_or(1, |(number, _)| number + 1);
+    install(Session::Record {
+        dir: dir.to_path_buf(),
+        next: AtomicUsize::new(next),
+    })
+}
+
+/// Starts answering every request from the exchanges recorded in `dir`.
+pub fn replay(dir: &Path) -> io::Result<()> {
+    let mut exchanges = Vec::new();
+    for (_, path) in recorded_files(dir)? {
+        let recorded = serde_json::from_slice(&fs::read(&path)?).map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), error),
+            )
+        })?;
+        exchanges.push(Some(recorded));
+    }
+    install(Session::Replay {
+        dir: dir.to_path_buf(),
+        exchanges: Mutex::new(exchanges),
+    })
+}
+
+fn install(session: Session) -> io::Result<()> {
+    SESSION
+        .set(session)
+        .map_err(|_| io::Error::other("recording or replaying is already installed"))
+}
+
+/// The numbered exchange documents in `dir`, in order.
+fn recorded_files(dir: &Path) -> io::Result<Vec<(usize, PathBuf)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            let number = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok());
+            if let Some(number) = number {
+                files.push((number, path));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Whether exchanges are being saved.
+pub(crate) fn is_recording() -> bool {
+    matches!(SESSION.get(), Some(Session::Record { .. }))
+}
+
+/// Whether requests are answered from a recording, so that waiting between polls
+/// is pointless.
+pub fn is_replaying() -> bool {
+    matches!(SESSION.get(), Some(Session::Replay { .. }))
+}
+
+/// Answers `request` from the recording, if replaying.
+///
+/// # Panics
+///
+/// When replaying and no unused exchange matches the request, since the run has
+/// diverged from the recording.
+pub(crate) fn replayed(request: &Request) -> Option<Response> {
+    let Some(Session::Replay { dir, exchanges }) = SESSION.get() else {
+        return None;
+    };
+    let method = request.method().as_str();
+    let url = redact_url(request.url());
+    let recorded = {
+        let mut exchanges = exchanges.lock().unwrap();
+        let position = exchanges.iter().position(|exchange| {
+            exchange
+                .as_ref()
+                .is_some_and(|exchange| exchange.method == method && exchange.url == url)
+        });
+        let Some(position) = position else {
+            let left = exchanges.iter().flatten().count();
+            panic!(
+                "--replay: {} has no recorded exchange left for {} {} \
+                 ({} of {} exchanges unused)",
+                dir.display(),
+                method,
+                url,
+                left,
+                exchanges.len()
+            );
+        };
+        exchanges[position].take().unwrap()
+    };
+
+    let body = match &recorded.body {
+        Some(file) => fs::read(dir.join(file)).unwrap_or_else(|error| {
+            panic!(
+                "--replay: cannot read {}: {}",
+                dir.join(file).display(),
+                error
+            )
+        }),
+        None => Vec::new(),
+    };
+    let status = StatusCode::from_u16(recorded.status)
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut headers = HeaderMap::new();
+    for (name, value) in &recorded.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            headers.append(name, value);
+        }
+    }
+    Some(rebuild(status, Version::HTTP_11, headers, body))
+}
+
+/// The request side of an exchange being recorded, taken before the request is
+/// sent.
+pub(crate) struct Pending {
+    method: String,
+    url: String,
+    request_body: Option<Value>,
+}
+
+impl Pending {
+    pub(crate) fn new(
+        request: &Request,
+        request_body: Option<Value>,
+    ) -> Self {
+        Pending {
+            method: request.method().to_string(),
+            url: redact_url(request.url()),
+            request_body,
+        }
+    }
+}
+
+/// Saves the exchange of `pending` and `response`, returning a response with the
+/// same body.
+pub(crate) async fn save(
+    pending: Pending,
+    response: Response,
+) -> reqwest::Result<Response> {
+    let Some(Session::Record { dir, next }) = SESSION.get() else {
+        return Ok(response);
+    };
+    let number = next.fetch_add(1, Ordering::Relaxed);
+    let mut recorded = Recorded {
+        method: pending.method,
+        url: pending.url,
+        request_body: pending.request_body,
+        status: response.status().as_u16(),
+        headers: response
+            .headers()
+            .iter()
+            .map(|(name, value)| (name.to_string(), header_text(name, value)))
+            .collect(),
+        body: None,
+    };
+
+    // Event streams never end, so their body is left unread and unrecorded.
+    if is_event_stream(&response) {
+        write(dir, number, &recorded, None);
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let version = response.version();
+    let response_headers = response.headers().clone();
+    let body = response.bytes().await?;
+    recorded.body = Some(format!("{:04}.body", number));
+    match std::str::from_utf8(&body) {
+        Ok(text) => write(
+            dir,
+            number,
+            &recorded,
+            Some(scrub(text.to_string()).as_bytes()),
+        ),
+        Err(_) => write(dir, number, &recorded, Some(&body)),
+    }
+
+    Ok(rebuild(status, version, response_headers, body))
+}
+
+fn write(
+    dir: &Path,
+    number: usize,
+    recorded: &Recorded,
+    body: Option<&[u8]>,
+) {
+    let Ok(json) = serde_json::to_string_pretty(recorded) else {
+        return;
+    };
+    // A failing recording must not fail the request it describes.
+    if let Some(body) = body {
+        let _ = fs::write(dir.join(format!("{:04}.body", number)), body);
+    }
+    let _ = fs::write(dir.join(format!("{:04}.json", number)), scrub(json) + "\n");
+}
@@ -0,0 +1,161 @@
+//! Accounting of the resources a run uses in each of its stages: the bytes written
+//! to temporary files, held to the limit [`install`]ed, and the largest buffer held
+//! in memory.
+//!
+//! The helpers here count what they read and write, and requests sent with
+//! [`SendTraced`](crate::SendTraced) count the bodies they hold, so code using them
+//! is accounted for as it is. Temporary files count as written even once removed.
+
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+/// The most bytes the run may write to temporary files.
+static MAX_TEMP_BYTES: OnceLock<u64> = OnceLock::new();
+
+/// The bytes written to temporary files since the run started.
+static TEMP_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// The bytes written to temporary files since the current stage started.
+static STAGE_TEMP_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// The largest buffer held in memory since the current stage started.
+static STAGE_PEAK_BUFFER: AtomicU64 = AtomicU64::new(0);
+
+/// What a stage used.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    pub temp_bytes: u64,
+    pub peak_buffer_bytes: u64,
+}
+
+/// Holds the bytes the run writes to temporary files to `max_temp_bytes`.
+pub fn install(max_temp_bytes: u64) -> io::Result<()> {
+    MAX_TEMP_BYTES
+        .set(max_temp_bytes)
+        .map_err(|_| io::Error::other("the temporary space limit is already installed"))
+}
+
+/// What the current stage used, starting the next one.
+pub fn take_stage() -> Usage {
+    Usage {
+        temp_bytes: STAGE_TEMP_BYTES.swap(0, Ordering::Relaxed),
+        peak_buffer_bytes: STAGE_PEAK_BUFFER.swap(0, Ordering::Relaxed),
+    }
+}
+
+/// Adds what another process, like a tool run by this one, used in the current
+/// stage, failing if its temporary files take the run over its limit.
+pub fn add(usage: Usage) -> io::Result<()> {
+    STAGE_PEAK_BUFFER.fetch_max(usage.peak_buffer_bytes, Ordering::Relaxed);
+    reserve_temp(usage.temp_bytes)
+}
+
+/// Counts a buffer of `bytes` held in memory.
+pub fn buffer(bytes: usize) {
+    STAGE_PEAK_BUFFER.fetch_max(bytes as u64, Ordering::Relaxed);
+}
+
+/// Counts `bytes` about to be written to temporary files, failing instead if the
+/// run would write more than its limit.
+pub fn reserve_temp(bytes: u64) -> io::Result<()> {
+    let total = TEMP_BYTES.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    if let Some(&max) = MAX_TEMP_BYTES.get().filter(|&&max| total > max) {
+        TEMP_BYTES.fetch_sub(bytes, Ordering::Relaxed);
+        return Err(io::Error::other(format!(
+            "writing {} more to temporary files would take the run to {}, over \
+             --max-temp-bytes {}",
+            format_size(bytes),
+            format_size(total),
+            format_size(max)
+        )));
+    }
+    STAGE_TEMP_BYTES.fetch_add(bytes, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Whether `path` is in the temporary directory.
+pub fn is_temp(path: &Path) -> bool {
+    path.starts_with(std::env::temp_dir())
+}
+
+/// Writes `bytes` to `path` as [`fs::write`] does, counting them if `path` is
+/// temporary.
+pub fn write(
+    path: &Path,
+    bytes: &[u8],
+) -> io::Result<()> {
+    if is_temp(path) {
+        reserve_temp(bytes.len() as u64)?;
+    }
+    fs::write(path, bytes)
+}
+
+/// Reads `path` into memory as [`fs::read`] does, counting the buffer.
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let bytes = fs::read(path)?;
+    buffer(bytes.len());
+    Ok(bytes)
+}
+
+/// Reads the body of `response` into memory, counting the buffer, and warning first
+/// if its announced length is more than the memory available.
+pub async fn read_body(response: Response) -> reqwest::Result<Vec<u8>> {
+    // A traced response streams the rest of its body, which hides its length from
+    // all but the header.
+    let announced = response.content_length().or_else(|| {
+        let header = response.headers().get(CONTENT_LENGTH)?;
+        header.to_str().ok()?.parse().ok()
+    });
+    if let Some(length) = announced {
+        warn_if_short("the response body", length);
+    }
+    let body = response.bytes().await?;
+    buffer(body.len());
+    Ok(body.into())
+}
+
+/// Warns if `what` may take more memory than is available, by the `estimate` of the
+/// bytes it holds.
+pub fn warn_if_short(
+    what: &str,
+    estimate: u64,
+) {
+    let Some(available) = available_memory() else {
+        return;
+    };
+    if estimate > available {
+        eprintln!(
+            "Warning: {} may take about {} of memory, more than the {} available",
+            what,
+            format_size(estimate),
+            format_size(available)
+        );
+    }
+}
+
+/// The memory available to new allocations without swapping, where the system tells.
+pub fn available_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kibibytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kibibytes * 1024)
+}
+
+/// `bytes` in the largest binary unit they make at least one of.
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..0x10_0000 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        0x10_0000..0x4000_0000 => format!("{:.1} MiB", bytes as f64 / 0x10_0000 as f64),
+        _ => format!("{:.1} GiB", bytes as f64 / 0x4000_0000 as f64),
+    }
+}
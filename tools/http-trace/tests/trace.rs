@@ -0,0 +1,88 @@
+//! The trace of requests carrying credentials, sent to a server echoing them back.
+
+use http_trace::SendTraced;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+
+const API_KEY: &str = "AIzaSy-test-api-key-0123456789";
+const TOKEN: &str = "test-bearer-token-abcdef";
+
+/// How much of a response body the trace records.
+const BODY_LIMIT: usize = 64;
+
+/// Answers each of `bodies` to a request in turn, returning the address served on.
+fn serve(bodies: Vec<Vec<u8>>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for body in bodies {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                line.clear();
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            // In parts, so that the body arrives in several chunks.
+            for part in body.chunks(16 * 1024) {
+                stream.write_all(part).unwrap();
+            }
+        }
+    });
+    address
+}
+
+#[tokio::test]
+async fn records_the_start_of_each_body_without_the_credentials() {
+    let dir = tempfile::tempdir().unwrap();
+    let trace_path = dir.path().join("trace.json");
+    http_trace::install(&trace_path, BODY_LIMIT).unwrap();
+
+    // A large body echoing the credentials at its start, and a short one cut by the
+    // limit in the middle of the token.
+    let mut large = format!("{} {} ", API_KEY, TOKEN).into_bytes();
+    large.extend((0..200_000).map(|i| b'a' + (i % 26) as u8));
+    let mut cut = vec![b'.'; BODY_LIMIT - 4];
+    cut.extend_from_slice(TOKEN.as_bytes());
+    let address = serve(vec![large.clone(), cut.clone()]);
+
+    let client = reqwest::Client::new();
+    for expected in [&large, &cut] {
+        let response = client
+            .get(format!("http://{}/v1/models?key={}", address, API_KEY))
+            .bearer_auth(TOKEN)
+            .send_traced()
+            .await
+            .unwrap();
+        // The body the caller reads is whole, though the trace only kept its start.
+        assert_eq!(response.bytes().await.unwrap(), expected[..]);
+    }
+
+    let trace = fs::read_to_string(&trace_path).unwrap();
+    assert!(
+        !trace.contains(API_KEY),
+        "the API key is in the trace:\n{}",
+        trace
+    );
+    assert!(
+        !trace.contains(TOKEN),
+        "the token is in the trace:\n{}",
+        trace
+    );
+    assert!(
+        !trace.contains(&TOKEN[..4]),
+        "a part of the token is in the trace:\n{}",
+        trace
+    );
+    assert!(trace.contains("\"authorization\": \"REDACTED\""));
+    assert!(trace.contains("key=REDACTED"));
+    assert!(trace.contains(&format!("REDACTED... ({} bytes in total)", cut.len())));
+    assert!(trace.contains(&format!("... ({} bytes in total)", large.len())));
+}
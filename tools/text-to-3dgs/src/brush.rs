@@ -0,0 +1,711 @@
+//! The vendored brush app, used to view models and to train them locally.
+
+use crate::cancel;
+use crate::checksum;
+use crate::ply;
+use crate::project::Layout;
+use clap::Args;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+pub const BRUSH_DIR: &str = "./tools/brush";
+const BRUSH_BINARY: &str = "brush_app";
+
+/// Environment variable naming the brush executable, like `--brush-path`.
+pub const BRUSH_PATH_VAR: &str = "BRUSH_APP";
+
+/// Where the dataset trained by brush is exported, unless `--export-dataset` is given.
+pub const BRUSH_DATASET_DIR: &str = "dataset";
+
+/// Where brush writes its checkpoints while training.
+pub const CHECKPOINT_DIR: &str = "brush-checkpoints";
+
+#[derive(Debug, Args)]
+pub struct BrushArgs {
+    /// The brush executable to use, instead of searching for one.
+    #[arg(long, value_name = "PATH")]
+    pub brush_path: Option<PathBuf>,
+
+    /// Number of training steps of the brush backend.
+    #[arg(long, default_value_t = 30000, value_name = "STEPS")]
+    pub brush_steps: u32,
+
+    /// Stop brush training after this many seconds and keep its latest checkpoint.
+    #[arg(long, value_name = "SECS")]
+    pub brush_time_budget: Option<u64>,
+}
+
+/// The operating system conventions brush is searched with.
+pub struct Platform {
+    pub os: &'static str,
+    pub exe_suffix: &'static str,
+}
+
+impl Platform {
+    pub const CURRENT: Platform = Platform {
+        os: std::env::consts::OS,
+        exe_suffix: std::env::consts::EXE_SUFFIX,
+    };
+}
+
+/// The view of the environment and filesystem that brush is searched in.
+pub trait Probe {
+    fn var(
+        &self,
+        name: &str,
+    ) -> Option<OsString>;
+
+    fn is_file(
+        &self,
+        path: &Path,
+    ) -> bool;
+}
+
+/// The real environment and filesystem.
+pub struct SystemProbe;
+
+impl Probe for SystemProbe {
+    fn var(
+        &self,
+        name: &str,
+    ) -> Option<OsString> {
+        std::env::var_os(name)
+    }
+
+    fn is_file(
+        &self,
+        path: &Path,
+    ) -> bool {
+        path.is_file()
+    }
+}
+
+/// The outcome of searching for brush: every location checked, in order, and the
+/// first one holding an executable.
+#[derive(Debug)]
+pub struct Discovery {
+    pub searched: Vec<PathBuf>,
+    pub found: Option<PathBuf>,
+}
+
+/// Searches for the brush executable: an explicit path, then `PATH`, the cargo bin
+/// directory, the vendored build, and finally an installed macOS app bundle.
+///
+/// An explicit path, from `--brush-path` or `BRUSH_APP`, is the only candidate when
+/// given.
+pub fn discover_brush(
+    explicit: Option<&Path>,
+    platform: &Platform,
+    probe: &impl Probe,
+) -> Discovery {
+    let binary = format!("{}{}", BRUSH_BINARY, platform.exe_suffix);
+    let windows = platform.os == "windows";
+    let home = probe
+        .var(if windows { "USERPROFILE" } else { "HOME" })
+        .map(PathBuf::from);
+
+    let mut candidates = Vec::new();
+    if let Some(path) = explicit
+        .map(Path::to_path_buf)
+        .or_else(|| probe.var(BRUSH_PATH_VAR).map(PathBuf::from))
+    {
+        candidates.push(path);
+    } else {
+        if let Some(path) = probe.var("PATH") {
+            let separator = if windows { ';' } else { ':' };
+            candidates.extend(
+                path.to_string_lossy()
+                    .split(separator)
+                    .filter(|dir| !dir.is_empty())
+                    .map(|dir| Path::new(dir).join(&binary)),
+            );
+        }
+        let cargo_home = probe
+            .var("CARGO_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.as_ref().map(|home| home.join(".cargo")));
+        if let Some(cargo_home) = cargo_home {
+            candidates.push(cargo_home.join("bin").join(&binary));
+        }
+        for profile in ["release", "debug"] {
+            candidates.push(
+                Path::new(BRUSH_DIR)
+                    .join("target")
+                    .join(profile)
+                    .join(&binary),
+            );
+        }
+        if platform.os == "macos" {
+            let bundle = Path::new("Applications/brush.app/Contents/MacOS").join(&binary);
+            candidates.push(Path::new("/").join(&bundle));
+            if let Some(home) = &home {
+                candidates.push(home.join(&bundle));
+            }
+        }
+    }
+
+    let found = candidates.iter().find(|path| probe.is_file(path)).cloned();
+    Discovery {
+        searched: candidates,
+        found,
+    }
+}
+
+/// Returns the brush executable, compiling the vendored one first if none is found.
+pub fn ensure_brush(args: &BrushArgs) -> Result<PathBuf> {
+    let discovery =
+        discover_brush(args.brush_path.as_deref(), &Platform::CURRENT, &SystemProbe);
+    if std::env::var("RUST_LOG").is_ok_and(|level| level.contains("debug")) {
+        for path in &discovery.searched {
+            eprintln!("[debug] Looked for brush at {}", path.display());
+        }
+    }
+    if let Some(executable) = discovery.found {
+        eprintln!("Using brush at {}", executable.display());
+        return Ok(executable);
+    }
+    if args.brush_path.is_some() || std::env::var_os(BRUSH_PATH_VAR).is_some() {
+        return Err(eyre!(
+            "brush was not found at {}",
+            discovery.searched[0].display()
+        ));
+    }
+    if !Path::new(BRUSH_DIR).join("Cargo.toml").exists() {
+        let searched: Vec<String> = discovery
+            .searched
+            .iter()
+            .map(|path| format!("  {}", path.display()))
+            .collect();
+        return Err(eyre!(
+            "brush was not found in any of:\n{}\nInstall it, pass --brush-path, or run \
+             `git submodule update --init {}` to build the vendored copy.",
+            searched.join("\n"),
+            BRUSH_DIR
+        ));
+    }
+
+    eprintln!("'brush_app' not found, compiling it first...");
+    let build_status = Command::new("cargo")
+        .arg("build")
+        .arg("--bin")
+        .arg("brush_app")
+        .arg("--locked")
+        .arg("--release")
+        .current_dir(BRUSH_DIR)
+        .status()
+        .wrap_err("Failed to build brush")?;
+
+    if !build_status.success() {
+        return Err(eyre!("Failed to compile brush"));
+    }
+    eprintln!("'brush_app' compiled successfully.");
+    Ok(Path::new(BRUSH_DIR).join("target/release").join(format!(
+        "{}{}",
+        BRUSH_BINARY,
+        Platform::CURRENT.exe_suffix
+    )))
+}
+
+/// Trains a model with brush on the Nerfstudio dataset in `dataset`, saving it to
+/// `output`.
+///
+/// Cancelled through `cancel`, it stops brush and deletes its checkpoints, saving
+/// nothing.
+pub fn train(
+    args: &BrushArgs,
+    layout: &Layout,
+    dataset: &Path,
+    output: &Path,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    eprintln!("--- Step 2: Training the model locally with brush ---");
+    let executable = ensure_brush(args)?;
+    cancel::check(cancel)?;
+    let checkpoints = &layout.checkpoints_dir();
+    if checkpoints.exists() {
+        fs::remove_dir_all(checkpoints).wrap_err_with(|| {
+            format!(
+                "Failed to clear the checkpoints in {}",
+                checkpoints.display()
+            )
+        })?;
+    }
+    let steps = args.brush_steps.max(1);
+    let export_every = steps.min(1000);
+
+    let mut child = Command::new(&executable)
+        .arg(dataset)
+        .arg("--total-steps")
+        .arg(steps.to_string())
+        .arg("--export-every")
+        .arg(export_every.to_string())
+        .arg("--export-path")
+        .arg(checkpoints)
+        .arg("--export-name")
+        .arg("step_{iter}.ply")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("Failed to execute {}", executable.display()))?;
+
+    let (sender, lines) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let forward = |stream: Box<dyn Read + Send>, sender: mpsc::Sender<String>| {
+        thread::spawn(move || {
+            // Progress bars redraw with carriage returns rather than newlines.
+            let mut line = Vec::new();
+            for byte in BufReader::new(stream).bytes() {
+                let Ok(byte) = byte else { break };
+                if byte == b'\n' || byte == b'\r' {
+                    let _ = sender.send(String::from_utf8_lossy(&line).into_owned());
+                    line.clear();
+                } else {
+                    line.push(byte);
+                }
+            }
+        })
+    };
+    forward(Box::new(stdout), sender.clone());
+    forward(Box::new(stderr), sender);
+
+    let budget = args.brush_time_budget.map(Duration::from_secs);
+    let start = Instant::now();
+    let mut reported = 0;
+    let mut diverged = false;
+    let status = loop {
+        match lines.recv_timeout(Duration::from_millis(200)) {
+            Ok(line) => {
+                if line.contains("NaN") {
+                    diverged = true;
+                }
+                if let Some(step) = parse_progress(&line, steps) {
+                    let percent = step as u64 * 100 / steps as u64;
+                    if percent >= reported + 10 || step == steps {
+                        reported = percent - percent % 10;
+                        eprintln!(
+                            "Training step {}/{} ({:.0}s elapsed)",
+                            step,
+                            steps,
+                            start.elapsed().as_secs_f64()
+                        );
+                    }
+                } else if !line.trim().is_empty() {
+                    eprintln!("brush: {}", line.trim_end());
+                }
+            },
+            Err(mpsc::RecvTimeoutError::Timeout) => {},
+            Err(mpsc::RecvTimeoutError::Disconnected) => break Some(child.wait()?),
+        }
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if cancel.is_cancelled() {
+            eprintln!("Stopping brush...");
+            child.kill()?;
+            child.wait()?;
+            break None;
+        }
+        if let Some(budget) = budget.filter(|&budget| start.elapsed() > budget) {
+            eprintln!(
+                "Training exceeded its time budget of {}s, stopping brush...",
+                budget.as_secs()
+            );
+            child.kill()?;
+            child.wait()?;
+            break None;
+        }
+    };
+
+    // Checkpoints of a cancelled training are partial models, kept by nothing.
+    if cancel.is_cancelled() {
+        fs::remove_dir_all(checkpoints).ok();
+        return Err(eyre!(cancel::CANCELLED));
+    }
+    if diverged {
+        return Err(eyre!(
+            "brush training diverged (NaN losses). Check the camera poses, or train \
+             with fewer --brush-steps."
+        ));
+    }
+    if let Some(status) = status.filter(|status| !status.success()) {
+        return Err(eyre!("brush training exited with {}", status));
+    }
+    let (step, checkpoint) = latest_checkpoint(checkpoints)?.ok_or_else(|| {
+        eyre!(
+            "brush training produced no model in {}. With --brush-time-budget, allow \
+             enough time for at least {} steps.",
+            checkpoints.display(),
+            export_every
+        )
+    })?;
+    if step < steps {
+        eprintln!("Keeping the checkpoint at step {}/{}", step, steps);
+    }
+
+    let stats = ply::inspect(&checkpoint)?;
+    let finite = stats.bounding_box.as_ref().is_some_and(|bounds| {
+        bounds
+            .min
+            .iter()
+            .chain(&bounds.max)
+            .all(|value| value.is_finite())
+    });
+    if !finite {
+        return Err(eyre!(
+            "brush training diverged, {} has no finite gaussians. Check the camera \
+             poses, or train with fewer --brush-steps.",
+            checkpoint.display()
+        ));
+    }
+    checksum::copy(&checkpoint, output)
+        .wrap_err_with(|| format!("Failed to save the model to {}", output.display()))?;
+    eprintln!("--- Step 2: brush training completed successfully ---\n");
+    Ok(())
+}
+
+/// Finds the current step in a line of brush's progress output, e.g. `1200/30000`.
+fn parse_progress(
+    line: &str,
+    steps: u32,
+) -> Option<u32> {
+    line.split(|c: char| !c.is_ascii_digit() && c != '/')
+        .filter_map(|token| token.split_once('/'))
+        .filter_map(|(step, total)| Some((step.parse().ok()?, total.parse().ok()?)))
+        .find(|&(step, total): &(u32, u32)| total == steps && step <= steps)
+        .map(|(step, _)| step)
+}
+
+/// Returns the checkpoint of the highest step saved in `dir`, if any.
+fn latest_checkpoint(dir: &Path) -> Result<Option<(u32, PathBuf)>> {
+    if !dir.exists() {
+        return Ok(None);
+    }
+    let checkpoints = fs::read_dir(dir)
+        .wrap_err_with(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| {
+            let step = path
+                .file_stem()?
+                .to_str()?
+                .strip_prefix("step_")?
+                .parse()
+                .ok()?;
+            Some((step, path))
+        });
+    Ok(checkpoints.max_by_key(|(step, _)| *step))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+
+    const LINUX: Platform = Platform {
+        os: "linux",
+        exe_suffix: "",
+    };
+    const MACOS: Platform = Platform {
+        os: "macos",
+        exe_suffix: "",
+    };
+    const WINDOWS: Platform = Platform {
+        os: "windows",
+        exe_suffix: ".exe",
+    };
+
+    /// An environment with the variables in `vars` and only the files in `files`.
+    struct FakeProbe {
+        vars: HashMap<&'static str, &'static str>,
+        files: HashSet<PathBuf>,
+    }
+
+    impl FakeProbe {
+        fn new(vars: &[(&'static str, &'static str)]) -> Self {
+            FakeProbe {
+                vars: vars.iter().copied().collect(),
+                files: HashSet::new(),
+            }
+        }
+
+        fn with_file(
+            mut self,
+            path: impl Into<PathBuf>,
+        ) -> Self {
+            self.files.insert(path.into());
+            self
+        }
+    }
+
+    impl Probe for FakeProbe {
+        fn var(
+            &self,
+            name: &str,
+        ) -> Option<OsString> {
+            self.vars.get(name).map(OsString::from)
+        }
+
+        fn is_file(
+            &self,
+            path: &Path,
+        ) -> bool {
+            self.files.contains(path)
+        }
+    }
+
+    fn vendored(
+        profile: &str,
+        binary: &str,
+    ) -> PathBuf {
+        Path::new(BRUSH_DIR)
+            .join("target")
+            .join(profile)
+            .join(binary)
+    }
+
+    #[test]
+    fn an_explicit_path_is_the_only_candidate() {
+        let probe =
+            FakeProbe::new(&[("PATH", "/usr/bin"), (BRUSH_PATH_VAR, "/env/brush")])
+                .with_file("/usr/bin/brush_app");
+        let discovery = discover_brush(Some(Path::new("/opt/brush")), &LINUX, &probe);
+        assert_eq!(discovery.searched, [PathBuf::from("/opt/brush")]);
+        assert_eq!(discovery.found, None);
+
+        let discovery = discover_brush(None, &LINUX, &probe);
+        assert_eq!(discovery.searched, [PathBuf::from("/env/brush")]);
+    }
+
+    #[test]
+    fn searches_path_then_cargo_then_the_vendored_build() {
+        let probe =
+            FakeProbe::new(&[("PATH", "/usr/local/bin::/usr/bin"), ("HOME", "/home/me")]);
+        let discovery = discover_brush(None, &LINUX, &probe);
+        assert_eq!(
+            discovery.searched,
+            [
+                PathBuf::from("/usr/local/bin/brush_app"),
+                PathBuf::from("/usr/bin/brush_app"),
+                PathBuf::from("/home/me/.cargo/bin/brush_app"),
+                vendored("release", "brush_app"),
+                vendored("debug", "brush_app"),
+            ]
+        );
+        assert_eq!(discovery.found, None);
+    }
+
+    #[test]
+    fn finds_brush_on_path() {
+        let probe =
+            FakeProbe::new(&[("PATH", "/usr/local/bin:/usr/bin"), ("HOME", "/home/me")])
+                .with_file("/usr/bin/brush_app")
+                .with_file("/home/me/.cargo/bin/brush_app");
+        let discovery = discover_brush(None, &LINUX, &probe);
+        assert_eq!(discovery.found, Some(PathBuf::from("/usr/bin/brush_app")));
+    }
+
+    #[test]
+    fn finds_brush_in_the_cargo_bin_directory() {
+        let probe = FakeProbe::new(&[("HOME", "/home/me")])
+            .with_file("/home/me/.cargo/bin/brush_app");
+        let discovery = discover_brush(None, &LINUX, &probe);
+        assert_eq!(
+            discovery.found,
+            Some(PathBuf::from("/home/me/.cargo/bin/brush_app"))
+        );
+
+        // CARGO_HOME takes the place of ~/.cargo.
+        let probe = FakeProbe::new(&[("HOME", "/home/me"), ("CARGO_HOME", "/cargo")])
+            .with_file("/cargo/bin/brush_app")
+            .with_file("/home/me/.cargo/bin/brush_app");
+        let discovery = discover_brush(None, &LINUX, &probe);
+        assert_eq!(discovery.found, Some(PathBuf::from("/cargo/bin/brush_app")));
+        assert!(!discovery
+            .searched
+            .contains(&PathBuf::from("/home/me/.cargo/bin/brush_app")));
+    }
+
+    #[test]
+    fn prefers_the_vendored_release_build_to_the_debug_one() {
+        let probe = FakeProbe::new(&[])
+            .with_file(vendored("release", "brush_app"))
+            .with_file(vendored("debug", "brush_app"));
+        let discovery = discover_brush(None, &LINUX, &probe);
+        assert_eq!(discovery.found, Some(vendored("release", "brush_app")));
+
+        let probe = FakeProbe::new(&[]).with_file(vendored("debug", "brush_app"));
+        let discovery = discover_brush(None, &LINUX, &probe);
+        assert_eq!(discovery.found, Some(vendored("debug", "brush_app")));
+    }
+
+    #[test]
+    fn searches_for_the_exe_on_windows() {
+        let probe = FakeProbe::new(&[
+            ("PATH", r"C:\Tools;C:\Windows"),
+            ("USERPROFILE", r"C:\Users\me"),
+            ("HOME", "/home/me"),
+        ])
+        .with_file(vendored("debug", "brush_app"))
+        .with_file(vendored("debug", "brush_app.exe"));
+        let discovery = discover_brush(None, &WINDOWS, &probe);
+        assert_eq!(
+            discovery.searched,
+            [
+                Path::new(r"C:\Tools").join("brush_app.exe"),
+                Path::new(r"C:\Windows").join("brush_app.exe"),
+                Path::new(r"C:\Users\me")
+                    .join(".cargo")
+                    .join("bin")
+                    .join("brush_app.exe"),
+                vendored("release", "brush_app.exe"),
+                vendored("debug", "brush_app.exe"),
+            ]
+        );
+        assert_eq!(discovery.found, Some(vendored("debug", "brush_app.exe")));
+    }
+
+    #[test]
+    fn searches_the_app_bundles_on_macos_only() {
+        let system = "/Applications/brush.app/Contents/MacOS/brush_app";
+        let user = "/Users/me/Applications/brush.app/Contents/MacOS/brush_app";
+        let probe = FakeProbe::new(&[("HOME", "/Users/me")]).with_file(user);
+        let discovery = discover_brush(None, &MACOS, &probe);
+        assert_eq!(
+            discovery.searched[discovery.searched.len() - 2..],
+            [PathBuf::from(system), PathBuf::from(user)]
+        );
+        assert_eq!(discovery.found, Some(PathBuf::from(user)));
+
+        let probe = probe.with_file(system);
+        let discovery = discover_brush(None, &MACOS, &probe);
+        assert_eq!(discovery.found, Some(PathBuf::from(system)));
+
+        let discovery = discover_brush(None, &LINUX, &probe);
+        assert_eq!(discovery.found, None);
+        assert!(!discovery.searched.contains(&PathBuf::from(system)));
+    }
+
+    /// A model with finite gaussians, saved by the fake brush as its checkpoints.
+    #[cfg(unix)]
+    const CHECKPOINT: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sh1.ply");
+
+    /// A fake brush that saves a checkpoint at step 1000 of 2000, reports it, then
+    /// runs `rest`. It records its process id in `brush.pid` next to `dir`.
+    #[cfg(unix)]
+    fn fake_brush(
+        dir: &Path,
+        rest: &str,
+    ) -> BrushArgs {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join("brush_app");
+        let script = format!(
+            "#!/bin/sh\n\
+             echo $$ > '{pid}'\n\
+             mkdir -p \"$7\"\n\
+             cp '{checkpoint}' \"$7/step_1000.ply\"\n\
+             echo \"1000/$3\"\n\
+             {rest}\n",
+            pid = dir.join("brush.pid").display(),
+            checkpoint = CHECKPOINT,
+            rest = rest,
+        );
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        BrushArgs {
+            brush_path: Some(path),
+            brush_steps: 2000,
+            brush_time_budget: None,
+        }
+    }
+
+    /// Trains with `args` in a project at `dir`, returning the result and the output.
+    #[cfg(unix)]
+    fn train_in(
+        dir: &Path,
+        args: &BrushArgs,
+        cancel: &CancellationToken,
+    ) -> (Result<()>, PathBuf) {
+        let layout = Layout::at(Some(dir.to_path_buf()));
+        let dataset = dir.join(BRUSH_DATASET_DIR);
+        fs::create_dir_all(&dataset).unwrap();
+        let output = dir.join("model.ply");
+        (train(args, &layout, &dataset, &output, cancel), output)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saves_the_latest_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = fake_brush(
+            dir.path(),
+            "cp \"$7/step_1000.ply\" \"$7/step_2000.ply\"\necho \"2000/$3\"",
+        );
+        let (result, output) = train_in(dir.path(), &args, &CancellationToken::new());
+        result.unwrap();
+        assert_eq!(fs::read(output).unwrap(), fs::read(CHECKPOINT).unwrap());
+        assert!(dir
+            .path()
+            .join(CHECKPOINT_DIR)
+            .join("step_2000.ply")
+            .exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn does_not_start_brush_once_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = fake_brush(dir.path(), "");
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let (result, output) = train_in(dir.path(), &args, &cancel);
+        assert_eq!(result.unwrap_err().to_string(), cancel::CANCELLED);
+        assert!(!dir.path().join("brush.pid").exists());
+        assert!(!dir.path().join(CHECKPOINT_DIR).exists());
+        assert!(!output.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stops_brush_and_deletes_its_checkpoints_when_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = fake_brush(dir.path(), "exec sleep 30");
+        let cancel = CancellationToken::new();
+
+        // Cancelled once brush has saved a checkpoint, while it is still training.
+        let checkpoint = dir.path().join(CHECKPOINT_DIR).join("step_1000.ply");
+        let canceller = {
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                while !checkpoint.exists() {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                cancel.cancel();
+            })
+        };
+        let started = Instant::now();
+        let (result, output) = train_in(dir.path(), &args, &cancel);
+        canceller.join().unwrap();
+
+        assert_eq!(result.unwrap_err().to_string(), cancel::CANCELLED);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let pid: libc::pid_t = fs::read_to_string(dir.path().join("brush.pid"))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        // SAFETY: signal 0 only checks whether the process exists.
+        assert_ne!(unsafe { libc::kill(pid, 0) }, 0, "brush is still running");
+        assert!(!dir.path().join(CHECKPOINT_DIR).exists());
+        assert!(!output.exists());
+    }
+}
@@ -0,0 +1,260 @@
+//! Checks of the environment, run before a pipeline run spends any quota.
+
+use clap::{Args, ValueEnum};
+use color_eyre::eyre::{eyre, Result};
+use http_trace::SendTraced;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+/// Free space needed in the temporary directory, for the downloaded video and the
+/// downscaled or fetched views.
+pub const TEMP_SPACE_NEEDED: u64 = 512 << 20;
+
+/// Free space needed where the outputs are written, for the views, the model, and
+/// its conversions.
+pub const OUTPUT_SPACE_NEEDED: u64 = 1 << 30;
+
+/// How long the reconstruction server has to answer the health check.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Args, Debug)]
+pub struct CheckArgs {
+    /// Only run the environment checks, then exit.
+    #[arg(long)]
+    pub check: bool,
+
+    /// Skip an environment check. Can be repeated.
+    #[arg(long, value_enum, value_name = "CHECK")]
+    pub skip_check: Vec<Check>,
+}
+
+/// An environment check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Check {
+    /// Free space in the temporary and output directories.
+    Disk,
+    /// The API key and video decoder of text-to-view.
+    Decoder,
+    /// Reachability of the reconstruction server.
+    Server,
+    /// A display for the brush viewer.
+    Display,
+}
+
+impl fmt::Display for Check {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        let name = match self {
+            Check::Disk => "disk",
+            Check::Decoder => "decoder",
+            Check::Server => "server",
+            Check::Display => "display",
+        };
+        f.pad(name)
+    }
+}
+
+enum Outcome {
+    Pass(String),
+    /// A likely problem that does not stop the run.
+    Warn(String),
+    Fail(String),
+    Skip(String),
+}
+
+/// What the run is going to do, deciding which checks apply.
+pub struct Plan<'a> {
+    /// Whether text-to-view generates the views.
+    pub generates_views: bool,
+    /// URL checked for a reconstruction server, if the run uses one.
+    pub server_url: Option<String>,
+    /// Whether the model is opened in the brush viewer.
+    pub views_model: bool,
+    /// Where the outputs are written.
+    pub output_dir: &'a Path,
+}
+
+/// Runs the checks that apply to `plan`, printing a table of their outcomes, and
+/// fails if any of them did.
+pub async fn run_checks(
+    args: &CheckArgs,
+    plan: &Plan<'_>,
+) -> Result<()> {
+    let mut rows = Vec::new();
+    for check in [Check::Disk, Check::Decoder, Check::Server, Check::Display] {
+        let outcome = if args.skip_check.contains(&check) {
+            Outcome::Skip("skipped".to_string())
+        } else {
+            match check {
+                Check::Disk => check_disk(plan.output_dir),
+                Check::Decoder if plan.generates_views => check_decoder(),
+                Check::Server => match &plan.server_url {
+                    Some(url) => check_server(url).await,
+                    None => Outcome::Skip("no reconstruction server is used".to_string()),
+                },
+                Check::Display if plan.views_model => check_display(),
+                Check::Decoder => Outcome::Skip("no views are generated".to_string()),
+                Check::Display => Outcome::Skip("the viewer is not launched".to_string()),
+            }
+        };
+        rows.push((check, outcome));
+    }
+
+    eprintln!("Environment checks:");
+    let mut failed = Vec::new();
+    for (check, outcome) in &rows {
+        let (label, detail) = match outcome {
+            Outcome::Pass(detail) => ("PASS", detail),
+            Outcome::Warn(detail) => ("WARN", detail),
+            Outcome::Fail(detail) => {
+                failed.push(check.to_string());
+                ("FAIL", detail)
+            },
+            Outcome::Skip(detail) => ("SKIP", detail),
+        };
+        eprintln!("  {}  {:<8} {}", label, check, detail);
+    }
+    eprintln!();
+    if !failed.is_empty() {
+        return Err(eyre!(
+            "Environment checks failed: {}. Fix them, or skip them with --skip-check.",
+            failed.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+fn check_disk(output_dir: &Path) -> Outcome {
+    let locations = [
+        (std::env::temp_dir(), TEMP_SPACE_NEEDED),
+        (output_dir.to_path_buf(), OUTPUT_SPACE_NEEDED),
+    ];
+    let mut details = Vec::new();
+    let mut short = false;
+    for (path, needed) in locations {
+        let Some(available) = available_space(&path) else {
+            return Outcome::Warn(format!(
+                "could not tell the free space in {}",
+                path.display()
+            ));
+        };
+        short |= available < needed;
+        details.push(format!(
+            "{} free in {} (needs {})",
+            format_size(available),
+            path.display(),
+            format_size(needed)
+        ));
+    }
+    let details = details.join(", ");
+    if short {
+        Outcome::Fail(details)
+    } else {
+        Outcome::Pass(details)
+    }
+}
+
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    // Outputs may go to a directory that does not exist yet.
+    let existing = path.ancestors().find(|path| path.exists())?;
+    let existing = if existing.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        existing
+    };
+    let path = CString::new(existing.as_os_str().as_bytes()).ok()?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is a valid C string, and `stats` is only read once filled in.
+    let stats = unsafe {
+        if libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stats.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)]
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+pub fn format_size(bytes: u64) -> String {
+    if bytes >= 1 << 30 {
+        format!("{:.1} GiB", bytes as f64 / (1u64 << 30) as f64)
+    } else {
+        format!("{} MiB", bytes >> 20)
+    }
+}
+
+fn check_decoder() -> Outcome {
+    let output = crate::text_to_view_command()
+        .arg("--check")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(error) => {
+            return Outcome::Fail(format!("could not run text-to-view: {}", error))
+        },
+    };
+    let report = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    match (output.status.success(), report.is_empty()) {
+        (true, _) => {
+            Outcome::Pass("the API key is set and the decoder initializes".to_string())
+        },
+        (false, false) => Outcome::Fail(report),
+        (false, true) => {
+            Outcome::Fail("text-to-view failed to build or start".to_string())
+        },
+    }
+}
+
+async fn check_server(url: &str) -> Outcome {
+    let client = match reqwest::Client::builder().timeout(SERVER_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(error) => return Outcome::Fail(error.to_string()),
+    };
+    match client.get(url).send_traced().await {
+        Ok(response) => Outcome::Pass(format!("{} answered {}", url, response.status())),
+        Err(error) => {
+            Outcome::Fail(format!("{} is unreachable: {}", url, error.without_url()))
+        },
+    }
+}
+
+fn check_display() -> Outcome {
+    // macOS and Windows always have a window server for a logged-in user.
+    if cfg!(any(target_os = "macos", windows)) {
+        return Outcome::Pass("a window server is available".to_string());
+    }
+    let display = ["WAYLAND_DISPLAY", "DISPLAY"]
+        .into_iter()
+        .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()));
+    match display {
+        Some(display) => Outcome::Pass(format!("display {}", display)),
+        None => Outcome::Warn(
+            "no DISPLAY or WAYLAND_DISPLAY is set, the viewer may fail to open; \
+             pass --no-view to skip it"
+                .to_string(),
+        ),
+    }
+}
+
+/// The directory the outputs are written to, for an output path.
+pub fn output_dir(output: &Path) -> PathBuf {
+    match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
@@ -0,0 +1,154 @@
+//! SHA-256 checksums of the views and the model, so that a run can be audited later.
+//!
+//! The model is hashed as it is written, since models can be large, and its writers
+//! record the digest here for the run manifest to pick up.
+
+use color_eyre::eyre::{Result, WrapErr};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+/// The digests of the files written by this run, by path.
+static WRITTEN: Mutex<BTreeMap<PathBuf, String>> = Mutex::new(BTreeMap::new());
+
+/// Hashes everything written through it.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Returns the writer and the hex digest of what was written.
+    pub fn finish(self) -> (W, String) {
+        (self.inner, format!("{:x}", self.hasher.finalize()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(
+        &mut self,
+        buf: &[u8],
+    ) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Hashes the file at `path` by streaming it.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut writer = HashingWriter::new(io::sink());
+    io::copy(&mut BufReader::new(File::open(path)?), &mut writer)?;
+    Ok(writer.finish().1)
+}
+
+/// Hashes the files at `paths` across threads, in the order given.
+pub fn sha256_files(paths: &[PathBuf]) -> Vec<io::Result<String>> {
+    let workers = thread::available_parallelism().map_or(1, |count| count.get());
+    let chunk_size = paths.len().div_ceil(workers).max(1);
+    thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(|| {
+                    chunk
+                        .iter()
+                        .map(|path| sha256_file(path))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("a hashing thread panicked"))
+            .collect()
+    })
+}
+
+/// Records the digest of the file just written at `path`.
+pub fn record(
+    path: &Path,
+    digest: String,
+) {
+    WRITTEN.lock().unwrap().insert(path.to_path_buf(), digest);
+}
+
+/// The digest of the file at `path`, as recorded when it was written, or else
+/// computed from its content.
+pub fn of(path: &Path) -> Result<String> {
+    if let Some(digest) = WRITTEN.lock().unwrap().get(path) {
+        return Ok(digest.clone());
+    }
+    sha256_file(path).wrap_err_with(|| format!("Failed to hash {}", path.display()))
+}
+
+/// The digests of the model and the views for the run manifest, by path relative to
+/// `dir` when under it, so that the run can be moved and still verified.
+pub fn run_checksums(
+    dir: &Path,
+    model: Option<&Path>,
+    views: &[PathBuf],
+) -> Result<BTreeMap<String, String>> {
+    let dir = dir.canonicalize()?;
+    let mut checksums = BTreeMap::new();
+    for (path, digest) in views.iter().zip(sha256_files(views)) {
+        let digest =
+            digest.wrap_err_with(|| format!("Failed to hash {}", path.display()))?;
+        checksums.insert(relative_path(&dir, path)?, digest);
+    }
+    if let Some(model) = model {
+        checksums.insert(relative_path(&dir, model)?, of(model)?);
+    }
+    Ok(checksums)
+}
+
+/// The path of `path` relative to `dir` with forward slashes, or its absolute path
+/// when outside.
+fn relative_path(
+    dir: &Path,
+    path: &Path,
+) -> Result<String> {
+    let path = path
+        .canonicalize()
+        .wrap_err_with(|| format!("Failed to find {}", path.display()))?;
+    Ok(match path.strip_prefix(dir) {
+        Ok(relative) => relative
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        Err(_) => path.to_string_lossy().into_owned(),
+    })
+}
+
+/// Copies the file at `from` to `to`, recording the digest of the copy.
+pub fn copy(
+    from: &Path,
+    to: &Path,
+) -> io::Result<()> {
+    let mut writer = HashingWriter::new(File::create(to)?);
+    io::copy(&mut BufReader::new(File::open(from)?), &mut writer)?;
+    let (mut file, digest) = writer.finish();
+    file.flush()?;
+    record(to, digest);
+    Ok(())
+}
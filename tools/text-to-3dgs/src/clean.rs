@@ -0,0 +1,284 @@
+//! The `clean` subcommand, removing the artifacts left behind by pipeline runs.
+//!
+//! Only paths recognized by the names the tools give them, or recorded in their
+//! manifests, are ever removed, so files of the user are never touched.
+
+use crate::dataset::CAMERAS_PATH;
+use crate::lock::RunLock;
+use crate::manifest::{RunManifest, RUN_MANIFEST_PATH, RUN_STATE_PATH};
+use crate::output::STDOUT_PATH;
+use crate::project::Layout;
+use crate::summary::SUMMARY_PATH;
+use crate::views::MANIFEST_FILE;
+use crate::watch::{RUNS_DIR, WATCH_STATE_FILE};
+use clap::{ArgGroup, Args};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// Where serve mode keeps its jobs by default, each in a directory with a job file.
+const JOBS_DIR: &str = "jobs";
+const JOB_FILE: &str = "job.json";
+
+/// The video text-to-view downloads into the temporary directory.
+const VIDEO_FILE: &str = "video.mp4";
+
+/// Prefix of the files and directories of text-to-3dgs in the temporary directory.
+const TEMP_PREFIX: &str = "text-to-3dgs-";
+
+/// Arguments of the `clean` subcommand.
+#[derive(Args, Debug)]
+#[command(group(
+    ArgGroup::new("targets")
+        .required(true)
+        .multiple(true)
+        .args(["views", "models", "runs", "cache", "all"]),
+))]
+pub struct CleanArgs {
+    /// Remove the views extracted by text-to-view.
+    #[arg(long)]
+    pub views: bool,
+
+    /// Remove the models, with their conversions and run manifests.
+    #[arg(long)]
+    pub models: bool,
+
+    /// Remove the run directories of watch mode and the jobs of serve mode.
+    #[arg(long)]
+    pub runs: bool,
+
+    /// Remove intermediates: brush datasets and checkpoints, and temporary files.
+    #[arg(long)]
+    pub cache: bool,
+
+    /// Remove all of the above.
+    #[arg(long)]
+    pub all: bool,
+
+    /// Clean this project directory instead of the working directory.
+    #[arg(long, value_name = "DIR")]
+    pub project_dir: Option<PathBuf>,
+
+    /// Only list what would be removed.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Remove without asking for confirmation.
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+pub fn run(args: &CleanArgs) -> Result<()> {
+    if let Some(dir) = args.project_dir.as_ref().filter(|dir| !dir.is_dir()) {
+        return Err(eyre!("{} is not a directory", dir.display()));
+    }
+    let layout = Layout::at(args.project_dir.clone());
+    // Never clean up under a run in progress.
+    let _lock = RunLock::acquire(&layout.dir())?;
+
+    let mut targets = Vec::new();
+    if args.views || args.all {
+        targets.extend(views(&layout));
+    }
+    if args.models || args.all {
+        targets.extend(models(&layout));
+    }
+    if args.runs || args.all {
+        targets.extend(runs(&layout.dir()));
+    }
+    if args.cache || args.all {
+        targets.extend(cache(&layout));
+    }
+    // Paths found under the working directory are listed relative to it.
+    let mut targets: Vec<PathBuf> = targets
+        .into_iter()
+        .map(|path| match path.strip_prefix(".") {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => path,
+        })
+        .collect();
+    targets.sort();
+    targets.dedup();
+    if targets.is_empty() {
+        eprintln!("Nothing to clean in {}", layout.dir().display());
+        return Ok(());
+    }
+
+    let mut total = 0;
+    for target in &targets {
+        let size = disk_usage(target);
+        total += size;
+        eprintln!("  {:>10}  {}", format_size(size), target.display());
+    }
+    eprintln!("{} items, {} in total", targets.len(), format_size(total));
+    if args.dry_run {
+        return Ok(());
+    }
+    if !args.yes && !confirm()? {
+        eprintln!("Nothing was removed");
+        return Ok(());
+    }
+
+    for target in &targets {
+        let removed = if target.is_dir() {
+            fs::remove_dir_all(target)
+        } else {
+            fs::remove_file(target)
+        };
+        removed.wrap_err_with(|| format!("Failed to remove {}", target.display()))?;
+    }
+    eprintln!(
+        "Removed {} items, freeing {}",
+        targets.len(),
+        format_size(total)
+    );
+    Ok(())
+}
+
+/// The views of text-to-view, told by its manifest, and in a project the views
+/// imported or fetched into it.
+fn views(layout: &Layout) -> Vec<PathBuf> {
+    let views = layout.views_dir();
+    let ours = views.join(MANIFEST_FILE).is_file()
+        || (layout.is_project() && layout.run_manifest().is_file());
+    if ours && views.is_dir() {
+        vec![views]
+    } else {
+        Vec::new()
+    }
+}
+
+/// The model and its conversion recorded in the run manifest, models with the
+/// fallback name, and the manifests of the run.
+fn models(layout: &Layout) -> Vec<PathBuf> {
+    let dir = layout.dir();
+    let mut targets = Vec::new();
+    let manifest = fs::read_to_string(layout.run_manifest())
+        .ok()
+        .and_then(|json| serde_json::from_str::<RunManifest>(&json).ok());
+    if let Some(manifest) = manifest {
+        if manifest.output != Path::new(STDOUT_PATH) {
+            targets.push(manifest.output);
+        }
+        targets.extend(manifest.conversion.map(|conversion| conversion.path));
+    }
+    targets.extend(layout.model());
+    for entry in read_dir(&dir) {
+        let name = entry.file_name().unwrap_or_default().to_string_lossy();
+        if is_fallback_model(&name) {
+            targets.push(entry);
+        }
+    }
+    targets.extend(
+        [
+            RUN_MANIFEST_PATH,
+            RUN_STATE_PATH,
+            SUMMARY_PATH,
+            CAMERAS_PATH,
+        ]
+        .map(|name| dir.join(name)),
+    );
+    targets.retain(|path| path.is_file());
+    targets
+}
+
+/// Whether `name` is `output.ply` or one of its numbered siblings, or their
+/// conversions, or models saved in the format the server returned instead.
+fn is_fallback_model(name: &str) -> bool {
+    let Some((stem, extension)) = name.rsplit_once('.') else {
+        return false;
+    };
+    let suffix = stem
+        .strip_prefix("output")
+        .map(|rest| rest.strip_prefix('-'));
+    let numbered = match suffix {
+        Some(None) => stem == "output",
+        Some(Some(number)) => {
+            !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())
+        },
+        None => false,
+    };
+    numbered && matches!(extension, "ply" | "splat" | "spz")
+}
+
+/// The run directories of watch mode, next to its state, and the job directories
+/// of serve mode, each with its job file.
+fn runs(dir: &Path) -> Vec<PathBuf> {
+    let mut targets = Vec::new();
+    let runs = dir.join(RUNS_DIR);
+    if runs.join(WATCH_STATE_FILE).is_file() {
+        targets.push(runs.join(WATCH_STATE_FILE));
+        targets.extend(read_dir(&runs).into_iter().filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            path.is_dir() && !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit())
+        }));
+    }
+    targets.extend(
+        read_dir(&dir.join(JOBS_DIR))
+            .into_iter()
+            .filter(|path| path.join(JOB_FILE).is_file()),
+    );
+    targets
+}
+
+/// The brush dataset and checkpoints of the run, and the temporary files of the
+/// tools.
+fn cache(layout: &Layout) -> Vec<PathBuf> {
+    let mut targets = Vec::new();
+    let dataset = layout.dataset_dir();
+    if dataset.join("transforms.json").is_file() {
+        targets.push(dataset);
+    }
+    let checkpoints = layout.checkpoints_dir();
+    if checkpoints.is_dir() {
+        targets.push(checkpoints);
+    }
+    let temp = std::env::temp_dir();
+    targets.extend(read_dir(&temp).into_iter().filter(|path| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        name.starts_with(TEMP_PREFIX) || name == VIDEO_FILE
+    }));
+    targets
+}
+
+fn read_dir(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// The size of the file or directory tree at `path`, without following links.
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if metadata.is_dir() {
+        read_dir(path).iter().map(|path| disk_usage(path)).sum()
+    } else {
+        metadata.len()
+    }
+}
+
+fn format_size(size: u64) -> String {
+    format!("{:.1} MiB", size as f64 / (1024.0 * 1024.0))
+}
+
+fn confirm() -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Err(eyre!(
+            "Refusing to remove files without confirmation. Pass --yes to remove them, \
+             or --dry-run to only list them."
+        ));
+    }
+    eprint!("Remove them? [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
@@ -0,0 +1,290 @@
+//! The `report` subcommand, comparing several runs side by side in an HTML page.
+//!
+//! Everything comes from the run manifests and the files next to them, so the page
+//! is made offline, and runs missing some of them get a placeholder instead.
+
+use crate::checks::format_size;
+use crate::manifest::{RunManifest, RUN_MANIFEST_PATH};
+use crate::reconstruct::list_views;
+use crate::report::{escape, thumbnail};
+use crate::views::VIEWS_DIR;
+use clap::Args;
+use color_eyre::eyre::{Result, WrapErr};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A render of the model next to the run manifest, shown when present.
+const MODEL_THUMBNAIL: &str = "model.png";
+
+/// Arguments of the `report` subcommand.
+#[derive(Args, Debug)]
+pub struct ReportArgs {
+    /// The directories of the runs, each holding its `run.json`.
+    #[arg(required = true, value_name = "RUN_DIR")]
+    pub runs: Vec<PathBuf>,
+
+    /// Where to write the HTML page.
+    #[arg(short, long, value_name = "PATH", default_value = "comparison.html")]
+    pub output: PathBuf,
+}
+
+/// What is known of a run, each part missing when its files are.
+struct Run {
+    dir: PathBuf,
+    manifest: Option<RunManifest>,
+    /// Inlined thumbnails of the views, by file name.
+    views: Vec<(String, String)>,
+    model_thumbnail: Option<String>,
+    /// Why parts of the run are missing.
+    notes: Vec<String>,
+}
+
+pub fn run(args: &ReportArgs) -> Result<()> {
+    let runs: Vec<Run> = args.runs.iter().map(|dir| read_run(dir)).collect();
+    fs::write(&args.output, render_html(&runs))
+        .wrap_err_with(|| format!("Failed to write {}", args.output.display()))?;
+    eprintln!("Compared {} runs in {}", runs.len(), args.output.display());
+    Ok(())
+}
+
+fn read_run(dir: &Path) -> Run {
+    let mut notes = Vec::new();
+    let manifest_path = dir.join(RUN_MANIFEST_PATH);
+    let manifest = match fs::read_to_string(&manifest_path) {
+        Ok(json) => match serde_json::from_str::<RunManifest>(&json) {
+            Ok(manifest) => Some(manifest),
+            Err(error) => {
+                notes.push(format!("{} is unparsable: {}", RUN_MANIFEST_PATH, error));
+                None
+            },
+        },
+        Err(_) => {
+            notes.push(format!("no {}", RUN_MANIFEST_PATH));
+            None
+        },
+    };
+
+    let mut views = Vec::new();
+    match list_views(&dir.join(VIEWS_DIR)) {
+        Ok(paths) if !paths.is_empty() => {
+            for path in paths {
+                let file = path.file_name().unwrap().to_string_lossy().into_owned();
+                match image::open(&path)
+                    .map_err(Into::into)
+                    .and_then(|image| thumbnail(&image))
+                {
+                    Ok(thumbnail) => views.push((file, thumbnail)),
+                    Err(error) => {
+                        notes.push(format!("{} is unreadable: {}", file, error))
+                    },
+                }
+            }
+        },
+        _ => notes.push("no views".to_string()),
+    }
+
+    let model_thumbnail = image::open(dir.join(MODEL_THUMBNAIL))
+        .ok()
+        .and_then(|image| thumbnail(&image).ok());
+    Run {
+        dir: dir.to_path_buf(),
+        manifest,
+        views,
+        model_thumbnail,
+        notes,
+    }
+}
+
+fn render_html(runs: &[Run]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Run comparison</title>\n<style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; table-layout: fixed; }\n\
+         th, td { border: 1px solid #ccc; padding: 4px 8px; vertical-align: top; width: 420px; }\n\
+         th:first-child { width: 120px; text-align: left; }\n\
+         .strip { display: flex; flex-wrap: wrap; gap: 4px; }\n\
+         .strip img { max-width: 96px; }\n\
+         .missing { color: #999; }\n\
+         .note { color: #b04a2a; }\n\
+         </style>\n</head>\n<body>\n<h1>Run comparison</h1>\n<table>\n",
+    );
+    let missing = "<span class=\"missing\">-</span>".to_string();
+
+    row(&mut html, "Run", runs, |run| {
+        format!("<code>{}</code>", escape(&run.dir.display().to_string()))
+    });
+    row(&mut html, "Prompt", runs, |run| match &run.manifest {
+        Some(manifest) if !manifest.prompt.is_empty() => escape(&manifest.prompt),
+        _ => missing.clone(),
+    });
+    row(&mut html, "Views", runs, |run| {
+        if run.views.is_empty() {
+            return missing.clone();
+        }
+        let mut strip = String::from("<div class=\"strip\">");
+        for (file, thumbnail) in &run.views {
+            let _ = write!(
+                strip,
+                "<img src=\"data:image/jpeg;base64,{}\" title=\"{}\">",
+                thumbnail,
+                escape(file)
+            );
+        }
+        strip.push_str("</div>");
+        strip
+    });
+    row(&mut html, "Model", runs, |run| match &run.model_thumbnail {
+        Some(thumbnail) => format!("<img src=\"data:image/jpeg;base64,{}\">", thumbnail),
+        None => "<span class=\"missing\">no render</span>".to_string(),
+    });
+    let stats = |run: &Run| {
+        run.manifest
+            .as_ref()
+            .and_then(|manifest| manifest.model.clone())
+    };
+    row(&mut html, "Gaussians", runs, |run| match stats(run) {
+        Some(stats) => stats.gaussian_count.to_string(),
+        None => missing.clone(),
+    });
+    row(&mut html, "File size", runs, |run| match stats(run) {
+        Some(stats) => format_size(stats.file_size),
+        None => missing.clone(),
+    });
+
+    // A row per stage, in the order the runs went through them.
+    let mut stages: Vec<&str> = Vec::new();
+    for manifest in runs.iter().filter_map(|run| run.manifest.as_ref()) {
+        for timing in &manifest.timings {
+            if !stages.contains(&timing.stage.as_str()) {
+                stages.push(&timing.stage);
+            }
+        }
+    }
+    for stage in stages {
+        row(&mut html, &format!("Time: {}", stage), runs, |run| {
+            let timing = run.manifest.as_ref().and_then(|manifest| {
+                manifest.timings.iter().find(|timing| timing.stage == stage)
+            });
+            match timing {
+                Some(timing)
+                    if timing.usage.temp_bytes + timing.usage.peak_buffer_bytes > 0 =>
+                {
+                    format!(
+                        "{:.1}s<br><span class=\"note\">{} temp, {} peak buffer</span>",
+                        timing.seconds,
+                        format_size(timing.usage.temp_bytes),
+                        format_size(timing.usage.peak_buffer_bytes)
+                    )
+                },
+                Some(timing) => format!("{:.1}s", timing.seconds),
+                None => missing.clone(),
+            }
+        });
+    }
+    if runs.iter().any(|run| !run.notes.is_empty()) {
+        row(&mut html, "Missing", runs, |run| {
+            let notes: Vec<String> = run.notes.iter().map(|note| escape(note)).collect();
+            format!("<span class=\"note\">{}</span>", notes.join("<br>"))
+        });
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// Appends a row titled `title`, with a cell per run made by `cell`.
+fn row(
+    html: &mut String,
+    title: &str,
+    runs: &[Run],
+    cell: impl Fn(&Run) -> String,
+) {
+    let _ = write!(html, "<tr><th>{}</th>", escape(title));
+    for run in runs {
+        let _ = write!(html, "<td>{}</td>", cell(run));
+    }
+    html.push_str("</tr>\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A run with its views and a render of its model, and an older one that kept
+    /// neither and went through other stages.
+    const RUNS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/runs");
+
+    /// The cells of the row titled `title` in `html`.
+    fn cells<'a>(
+        html: &'a str,
+        title: &str,
+    ) -> Vec<&'a str> {
+        let start = format!("<tr><th>{}</th>", title);
+        let row = html
+            .lines()
+            .find_map(|line| line.strip_prefix(start.as_str()))
+            .unwrap_or_else(|| panic!("no row titled {}", title));
+        row.trim_end_matches("</tr>")
+            .split("<td>")
+            .skip(1)
+            .map(|cell| cell.trim_end_matches("</td>"))
+            .collect()
+    }
+
+    #[test]
+    fn compares_a_complete_run_with_one_missing_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = ReportArgs {
+            runs: vec![
+                Path::new(RUNS).join("complete"),
+                Path::new(RUNS).join("partial"),
+            ],
+            output: dir.path().join("comparison.html"),
+        };
+        run(&args).unwrap();
+        let html = fs::read_to_string(&args.output).unwrap();
+
+        assert_eq!(
+            cells(&html, "Prompt"),
+            ["A red bicycle &amp; a &lt;blue&gt; bench", "A wooden chair"]
+        );
+        let views = cells(&html, "Views");
+        assert_eq!(
+            views[0]
+                .matches("<img src=\"data:image/jpeg;base64,")
+                .count(),
+            2
+        );
+        assert!(
+            views[0].contains("title=\"0.png\"") && views[0].contains("title=\"1.png\"")
+        );
+        assert_eq!(views[1], "<span class=\"missing\">-</span>");
+        let model = cells(&html, "Model");
+        assert!(model[0].starts_with("<img src=\"data:image/jpeg;base64,"));
+        assert_eq!(model[1], "<span class=\"missing\">no render</span>");
+        assert_eq!(
+            cells(&html, "Gaussians"),
+            ["24000", "<span class=\"missing\">-</span>"]
+        );
+
+        // Each stage gets a row, blank for the runs that did not go through it.
+        assert_eq!(cells(&html, "Time: views"), ["41.2s", "38.0s"]);
+        let reconstruction = cells(&html, "Time: reconstruction");
+        assert!(reconstruction[0].starts_with("95.5s<br>"));
+        assert_eq!(reconstruction[1], "<span class=\"missing\">-</span>");
+        assert_eq!(
+            cells(&html, "Time: training"),
+            ["<span class=\"missing\">-</span>", "600.0s"]
+        );
+
+        assert_eq!(
+            cells(&html, "Missing"),
+            [
+                "<span class=\"note\"></span>",
+                "<span class=\"note\">no views</span>"
+            ]
+        );
+    }
+}
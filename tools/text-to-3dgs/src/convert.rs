@@ -0,0 +1,61 @@
+//! Conversion of the reconstructed PLY model into other gaussian-splat formats.
+
+use crate::spz;
+use clap::ValueEnum;
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Formats the model can be converted into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConvertFormat {
+    /// Niantic's compressed SPZ format.
+    Spz,
+}
+
+impl ConvertFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ConvertFormat::Spz => "spz",
+        }
+    }
+}
+
+/// A converted copy of the model, as recorded in the run manifest.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Conversion {
+    pub format: ConvertFormat,
+    pub path: PathBuf,
+    pub file_size: u64,
+    pub compression_ratio: f64,
+}
+
+/// Converts the model at `path` next to it, with the extension of `format`.
+pub fn convert_model(
+    path: &Path,
+    format: ConvertFormat,
+) -> Result<Conversion> {
+    let output = path.with_extension(format.extension());
+    eprintln!("Converting {} to {}...", path.display(), output.display());
+    match format {
+        ConvertFormat::Spz => spz::write_spz(path, &output)?,
+    }
+
+    let original_size = fs::metadata(path)?.len();
+    let file_size = fs::metadata(&output)?.len();
+    let compression_ratio = original_size as f64 / file_size.max(1) as f64;
+    eprintln!(
+        "Converted model saved to {} ({:.2} MiB, {:.1}x smaller than the PLY)",
+        output.display(),
+        file_size as f64 / (1024.0 * 1024.0),
+        compression_ratio
+    );
+    Ok(Conversion {
+        format,
+        path: output,
+        file_size,
+        compression_ratio,
+    })
+}
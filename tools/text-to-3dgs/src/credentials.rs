@@ -0,0 +1,153 @@
+//! Credentials read from named profiles in `~/.config/text-to-3dgs/credentials.toml`,
+//! so that keys need not be exported in every shell.
+//!
+//! Options and environment variables take precedence over the profile. The name of
+//! the profile in use is logged and recorded, never its secrets.
+
+use clap::Args;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// The profile used when none is named.
+const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Args, Clone, Debug)]
+pub struct ProfileArgs {
+    /// The profile of the credentials file to use, `default` if there is one.
+    #[arg(long, value_name = "NAME", env = "T2G_PROFILE")]
+    pub profile: Option<String>,
+}
+
+/// A section of the credentials file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub gemini_api_key: Option<String>,
+    pub vertex_project: Option<String>,
+    pub vertex_region: Option<String>,
+    /// Base URL of the reconstruction server.
+    pub server: Option<String>,
+    /// Bearer token sent to the reconstruction server.
+    pub server_token: Option<String>,
+}
+
+/// The profile in use and its name.
+#[derive(Debug)]
+pub struct Credentials {
+    pub name: String,
+    pub profile: Profile,
+}
+
+static CREDENTIALS: OnceLock<Credentials> = OnceLock::new();
+
+/// Where the credentials file is, under `$XDG_CONFIG_HOME` or `~/.config`.
+pub fn credentials_path() -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+        })?;
+    Some(config.join("text-to-3dgs").join("credentials.toml"))
+}
+
+/// Reads the profile named in `args`, or the default one if the file has it, and
+/// keeps it for the commands of text-to-view.
+///
+/// A profile named explicitly must exist. Without one, a missing file or default
+/// profile just means that no profile is used.
+pub fn install(args: &ProfileArgs) -> Result<Option<&'static Credentials>> {
+    let Some(path) = credentials_path() else {
+        return match &args.profile {
+            Some(_) => Err(eyre!("Cannot locate the credentials file: HOME is not set")),
+            None => Ok(None),
+        };
+    };
+    if !path.exists() && args.profile.is_none() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(&path).wrap_err_with(|| {
+        format!("Failed to read the credentials file {}", path.display())
+    })?;
+    warn_if_readable(&path);
+    let mut profiles: BTreeMap<String, Profile> =
+        toml::from_str(&text).wrap_err_with(|| {
+            format!("Failed to parse the credentials file {}", path.display())
+        })?;
+
+    let name = args.profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+    let Some(profile) = profiles.remove(name) else {
+        if args.profile.is_none() {
+            return Ok(None);
+        }
+        let known: Vec<&str> = profiles.keys().map(String::as_str).collect();
+        return Err(eyre!(
+            "There is no profile '{}' in {}. Its profiles are: {}",
+            name,
+            path.display(),
+            if known.is_empty() {
+                "none".to_string()
+            } else {
+                known.join(", ")
+            }
+        ));
+    };
+    for secret in [&profile.gemini_api_key, &profile.server_token]
+        .into_iter()
+        .flatten()
+    {
+        http_trace::redact(secret);
+    }
+    eprintln!(
+        "Using the credentials profile '{}' from {}",
+        name,
+        path.display()
+    );
+    let credentials = Credentials {
+        name: name.to_string(),
+        profile,
+    };
+    Ok(Some(CREDENTIALS.get_or_init(|| credentials)))
+}
+
+/// The environment variables handing the profile to text-to-view, leaving out those
+/// already set, which take precedence.
+pub fn text_to_view_envs() -> Vec<(&'static str, String)> {
+    let Some(credentials) = CREDENTIALS.get() else {
+        return Vec::new();
+    };
+    let profile = &credentials.profile;
+    [
+        ("GEMINI_API_KEY", &profile.gemini_api_key),
+        ("GOOGLE_CLOUD_PROJECT", &profile.vertex_project),
+        ("GOOGLE_CLOUD_LOCATION", &profile.vertex_region),
+    ]
+    .into_iter()
+    .filter(|(variable, _)| env::var_os(variable).is_none())
+    .filter_map(|(variable, value)| Some((variable, value.clone()?)))
+    .collect()
+}
+
+/// Warns when other users may read the credentials file.
+#[cfg(unix)]
+fn warn_if_readable(path: &std::path::Path) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(metadata) = fs::metadata(path) {
+        if metadata.permissions().mode() & 0o004 != 0 {
+            eprintln!(
+                "Warning: the credentials file {} is readable by every user. \
+                 Restrict it with `chmod 600 {}`.",
+                path.display(),
+                path.display()
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_readable(_path: &std::path::Path) {}
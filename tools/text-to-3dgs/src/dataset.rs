@@ -0,0 +1,377 @@
+//! Export of the views and their camera poses as a ready-to-train dataset.
+//!
+//! The dataset follows the Nerfstudio layout, which brush and most other 3DGS
+//! trainers read: an `images/` directory next to a `transforms.json` describing
+//! every frame's intrinsics and camera-to-world matrix.
+//!
+//! A `transforms.json` alone can also be exported, referencing the views where they
+//! are, for reconstruction stacks that estimate poses themselves.
+
+use crate::normalize::Normalization;
+use crate::reconstruct::list_views;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where the camera poses returned by the reconstruction server are saved.
+pub const CAMERAS_PATH: &str = "cameras.json";
+
+/// Horizontal field of view assumed for views without camera poses.
+const PLACEHOLDER_FOV_DEGREES: f64 = 50.0;
+
+/// A camera in the `cameras.json` layout written by the reference 3DGS code.
+///
+/// `rotation` and `position` form the camera-to-world transform in the OpenCV
+/// convention: +X right, +Y down, +Z forward.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Camera {
+    pub img_name: String,
+    pub width: u32,
+    pub height: u32,
+    pub position: [f64; 3],
+    pub rotation: [[f64; 3]; 3],
+    pub fx: f64,
+    pub fy: f64,
+}
+
+/// The Nerfstudio `transforms.json` document.
+#[derive(Debug, Serialize)]
+pub struct Transforms {
+    pub camera_model: &'static str,
+    /// Set when no poses were available, so that every frame has a placeholder
+    /// identity pose and intrinsics from an assumed field of view.
+    pub poses_missing: bool,
+    pub frames: Vec<Frame>,
+}
+
+/// One frame of a `transforms.json`, with per-frame intrinsics.
+#[derive(Debug, Serialize)]
+pub struct Frame {
+    pub file_path: String,
+    pub w: u32,
+    pub h: u32,
+    pub fl_x: f64,
+    pub fl_y: f64,
+    pub cx: f64,
+    pub cy: f64,
+    /// Camera-to-world matrix in the OpenGL convention: +X right, +Y up, +Z back.
+    pub transform_matrix: [[f64; 4]; 4],
+}
+
+pub fn read_cameras(path: &Path) -> Result<Vec<Camera>> {
+    let json = fs::read_to_string(path).map_err(|_| {
+        eyre!(
+            "Exporting a dataset requires camera poses from the reconstruction server, \
+             but none were returned. Poses are fetched from servers that support jobs \
+             and publish them at <job>/cameras."
+        )
+    })?;
+    serde_json::from_str(&json).wrap_err_with(|| {
+        format!("Failed to parse the camera poses in {}", path.display())
+    })
+}
+
+impl Camera {
+    fn frame(
+        &self,
+        file_path: String,
+        normalization: Option<&Normalization>,
+    ) -> Frame {
+        Frame {
+            file_path,
+            w: self.width,
+            h: self.height,
+            fl_x: self.fx,
+            fl_y: self.fy,
+            cx: self.width as f64 / 2.0,
+            cy: self.height as f64 / 2.0,
+            transform_matrix: self.opengl_transform(normalization),
+        }
+    }
+
+    /// The camera-to-world matrix converted to the OpenGL convention, after applying
+    /// the model's normalization, if any.
+    pub fn opengl_transform(
+        &self,
+        normalization: Option<&Normalization>,
+    ) -> [[f64; 4]; 4] {
+        let position = match normalization {
+            Some(normalization) => normalization.apply(self.position),
+            None => self.position,
+        };
+        // Flipping the camera's Y and Z axes turns OpenCV into OpenGL.
+        let r = self.rotation;
+        [
+            [r[0][0], -r[0][1], -r[0][2], position[0]],
+            [r[1][0], -r[1][1], -r[1][2], position[1]],
+            [r[2][0], -r[2][1], -r[2][2], position[2]],
+            [0.0, 0.0, 0.0, 1.0],
+        ]
+    }
+}
+
+/// Writes the views in `views_dir` and the poses in `cameras_path` as a Nerfstudio
+/// dataset in `dir`.
+pub fn export_dataset(
+    dir: &Path,
+    views_dir: &Path,
+    cameras_path: &Path,
+    normalization: Option<&Normalization>,
+) -> Result<()> {
+    let cameras = read_cameras(cameras_path)?;
+    let images_dir = dir.join("images");
+    fs::create_dir_all(&images_dir)
+        .wrap_err_with(|| format!("Failed to create {}", images_dir.display()))?;
+
+    let mut frames = Vec::with_capacity(cameras.len());
+    for camera in &cameras {
+        let view = find_view(views_dir, &camera.img_name)?;
+        let file_name = view.file_name().unwrap().to_string_lossy().into_owned();
+        fs::copy(&view, images_dir.join(&file_name))
+            .wrap_err_with(|| format!("Failed to copy {}", view.display()))?;
+        frames.push(camera.frame(format!("images/{}", file_name), normalization));
+    }
+
+    let transforms = Transforms {
+        camera_model: "OPENCV",
+        poses_missing: false,
+        frames,
+    };
+    let path = dir.join("transforms.json");
+    fs::write(&path, serde_json::to_string_pretty(&transforms)?)
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+    eprintln!(
+        "Exported a dataset of {} posed views to {}",
+        cameras.len(),
+        dir.display()
+    );
+    Ok(())
+}
+
+/// Writes a `transforms.json` at `path` describing the views in `views_dir` where
+/// they are, with the poses in `cameras_path` if there are any.
+pub fn export_transforms(
+    path: &Path,
+    views_dir: &Path,
+    cameras_path: &Path,
+    normalization: Option<&Normalization>,
+) -> Result<()> {
+    let base = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    fs::create_dir_all(base)
+        .wrap_err_with(|| format!("Failed to create {}", base.display()))?;
+    let base = base.canonicalize()?;
+    let file_path = |view: &Path| -> Result<String> {
+        let view = view
+            .canonicalize()
+            .wrap_err_with(|| format!("Failed to find {}", view.display()))?;
+        // Views outside the directory of the file are referenced by absolute path.
+        Ok(match view.strip_prefix(&base) {
+            Ok(relative) => relative
+                .components()
+                .map(|part| part.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            Err(_) => view.to_string_lossy().into_owned(),
+        })
+    };
+
+    let poses_missing = !cameras_path.exists();
+    let mut frames = Vec::new();
+    if poses_missing {
+        for view in list_views(views_dir)? {
+            let (width, height) = image::image_dimensions(&view)
+                .wrap_err_with(|| format!("Failed to read {}", view.display()))?;
+            let focal =
+                width as f64 / 2.0 / (PLACEHOLDER_FOV_DEGREES.to_radians() / 2.0).tan();
+            frames.push(Frame {
+                file_path: file_path(&view)?,
+                w: width,
+                h: height,
+                fl_x: focal,
+                fl_y: focal,
+                cx: width as f64 / 2.0,
+                cy: height as f64 / 2.0,
+                transform_matrix: [
+                    [1.0, 0.0, 0.0, 0.0],
+                    [0.0, 1.0, 0.0, 0.0],
+                    [0.0, 0.0, 1.0, 0.0],
+                    [0.0, 0.0, 0.0, 1.0],
+                ],
+            });
+        }
+    } else {
+        for camera in read_cameras(cameras_path)? {
+            let view = find_view(views_dir, &camera.img_name)?;
+            frames.push(camera.frame(file_path(&view)?, normalization));
+        }
+    }
+
+    let transforms = Transforms {
+        camera_model: "OPENCV",
+        poses_missing,
+        frames,
+    };
+    fs::write(path, serde_json::to_string_pretty(&transforms)?)
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+    eprintln!(
+        "Exported the transforms of {} views to {}{}",
+        transforms.frames.len(),
+        path.display(),
+        if poses_missing {
+            ", with placeholder poses"
+        } else {
+            ""
+        }
+    );
+    Ok(())
+}
+
+/// Finds the view a camera refers to, whose name may lack the file extension.
+fn find_view(
+    views_dir: &Path,
+    img_name: &str,
+) -> Result<PathBuf> {
+    let exact = views_dir.join(img_name);
+    if exact.is_file() {
+        return Ok(exact);
+    }
+    fs::read_dir(views_dir)
+        .wrap_err_with(|| format!("Failed to read {}", views_dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .find(|path| path.file_stem().is_some_and(|stem| stem == img_name))
+        .ok_or_else(|| {
+            eyre!(
+                "The camera '{}' refers to a view missing from {}",
+                img_name,
+                views_dir.display()
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    /// Writes blank views of `width` by `height` pixels named `names` into `dir`.
+    fn write_views(
+        dir: &Path,
+        names: &[&str],
+        width: u32,
+        height: u32,
+    ) {
+        fs::create_dir_all(dir).unwrap();
+        for name in names {
+            image::RgbImage::new(width, height)
+                .save(dir.join(name))
+                .unwrap();
+        }
+    }
+
+    fn read_json(path: &Path) -> Value {
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    /// Checks the fields Nerfstudio reads from every frame of `transforms`.
+    fn assert_nerfstudio_schema(transforms: &Value) {
+        assert_eq!(transforms["camera_model"], "OPENCV");
+        for frame in transforms["frames"].as_array().unwrap() {
+            assert!(frame["file_path"].is_string(), "{}", frame);
+            for field in ["fl_x", "fl_y", "cx", "cy"] {
+                assert!(frame[field].is_f64(), "{} in {}", field, frame);
+            }
+            for field in ["w", "h"] {
+                assert!(frame[field].is_u64(), "{} in {}", field, frame);
+            }
+            let matrix = frame["transform_matrix"].as_array().unwrap();
+            assert_eq!(matrix.len(), 4);
+            assert!(matrix.iter().all(|row| row.as_array().unwrap().len() == 4));
+            assert_eq!(matrix[3], json!([0.0, 0.0, 0.0, 1.0]));
+        }
+    }
+
+    #[test]
+    fn exports_a_nerfstudio_dataset() {
+        let dir = tempfile::tempdir().unwrap();
+        let views = dir.path().join("views");
+        write_views(&views, &["0.png", "1.png"], 64, 48);
+        let cameras = dir.path().join(CAMERAS_PATH);
+        let camera = |name: &str, position: [f64; 3]| Camera {
+            img_name: name.to_string(),
+            width: 64,
+            height: 48,
+            position,
+            rotation: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            fx: 70.0,
+            fy: 71.0,
+        };
+        // The second camera names its view without the extension.
+        let list = [
+            camera("0.png", [1.0, 2.0, 3.0]),
+            camera("1", [-1.0, 0.0, 0.5]),
+        ];
+        fs::write(&cameras, serde_json::to_string(&list).unwrap()).unwrap();
+
+        let out = dir.path().join("dataset");
+        export_dataset(&out, &views, &cameras, None).unwrap();
+        let transforms = read_json(&out.join("transforms.json"));
+        assert_nerfstudio_schema(&transforms);
+
+        let frames = transforms["frames"].as_array().unwrap();
+        assert_eq!(frames.len(), 2);
+        for (frame, name) in frames.iter().zip(["0.png", "1.png"]) {
+            assert_eq!(frame["file_path"], format!("images/{}", name));
+            assert!(out.join("images").join(name).is_file());
+        }
+        assert_eq!(frames[0]["w"], 64);
+        assert_eq!(frames[0]["h"], 48);
+        assert_eq!(frames[0]["fl_x"], 70.0);
+        assert_eq!(frames[0]["fl_y"], 71.0);
+        assert_eq!(frames[0]["cx"], 32.0);
+        assert_eq!(frames[0]["cy"], 24.0);
+        // The OpenCV camera looking down +Z looks down -Z in OpenGL.
+        assert_eq!(
+            frames[0]["transform_matrix"],
+            json!([
+                [1.0, 0.0, 0.0, 1.0],
+                [0.0, -1.0, 0.0, 2.0],
+                [0.0, 0.0, -1.0, 3.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ])
+        );
+    }
+
+    #[test]
+    fn exports_placeholder_transforms_without_poses() {
+        let dir = tempfile::tempdir().unwrap();
+        let views = dir.path().join("views");
+        write_views(&views, &["0.jpg", "1.jpg"], 100, 50);
+        fs::write(views.join("notes.txt"), "not a view").unwrap();
+        let path = dir.path().join("transforms.json");
+        export_transforms(&path, &views, &dir.path().join(CAMERAS_PATH), None).unwrap();
+
+        let transforms = read_json(&path);
+        assert_nerfstudio_schema(&transforms);
+        assert_eq!(transforms["poses_missing"], true);
+        let frames = transforms["frames"].as_array().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0]["file_path"], "views/0.jpg");
+        assert_eq!(frames[1]["file_path"], "views/1.jpg");
+        assert_eq!(frames[0]["w"], 100);
+        assert_eq!(frames[0]["h"], 50);
+        assert_eq!(frames[0]["fl_x"], frames[0]["fl_y"]);
+        assert_eq!(
+            frames[0]["transform_matrix"],
+            json!([
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ])
+        );
+    }
+}
@@ -0,0 +1,399 @@
+//! The `doctor` subcommand, diagnosing the whole environment of the pipeline.
+//!
+//! The checks of text-to-view come from `text-to-view doctor --json`, and the rest
+//! are run here. Unlike the checks before a run, every check applies, and each
+//! problem comes with a hint on how to fix it.
+
+use crate::brush::{discover_brush, Platform, SystemProbe, BRUSH_DIR, BRUSH_PATH_VAR};
+use crate::checks::{
+    available_space, format_size, OUTPUT_SPACE_NEEDED, TEMP_SPACE_NEEDED,
+};
+use crate::credentials::{self, ProfileArgs};
+use crate::reconstruct::{Backend, DEFAULT_SERVER};
+use clap::Args;
+use color_eyre::eyre::{eyre, Result};
+use http_trace::SendTraced;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+/// How long the reconstruction server has to answer each request.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Args, Debug)]
+pub struct DoctorArgs {
+    /// Print the findings as JSON on stdout, for CI.
+    #[arg(long)]
+    json: bool,
+
+    /// Where the models are written, checked for write permission and free space.
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    output_dir: PathBuf,
+
+    /// How models are going to be reconstructed, deciding whether the server or
+    /// brush is a hard requirement.
+    #[arg(long, value_enum, default_value_t = Backend::Server)]
+    backend: Backend,
+
+    /// Base URL of the reconstruction server [default: the credentials profile's, or
+    /// http://localhost:8888].
+    #[arg(long, value_name = "URL")]
+    server: Option<String>,
+
+    /// Path of the server's capabilities document.
+    #[arg(long, value_name = "PATH", default_value = "/capabilities")]
+    capabilities_path: String,
+
+    /// The brush executable to use, instead of searching for one.
+    #[arg(long, value_name = "PATH")]
+    brush_path: Option<PathBuf>,
+
+    #[command(flatten)]
+    credentials: ProfileArgs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    /// A likely problem that does not stop a run.
+    Warn,
+    Fail,
+}
+
+/// The outcome of a check.
+#[derive(Debug, Serialize, Deserialize)]
+struct Finding {
+    name: String,
+    status: Status,
+    detail: String,
+    /// How to fix the problem, for findings that do not pass.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hint: Option<String>,
+}
+
+impl Finding {
+    fn new(
+        name: &str,
+        status: Status,
+        detail: impl Into<String>,
+        hint: Option<&str>,
+    ) -> Self {
+        Finding {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+            hint: hint.map(str::to_string),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Report {
+    ok: bool,
+    checks: Vec<Finding>,
+}
+
+pub async fn run(args: &DoctorArgs) -> Result<()> {
+    let (credentials, profile_server) = check_credentials(args);
+    let mut findings = vec![credentials];
+    findings.extend(text_to_view_findings());
+    findings.push(check_dir(
+        "temp-dir",
+        &std::env::temp_dir(),
+        TEMP_SPACE_NEEDED,
+        "Point TMPDIR at a directory with more room.",
+    ));
+    findings.push(check_dir(
+        "output-dir",
+        &args.output_dir,
+        OUTPUT_SPACE_NEEDED,
+        "Free some space, or write the models elsewhere with --output.",
+    ));
+    let server = args
+        .server
+        .clone()
+        .or(profile_server)
+        .unwrap_or_else(|| DEFAULT_SERVER.to_string());
+    findings.extend(check_server(args, &server).await);
+    findings.push(check_brush(args));
+
+    let failed = findings
+        .iter()
+        .filter(|finding| finding.status == Status::Fail)
+        .count();
+    if args.json {
+        let report = Report {
+            ok: failed == 0,
+            checks: findings,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if failed > 0 {
+            return Err(eyre!("{} of {} checks failed", failed, report.checks.len()));
+        }
+        return Ok(());
+    }
+    print_report(&findings);
+    if failed > 0 {
+        return Err(eyre!("{} of {} checks failed", failed, findings.len()));
+    }
+    Ok(())
+}
+
+/// Reads the credentials profile, returning the server it names, if any.
+fn check_credentials(args: &DoctorArgs) -> (Finding, Option<String>) {
+    match credentials::install(&args.credentials) {
+        Ok(Some(credentials)) => (
+            Finding::new(
+                "credentials",
+                Status::Pass,
+                format!("using the profile '{}'", credentials.name),
+                None,
+            ),
+            credentials.profile.server.clone(),
+        ),
+        Ok(None) => (
+            Finding::new(
+                "credentials",
+                Status::Pass,
+                "no profile, the credentials come from the environment",
+                None,
+            ),
+            None,
+        ),
+        Err(error) => (
+            Finding::new(
+                "credentials",
+                Status::Fail,
+                format!("{:#}", error),
+                Some("Fix the credentials file, or pick another profile with --profile."),
+            ),
+            None,
+        ),
+    }
+}
+
+/// The findings of `text-to-view doctor`, or a failure to run it.
+fn text_to_view_findings() -> Vec<Finding> {
+    let output = crate::text_to_view_command()
+        .args(["doctor", "--json"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+    let hint = "Check that cargo is on PATH and that `cargo build -p text-to-view` \
+                succeeds; it needs the FFmpeg libraries.";
+    let output = match output {
+        Ok(output) => output,
+        Err(error) => {
+            return vec![Finding::new(
+                "text-to-view",
+                Status::Fail,
+                format!("could not run text-to-view: {}", error),
+                Some(hint),
+            )]
+        },
+    };
+    match serde_json::from_slice::<Report>(&output.stdout) {
+        Ok(report) => report.checks,
+        Err(_) => vec![Finding::new(
+            "text-to-view",
+            Status::Fail,
+            "text-to-view failed to build or start",
+            Some(hint),
+        )],
+    }
+}
+
+/// Checks that `dir`, or its closest existing ancestor, is writable and has
+/// `needed` bytes free.
+fn check_dir(
+    name: &str,
+    dir: &Path,
+    needed: u64,
+    space_hint: &str,
+) -> Finding {
+    let existing = dir
+        .ancestors()
+        .find(|path| path.exists())
+        .filter(|path| !path.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let probe = existing.join(format!(".text-to-3dgs-doctor-{}", std::process::id()));
+    if let Err(error) = fs::write(&probe, b"") {
+        return Finding::new(
+            name,
+            Status::Fail,
+            format!("cannot write to {}: {}", existing.display(), error),
+            Some("Fix the permissions of the directory, or pick another one."),
+        );
+    }
+    let _ = fs::remove_file(&probe);
+    match available_space(dir) {
+        Some(available) if available < needed => Finding::new(
+            name,
+            Status::Fail,
+            format!(
+                "{} free in {}, needs {}",
+                format_size(available),
+                dir.display(),
+                format_size(needed)
+            ),
+            Some(space_hint),
+        ),
+        Some(available) => Finding::new(
+            name,
+            Status::Pass,
+            format!(
+                "{} is writable with {} free",
+                dir.display(),
+                format_size(available)
+            ),
+            None,
+        ),
+        None => Finding::new(
+            name,
+            Status::Warn,
+            format!(
+                "{} is writable, but its free space is unknown",
+                dir.display()
+            ),
+            Some("Make sure it has room for the views and the model."),
+        ),
+    }
+}
+
+/// Checks that the reconstruction server answers, and reads its capabilities.
+async fn check_server(
+    args: &DoctorArgs,
+    server: &str,
+) -> Vec<Finding> {
+    let required = args.backend == Backend::Server;
+    let url = |path: &str| format!("{}{}", server.trim_end_matches('/'), path);
+    let client = match reqwest::Client::builder().timeout(SERVER_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(error) => {
+            return vec![Finding::new(
+                "server",
+                Status::Fail,
+                error.to_string(),
+                Some("Check the TLS setup of this machine."),
+            )]
+        },
+    };
+    if let Err(error) = client.get(url("/")).send_traced().await {
+        return vec![Finding::new(
+            "server",
+            if required { Status::Fail } else { Status::Warn },
+            format!("{} is unreachable: {}", server, error.without_url()),
+            Some(
+                "Start view-to-3dgs as described in its README, or point --server at \
+                 a running server. Runs with --backend brush do not need it.",
+            ),
+        )];
+    }
+    let mut findings = vec![Finding::new(
+        "server",
+        Status::Pass,
+        format!("{} answers", server),
+        None,
+    )];
+
+    let capabilities = client
+        .get(url(&args.capabilities_path))
+        .send_traced()
+        .await
+        .ok()
+        .filter(|response| response.status().is_success());
+    let finding = match capabilities {
+        None => Finding::new(
+            "capabilities",
+            Status::Pass,
+            "the server publishes none, the upload options are used as given",
+            None,
+        ),
+        Some(response) => match response.json::<serde_json::Value>().await {
+            Ok(document) => Finding::new(
+                "capabilities",
+                Status::Pass,
+                format!(
+                    "schema {}, server version {}",
+                    document["schema"].as_u64().unwrap_or(1),
+                    document["version"].as_str().unwrap_or("unknown")
+                ),
+                None,
+            ),
+            Err(error) => Finding::new(
+                "capabilities",
+                Status::Warn,
+                format!("the capabilities document is unparsable: {}", error),
+                Some("Fix the server's capabilities document; until then it is ignored."),
+            ),
+        },
+    };
+    findings.push(finding);
+    findings
+}
+
+/// Looks for brush the way runs do, which build the vendored copy when there is no
+/// other.
+fn check_brush(args: &DoctorArgs) -> Finding {
+    let discovery =
+        discover_brush(args.brush_path.as_deref(), &Platform::CURRENT, &SystemProbe);
+    if let Some(path) = discovery.found {
+        return Finding::new("brush", Status::Pass, path.display().to_string(), None);
+    }
+    if args.brush_path.is_some() || std::env::var_os(BRUSH_PATH_VAR).is_some() {
+        return Finding::new(
+            "brush",
+            Status::Fail,
+            format!("not found at {}", discovery.searched[0].display()),
+            Some("Fix --brush-path or BRUSH_APP."),
+        );
+    }
+    if Path::new(BRUSH_DIR).join("Cargo.toml").exists() {
+        return Finding::new(
+            "brush",
+            Status::Warn,
+            "not built yet, the first run needing it builds the vendored copy",
+            Some("Build it ahead with `cargo build --release --bin brush_app` in tools/brush."),
+        );
+    }
+    Finding::new(
+        "brush",
+        if args.backend == Backend::Brush {
+            Status::Fail
+        } else {
+            Status::Warn
+        },
+        format!("not found in any of {} places", discovery.searched.len()),
+        Some(
+            "Install brush, pass --brush-path, or run \
+             `git submodule update --init tools/brush` to build the vendored copy. \
+             Runs with --no-view and the server backend do not need it.",
+        ),
+    )
+}
+
+/// Prints the findings as a table on stderr, colored on terminals.
+fn print_report(findings: &[Finding]) {
+    let color = io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    eprintln!("text-to-3dgs doctor:");
+    for finding in findings {
+        let (label, code) = match finding.status {
+            Status::Pass => ("PASS", "32"),
+            Status::Warn => ("WARN", "33"),
+            Status::Fail => ("FAIL", "31"),
+        };
+        let label = if color {
+            format!("\x1b[{}m{}\x1b[0m", code, label)
+        } else {
+            label.to_string()
+        };
+        eprintln!("  {}  {:<13} {}", label, finding.name, finding.detail);
+        if let Some(hint) = &finding.hint {
+            eprintln!("        {:<13} hint: {}", "", hint);
+        }
+    }
+}
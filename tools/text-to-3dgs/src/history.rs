@@ -0,0 +1,334 @@
+//! The prompts of earlier runs, indexed by a hash of their normalized text in
+//! `~/.cache/text-to-3dgs/prompts.json`, so that a prompt is not generated twice by
+//! accident.
+//!
+//! Runs of every directory share the index. It is locked while it is read and
+//! updated, so that concurrent runs, like the jobs of serve mode, see each other's
+//! prompts and only one of them generates a given prompt.
+
+use crate::{checksum, lock};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+
+/// The name of the index in the cache directory.
+const INDEX_FILE: &str = "prompts.json";
+
+/// How the last run of a prompt ended, or that it has not yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Running => "running",
+            Status::Completed => "completed",
+            Status::Failed => "failed",
+            Status::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// The last run of a prompt.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PromptRun {
+    /// The prompt as it was given.
+    pub prompt: String,
+    /// The directory of the run.
+    pub dir: PathBuf,
+    /// The model of the run, unless it was written to stdout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    pub status: Status,
+    /// When the run started, in seconds since the Unix epoch.
+    pub started: u64,
+    /// The process of the run, which holds the lock on its directory while running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+}
+
+impl PromptRun {
+    /// Whether another run of the prompt would duplicate this one. Prompts whose
+    /// run failed or was cancelled are meant to be run again.
+    pub fn is_duplicated_by_rerun(&self) -> bool {
+        matches!(self.status, Status::Running | Status::Completed)
+    }
+
+    /// Marks the run as failed if it is recorded as running but its process no
+    /// longer holds the lock on its directory: it was killed before it could record
+    /// how it ended.
+    fn settle(&mut self) {
+        if self.status == Status::Running && !lock::is_held(&self.dir, self.pid) {
+            self.status = Status::Failed;
+        }
+    }
+
+    /// Where the run is, how it ended, and where its model is.
+    pub fn describe(&self) -> String {
+        let output = match &self.output {
+            Some(output) => format!(", model at {}", output.display()),
+            None => String::new(),
+        };
+        format!(
+            "{} ({}{})",
+            self.dir.display(),
+            self.status.as_str(),
+            output
+        )
+    }
+}
+
+/// The last run of each prompt, by the hash of its normalized text.
+type Index = BTreeMap<String, PromptRun>;
+
+/// Where the index is, under `$XDG_CACHE_HOME` or `~/.cache`.
+pub fn index_path() -> Option<PathBuf> {
+    let cache = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache.join("text-to-3dgs").join(INDEX_FILE))
+}
+
+/// The key of `prompt` in the index, the same whatever its case and whitespace.
+fn key(prompt: &str) -> String {
+    let normalized: Vec<String> =
+        prompt.split_whitespace().map(str::to_lowercase).collect();
+    checksum::sha256(normalized.join(" ").as_bytes())
+}
+
+/// Runs `update` on the index while holding its lock, then saves it.
+///
+/// Without a home directory, there is no index and `update` gets an empty one.
+fn with_index<T>(update: impl FnOnce(&mut Index) -> T) -> Result<T> {
+    let Some(path) = index_path() else {
+        return Ok(update(&mut Index::new()));
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .wrap_err_with(|| {
+            format!("Failed to open the prompt index {}", path.display())
+        })?;
+    // Released when the file is closed, however the update ends.
+    file.lock_exclusive().wrap_err_with(|| {
+        format!("Failed to lock the prompt index {}", path.display())
+    })?;
+    let mut json = String::new();
+    file.read_to_string(&mut json)?;
+    let mut index: Index = if json.trim().is_empty() {
+        Index::new()
+    } else {
+        serde_json::from_str(&json).unwrap_or_else(|error| {
+            eprintln!(
+                "Warning: the prompt index {} is unreadable ({}), starting a new one",
+                path.display(),
+                error
+            );
+            Index::new()
+        })
+    };
+    let result = update(&mut index);
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(serde_json::to_string_pretty(&index)?.as_bytes())
+        .wrap_err_with(|| {
+            format!("Failed to write the prompt index {}", path.display())
+        })?;
+    Ok(result)
+}
+
+/// The last run of `prompt`, if it was run before.
+pub fn previous(prompt: &str) -> Result<Option<PromptRun>> {
+    with_index(|index| {
+        let run = index.get_mut(&key(prompt))?;
+        run.settle();
+        Some(run.clone())
+    })
+}
+
+/// A run of a prompt recorded in the index, marked as failed or cancelled when
+/// dropped before it is finished.
+pub struct Claim {
+    key: String,
+    dir: PathBuf,
+    started: u64,
+    cancel: CancellationToken,
+    finished: bool,
+}
+
+/// Records the run of `prompt` in `dir` as running, or fails with where it was run
+/// before when that run is running or completed, unless `allow_duplicate`. The
+/// caller holds the lock on `dir`, which tells later runs that this one is alive.
+///
+/// The check and the record happen under one lock, so that of concurrent runs of a
+/// prompt, only one proceeds.
+pub fn claim(
+    prompt: &str,
+    dir: &Path,
+    output: Option<&Path>,
+    allow_duplicate: bool,
+    cancel: &CancellationToken,
+) -> Result<Claim> {
+    let key = key(prompt);
+    let dir = std::path::absolute(dir)?;
+    let output = output.map(std::path::absolute).transpose()?;
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let run = PromptRun {
+        prompt: prompt.to_string(),
+        dir: dir.clone(),
+        output,
+        status: Status::Running,
+        started,
+        pid: Some(std::process::id()),
+    };
+    let previous = with_index(|index| {
+        if let Some(previous) = index.get_mut(&key) {
+            previous.settle();
+            if previous.is_duplicated_by_rerun() && !allow_duplicate {
+                return Err(eyre!(
+                    "The prompt was already run in {}. Pass --allow-duplicate to \
+                     generate it again.",
+                    previous.describe()
+                ));
+            }
+        }
+        Ok(index.insert(key.clone(), run))
+    })??;
+    if let Some(previous) = previous {
+        eprintln!(
+            "The prompt was run before in {}, running it again",
+            previous.describe()
+        );
+    }
+    Ok(Claim {
+        key,
+        dir,
+        started,
+        cancel: cancel.clone(),
+        finished: false,
+    })
+}
+
+impl Claim {
+    /// Records how the run ended.
+    pub fn finish(
+        mut self,
+        status: Status,
+    ) -> Result<()> {
+        self.finished = true;
+        self.record(status)
+    }
+
+    fn record(
+        &self,
+        status: Status,
+    ) -> Result<()> {
+        with_index(|index| {
+            // A later run of the prompt, with --allow-duplicate, owns the entry now.
+            if let Some(run) = index
+                .get_mut(&self.key)
+                .filter(|run| run.dir == self.dir && run.started == self.started)
+            {
+                run.status = status;
+                run.pid = None;
+            }
+        })
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let status = if self.cancel.is_cancelled() {
+            Status::Cancelled
+        } else {
+            Status::Failed
+        };
+        if let Err(error) = self.record(status) {
+            eprintln!("Warning: could not update the prompt index: {:#}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lock::RunLock;
+
+    fn running_in(dir: &Path) -> PromptRun {
+        PromptRun {
+            prompt: "A red vintage bicycle".to_string(),
+            dir: dir.to_path_buf(),
+            output: None,
+            status: Status::Running,
+            started: 0,
+            pid: Some(std::process::id()),
+        }
+    }
+
+    #[test]
+    fn keeps_a_run_running_while_it_holds_its_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let _lock = RunLock::acquire(dir.path()).unwrap();
+        let mut run = running_in(dir.path());
+        run.settle();
+        assert_eq!(run.status, Status::Running);
+        assert!(run.is_duplicated_by_rerun());
+    }
+
+    #[test]
+    fn fails_a_run_killed_before_it_ended() {
+        let dir = tempfile::tempdir().unwrap();
+        // The lock of a killed run is released, and its file left behind.
+        drop(RunLock::acquire(dir.path()).unwrap());
+        let mut run = running_in(dir.path());
+        run.settle();
+        assert_eq!(run.status, Status::Failed);
+        assert!(!run.is_duplicated_by_rerun());
+
+        // Nor does a run whose directory is gone, or held by another process, run on.
+        let mut run = running_in(&dir.path().join("gone"));
+        run.settle();
+        assert_eq!(run.status, Status::Failed);
+        let _lock = RunLock::acquire(dir.path()).unwrap();
+        let mut run = running_in(dir.path());
+        run.pid = Some(std::process::id() + 1);
+        run.settle();
+        assert_eq!(run.status, Status::Failed);
+    }
+
+    #[test]
+    fn leaves_ended_runs_as_they_ended() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut run = running_in(dir.path());
+        run.status = Status::Completed;
+        run.settle();
+        assert_eq!(run.status, Status::Completed);
+    }
+}
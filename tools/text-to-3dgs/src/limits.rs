@@ -0,0 +1,59 @@
+//! Rate limits on the API calls of text-to-view, handed to it through its
+//! environment so that they reach every run of watch and serve mode alike.
+
+use clap::Args;
+
+#[derive(Args, Clone, Debug)]
+pub struct RateLimitArgs {
+    /// The most Gemini prompt requests per minute, shared by all runs on this machine.
+    #[arg(
+        long,
+        value_name = "N",
+        env = "TEXT_TO_VIEW_GEMINI_RPM",
+        value_parser = clap::value_parser!(u32).range(1..),
+    )]
+    pub gemini_rpm: Option<u32>,
+
+    /// The most Veo generation requests per minute, shared by all runs on this machine.
+    #[arg(
+        long,
+        value_name = "N",
+        env = "TEXT_TO_VIEW_VEO_RPM",
+        value_parser = clap::value_parser!(u32).range(1..),
+    )]
+    pub veo_rpm: Option<u32>,
+
+    /// The most polls of Veo operations per minute, shared by all runs on this machine.
+    #[arg(
+        long,
+        value_name = "N",
+        env = "TEXT_TO_VIEW_OPERATIONS_RPM",
+        value_parser = clap::value_parser!(u32).range(1..),
+    )]
+    pub operations_rpm: Option<u32>,
+
+    /// The most Imagen image requests per minute, shared by all runs on this machine.
+    #[arg(
+        long,
+        value_name = "N",
+        env = "TEXT_TO_VIEW_IMAGEN_RPM",
+        value_parser = clap::value_parser!(u32).range(1..),
+    )]
+    pub imagen_rpm: Option<u32>,
+}
+
+impl RateLimitArgs {
+    /// The environment variables text-to-view reads the limits from, for its
+    /// command.
+    pub fn envs(&self) -> Vec<(&'static str, String)> {
+        [
+            ("TEXT_TO_VIEW_GEMINI_RPM", self.gemini_rpm),
+            ("TEXT_TO_VIEW_VEO_RPM", self.veo_rpm),
+            ("TEXT_TO_VIEW_OPERATIONS_RPM", self.operations_rpm),
+            ("TEXT_TO_VIEW_IMAGEN_RPM", self.imagen_rpm),
+        ]
+        .into_iter()
+        .filter_map(|(variable, limit)| Some((variable, limit?.to_string())))
+        .collect()
+    }
+}
@@ -0,0 +1,97 @@
+//! An advisory lock on the directory of a run, so that concurrent runs never write
+//! over each other's views and models.
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use fs2::FileExt;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The lock file in the directory of a run, holding the PID of its process.
+const LOCK_FILE: &str = ".text-to-3dgs.lock";
+
+/// Holds the lock on the directory of a run until dropped.
+///
+/// The operating system releases the lock along with the process, however it ends,
+/// so a lock file left behind by a killed run never blocks the next one.
+pub struct RunLock {
+    file: File,
+}
+
+impl RunLock {
+    /// Locks `dir`, creating it if needed, or fails naming the process holding it.
+    pub fn acquire(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(LOCK_FILE);
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .wrap_err_with(|| {
+                format!("Failed to open the lock file {}", path.display())
+            })?;
+        let mut holder = String::new();
+        file.read_to_string(&mut holder).ok();
+        let holder = holder.trim();
+
+        if file.try_lock_exclusive().is_err() {
+            let holder = match holder {
+                "" => "another process".to_string(),
+                pid => format!("process {}", pid),
+            };
+            return Err(eyre!(
+                "Another text-to-3dgs run ({}) is using {}. Wait for it to finish, or \
+                 run in another directory or with another --project-dir.",
+                holder,
+                dir.display()
+            ));
+        }
+        // A finished run clears its PID, so one left here belongs to a dead process.
+        if !holder.is_empty() {
+            eprintln!(
+                "Broke the stale lock of process {} on {}",
+                holder,
+                dir.display()
+            );
+        }
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+        Ok(RunLock { file })
+    }
+}
+
+/// Whether the lock on `dir` is held, by the process `pid` when known.
+///
+/// A holder that has yet to write its PID is taken to be that process.
+pub fn is_held(
+    dir: &Path,
+    pid: Option<u32>,
+) -> bool {
+    let Ok(mut file) = File::open(dir.join(LOCK_FILE)) else {
+        return false;
+    };
+    // Released when the file is closed.
+    if FileExt::try_lock_shared(&file).is_ok() {
+        return false;
+    }
+    let mut holder = String::new();
+    file.read_to_string(&mut holder).ok();
+    match (pid, holder.trim().parse::<u32>()) {
+        (Some(pid), Ok(holder)) => holder == pid,
+        _ => true,
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // The file stays, since removing it could let two later runs lock different
+        // files at the same path.
+        self.file.set_len(0).ok();
+        FileExt::unlock(&self.file).ok();
+    }
+}
@@ -1,23 +1,280 @@
+mod brush;
+mod cancel;
+mod checks;
+mod checksum;
+mod clean;
+mod compare;
+mod convert;
+mod credentials;
+mod dataset;
+mod doctor;
+mod history;
+mod limits;
+mod lock;
+mod manifest;
+mod merge;
+mod model;
+mod normalize;
+mod output;
+mod ply;
+mod preflight;
+mod project;
+mod prune;
+mod reconstruct;
+mod recording;
+mod remote;
+mod report;
+mod reveal;
+#[cfg(feature = "serve")]
+mod serve;
+mod spz;
+mod summary;
+mod upload;
+mod verify;
+mod views;
+mod watch;
+
+use brush::BrushArgs;
+use checks::{CheckArgs, Plan};
+use clap::{CommandFactory, Parser, Subcommand};
 use color_eyre::eyre::{eyre, Result, WrapErr};
-use reqwest::{multipart, Body};
-use std::env;
-use std::fs;
+use convert::{convert_model, ConvertFormat};
+use credentials::ProfileArgs;
+use dataset::{export_dataset, export_transforms};
+use limits::RateLimitArgs;
+use lock::RunLock;
+use manifest::{RunManifest, StageTiming};
+use merge::MergeArgs;
+use model::ModelFormat;
+use normalize::normalize_model;
+use ply::ModelStats;
+use project::{Layout, ProjectArgs};
+use prune::{prune_model, PruneArgs};
+use reconstruct::{list_views, run_view_to_3dgs, Backend, Model, ReconstructArgs};
+use recording::RecordingArgs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use tokio::fs::File;
-use tokio_util::codec::{BytesCodec, FramedRead};
+use std::time::Instant;
+use summary::{RunSummary, Stage};
+use tokio_util::sync::CancellationToken;
+use upload::UploadArgs;
+use watch::WatchArgs;
+
+/// Generates a 3DGS model from a text prompt and opens it in the brush viewer.
+#[derive(Debug, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// The text prompt describing the scene to generate.
+    #[arg(required_unless_present_any = [
+        "images",
+        "images_urls",
+        "images_url_file",
+        "views_tar",
+        "watch",
+        "check",
+    ])]
+    prompt: Vec<String>,
+
+    /// Reconstruct from the images in this directory instead of generating views.
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["images_urls", "images_url_file"])]
+    images: Option<PathBuf>,
+
+    /// Reconstruct from the image at this URL instead of generating views. Can be
+    /// repeated, in view order.
+    #[arg(long = "images-url", value_name = "URL")]
+    images_urls: Vec<String>,
+
+    /// Reconstruct from the images at the URLs listed in this file, one per line.
+    #[arg(long, value_name = "FILE")]
+    images_url_file: Option<PathBuf>,
+
+    /// Reconstruct from the views in this tar archive written by `text-to-view --tar`,
+    /// or read it from stdin with `-`.
+    #[arg(long, value_name = "PATH|-", conflicts_with_all = [
+        "images",
+        "images_urls",
+        "images_url_file",
+    ])]
+    views_tar: Option<PathBuf>,
+
+    /// A reference photo of the subject, conditioning the video generation and added
+    /// to the views, since it is usually sharper than any frame.
+    #[arg(long, value_name = "PATH", conflicts_with_all = [
+        "images",
+        "images_urls",
+        "images_url_file",
+        "views_tar",
+    ])]
+    seed_image: Option<PathBuf>,
+
+    /// Generate the prompt even when an earlier run, in any directory, already did or
+    /// is doing so.
+    #[arg(long)]
+    allow_duplicate: bool,
+
+    /// Keep the fetched or unpacked images instead of deleting them at the end of the
+    /// run.
+    #[arg(long)]
+    keep_intermediates: bool,
+
+    /// Where to save the model, by default a file named after the prompt. With `-`,
+    /// the model is written to stdout and the viewer is not launched.
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Write the model to stdout even when it is a terminal.
+    #[arg(long)]
+    force: bool,
+
+    /// Fail instead of warning when the model lacks gaussian-splat attributes.
+    #[arg(long)]
+    require_3dgs: bool,
+
+    /// Recenter the model on the origin and rescale it to fit a unit sphere.
+    #[arg(long)]
+    normalize_model: bool,
+
+    /// Also save the model in another format, next to the PLY.
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    convert: Option<ConvertFormat>,
+
+    /// Export the views and their camera poses as a Nerfstudio dataset for training.
+    #[arg(long, value_name = "DIR")]
+    export_dataset: Option<PathBuf>,
+
+    /// Write an HTML report of the views and the model to this file, and their
+    /// statistics as JSON next to it.
+    #[arg(long, value_name = "PATH")]
+    quality_report: Option<PathBuf>,
+
+    /// Write a Nerfstudio `transforms.json` describing the views where they are, with
+    /// placeholder poses when the server returned none.
+    #[arg(long, value_name = "PATH")]
+    export_transforms: Option<PathBuf>,
+
+    /// Fail the run, text-to-view included, before it writes more than this to
+    /// temporary files, e.g. `512M`.
+    #[arg(long, value_name = "SIZE", value_parser = preflight::parse_size)]
+    max_temp_bytes: Option<u64>,
+
+    /// Record every HTTP exchange, with credentials redacted, as JSON in this file.
+    #[arg(long, value_name = "PATH")]
+    debug_http: Option<PathBuf>,
+
+    /// Do not open the model in the brush viewer.
+    #[arg(long)]
+    no_view: bool,
+
+    /// Show the model in the system file manager once it is written.
+    #[arg(long)]
+    reveal: bool,
+
+    /// Extra arguments for the brush viewer, given after `--`.
+    #[arg(last = true, value_name = "BRUSH_ARGS")]
+    viewer_args: Vec<String>,
+
+    #[command(flatten)]
+    reconstruct: ReconstructArgs,
 
-async fn run_text_to_view(prompt: &str) -> Result<()> {
+    #[command(flatten)]
+    brush: BrushArgs,
+
+    #[command(flatten)]
+    prune: PruneArgs,
+
+    #[command(flatten)]
+    watch: WatchArgs,
+
+    #[command(flatten)]
+    checks: CheckArgs,
+
+    #[command(flatten)]
+    project: ProjectArgs,
+
+    #[command(flatten)]
+    upload: UploadArgs,
+
+    #[command(flatten)]
+    rate_limits: RateLimitArgs,
+
+    #[command(flatten)]
+    recording: RecordingArgs,
+
+    #[command(flatten)]
+    credentials: ProfileArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Merge several 3DGS models into a single scene.
+    Merge(MergeArgs),
+    /// Remove the artifacts left behind by pipeline runs.
+    Clean(clean::CleanArgs),
+    /// Check the files of a run against the checksums recorded in its manifests.
+    Verify(verify::VerifyArgs),
+    /// Compare several runs side by side in an HTML page.
+    Report(compare::ReportArgs),
+    /// Diagnose the environment: the API key, the decoder, disk space, the
+    /// reconstruction server and brush.
+    Doctor(doctor::DoctorArgs),
+    /// Serve the pipeline as a REST API of jobs.
+    #[cfg(feature = "serve")]
+    Serve(serve::ServeArgs),
+}
+
+/// A command running text-to-view, to be completed with its arguments.
+fn text_to_view_command() -> Command {
+    let mut command = Command::new("cargo");
+    // Runs may happen outside the workspace, e.g. in the run directories of watch mode.
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/../../Cargo.toml");
+    command.args([
+        "run",
+        "--manifest-path",
+        manifest,
+        "-p",
+        "text-to-view",
+        "--",
+    ]);
+    command.envs(credentials::text_to_view_envs());
+    // Signals reach text-to-view only from the run, which forwards one when it is
+    // cancelled, so that a Ctrl-C does not stop it twice.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    command
+}
+
+async fn run_text_to_view(
+    cli: &Cli,
+    prompt: &str,
+    views_dir: &Path,
+    log: Option<&Path>,
+    cancel: &CancellationToken,
+) -> Result<()> {
     eprintln!("--- Step 1: Running text-to-view ---");
-    let status = Command::new("cargo")
-        .args([
-            "run",
-            "-p",
-            "text-to-view",
-            "--",
-            prompt,
-        ])
-        .status()
+    let mut command = text_to_view_command();
+    command.envs(cli.rate_limits.envs());
+    command.args(cli.recording.text_to_view_args());
+    if let Some(path) = &cli.debug_http {
+        command.arg("--debug-http").arg(path);
+    }
+    if let Some(path) = &cli.seed_image {
+        command.arg("--image").arg(path);
+    }
+    if let Some(bytes) = cli.max_temp_bytes {
+        command.arg("--max-temp-bytes").arg(bytes.to_string());
+    }
+    command
+        .arg("--views-dir")
+        .arg(views_dir)
+        .arg(prompt)
+        // Keep stdout free for the model when it is written there.
+        .stdout(std::io::stderr());
+    let status = project::run_logged(&mut command, log, cancel)
         .wrap_err("Failed to execute text-to-view command")?;
+    cancel::check(cancel)?;
 
     if !status.success() {
         return Err(eyre!("text-to-view process exited with non-zero status"));
@@ -26,101 +283,486 @@ async fn run_text_to_view(prompt: &str) -> Result<()> {
     Ok(())
 }
 
-async fn run_view_to_3dgs() -> Result<()> {
-    eprintln!("--- Step 2: Running view-to-3dgs (peropero) ---");
-    
-    // For now, we assume the peropero server is already running locally.
-    // A more robust implementation would handle starting/stopping the server.
-    
-    let client = reqwest::Client::new();
-    let url = "http://localhost:8888/reconstruction";
-
-    let image_paths: Vec<_> = glob::glob("views/*.jpg")?
-        .filter_map(Result::ok)
-        .collect();
-
-    if image_paths.is_empty() {
-        return Err(eyre!("No images found in the 'views' directory. Did text-to-view run correctly?"));
-    }
-
-    let mut form = multipart::Form::new();
-    let image_count = image_paths.len();
-    for path in image_paths {
-        let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
-        let file = File::open(&path).await?;
-        let stream = FramedRead::new(file, BytesCodec::new());
-        let body = Body::wrap_stream(stream);
-        let part = multipart::Part::stream(body)
-            .file_name(file_name)
-            .mime_str("image/jpeg")?;
-        form = form.part("images", part);
-    }
+/// Validates the reconstructed model and gathers its statistics.
+fn inspect_model(
+    path: &Path,
+    held: Option<&[u8]>,
+    require_3dgs: bool,
+) -> Result<ModelStats> {
+    let stats = held
+        .map_or_else(|| ply::inspect(path), ply::inspect_bytes)
+        .wrap_err("The reconstruction server returned an invalid PLY model")?;
 
-    eprintln!("Uploading {} images to reconstruction server...", image_count);
-
-    let response = client
-        .post(url)
-        .multipart(form)
-        .send()
-        .await
-        .wrap_err("Failed to send request to reconstruction server. Is it running at http://localhost:8888?")?;
-
-    if !response.status().is_success() {
-        let error_body = response.text().await.unwrap_or_else(|_| "Could not read error body".to_string());
-        return Err(eyre!("Reconstruction server returned an error: {}", error_body));
+    if !stats.missing_attributes.is_empty() {
+        let missing = stats.missing_attributes.join(", ");
+        if require_3dgs {
+            return Err(eyre!(
+                "The model is not a 3DGS model, it lacks the attributes: {}",
+                missing
+            ));
+        }
+        eprintln!(
+            "Warning: the model lacks the 3DGS attributes {}. It may be a plain point cloud.",
+            missing
+        );
     }
 
-    let ply_data = response.bytes().await?;
-    fs::write("output.ply", &ply_data)?;
-
-    eprintln!("--- Step 2: Reconstruction successful! Model saved to output.ply ---\n");
-    Ok(())
+    Ok(stats)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
 
-    let prompt_parts: Vec<String> = env::args().skip(1).collect();
-    if prompt_parts.is_empty() {
-        return Err(eyre!("Please provide a prompt as a command-line argument."));
+    // Only runs of the pipeline itself have a summary, once past their checks.
+    let mut summary = None;
+    let result = run(Cli::parse(), &mut summary).await;
+    if let Some(summary) = summary {
+        summary.finish(result.as_ref().err());
+    }
+    // Exiting only once the run has returned, so that its lock and claim are released.
+    if let Some(error) = result
+        .as_ref()
+        .err()
+        .filter(|error| error.downcast_ref::<upload::UploadFailed>().is_some())
+    {
+        eprintln!("Error: {:?}", error);
+        std::process::exit(upload::UPLOAD_FAILED_EXIT_CODE);
+    }
+    result
+}
+
+async fn run(
+    mut cli: Cli,
+    summary_slot: &mut Option<RunSummary>,
+) -> Result<()> {
+    if let Some(command) = &cli.command {
+        return match command {
+            Commands::Merge(args) => merge::run(args),
+            Commands::Clean(args) => clean::run(args),
+            Commands::Verify(args) => verify::run(args),
+            Commands::Report(args) => compare::run(args),
+            Commands::Doctor(args) => doctor::run(args).await,
+            #[cfg(feature = "serve")]
+            Commands::Serve(args) => serve::run(args).await,
+        };
+    }
+    if cli.watch.watch.is_some() {
+        return watch::run(&cli.watch, cli.allow_duplicate).await;
+    }
+    let profile = credentials::install(&cli.credentials)?;
+    if let Some(credentials) = profile {
+        cli.reconstruct.apply_profile(&credentials.profile);
     }
-    let user_prompt = prompt_parts.join(" ");
 
-    // Step 1: Generate views from text
-    run_text_to_view(&user_prompt).await?;
+    let user_prompt = cli.prompt.join(" ");
+    let layout = Layout::new(&cli.project);
+    let output_path = cli
+        .output
+        .clone()
+        .or_else(|| layout.model())
+        .unwrap_or_else(|| output::default_output(&user_prompt, cli.images.as_deref()));
+    let to_stdout = output::is_stdout(&output_path);
+    if cli.output.is_none() && !cli.checks.check {
+        eprintln!("The model will be saved to '{}'\n", output_path.display());
+    }
+    if to_stdout {
+        output::check_stdout(cli.force)?;
+        if cli.convert.is_some() {
+            return Err(eyre!(
+                "--convert saves the converted model next to the PLY, so it needs \
+                 an output file rather than stdout"
+            ));
+        }
+    }
+    let output_dir = checks::output_dir(&output_path);
+    let plan = Plan {
+        generates_views: cli.images.is_none()
+            && cli.images_urls.is_empty()
+            && cli.images_url_file.is_none()
+            && cli.views_tar.is_none(),
+        server_url: (cli.reconstruct.backend == Backend::Server)
+            .then(|| cli.reconstruct.heartbeat_url()),
+        views_model: !cli.no_view && !to_stdout,
+        output_dir: &output_dir,
+    };
+    // Installed before the checks, so that the server check is replayed as well.
+    cli.recording.install()?;
+    let checked = checks::run_checks(&cli.checks, &plan).await;
+    if cli.checks.check {
+        return checked;
+    }
+    checked?;
+    let _lock = RunLock::acquire(&layout.dir())?;
+    let cancel = &cancel::on_signal();
+    // Only the prompts views are generated from are indexed.
+    let claim = plan
+        .generates_views
+        .then(|| {
+            history::claim(
+                &user_prompt,
+                &layout.dir(),
+                (!to_stdout).then_some(output_path.as_path()),
+                cli.allow_duplicate,
+                cancel,
+            )
+        })
+        .transpose()?;
+    layout.create()?;
+    layout.write_prompt(&user_prompt)?;
+    let summary = summary_slot.insert(RunSummary::new(
+        layout.summary(),
+        summary::Context {
+            generates_views: plan.generates_views,
+            carried_args: summary::carried_args(Cli::command()),
+            output: (cli.output.is_some() || !layout.is_project())
+                .then(|| output_path.clone()),
+            server: (cli.reconstruct.backend == Backend::Server)
+                .then(|| cli.reconstruct.server().to_string()),
+            run_state: layout.run_state(),
+            brush_path: cli.brush.brush_path.clone(),
+            viewer_args: cli.viewer_args.clone(),
+        },
+        cancel,
+    ));
+    for check in &cli.checks.skip_check {
+        summary.skip(format!("the {} check", check), "--skip-check was given");
+    }
+    if let Some(bytes) = cli.max_temp_bytes {
+        http_trace::usage::install(bytes)?;
+    }
+    if let Some(path) = &cli.debug_http {
+        http_trace::install(path, http_trace::DEFAULT_BODY_LIMIT).wrap_err_with(|| {
+            format!("Failed to create the HTTP trace at {}", path.display())
+        })?;
+        summary.logs.push(path.clone());
+    }
+
+    if let Some(path) = &cli.seed_image {
+        // Fail before generating a video the seed could not join.
+        image::image_dimensions(path)
+            .wrap_err_with(|| format!("Failed to read the seed image {}", path.display()))?;
+    }
+
+    // Step 1: Generate views from text, or fetch them when given
+    let started = Instant::now();
+    let mut urls = cli.images_urls.clone();
+    if let Some(path) = &cli.images_url_file {
+        urls.extend(remote::read_url_list(path)?);
+    }
+    // A project keeps the fetched views among its artifacts.
+    let fetched_dir = (!urls.is_empty() || cli.views_tar.is_some()).then(|| {
+        if layout.is_project() {
+            layout.views_dir()
+        } else {
+            remote::fetch_dir()
+        }
+    });
+    let views_dir = match (&cli.images, &fetched_dir) {
+        (Some(dir), _) if layout.is_project() => layout.import_views(dir)?,
+        (Some(dir), _) => dir.clone(),
+        (None, Some(dir)) => {
+            match &cli.views_tar {
+                Some(source) => views::unpack_tar(source, dir)?,
+                None => remote::fetch_views(&urls, dir, cancel).await?,
+            }
+            dir.clone()
+        },
+        (None, None) => {
+            summary.logs.extend(layout.log("text-to-view"));
+            run_text_to_view(
+                &cli,
+                &user_prompt,
+                &layout.views_dir(),
+                layout.log("text-to-view").as_deref(),
+                cancel,
+            )
+            .await?;
+            // text-to-view ran apart, so its usage counts toward the run's as well.
+            let manifest = views::ViewsManifest::read(&layout.views_dir())?;
+            if let Some(usage) = manifest.and_then(|manifest| manifest.usage) {
+                http_trace::usage::add(usage)
+                    .wrap_err("text-to-view used too much temporary space")?;
+            }
+            if let Some(seed) = &cli.seed_image {
+                views::add_seed(&layout.views_dir(), seed)?;
+            }
+            layout.views_dir()
+        },
+    };
+    let views_dir = views_dir.as_path();
+    cancel::check(cancel)?;
+    summary.views = Some(summary::ViewsOutput {
+        dir: views_dir.to_path_buf(),
+        count: list_views(views_dir)?.len(),
+        kept: true,
+    });
+    let mut timings = vec![StageTiming::since("views", started)];
 
     // Step 2: Reconstruct 3DGS model from views
-    run_view_to_3dgs().await?;
+    summary.stage = Stage::Reconstruction;
+    let started = Instant::now();
+    // A model bound for stdout is held in memory when nothing rewrites or reads it
+    // back, and otherwise processed in a staging directory like any other. Either way
+    // it is written out once validated, at the end of the run.
+    let staging = to_stdout.then(output::Staging::new).transpose()?;
+    let staged = staging.as_ref().map(output::Staging::model);
+    let output = staged.as_deref().unwrap_or(&output_path);
+    let hold = to_stdout
+        && !cli.prune.is_enabled()
+        && !cli.normalize_model
+        && cli.quality_report.is_none()
+        && cli.upload.upload_to.is_none();
+    let cameras = &layout.cameras();
+    let (model_path, format, downscales, held) = match cli.reconstruct.backend {
+        Backend::Server => {
+            let (model, downscales) =
+                run_view_to_3dgs(&cli.reconstruct, &layout, views_dir, output, hold, cancel)
+                    .await?;
+            match model {
+                // Saved under the extension of its format, when not the one asked for.
+                Model::Saved(path) => {
+                    let format = ModelFormat::from_path(&path);
+                    (path, format, downscales, None)
+                },
+                Model::Held(model) => {
+                    (output.to_path_buf(), ModelFormat::Ply, downscales, Some(model))
+                },
+            }
+        },
+        Backend::Brush => {
+            if !cameras.exists() {
+                return Err(eyre!(
+                    "The brush backend trains on posed views, but there are no camera poses \
+                     in {}. Provide poses for the views there, or use --backend server.",
+                    cameras.display()
+                ));
+            }
+            let dataset = cli
+                .export_dataset
+                .clone()
+                .unwrap_or_else(|| layout.dataset_dir());
+            export_dataset(&dataset, views_dir, cameras, None)?;
+            brush::train(&cli.brush, &layout, &dataset, output, cancel)?;
+            (output.to_path_buf(), ModelFormat::Ply, Vec::new(), None)
+        },
+    };
+    let output = model_path.as_path();
+    timings.push(StageTiming::since("reconstruction", started));
+    // A cancelled run leaves no model behind, even one already reconstructed.
+    let discard_if_cancelled = || {
+        let cancelled = cancel::check(cancel);
+        if cancelled.is_err() {
+            std::fs::remove_file(output).ok();
+        }
+        cancelled
+    };
+    discard_if_cancelled()?;
+    summary.stage = Stage::PostProcessing;
+    let started = Instant::now();
+    let (stats, pruning, normalization) = if format == ModelFormat::Ply {
+        let stats = inspect_model(output, held.as_deref(), cli.require_3dgs)?;
+        let pruning = prune_model(output, &cli.prune)?;
+        let normalization = if cli.normalize_model {
+            Some(normalize_model(output)?)
+        } else {
+            None
+        };
+        let stats = if pruning.is_some() || normalization.is_some() {
+            ply::inspect(output)?
+        } else {
+            stats
+        };
+        eprintln!("Model summary:\n{}\n", stats);
+        if let Some(path) = &cli.quality_report {
+            // The report only helps diagnose the run, so it never fails it.
+            if let Err(error) = report::write_report(path, views_dir, output, &stats) {
+                eprintln!("Warning: could not write the quality report: {:#}", error);
+                summary.skip("--quality-report", format!("{:#}", error));
+            }
+        }
+        summary.model = Some(summary::ModelOutput {
+            path: if to_stdout { output_path.clone() } else { output.to_path_buf() },
+            format,
+            size: stats.file_size,
+            gaussians: stats.gaussian_count as u64,
+        });
+        (Some(stats), pruning, normalization)
+    } else {
+        let inspected = model::inspect(output, format).wrap_err_with(|| {
+            format!("The reconstruction server returned an invalid {} model", format)
+        })?;
+        eprintln!("Model summary:\n{}\n", inspected);
+        summary.model = Some(summary::ModelOutput {
+            path: if to_stdout { output_path.clone() } else { output.to_path_buf() },
+            format,
+            size: std::fs::metadata(output).map_or(0, |metadata| metadata.len()),
+            gaussians: inspected.gaussians,
+        });
+        let skipped: Vec<&str> = [
+            (cli.prune.is_enabled(), "pruning"),
+            (cli.normalize_model, "--normalize-model"),
+            (cli.quality_report.is_some(), "--quality-report"),
+        ]
+        .into_iter()
+        .filter_map(|(asked, step)| asked.then_some(step))
+        .collect();
+        if !skipped.is_empty() {
+            eprintln!(
+                "Warning: skipping {}, which only work on PLY models",
+                skipped.join(", ")
+            );
+        }
+        for step in skipped {
+            summary.skip(step, format!("it only works on PLY models, not {}", format));
+        }
+        (None, None, None)
+    };
+    let convert = match cli.convert {
+        Some(target) if format != ModelFormat::Ply => {
+            let step = format!("--convert {}", target.extension());
+            if target.extension() == format.extension() {
+                eprintln!("The model is already in {}, it needs no conversion", format);
+                summary.skip(step, format!("the model is already in {}", format));
+            } else {
+                eprintln!(
+                    "Warning: skipping --convert {}, as only PLY models are converted \
+                     and the model is in {}",
+                    target.extension(),
+                    format
+                );
+                summary.skip(
+                    step,
+                    format!("only PLY models are converted, not {}", format),
+                );
+            }
+            None
+        },
+        convert => convert,
+    };
+    if convert.is_some() {
+        // Converters hold every gaussian in memory, about as large as the PLY.
+        let size = std::fs::metadata(output).map_or(0, |metadata| metadata.len());
+        http_trace::usage::warn_if_short("converting the model", size);
+    }
+    let conversion = convert
+        .map(|format| convert_model(output, format))
+        .transpose()?;
+    if let Some(dir) = &cli.export_dataset {
+        export_dataset(dir, views_dir, cameras, normalization.as_ref())?;
+    }
+    if let Some(path) = &cli.export_transforms {
+        export_transforms(path, views_dir, cameras, normalization.as_ref())?;
+    }
+    timings.push(StageTiming::since("post-processing", started));
+    discard_if_cancelled()?;
+    if let Some(dir) = fetched_dir.as_ref().filter(|_| !layout.is_project()) {
+        if cli.keep_intermediates {
+            eprintln!("Kept the views in {}", dir.display());
+        } else {
+            std::fs::remove_dir_all(dir).ok();
+            if let Some(views) = &mut summary.views {
+                views.kept = false;
+            }
+        }
+    }
 
-    eprintln!("Hooray! The entire pipeline is complete. Your 3DGS model is ready in 'output.ply'!");
-    eprintln!("--- Step 3: Launching brush viewer ---");
+    // Views deleted at the end of the run cannot be verified later.
+    let views_kept =
+        fetched_dir.is_none() || layout.is_project() || cli.keep_intermediates;
+    let views = if views_kept {
+        list_views(views_dir)?
+    } else {
+        Vec::new()
+    };
+    let checksums =
+        checksum::run_checksums(&layout.dir(), (!to_stdout).then_some(output), &views)?;
 
-    let brush_executable = "./tools/brush/target/release/brush_app";
-
-    if !std::path::Path::new(brush_executable).exists() {
-        eprintln!("'brush_app' not found, compiling it first...");
-        let build_status = Command::new("cargo")
-            .arg("build")
-            .arg("--bin")
-            .arg("brush_app")
-            .arg("--locked")
-            .arg("--release")
-            .current_dir("./tools/brush")
-            .status()
-            .wrap_err("Failed to build brush viewer")?;
-
-        if !build_status.success() {
-            return Err(eyre!("Failed to compile brush viewer"));
+    eprintln!("Stages:");
+    for timing in &timings {
+        eprintln!("  {}", timing);
+    }
+    RunManifest {
+        prompt: user_prompt,
+        profile: profile.map(|credentials| credentials.name.clone()),
+        output: if to_stdout {
+            output_path.clone()
+        } else {
+            output.to_path_buf()
+        },
+        model: stats,
+        pruning,
+        normalization,
+        conversion,
+        downscales,
+        dataset: cli.export_dataset.clone(),
+        viewer_args: cli.viewer_args.clone(),
+        checksums,
+        timings,
+    }
+    .write(&layout.run_manifest())?;
+    summary.manifest = Some(layout.run_manifest());
+    if let Some(claim) = claim {
+        claim.finish(history::Status::Completed)?;
+    }
+    if let Some(dir) = cli.project.project_dir.as_deref().filter(|_| cli.project.package) {
+        let archive = project::package(dir)?;
+        eprintln!("Packaged the project into {}", archive.display());
+    }
+    summary.stage = Stage::Upload;
+    let uploaded = cancel::or_cancelled(cancel, upload::upload(&cli.upload, output));
+    if let Err(error) = uploaded.await {
+        // The model was reconstructed, so keep it and tell this failure apart.
+        return Err(error.wrap_err(upload::UploadFailed(output.to_path_buf())));
+    }
+
+    if to_stdout {
+        match &held {
+            Some(model) => output::emit_held(model)?,
+            None => output::emit(output)?,
         }
-        eprintln!("'brush_app' compiled successfully.");
+        eprintln!("Hooray! The entire pipeline is complete. Your 3DGS model was written to stdout!");
+        summary.skip("the brush viewer", "the model was written to stdout");
+        return Ok(());
+    }
+    eprintln!(
+        "Hooray! The entire pipeline is complete. Your 3DGS model is ready in '{}'!",
+        output.display()
+    );
+    if cli.reveal {
+        reveal::reveal(output);
     }
+    if cli.no_view {
+        summary.skip("the brush viewer", "--no-view was given");
+        return Ok(());
+    }
+    if format != ModelFormat::Ply {
+        eprintln!(
+            "Not launching the brush viewer, which is only given PLY models. Open the {} \
+             model in a viewer that reads it.",
+            format
+        );
+        summary.skip(
+            "the brush viewer",
+            format!("it only opens PLY models, not {}", format),
+        );
+        return Ok(());
+    }
+    // The viewer runs until it is closed, so the run is summed up before.
+    summary.opens_viewer = true;
+    if let Some(summary) = summary_slot.take() {
+        summary.finish(None);
+    }
+
+    eprintln!("--- Step 3: Launching brush viewer ---");
 
+    let brush_executable = brush::ensure_brush(&cli.brush)?;
     let status = Command::new(brush_executable)
-        .arg("output.ply")
+        .arg(output)
         .arg("--with-viewer")
         .arg("--sh-degree")
         .arg("0")
+        .args(&cli.viewer_args)
         .status()
         .wrap_err("Failed to execute brush viewer")?;
 
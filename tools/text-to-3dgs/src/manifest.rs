@@ -0,0 +1,129 @@
+//! The run manifest, a JSON record of what a pipeline run produced.
+
+use crate::convert::Conversion;
+use crate::normalize::Normalization;
+use crate::ply::{ModelStats, RetainSummary};
+use crate::preflight::Downscale;
+use color_eyre::eyre::{Result, WrapErr};
+use http_trace::usage::{self, Usage};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Where the manifest of the current run is written.
+pub const RUN_MANIFEST_PATH: &str = "run.json";
+
+/// Where the state of an in-flight reconstruction is kept until it completes.
+pub const RUN_STATE_PATH: &str = "run-state.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub prompt: String,
+    /// The credentials profile the run used, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub output: PathBuf,
+    pub model: Option<ModelStats>,
+    pub pruning: Option<RetainSummary>,
+    /// The transform from the reconstruction frame into the normalized one.
+    pub normalization: Option<Normalization>,
+    pub conversion: Option<Conversion>,
+    /// The views uploaded as downscaled copies to fit the server's size limit.
+    pub downscales: Vec<Downscale>,
+    pub dataset: Option<PathBuf>,
+    /// The arguments passed through to the brush viewer.
+    pub viewer_args: Vec<String>,
+    /// SHA-256 of the model and the views, by path relative to the manifest.
+    #[serde(default)]
+    pub checksums: BTreeMap<String, String>,
+    /// How long each stage of the run took, in order.
+    #[serde(default)]
+    pub timings: Vec<StageTiming>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StageTiming {
+    pub stage: String,
+    pub seconds: f64,
+    /// The bytes the stage wrote to temporary files and the largest buffer it held.
+    #[serde(default, flatten)]
+    pub usage: Usage,
+}
+
+impl StageTiming {
+    /// The timing of `stage`, which started at `started`, ending now, with what it
+    /// used since the previous stage ended.
+    pub fn since(
+        stage: &str,
+        started: Instant,
+    ) -> Self {
+        StageTiming {
+            stage: stage.to_string(),
+            seconds: started.elapsed().as_secs_f64(),
+            usage: usage::take_stage(),
+        }
+    }
+}
+
+impl fmt::Display for StageTiming {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "{:<16} {:>7.1}s  {:>10} temp  {:>10} peak buffer",
+            self.stage,
+            self.seconds,
+            usage::format_size(self.usage.temp_bytes),
+            usage::format_size(self.usage.peak_buffer_bytes)
+        )
+    }
+}
+
+impl RunManifest {
+    pub fn write(
+        &self,
+        path: &Path,
+    ) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).wrap_err_with(|| {
+            format!("Failed to write the run manifest to {}", path.display())
+        })
+    }
+}
+
+/// The state of an in-flight reconstruction, persisted so that a crashed client can
+/// query its job again instead of uploading the views twice.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunState {
+    pub server: String,
+    /// The names and sizes of the uploaded views.
+    pub views: String,
+    /// Sent with every upload of these views, so that the server can tell retries
+    /// from new reconstructions.
+    pub idempotency_key: String,
+    /// Status URL of the job, once the server accepted it.
+    pub job_url: Option<String>,
+}
+
+impl RunState {
+    /// Reads the state left by an earlier run, if any.
+    pub fn load(path: &Path) -> Option<Self> {
+        let json = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&json).ok()
+    }
+
+    pub fn save(
+        &self,
+        path: &Path,
+    ) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).wrap_err_with(|| {
+            format!("Failed to write the run state to {}", path.display())
+        })
+    }
+}
@@ -0,0 +1,416 @@
+//! The `merge` subcommand, composing several reconstructed models into one scene.
+
+use crate::ply::{self, Header, VertexLayout};
+use clap::Args;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Arguments of the `merge` subcommand.
+#[derive(Args, Debug)]
+pub struct MergeArgs {
+    /// The 3DGS models to merge. The first one decides the attribute layout.
+    #[arg(required = true, value_name = "MODEL")]
+    pub inputs: Vec<PathBuf>,
+
+    /// Where to write the merged model.
+    #[arg(short, long, value_name = "PATH")]
+    pub output: PathBuf,
+
+    /// Transform one input before merging, e.g. `vase:translate=0,0.8,0`.
+    ///
+    /// Inputs are named by file stem or file name. The operations are
+    /// `translate=X,Y,Z`, `scale=S`, and `rotate=X,Y,Z` (Euler angles in degrees,
+    /// applied about X, then Y, then Z). Repeated operations on the same input
+    /// are applied in the order given.
+    #[arg(long = "transform", value_name = "NAME:OP=VALUES")]
+    pub transforms: Vec<String>,
+}
+
+/// A uniform scale, followed by a rotation, followed by a translation.
+#[derive(Clone, Copy, Debug)]
+struct Similarity {
+    scale: f64,
+    /// Unit quaternion in `w, x, y, z` order, matching `rot_0..3`.
+    rotation: [f64; 4],
+    translation: [f64; 3],
+}
+
+impl Default for Similarity {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            rotation: [1.0, 0.0, 0.0, 0.0],
+            translation: [0.0; 3],
+        }
+    }
+}
+
+impl Similarity {
+    /// Appends the operation `op=values` to the transform.
+    fn then(
+        mut self,
+        op: &str,
+        values: &str,
+    ) -> Result<Self> {
+        let values = values
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err_with(|| format!("Invalid numbers in '{}={}'", op, values))?;
+        match (op, values.as_slice()) {
+            ("translate", &[x, y, z]) => {
+                self.translation = [
+                    self.translation[0] + x,
+                    self.translation[1] + y,
+                    self.translation[2] + z,
+                ];
+            },
+            ("scale", &[scale]) if scale > 0.0 => {
+                self.scale *= scale;
+                self.translation = self.translation.map(|value| value * scale);
+            },
+            ("rotate", &[x, y, z]) => {
+                let rotation = euler_degrees_to_quaternion([x, y, z]);
+                self.rotation = quaternion_mul(rotation, self.rotation);
+                self.translation = rotate(rotation, self.translation);
+            },
+            _ => {
+                return Err(eyre!(
+                    "Unsupported transform '{}={:?}'. Expected translate=X,Y,Z, \
+                     scale=S (positive), or rotate=X,Y,Z",
+                    op,
+                    values
+                ))
+            },
+        }
+        Ok(self)
+    }
+
+    fn is_identity(&self) -> bool {
+        self.scale == 1.0
+            && self.rotation == [1.0, 0.0, 0.0, 0.0]
+            && self.translation == [0.0; 3]
+    }
+}
+
+/// How the records of one input map onto the merged layout.
+struct Input {
+    path: PathBuf,
+    header: Header,
+    /// For every merged property, the input property it is copied from, if any.
+    sources: Vec<Option<usize>>,
+    transform: Similarity,
+}
+
+pub fn run(args: &MergeArgs) -> Result<()> {
+    let mut transforms = vec![Similarity::default(); args.inputs.len()];
+    for spec in &args.transforms {
+        let (name, operation) = spec.split_once(':').ok_or_else(|| {
+            eyre!("Invalid transform '{}', expected NAME:OP=VALUES", spec)
+        })?;
+        let (op, values) = operation.split_once('=').ok_or_else(|| {
+            eyre!("Invalid transform '{}', expected NAME:OP=VALUES", spec)
+        })?;
+        let index = find_input(&args.inputs, name)?;
+        transforms[index] = transforms[index].then(op, values)?;
+    }
+
+    let (target, _) = ply::open(&args.inputs[0])?;
+    let mut target = Header {
+        elements: vec![target.vertex()?.clone()],
+        ..target
+    };
+    let target_layout = target.vertex_layout()?;
+    let attributes = Attributes::new(&target_layout);
+
+    let mut inputs = Vec::with_capacity(args.inputs.len());
+    for (path, transform) in args.inputs.iter().zip(transforms) {
+        let (header, _) = ply::open(path)?;
+        let layout = header.vertex_layout()?;
+        for name in ["x", "y", "z"] {
+            layout
+                .require(name)
+                .wrap_err_with(|| format!("Cannot merge {}", path.display()))?;
+        }
+        let sources = harmonize(path, &header, &target)?;
+        if header.elements.len() > 1 {
+            eprintln!(
+                "Warning: only the vertices of {} are merged, its other elements are dropped",
+                path.display()
+            );
+        }
+        if transform.rotation != [1.0, 0.0, 0.0, 0.0]
+            && target.sh_degree().unwrap_or(0) > 0
+        {
+            eprintln!(
+                "Warning: {} is rotated, but its view-dependent (SH) colors are not",
+                path.display()
+            );
+        }
+        inputs.push(Input {
+            path: path.clone(),
+            header,
+            sources,
+            transform,
+        });
+    }
+
+    target.vertex_mut()?.count = inputs
+        .iter()
+        .map(|input| input.header.vertex().map(|vertex| vertex.count))
+        .sum::<Result<usize>>()?;
+
+    let mut writer = BufWriter::new(
+        File::create(&args.output)
+            .wrap_err_with(|| format!("Failed to create {}", args.output.display()))?,
+    );
+    target.write(&mut writer)?;
+    let mut merged = vec![0; target_layout.stride];
+    for input in &inputs {
+        let (_, mut vertices) = ply::open(&input.path)?;
+        let layout = vertices.layout().clone();
+        let count = input.header.vertex()?.count;
+        while let Some(record) = vertices.next_record()? {
+            merged.fill(0);
+            for (index, source) in input.sources.iter().enumerate() {
+                if let Some(source) = *source {
+                    target_layout.set(&mut merged, index, layout.get(record, source));
+                }
+            }
+            if !input.transform.is_identity() {
+                transform_record(
+                    &target_layout,
+                    &attributes,
+                    &mut merged,
+                    &input.transform,
+                );
+            }
+            writer.write_all(&merged)?;
+        }
+        eprintln!("Merged {} gaussians from {}", count, input.path.display());
+    }
+    writer.flush()?;
+
+    eprintln!(
+        "Wrote {} gaussians to {}",
+        target.vertex()?.count,
+        args.output.display()
+    );
+    Ok(())
+}
+
+fn find_input(
+    inputs: &[PathBuf],
+    name: &str,
+) -> Result<usize> {
+    let matches: Vec<usize> = inputs
+        .iter()
+        .enumerate()
+        .filter(|(_, path)| {
+            path.file_stem().is_some_and(|stem| stem == name)
+                || path.file_name().is_some_and(|file_name| file_name == name)
+        })
+        .map(|(index, _)| index)
+        .collect();
+    match matches.as_slice() {
+        [index] => Ok(*index),
+        [] => Err(eyre!(
+            "The transform target '{}' matches no input model",
+            name
+        )),
+        _ => Err(eyre!(
+            "The transform target '{}' matches several input models",
+            name
+        )),
+    }
+}
+
+/// Maps the properties of `header` onto those of `target`, warning about any
+/// attribute that has to be dropped or zero-filled.
+///
+/// Higher-order SH coefficients are matched per color channel, since `f_rest_*`
+/// stores all coefficients of the red channel first, then green, then blue.
+fn harmonize(
+    path: &Path,
+    header: &Header,
+    target: &Header,
+) -> Result<Vec<Option<usize>>> {
+    let vertex = header.vertex()?;
+    let source_rest = sh_rest_per_channel(header);
+    let target_rest = sh_rest_per_channel(target);
+
+    let sources: Vec<Option<usize>> = target
+        .vertex()?
+        .properties
+        .iter()
+        .map(|property| match property.name.strip_prefix("f_rest_") {
+            Some(index) if target_rest > 0 => {
+                let index: usize = index.parse().ok()?;
+                let (channel, coefficient) = (index / target_rest, index % target_rest);
+                (coefficient < source_rest).then(|| {
+                    vertex.property_index(&format!(
+                        "f_rest_{}",
+                        channel * source_rest + coefficient
+                    ))
+                })?
+            },
+            _ => vertex.property_index(&property.name),
+        })
+        .collect();
+
+    let used: Vec<bool> = (0..vertex.properties.len())
+        .map(|index| sources.contains(&Some(index)))
+        .collect();
+    let dropped: Vec<&str> = vertex
+        .properties
+        .iter()
+        .zip(used)
+        .filter(|(_, used)| !used)
+        .map(|(property, _)| property.name.as_str())
+        .collect();
+    let padded: Vec<&str> = target
+        .vertex()?
+        .properties
+        .iter()
+        .zip(&sources)
+        .filter(|(_, source)| source.is_none())
+        .map(|(property, _)| property.name.as_str())
+        .collect();
+
+    if header.sh_degree() != target.sh_degree() {
+        eprintln!(
+            "Warning: {} has SH degree {:?}, harmonized to {:?}",
+            path.display(),
+            header.sh_degree(),
+            target.sh_degree()
+        );
+    }
+    if !dropped.is_empty() {
+        eprintln!(
+            "Warning: dropping attributes of {}: {}",
+            path.display(),
+            dropped.join(", ")
+        );
+    }
+    if !padded.is_empty() {
+        eprintln!(
+            "Warning: zero-filling attributes missing from {}: {}",
+            path.display(),
+            padded.join(", ")
+        );
+    }
+    Ok(sources)
+}
+
+/// Number of `f_rest_*` coefficients stored for each color channel.
+fn sh_rest_per_channel(header: &Header) -> usize {
+    let degree = header.sh_degree().unwrap_or(0) as usize;
+    (degree + 1).pow(2) - 1
+}
+
+/// Indices of the merged attributes affected by a transform.
+struct Attributes {
+    position: Option<[usize; 3]>,
+    normal: Option<[usize; 3]>,
+    scale: Option<[usize; 3]>,
+    rotation: Option<[usize; 4]>,
+}
+
+impl Attributes {
+    fn new(layout: &VertexLayout) -> Self {
+        Self {
+            position: find_all(layout, ["x", "y", "z"]),
+            normal: find_all(layout, ["nx", "ny", "nz"]),
+            scale: find_all(layout, ["scale_0", "scale_1", "scale_2"]),
+            rotation: find_all(layout, ["rot_0", "rot_1", "rot_2", "rot_3"]),
+        }
+    }
+}
+
+fn find_all<const N: usize>(
+    layout: &VertexLayout,
+    names: [&str; N],
+) -> Option<[usize; N]> {
+    let mut indices = [0; N];
+    for (index, name) in indices.iter_mut().zip(names) {
+        *index = layout.index_of(name)?;
+    }
+    Some(indices)
+}
+
+/// Applies `transform` to the position, normal, scale and rotation of a record.
+fn transform_record(
+    layout: &VertexLayout,
+    attributes: &Attributes,
+    record: &mut [u8],
+    transform: &Similarity,
+) {
+    if let Some(indices) = attributes.position {
+        let position = indices.map(|index| layout.get(record, index) * transform.scale);
+        let position = rotate(transform.rotation, position);
+        for (axis, index) in indices.into_iter().enumerate() {
+            layout.set(record, index, position[axis] + transform.translation[axis]);
+        }
+    }
+    if let Some(indices) = attributes.normal {
+        let normal = rotate(
+            transform.rotation,
+            indices.map(|index| layout.get(record, index)),
+        );
+        for (index, value) in indices.into_iter().zip(normal) {
+            layout.set(record, index, value);
+        }
+    }
+    if let Some(indices) = attributes.scale {
+        // Scales are stored in log space, so a uniform scaling becomes an offset.
+        let log_scale = transform.scale.ln();
+        for index in indices {
+            layout.set(record, index, layout.get(record, index) + log_scale);
+        }
+    }
+    if let Some(indices) = attributes.rotation {
+        let rotation = quaternion_mul(
+            transform.rotation,
+            indices.map(|index| layout.get(record, index)),
+        );
+        for (index, value) in indices.into_iter().zip(rotation) {
+            layout.set(record, index, value);
+        }
+    }
+}
+
+fn euler_degrees_to_quaternion(angles: [f64; 3]) -> [f64; 4] {
+    let [x, y, z] = angles.map(|angle| angle.to_radians() / 2.0);
+    let qx = [x.cos(), x.sin(), 0.0, 0.0];
+    let qy = [y.cos(), 0.0, y.sin(), 0.0];
+    let qz = [z.cos(), 0.0, 0.0, z.sin()];
+    quaternion_mul(qz, quaternion_mul(qy, qx))
+}
+
+fn quaternion_mul(
+    a: [f64; 4],
+    b: [f64; 4],
+) -> [f64; 4] {
+    let [aw, ax, ay, az] = a;
+    let [bw, bx, by, bz] = b;
+    [
+        aw * bw - ax * bx - ay * by - az * bz,
+        aw * bx + ax * bw + ay * bz - az * by,
+        aw * by - ax * bz + ay * bw + az * bx,
+        aw * bz + ax * by - ay * bx + az * bw,
+    ]
+}
+
+/// Rotates `v` by the unit quaternion `q`.
+fn rotate(
+    q: [f64; 4],
+    v: [f64; 3],
+) -> [f64; 3] {
+    let [_, x, y, z] = quaternion_mul(
+        quaternion_mul(q, [0.0, v[0], v[1], v[2]]),
+        [q[0], -q[1], -q[2], -q[3]],
+    );
+    [x, y, z]
+}
@@ -0,0 +1,280 @@
+//! The gaussian-splat formats the reconstruction server may return the model in.
+//!
+//! The server is asked for the format the output is named after, but newer servers
+//! may answer with another one they know. Such a model is saved under the extension
+//! of its own format, and checked as that format rather than as a PLY. The steps
+//! that rewrite or read the model as a PLY are skipped for it.
+
+use crate::spz;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Size of the records of a `.splat` file: position and scale as three 32-bit floats
+/// each, color and rotation as four bytes each.
+const SPLAT_RECORD: u64 = 32;
+
+/// Size of the header of an SPZ file, once decompressed.
+const SPZ_HEADER: usize = 16;
+
+/// How many leading bytes of a model in an unknown format are shown.
+const PREVIEW_BYTES: usize = 16;
+
+/// A format of gaussian-splat models.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelFormat {
+    Ply,
+    /// The format of antimatter15's WebGL viewer, without a header.
+    Splat,
+    /// Niantic's compressed format.
+    Spz,
+}
+
+impl ModelFormat {
+    const ALL: [ModelFormat; 3] =
+        [ModelFormat::Ply, ModelFormat::Splat, ModelFormat::Spz];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ModelFormat::Ply => "ply",
+            ModelFormat::Splat => "splat",
+            ModelFormat::Spz => "spz",
+        }
+    }
+
+    /// The media type models in this format are sent with.
+    pub fn media_type(self) -> &'static str {
+        self.media_types()[0]
+    }
+
+    /// The media types recognized in responses, the one sent with models first.
+    fn media_types(self) -> &'static [&'static str] {
+        match self {
+            ModelFormat::Ply => &["application/ply", "application/x-ply", "model/ply"],
+            ModelFormat::Splat => &["application/x-splat", "model/splat"],
+            ModelFormat::Spz => &["application/x-spz", "model/spz"],
+        }
+    }
+
+    /// The format named by the extension of `path`, or PLY for any other.
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        Self::ALL
+            .into_iter()
+            .find(|format| {
+                extension.is_some_and(|extension| {
+                    extension.eq_ignore_ascii_case(format.extension())
+                })
+            })
+            .unwrap_or(ModelFormat::Ply)
+    }
+
+    /// The `Accept` header asking for a model in this format, or else a PLY.
+    ///
+    /// Any other type is still accepted last, for the JSON of jobs and sessions,
+    /// and for servers that do not label their models.
+    pub fn accept(self) -> String {
+        let mut accept = self.media_type().to_string();
+        if self != ModelFormat::Ply {
+            accept.push_str(", application/ply;q=0.8");
+        }
+        accept.push_str(", */*;q=0.1");
+        accept
+    }
+
+    /// The format of the model in `bytes`, told by its magic bytes, or for `.splat`
+    /// models, which have none, by the `content_type` of the response.
+    pub fn detect(
+        content_type: Option<&str>,
+        bytes: &[u8],
+    ) -> Result<Self> {
+        if bytes.starts_with(b"ply\n") || bytes.starts_with(b"ply\r\n") {
+            return Ok(ModelFormat::Ply);
+        }
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            let mut magic = [0; 4];
+            if GzDecoder::new(bytes).read_exact(&mut magic).is_ok()
+                && u32::from_le_bytes(magic) == spz::MAGIC
+            {
+                return Ok(ModelFormat::Spz);
+            }
+        }
+        let essence = content_type
+            .and_then(|content_type| content_type.split(';').next())
+            .map(|essence| essence.trim().to_ascii_lowercase());
+        let labelled_splat = essence
+            .as_deref()
+            .is_some_and(|essence| ModelFormat::Splat.media_types().contains(&essence));
+        if labelled_splat && !bytes.is_empty() && bytes.len() as u64 % SPLAT_RECORD == 0 {
+            return Ok(ModelFormat::Splat);
+        }
+        let preview = &bytes[..bytes.len().min(PREVIEW_BYTES)];
+        let hex: Vec<String> =
+            preview.iter().map(|byte| format!("{:02x}", byte)).collect();
+        Err(eyre!(
+            "The reconstruction server returned a model in an unknown format \
+             (Content-Type: {}, {} bytes starting with {} \"{}\"). Expected a PLY, \
+             .splat or SPZ model.",
+            content_type.unwrap_or("none"),
+            bytes.len(),
+            hex.join(" "),
+            preview.escape_ascii()
+        ))
+    }
+}
+
+impl fmt::Display for ModelFormat {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        let name = match self {
+            ModelFormat::Ply => "PLY",
+            ModelFormat::Splat => ".splat",
+            ModelFormat::Spz => "SPZ",
+        };
+        f.pad(name)
+    }
+}
+
+/// Where a model in `format` is saved when asked for at `requested`: there when the
+/// format is the one asked for, else next to it under the extension of `format`.
+pub fn saved_path(
+    requested: &Path,
+    format: ModelFormat,
+) -> PathBuf {
+    if format == ModelFormat::from_path(requested) {
+        requested.to_path_buf()
+    } else {
+        requested.with_extension(format.extension())
+    }
+}
+
+/// Where the model asked for at `requested` was saved, in whichever format the
+/// server returned it, if it was.
+#[cfg(feature = "serve")]
+pub fn find_saved(requested: &Path) -> Option<PathBuf> {
+    ModelFormat::ALL
+        .into_iter()
+        .map(|format| saved_path(requested, format))
+        .find(|path| path.exists())
+}
+
+/// What the check of a model that is not a PLY found.
+pub struct ModelSummary {
+    pub format: ModelFormat,
+    pub gaussians: u64,
+    pub sh_degree: Option<u8>,
+}
+
+impl fmt::Display for ModelSummary {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        writeln!(f, "Format:       {}", self.format)?;
+        write!(f, "Gaussians:    {}", self.gaussians)?;
+        if let Some(degree) = self.sh_degree {
+            write!(f, "\nSH degree:    {}", degree)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the `.splat` or SPZ model at `path` is whole, reading the number of
+/// its gaussians. PLY models are checked with [`crate::ply::inspect`] instead.
+pub fn inspect(
+    path: &Path,
+    format: ModelFormat,
+) -> Result<ModelSummary> {
+    match format {
+        ModelFormat::Ply => Err(eyre!("PLY models are inspected as such")),
+        ModelFormat::Splat => {
+            let size = fs::metadata(path)
+                .wrap_err_with(|| format!("Failed to read {}", path.display()))?
+                .len();
+            if size == 0 || size % SPLAT_RECORD != 0 {
+                return Err(eyre!(
+                    "The .splat model {} is truncated: its {} bytes are not a whole \
+                     number of {}-byte gaussians",
+                    path.display(),
+                    size,
+                    SPLAT_RECORD
+                ));
+            }
+            Ok(ModelSummary {
+                format,
+                gaussians: size / SPLAT_RECORD,
+                sh_degree: None,
+            })
+        },
+        ModelFormat::Spz => inspect_spz(path),
+    }
+}
+
+fn inspect_spz(path: &Path) -> Result<ModelSummary> {
+    let compressed =
+        fs::read(path).wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+    let mut data = Vec::new();
+    GzDecoder::new(compressed.as_slice())
+        .read_to_end(&mut data)
+        .wrap_err_with(|| {
+            format!("The SPZ model {} is not valid gzip", path.display())
+        })?;
+    if data.len() < SPZ_HEADER {
+        return Err(eyre!("The SPZ model {} has no header", path.display()));
+    }
+    let word = |i: usize| u32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+    let (magic, version, gaussians, sh_degree) = (word(0), word(1), word(2), data[12]);
+    if magic != spz::MAGIC {
+        return Err(eyre!("The SPZ model {} has no SPZ header", path.display()));
+    }
+    if sh_degree > 3 {
+        return Err(eyre!(
+            "The SPZ model {} declares SH degree {}, above the highest of 3",
+            path.display(),
+            sh_degree
+        ));
+    }
+    // Version 1 stores positions as half floats, and version 3 rotations in four
+    // bytes rather than three.
+    let (position, rotation) = match version {
+        1 => (6, 3),
+        2 => (9, 3),
+        3 => (9, 4),
+        _ => {
+            eprintln!(
+                "Warning: the SPZ model is of version {}, newer than this tool knows; \
+                 its size is not checked",
+                version
+            );
+            return Ok(ModelSummary {
+                format: ModelFormat::Spz,
+                gaussians: gaussians as u64,
+                sh_degree: Some(sh_degree),
+            });
+        },
+    };
+    let coefficients = ((sh_degree as u64 + 1).pow(2) - 1) * 3;
+    let expected = SPZ_HEADER as u64
+        + gaussians as u64 * (position + 1 + 3 + 3 + rotation + coefficients);
+    if data.len() as u64 != expected {
+        return Err(eyre!(
+            "The SPZ model {} holds {} bytes, but its {} gaussians take {}",
+            path.display(),
+            data.len(),
+            gaussians,
+            expected
+        ));
+    }
+    Ok(ModelSummary {
+        format: ModelFormat::Spz,
+        gaussians: gaussians as u64,
+        sh_degree: Some(sh_degree),
+    })
+}
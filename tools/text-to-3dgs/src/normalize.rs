@@ -0,0 +1,100 @@
+//! Moving a reconstructed model from its arbitrary SfM frame into a canonical one.
+
+use crate::ply::{self, sigmoid};
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Fraction of gaussians that must fit inside the unit sphere after normalization.
+///
+/// Using a percentile instead of the farthest gaussian keeps stray background
+/// splats from shrinking the subject.
+const RADIUS_PERCENTILE: f64 = 0.9;
+
+/// The similarity transform applied by normalization.
+///
+/// A point `p` in the original frame maps to `scale * (p - center)`, so the
+/// original is recovered with `p' / scale + center`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Normalization {
+    pub center: [f64; 3],
+    pub scale: f64,
+}
+
+impl Normalization {
+    pub fn apply(
+        &self,
+        point: [f64; 3],
+    ) -> [f64; 3] {
+        [0, 1, 2].map(|axis| self.scale * (point[axis] - self.center[axis]))
+    }
+}
+
+/// Recenters the model at `path` on its opacity-weighted centroid and rescales it
+/// so that most gaussians lie within the unit sphere.
+pub fn normalize_model(path: &Path) -> Result<Normalization> {
+    eprintln!("Normalizing the model in {}...", path.display());
+    let (_, mut vertices) = ply::open(path)?;
+    let layout = vertices.layout().clone();
+    let position = [
+        layout.require("x")?,
+        layout.require("y")?,
+        layout.require("z")?,
+    ];
+    let opacity = layout.index_of("opacity");
+
+    let mut points = Vec::new();
+    let mut weighted_sum = [0.0; 3];
+    let mut total_weight = 0.0;
+    while let Some(record) = vertices.next_record()? {
+        let point = position.map(|index| layout.get(record, index));
+        let weight = opacity.map_or(1.0, |index| sigmoid(layout.get(record, index)));
+        for (sum, value) in weighted_sum.iter_mut().zip(point) {
+            *sum += weight * value;
+        }
+        total_weight += weight;
+        points.push(point.map(|value| value as f32));
+    }
+    if points.is_empty() || total_weight <= 0.0 {
+        return Err(eyre!("The model has no visible gaussians to normalize"));
+    }
+    let center = weighted_sum.map(|sum| sum / total_weight);
+
+    let mut distances: Vec<f64> = points
+        .iter()
+        .map(|point| {
+            (0..3)
+                .map(|axis| (point[axis] as f64 - center[axis]).powi(2))
+                .sum::<f64>()
+                .sqrt()
+        })
+        .collect();
+    let rank = ((distances.len() - 1) as f64 * RADIUS_PERCENTILE) as usize;
+    let (_, &mut radius, _) = distances.select_nth_unstable_by(rank, f64::total_cmp);
+    let normalization = Normalization {
+        center,
+        scale: if radius > 0.0 { 1.0 / radius } else { 1.0 },
+    };
+
+    // Scales are stored in log space, so a uniform scaling becomes an offset.
+    let log_scale = normalization.scale.ln();
+    let scales: Vec<usize> = ["scale_0", "scale_1", "scale_2"]
+        .into_iter()
+        .filter_map(|name| layout.index_of(name))
+        .collect();
+    ply::map_vertices(path, |layout, record| {
+        let point = normalization.apply(position.map(|index| layout.get(record, index)));
+        for (&index, value) in position.iter().zip(point) {
+            layout.set(record, index, value);
+        }
+        for &index in &scales {
+            layout.set(record, index, layout.get(record, index) + log_scale);
+        }
+    })?;
+
+    eprintln!(
+        "Moved the model by [{:.3}, {:.3}, {:.3}] and scaled it by {:.6}",
+        -center[0], -center[1], -center[2], normalization.scale
+    );
+    Ok(normalization)
+}
@@ -0,0 +1,112 @@
+//! Where the model is written: a file named after the prompt by default, or stdout
+//! for composing the tool in shell pipelines.
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// The output path standing for stdout.
+pub const STDOUT_PATH: &str = "-";
+
+/// Where the model is saved when there is nothing to name it after.
+const FALLBACK_NAME: &str = "output";
+
+/// Maximum length of an output name derived from the prompt.
+const MAX_SLUG_LEN: usize = 48;
+
+/// Names the model after the prompt, or after the directory of the given images,
+/// adding a numeric suffix if a file of that name already exists.
+pub fn default_output(
+    prompt: &str,
+    images: Option<&Path>,
+) -> PathBuf {
+    let source = match images {
+        Some(dir) if prompt.is_empty() => fs::canonicalize(dir)
+            .ok()
+            .and_then(|dir| {
+                dir.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_default(),
+        _ => prompt.to_string(),
+    };
+    let stem = Some(slug(&source))
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or_else(|| FALLBACK_NAME.to_string());
+    (1..)
+        .map(|index| match index {
+            1 => PathBuf::from(format!("{}.ply", stem)),
+            index => PathBuf::from(format!("{}-{}.ply", stem, index)),
+        })
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+/// Lowercases `text` and joins its ASCII words with dashes, truncated to
+/// [`MAX_SLUG_LEN`].
+fn slug(text: &str) -> String {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect();
+    let mut slug = words.join("-");
+    slug.truncate(MAX_SLUG_LEN);
+    slug.trim_end_matches('-').to_string()
+}
+
+pub fn is_stdout(path: &Path) -> bool {
+    path == Path::new(STDOUT_PATH)
+}
+
+/// Refuses to write the binary model to a terminal, unless forced to.
+pub fn check_stdout(force: bool) -> Result<()> {
+    if io::stdout().is_terminal() && !force {
+        return Err(eyre!(
+            "Refusing to write a binary model to the terminal. Redirect stdout to a file \
+             or a pipe, or pass --force."
+        ));
+    }
+    Ok(())
+}
+
+/// The temporary directory a model bound for stdout is processed in, when it cannot
+/// be held in memory. It is deleted along with the model however the run ends.
+pub struct Staging(TempDir);
+
+impl Staging {
+    pub fn new() -> Result<Self> {
+        tempfile::Builder::new()
+            .prefix("text-to-3dgs-")
+            .tempdir()
+            .map(Staging)
+            .wrap_err("Failed to create a staging directory for the model")
+    }
+
+    /// Where the model is processed, named so that a PLY model is asked for.
+    pub fn model(&self) -> PathBuf {
+        self.0.path().join("model.ply")
+    }
+}
+
+/// Copies the validated model at `staged` to stdout.
+pub fn emit(staged: &Path) -> Result<()> {
+    let model = File::open(staged)
+        .wrap_err_with(|| format!("Failed to open the model at {}", staged.display()))?;
+    write_out(model)
+}
+
+/// Writes the validated model held in `model` to stdout.
+pub fn emit_held(model: &[u8]) -> Result<()> {
+    write_out(model)
+}
+
+fn write_out(mut model: impl Read) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    io::copy(&mut model, &mut stdout)
+        .and_then(|_| stdout.flush())
+        .wrap_err("Failed to write the model to stdout")?;
+    Ok(())
+}
@@ -0,0 +1,900 @@
+//! Streaming access to 3DGS models stored as PLY files.
+//!
+//! Only the header is held in memory. Vertex records are read one at a time from
+//! binary bodies, so models with millions of gaussians can be inspected cheaply.
+//! ASCII models can be inspected too, but not rewritten or converted.
+
+use crate::checksum::{self, HashingWriter};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Attributes a gaussian-splat model must provide besides its position.
+pub const REQUIRED_3DGS_ATTRIBUTES: [&str; 11] = [
+    "f_dc_0", "f_dc_1", "f_dc_2", "opacity", "scale_0", "scale_1", "scale_2", "rot_0",
+    "rot_1", "rot_2", "rot_3",
+];
+
+/// Activation turning a stored `opacity` attribute into an opacity in `[0, 1]`.
+pub fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropertyKind {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+#[derive(Clone, Debug)]
+pub struct Property {
+    pub name: String,
+    pub kind: PropertyKind,
+    /// The declaration as written after `property`, kept to preserve type spellings.
+    declaration: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Element {
+    pub name: String,
+    pub count: usize,
+    pub properties: Vec<Property>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Header {
+    pub format: Format,
+    pub version: String,
+    /// `comment` and `obj_info` lines, verbatim.
+    pub comments: Vec<String>,
+    pub elements: Vec<Element>,
+}
+
+/// Byte layout of the vertex records in a binary PLY body.
+#[derive(Clone, Debug)]
+pub struct VertexLayout {
+    pub stride: usize,
+    offsets: Vec<usize>,
+    types: Vec<ScalarType>,
+    names: Vec<String>,
+    big_endian: bool,
+}
+
+/// Reads vertex records one at a time from a binary PLY body.
+pub struct VertexReader<R> {
+    reader: R,
+    layout: VertexLayout,
+    remaining: usize,
+    record: Vec<u8>,
+}
+
+/// Axis-aligned bounds of the gaussian centers.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct BoundingBox {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+/// Summary of a reconstructed model, as printed and recorded in the run manifest.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModelStats {
+    pub file_size: u64,
+    pub gaussian_count: usize,
+    pub sh_degree: Option<u32>,
+    pub bounding_box: Option<BoundingBox>,
+    pub missing_attributes: Vec<String>,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return Err(eyre!("Unknown PLY property type '{}'", name)),
+        })
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+}
+
+impl Property {
+    fn parse(declaration: &str) -> Result<Self> {
+        let tokens: Vec<&str> = declaration.split_whitespace().collect();
+        let (kind, name) = match tokens.as_slice() {
+            ["list", count, item, name] => (
+                PropertyKind::List {
+                    count: ScalarType::parse(count)?,
+                    item: ScalarType::parse(item)?,
+                },
+                name,
+            ),
+            [ty, name] => (PropertyKind::Scalar(ScalarType::parse(ty)?), name),
+            _ => {
+                return Err(eyre!(
+                    "Malformed PLY property declaration '{}'",
+                    declaration
+                ))
+            },
+        };
+        Ok(Self {
+            name: name.to_string(),
+            kind,
+            declaration: tokens.join(" "),
+        })
+    }
+}
+
+impl Element {
+    pub fn property_index(
+        &self,
+        name: &str,
+    ) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|property| property.name == name)
+    }
+
+    pub fn has_property(
+        &self,
+        name: &str,
+    ) -> bool {
+        self.property_index(name).is_some()
+    }
+
+    /// The size of one record, or `None` if the element has list properties.
+    fn fixed_size(&self) -> Option<usize> {
+        self.properties
+            .iter()
+            .map(|property| match property.kind {
+                PropertyKind::Scalar(ty) => Some(ty.size()),
+                PropertyKind::List { .. } => None,
+            })
+            .sum()
+    }
+}
+
+impl Header {
+    /// Parses a header, consuming the reader up to and including `end_header`.
+    pub fn read(reader: &mut impl BufRead) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader
+            .read_exact(&mut magic)
+            .wrap_err("The file is too short to be a PLY model")?;
+        if &magic[..3] != b"ply" || !matches!(magic[3], b'\n' | b'\r') {
+            return Err(eyre!(
+                "The file is not a PLY model: it starts with {:?}",
+                String::from_utf8_lossy(&magic)
+            ));
+        }
+        if magic[3] == b'\r' {
+            read_header_line(reader)?;
+        }
+
+        let mut format = None;
+        let mut version = String::new();
+        let mut comments = Vec::new();
+        let mut elements: Vec<Element> = Vec::new();
+        loop {
+            let line = read_header_line(reader)?;
+            let (keyword, rest) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+            match keyword {
+                "format" => {
+                    let mut tokens = rest.split_whitespace();
+                    let name = tokens.next().unwrap_or_default();
+                    version = tokens.next().unwrap_or("1.0").to_string();
+                    format = Some(match name {
+                        "ascii" => Format::Ascii,
+                        "binary_little_endian" => Format::BinaryLittleEndian,
+                        "binary_big_endian" => Format::BinaryBigEndian,
+                        _ => return Err(eyre!("Unknown PLY format '{}'", name)),
+                    });
+                },
+                "comment" | "obj_info" => comments.push(line.clone()),
+                "element" => {
+                    let (name, count) = rest
+                        .trim()
+                        .split_once(' ')
+                        .ok_or_else(|| eyre!("Malformed PLY element line '{}'", line))?;
+                    elements.push(Element {
+                        name: name.to_string(),
+                        count: count.trim().parse().wrap_err_with(|| {
+                            format!("Invalid count in PLY element line '{}'", line)
+                        })?,
+                        properties: Vec::new(),
+                    });
+                },
+                "property" => elements
+                    .last_mut()
+                    .ok_or_else(|| eyre!("PLY property declared before any element"))?
+                    .properties
+                    .push(Property::parse(rest)?),
+                "end_header" => break,
+                "" => {},
+                _ => return Err(eyre!("Unexpected PLY header line '{}'", line)),
+            }
+        }
+
+        Ok(Self {
+            format: format.ok_or_else(|| eyre!("The PLY header has no format line"))?,
+            version,
+            comments,
+            elements,
+        })
+    }
+
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+    ) -> io::Result<()> {
+        let format = match self.format {
+            Format::Ascii => "ascii",
+            Format::BinaryLittleEndian => "binary_little_endian",
+            Format::BinaryBigEndian => "binary_big_endian",
+        };
+        writeln!(writer, "ply")?;
+        writeln!(writer, "format {} {}", format, self.version)?;
+        for comment in &self.comments {
+            writeln!(writer, "{}", comment)?;
+        }
+        for element in &self.elements {
+            writeln!(writer, "element {} {}", element.name, element.count)?;
+            for property in &element.properties {
+                writeln!(writer, "property {}", property.declaration)?;
+            }
+        }
+        writeln!(writer, "end_header")
+    }
+
+    pub fn vertex(&self) -> Result<&Element> {
+        self.elements
+            .iter()
+            .find(|element| element.name == "vertex")
+            .ok_or_else(|| eyre!("The PLY model has no vertex element"))
+    }
+
+    pub fn vertex_mut(&mut self) -> Result<&mut Element> {
+        self.elements
+            .iter_mut()
+            .find(|element| element.name == "vertex")
+            .ok_or_else(|| eyre!("The PLY model has no vertex element"))
+    }
+
+    /// Number of bytes between the end of the header and the first vertex record.
+    fn vertex_offset(&self) -> Result<u64> {
+        let mut offset = 0;
+        for element in self
+            .elements
+            .iter()
+            .take_while(|element| element.name != "vertex")
+        {
+            let size = element.fixed_size().ok_or_else(|| {
+                eyre!(
+                    "The PLY element '{}' precedes the vertices and has list properties",
+                    element.name
+                )
+            })?;
+            offset += (size * element.count) as u64;
+        }
+        Ok(offset)
+    }
+
+    /// The byte layout of vertex records, available for binary models only.
+    pub fn vertex_layout(&self) -> Result<VertexLayout> {
+        let big_endian = match self.format {
+            Format::BinaryLittleEndian => false,
+            Format::BinaryBigEndian => true,
+            Format::Ascii => {
+                return Err(eyre!(
+                    "ASCII PLY models can only be inspected. Convert the model to binary \
+                     PLY to prune, normalize, convert or train it."
+                ))
+            },
+        };
+        let vertex = self.vertex()?;
+        let mut offsets = Vec::with_capacity(vertex.properties.len());
+        let mut types = Vec::with_capacity(vertex.properties.len());
+        let mut stride = 0;
+        for property in &vertex.properties {
+            let PropertyKind::Scalar(ty) = property.kind else {
+                return Err(eyre!(
+                    "The PLY vertex property '{}' is a list, which is not supported",
+                    property.name
+                ));
+            };
+            offsets.push(stride);
+            types.push(ty);
+            stride += ty.size();
+        }
+        Ok(VertexLayout {
+            stride,
+            offsets,
+            types,
+            names: vertex.properties.iter().map(|p| p.name.clone()).collect(),
+            big_endian,
+        })
+    }
+
+    /// Degree of the spherical harmonics stored in `f_dc_*` and `f_rest_*`.
+    pub fn sh_degree(&self) -> Option<u32> {
+        let vertex = self.vertex().ok()?;
+        if !vertex.has_property("f_dc_0") {
+            return None;
+        }
+        let rest = vertex
+            .properties
+            .iter()
+            .filter(|property| property.name.starts_with("f_rest_"))
+            .count();
+        let coefficients = rest / 3 + 1;
+        let degree = (coefficients as f64).sqrt() as u32;
+        ((degree * degree) as usize == coefficients).then(|| degree - 1)
+    }
+
+    /// Gaussian-splat attributes absent from the vertex element.
+    pub fn missing_3dgs_attributes(&self) -> Vec<String> {
+        let Ok(vertex) = self.vertex() else {
+            return REQUIRED_3DGS_ATTRIBUTES
+                .iter()
+                .map(|name| name.to_string())
+                .collect();
+        };
+        REQUIRED_3DGS_ATTRIBUTES
+            .iter()
+            .filter(|name| !vertex.has_property(name))
+            .map(|name| name.to_string())
+            .collect()
+    }
+}
+
+fn read_header_line(reader: &mut impl BufRead) -> Result<String> {
+    let mut line = Vec::new();
+    let read = reader.read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Err(eyre!("The PLY header ended before 'end_header'"));
+    }
+    let line =
+        String::from_utf8(line).map_err(|_| eyre!("The PLY header is not valid text"))?;
+    Ok(line.trim_end_matches(['\n', '\r']).to_string())
+}
+
+impl VertexLayout {
+    pub fn index_of(
+        &self,
+        name: &str,
+    ) -> Option<usize> {
+        self.names.iter().position(|candidate| candidate == name)
+    }
+
+    /// Like [`Self::index_of`], failing with the missing attribute's name.
+    pub fn require(
+        &self,
+        name: &str,
+    ) -> Result<usize> {
+        self.index_of(name)
+            .ok_or_else(|| eyre!("The PLY model has no '{}' attribute", name))
+    }
+
+    pub fn get(
+        &self,
+        record: &[u8],
+        index: usize,
+    ) -> f64 {
+        let bytes = &record[self.offsets[index]..][..self.types[index].size()];
+        macro_rules! decode {
+            ($ty:ty) => {{
+                let bytes = bytes.try_into().unwrap();
+                if self.big_endian {
+                    <$ty>::from_be_bytes(bytes) as f64
+                } else {
+                    <$ty>::from_le_bytes(bytes) as f64
+                }
+            }};
+        }
+        match self.types[index] {
+            ScalarType::I8 => decode!(i8),
+            ScalarType::U8 => decode!(u8),
+            ScalarType::I16 => decode!(i16),
+            ScalarType::U16 => decode!(u16),
+            ScalarType::I32 => decode!(i32),
+            ScalarType::U32 => decode!(u32),
+            ScalarType::F32 => decode!(f32),
+            ScalarType::F64 => decode!(f64),
+        }
+    }
+
+    pub fn set(
+        &self,
+        record: &mut [u8],
+        index: usize,
+        value: f64,
+    ) {
+        let bytes = &mut record[self.offsets[index]..][..self.types[index].size()];
+        macro_rules! encode {
+            ($ty:ty) => {{
+                let value = value as $ty;
+                bytes.copy_from_slice(&if self.big_endian {
+                    value.to_be_bytes()
+                } else {
+                    value.to_le_bytes()
+                });
+            }};
+        }
+        match self.types[index] {
+            ScalarType::I8 => encode!(i8),
+            ScalarType::U8 => encode!(u8),
+            ScalarType::I16 => encode!(i16),
+            ScalarType::U16 => encode!(u16),
+            ScalarType::I32 => encode!(i32),
+            ScalarType::U32 => encode!(u32),
+            ScalarType::F32 => encode!(f32),
+            ScalarType::F64 => encode!(f64),
+        }
+    }
+}
+
+impl<R: Read> VertexReader<R> {
+    /// Wraps `reader`, which must be positioned at the first vertex record.
+    pub fn new(
+        reader: R,
+        header: &Header,
+    ) -> Result<Self> {
+        let layout = header.vertex_layout()?;
+        Ok(Self {
+            reader,
+            record: vec![0; layout.stride],
+            remaining: header.vertex()?.count,
+            layout,
+        })
+    }
+
+    pub fn layout(&self) -> &VertexLayout {
+        &self.layout
+    }
+
+    /// Reads the next vertex record, or `None` once all have been read.
+    pub fn next_record(&mut self) -> Result<Option<&mut [u8]>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.reader
+            .read_exact(&mut self.record)
+            .wrap_err("The PLY model ends before all of its vertices")?;
+        self.remaining -= 1;
+        Ok(Some(&mut self.record))
+    }
+
+    /// Returns the underlying reader, positioned after the last record read.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// The most vertices of `header` that a model of `size` bytes can hold, to size
+/// buffers by: the count in a header comes from the server, and may be bogus.
+pub fn vertex_capacity(
+    header: &Header,
+    size: u64,
+) -> Result<usize> {
+    let stride = header.vertex_layout()?.stride.max(1) as u64;
+    Ok(header.vertex()?.count.min((size / stride) as usize))
+}
+
+/// Outcome of rewriting a model with a subset of its gaussians.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RetainSummary {
+    pub kept: usize,
+    pub removed: usize,
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// Opens a binary PLY model, returning its header and a reader over its vertices.
+pub fn open(path: &Path) -> Result<(Header, VertexReader<BufReader<File>>)> {
+    let file = File::open(path)
+        .wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let header = Header::read(&mut reader).wrap_err_with(|| {
+        format!("Failed to parse the PLY header of {}", path.display())
+    })?;
+    let vertices = skip_to_vertices(reader, &header)?;
+    Ok((header, vertices))
+}
+
+/// A reader over the vertices of the binary model read by `reader`, just past its
+/// `header`.
+fn skip_to_vertices<R: BufRead>(
+    mut reader: R,
+    header: &Header,
+) -> Result<VertexReader<R>> {
+    io::copy(
+        &mut (&mut reader).take(header.vertex_offset()?),
+        &mut io::sink(),
+    )?;
+    VertexReader::new(reader, header)
+}
+
+/// Rewrites the model at `path` in place, keeping the gaussians `keep` accepts.
+///
+/// Surviving records are copied byte for byte, so attribute order, types and
+/// endianness are preserved. Only the vertex count in the header changes.
+pub fn retain_vertices(
+    path: &Path,
+    mut keep: impl FnMut(&VertexLayout, &[u8]) -> bool,
+) -> Result<RetainSummary> {
+    let size_before = fs::metadata(path)?.len();
+    let (header, mut vertices) = open(path)?;
+    let layout = vertices.layout().clone();
+    let mut mask = Vec::with_capacity(vertex_capacity(&header, size_before)?);
+    while let Some(record) = vertices.next_record()? {
+        mask.push(keep(&layout, record));
+    }
+    let kept = mask.iter().filter(|&&keep| keep).count();
+    let removed = mask.len() - kept;
+    if removed == 0 {
+        return Ok(RetainSummary {
+            kept,
+            removed,
+            size_before,
+            size_after: size_before,
+        });
+    }
+
+    let mut output = header.clone();
+    output.vertex_mut()?.count = kept;
+    let mut mask = mask.into_iter();
+    rewrite(path, &header, &output, |_, _| mask.next().unwrap_or(false))?;
+    Ok(RetainSummary {
+        kept,
+        removed,
+        size_before,
+        size_after: fs::metadata(path)?.len(),
+    })
+}
+
+/// Rewrites the model at `path` in place, letting `edit` modify every vertex record.
+pub fn map_vertices(
+    path: &Path,
+    mut edit: impl FnMut(&VertexLayout, &mut [u8]),
+) -> Result<()> {
+    let (header, _) = open(path)?;
+    rewrite(path, &header, &header, |layout, record| {
+        edit(layout, record);
+        true
+    })
+}
+
+/// Streams the model at `path` into a temporary file under the `output` header, then
+/// replaces the original. Records are written when `visit` returns true.
+fn rewrite(
+    path: &Path,
+    header: &Header,
+    output: &Header,
+    mut visit: impl FnMut(&VertexLayout, &mut [u8]) -> bool,
+) -> Result<()> {
+    let temp_path = path.with_extension("ply.part");
+    let mut writer = BufWriter::new(HashingWriter::new(
+        File::create(&temp_path)
+            .wrap_err_with(|| format!("Failed to create {}", temp_path.display()))?,
+    ));
+    output.write(&mut writer)?;
+
+    let mut reader = BufReader::new(File::open(path)?);
+    Header::read(&mut reader)?;
+    io::copy(
+        &mut (&mut reader).take(header.vertex_offset()?),
+        &mut writer,
+    )?;
+    let mut vertices = VertexReader::new(reader, header)?;
+    let layout = vertices.layout().clone();
+    while let Some(record) = vertices.next_record()? {
+        if visit(&layout, record) {
+            writer.write_all(record)?;
+        }
+    }
+    // Elements after the vertices, such as faces, are carried over untouched.
+    io::copy(&mut vertices.into_inner(), &mut writer)?;
+    let (_, digest) = writer
+        .into_inner()
+        .map_err(|error| error.into_error())?
+        .finish();
+
+    fs::rename(&temp_path, path)
+        .wrap_err_with(|| format!("Failed to replace {}", path.display()))?;
+    checksum::record(path, digest);
+    Ok(())
+}
+
+/// Parses the model at `path` and gathers its statistics in a single streaming pass.
+pub fn inspect(path: &Path) -> Result<ModelStats> {
+    let file_size = fs::metadata(path)
+        .wrap_err_with(|| format!("Failed to read metadata of {}", path.display()))?
+        .len();
+    let open = || Ok(BufReader::new(File::open(path)?));
+    let header = Header::read(&mut open()?).wrap_err_with(|| {
+        format!("Failed to parse the PLY header of {}", path.display())
+    })?;
+    gather_stats(file_size, &header, open)
+}
+
+/// Parses the model held in `data` and gathers its statistics, as [`inspect`].
+pub fn inspect_bytes(data: &[u8]) -> Result<ModelStats> {
+    let header = Header::read(&mut &data[..])?;
+    gather_stats(data.len() as u64, &header, || Ok(data))
+}
+
+/// Gathers the statistics of a model of `file_size` bytes under `header`, reading
+/// it from the start each time `open` is called.
+fn gather_stats<R: BufRead>(
+    file_size: u64,
+    header: &Header,
+    open: impl Fn() -> Result<R>,
+) -> Result<ModelStats> {
+    let bounds = if header.format == Format::Ascii {
+        ascii_bounds(open()?, header)?
+    } else {
+        binary_bounds(open()?)?
+    };
+
+    Ok(ModelStats {
+        file_size,
+        gaussian_count: header.vertex()?.count,
+        sh_degree: header.sh_degree(),
+        bounding_box: bounds,
+        missing_attributes: header.missing_3dgs_attributes(),
+    })
+}
+
+/// The bounds of the gaussian centers of the binary model read by `reader`.
+fn binary_bounds(mut reader: impl BufRead) -> Result<Option<BoundingBox>> {
+    let header = Header::read(&mut reader)?;
+    let mut vertices = skip_to_vertices(reader, &header)?;
+    let layout = vertices.layout().clone();
+    let position = [
+        layout.require("x")?,
+        layout.require("y")?,
+        layout.require("z")?,
+    ];
+    let mut bounds = None;
+    while let Some(record) = vertices.next_record()? {
+        extend(
+            &mut bounds,
+            position.map(|index| layout.get(record, index) as f32),
+        );
+    }
+    Ok(bounds)
+}
+
+/// The bounds of the gaussian centers of the ASCII model read by `reader`, read a
+/// line, and so an element, at a time.
+fn ascii_bounds(
+    mut reader: impl BufRead,
+    header: &Header,
+) -> Result<Option<BoundingBox>> {
+    let vertex = header.vertex()?;
+    let position = ["x", "y", "z"].map(|name| vertex.property_index(name));
+    let [Some(x), Some(y), Some(z)] = position else {
+        return Err(eyre!("The PLY model has no 'x', 'y' and 'z' attributes"));
+    };
+    Header::read(&mut reader)?;
+    let mut lines = reader.lines();
+    let mut bounds = None;
+    for element in &header.elements {
+        for _ in 0..element.count {
+            let line = lines.next().ok_or_else(|| {
+                eyre!(
+                    "The PLY model ends before all of its {} elements",
+                    element.name
+                )
+            })??;
+            if element.name != "vertex" {
+                continue;
+            }
+            let values = ascii_values(&line, &element.properties)?;
+            extend(&mut bounds, [x, y, z].map(|index| values[index] as f32));
+        }
+        if element.name == "vertex" {
+            break;
+        }
+    }
+    Ok(bounds)
+}
+
+/// The values of the scalar properties in an ASCII element `line`, with NaN for the
+/// list ones.
+fn ascii_values(
+    line: &str,
+    properties: &[Property],
+) -> Result<Vec<f64>> {
+    let mut tokens = line.split_whitespace();
+    let mut next = |name: &str| -> Result<f64> {
+        let token = tokens.next().ok_or_else(|| {
+            eyre!("The PLY line '{}' lacks the '{}' property", line, name)
+        })?;
+        token.parse().wrap_err_with(|| {
+            format!("Invalid value '{}' of the PLY property '{}'", token, name)
+        })
+    };
+    let mut values = Vec::with_capacity(properties.len());
+    for property in properties {
+        match property.kind {
+            PropertyKind::Scalar(_) => values.push(next(&property.name)?),
+            PropertyKind::List { .. } => {
+                for _ in 0..next(&property.name)? as usize {
+                    next(&property.name)?;
+                }
+                values.push(f64::NAN);
+            },
+        }
+    }
+    Ok(values)
+}
+
+/// Grows `bounds` to hold `point`.
+fn extend(
+    bounds: &mut Option<BoundingBox>,
+    point: [f32; 3],
+) {
+    let bounds = bounds.get_or_insert(BoundingBox {
+        min: point,
+        max: point,
+    });
+    for ((min, max), value) in bounds.min.iter_mut().zip(&mut bounds.max).zip(point) {
+        *min = min.min(value);
+        *max = max.max(value);
+    }
+}
+
+impl fmt::Display for ModelStats {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        writeln!(f, "Gaussians:    {}", self.gaussian_count)?;
+        if let Some(degree) = self.sh_degree {
+            writeln!(f, "SH degree:    {}", degree)?;
+        }
+        if let Some(BoundingBox { min, max }) = self.bounding_box {
+            writeln!(
+                f,
+                "Bounding box: [{:.3}, {:.3}, {:.3}] .. [{:.3}, {:.3}, {:.3}]",
+                min[0], min[1], min[2], max[0], max[1], max[2]
+            )?;
+        }
+        write!(
+            f,
+            "File size:    {:.2} MiB",
+            self.file_size as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two points in ASCII after an element of another kind, with a list property
+    /// among theirs and no 3DGS attributes but opacity.
+    const ASCII_MODEL: &str = "ply\nformat ascii 1.0\ncomment made by hand\n\
+        element camera 1\nproperty float fov\n\
+        element vertex 2\nproperty float x\nproperty list uchar int tags\n\
+        property float y\nproperty float z\nproperty float opacity\n\
+        end_header\n\
+        0.8\n\
+        1.5 2 7 8 -2 3.25 0.1\n\
+        -0.5 0 4 -1.75 -3\n";
+
+    fn write_model(contents: &str) -> (tempfile::TempDir, std::path::PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.ply");
+        fs::write(&path, contents).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn inspects_an_ascii_model() {
+        let (_dir, path) = write_model(ASCII_MODEL);
+        let stats = inspect(&path).unwrap();
+        assert_eq!(stats.gaussian_count, 2);
+        assert_eq!(stats.sh_degree, None);
+        let bounds = stats.bounding_box.unwrap();
+        assert_eq!(bounds.min, [-0.5, -2.0, -1.75]);
+        assert_eq!(bounds.max, [1.5, 4.0, 3.25]);
+        let missing: Vec<&str> = REQUIRED_3DGS_ATTRIBUTES
+            .into_iter()
+            .filter(|&name| name != "opacity")
+            .collect();
+        assert_eq!(stats.missing_attributes, missing);
+    }
+
+    #[test]
+    fn inspects_a_model_held_in_memory() {
+        let (_dir, path) = write_model(ASCII_MODEL);
+        let stats = inspect_bytes(ASCII_MODEL.as_bytes()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&stats).unwrap(),
+            serde_json::to_value(inspect(&path).unwrap()).unwrap()
+        );
+        let truncated = ASCII_MODEL.trim_end().rsplit_once('\n').unwrap().0;
+        assert!(inspect_bytes(truncated.as_bytes()).is_err());
+        assert!(inspect_bytes(b"{}").is_err());
+    }
+
+    #[test]
+    fn reports_a_truncated_ascii_model() {
+        let truncated = ASCII_MODEL.trim_end().rsplit_once('\n').unwrap().0;
+        let (_dir, path) = write_model(truncated);
+        let error = inspect(&path).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The PLY model ends before all of its vertex elements"
+        );
+    }
+
+    #[test]
+    fn refuses_to_rewrite_a_model_with_more_vertices_than_it_holds() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.ply");
+        let model = format!(
+            "ply\nformat binary_little_endian 1.0\nelement vertex {}\n\
+             property float x\nend_header\n",
+            usize::MAX
+        );
+        let data = [model.as_bytes(), &1.0f32.to_le_bytes()].concat();
+        fs::write(&path, &data).unwrap();
+        let error = retain_vertices(&path, |_, _| true).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The PLY model ends before all of its vertices"
+        );
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+
+    #[test]
+    fn refuses_to_rewrite_an_ascii_model() {
+        let (_dir, path) = write_model(ASCII_MODEL);
+        let error = retain_vertices(&path, |_, _| true).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("ASCII PLY models can only be inspected"),
+            "{}",
+            error
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), ASCII_MODEL);
+    }
+}
@@ -0,0 +1,194 @@
+//! Checking the size of the upload before it is sent, and shrinking it to fit.
+
+use clap::Args;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Each downscaling round shrinks a view to this fraction of its resolution.
+const DOWNSCALE_FACTOR: f64 = 0.75;
+
+/// Quality of downscaled JPEG views.
+const JPEG_QUALITY: u8 = 85;
+
+/// Views are never downscaled below this many pixels on their shorter side.
+const MIN_DIMENSION: u32 = 64;
+
+#[derive(Args, Debug)]
+pub struct PreflightArgs {
+    /// Largest upload the server accepts, e.g. `64M`. Defaults to the limit in the
+    /// server's capabilities, if it publishes one.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_upload_size: Option<u64>,
+
+    /// Downscale the largest views until the upload fits, instead of failing.
+    #[arg(long)]
+    pub auto_downscale: bool,
+}
+
+/// A view uploaded as a downscaled copy, as recorded in the run manifest.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Downscale {
+    pub file: String,
+    pub original_size: u64,
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Parses a size in bytes, with an optional `K`, `M`, or `G` binary suffix.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let upper = value.to_ascii_uppercase();
+    let digits = upper.trim_end_matches(['B', 'I']);
+    let (number, unit) = match digits.char_indices().last() {
+        Some((index, 'K')) => (&digits[..index], 1 << 10),
+        Some((index, 'M')) => (&digits[..index], 1 << 20),
+        Some((index, 'G')) => (&digits[..index], 1 << 30),
+        _ => (digits, 1),
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| *number > 0.0)
+        .map(|number| (number * unit as f64) as u64)
+        .ok_or_else(|| format!("'{}' is not a size, e.g. 64M", value))
+}
+
+/// Checks that the views fit in `limit` bytes once encoded, with `overhead` the
+/// ratio of the encoded size to the file size.
+///
+/// Returns the views to upload, which are downscaled copies of the largest views
+/// under `--auto-downscale`, and the downscales applied.
+pub fn preflight(
+    args: &PreflightArgs,
+    image_paths: &[PathBuf],
+    limit: u64,
+    overhead: f64,
+) -> Result<(Vec<PathBuf>, Vec<Downscale>)> {
+    let mut views = image_paths
+        .iter()
+        .map(|path| {
+            let size = fs::metadata(path)
+                .wrap_err_with(|| format!("Failed to read {}", path.display()))?
+                .len();
+            Ok((path.clone(), size))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let encoded = |views: &[(PathBuf, u64)]| {
+        (views.iter().map(|(_, size)| *size).sum::<u64>() as f64 * overhead) as u64
+    };
+    if encoded(&views) <= limit {
+        return Ok((image_paths.to_vec(), Vec::new()));
+    }
+
+    if !args.auto_downscale {
+        let mut largest = views.clone();
+        largest.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+        let breakdown: Vec<String> = largest
+            .iter()
+            .map(|(path, size)| format!("  {} ({})", path.display(), format_size(*size)))
+            .collect();
+        return Err(eyre!(
+            "The views take {} to upload, over the limit of {}:\n{}\n\
+             Retry with --auto-downscale to shrink the largest views.",
+            format_size(encoded(&views)),
+            format_size(limit),
+            breakdown.join("\n")
+        ));
+    }
+
+    let dir = std::env::temp_dir().join("text-to-3dgs-downscaled");
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir)
+        .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+    let mut downscales: Vec<Downscale> = Vec::new();
+    while encoded(&views) > limit {
+        let (index, (path, size)) = views
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, (_, size))| *size)
+            .unwrap();
+        let file = path.file_name().unwrap().to_string_lossy().into_owned();
+        let image = image::open(path)
+            .wrap_err_with(|| format!("Failed to decode {}", path.display()))?;
+        let width = (image.width() as f64 * DOWNSCALE_FACTOR) as u32;
+        let height = (image.height() as f64 * DOWNSCALE_FACTOR) as u32;
+        if width.min(height) < MIN_DIMENSION {
+            return Err(eyre!(
+                "The views still take {} to upload after downscaling, over the limit \
+                 of {}. Raise --max-upload-size or upload fewer views.",
+                format_size(encoded(&views)),
+                format_size(limit)
+            ));
+        }
+        let copy = dir.join(&file);
+        save(
+            &image.resize_exact(width, height, FilterType::Lanczos3),
+            &copy,
+        )?;
+        let new_size = fs::metadata(&copy)?.len();
+        http_trace::usage::reserve_temp(new_size)?;
+        eprintln!(
+            "Downscaled {} to {}x{} ({} -> {})",
+            file,
+            width,
+            height,
+            format_size(*size),
+            format_size(new_size)
+        );
+
+        match downscales
+            .iter_mut()
+            .find(|downscale| downscale.file == file)
+        {
+            Some(downscale) => {
+                downscale.size = new_size;
+                downscale.width = width;
+                downscale.height = height;
+            },
+            None => downscales.push(Downscale {
+                file,
+                original_size: *size,
+                size: new_size,
+                width,
+                height,
+            }),
+        }
+        views[index] = (copy, new_size);
+    }
+
+    Ok((
+        views.into_iter().map(|(path, _)| path).collect(),
+        downscales,
+    ))
+}
+
+/// Saves `image` at `path` in the format of its extension, JPEGs at reduced quality.
+fn save(
+    image: &DynamicImage,
+    path: &Path,
+) -> Result<()> {
+    let format = ImageFormat::from_path(path).unwrap_or(ImageFormat::Jpeg);
+    if format == ImageFormat::Jpeg {
+        let file = File::create(path)
+            .wrap_err_with(|| format!("Failed to create {}", path.display()))?;
+        let encoder = JpegEncoder::new_with_quality(BufWriter::new(file), JPEG_QUALITY);
+        image.to_rgb8().write_with_encoder(encoder)?;
+    } else {
+        image
+            .save_with_format(path, format)
+            .wrap_err_with(|| format!("Failed to save {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn format_size(size: u64) -> String {
+    format!("{:.1} MiB", size as f64 / (1024.0 * 1024.0))
+}
@@ -0,0 +1,286 @@
+//! The layout of the artifacts of a run, either flat in the working directory or
+//! gathered in a project directory that can be shared as a whole.
+
+use crate::brush::{BRUSH_DATASET_DIR, CHECKPOINT_DIR};
+use crate::dataset::CAMERAS_PATH;
+use crate::manifest::{RUN_MANIFEST_PATH, RUN_STATE_PATH};
+use crate::summary::SUMMARY_PATH;
+use crate::views::VIEWS_DIR;
+use clap::Args;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+/// The model in a project directory.
+const MODEL_FILE: &str = "model.ply";
+
+/// The prompt of the run in a project directory.
+const PROMPT_FILE: &str = "prompt.txt";
+
+/// The logs of the tools run by the pipeline, in a project directory.
+const LOGS_DIR: &str = "logs";
+
+/// How often a running tool is checked for having exited or been cancelled.
+const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Args, Debug)]
+pub struct ProjectArgs {
+    /// Gather every artifact of the run in this directory: the views, the model, the
+    /// manifests, and the logs.
+    #[arg(long, value_name = "DIR")]
+    pub project_dir: Option<PathBuf>,
+
+    /// Also archive the project directory as `<DIR>.zip` at the end of the run.
+    #[arg(long, requires = "project_dir")]
+    pub package: bool,
+}
+
+/// Where the artifacts of a run are written.
+///
+/// Without a project directory, they keep their historical places in the working
+/// directory.
+pub struct Layout {
+    root: Option<PathBuf>,
+}
+
+impl Layout {
+    pub fn new(args: &ProjectArgs) -> Self {
+        Self::at(args.project_dir.clone())
+    }
+
+    /// The layout of the project directory `root`, or the flat one without it.
+    pub fn at(root: Option<PathBuf>) -> Self {
+        Layout { root }
+    }
+
+    fn path(
+        &self,
+        name: &str,
+    ) -> PathBuf {
+        match &self.root {
+            Some(root) => root.join(name),
+            None => PathBuf::from(name),
+        }
+    }
+
+    /// The directory the artifacts are written to.
+    pub fn dir(&self) -> PathBuf {
+        self.root.clone().unwrap_or_else(|| PathBuf::from("."))
+    }
+
+    pub fn is_project(&self) -> bool {
+        self.root.is_some()
+    }
+
+    /// Creates the project directory, if any.
+    pub fn create(&self) -> Result<()> {
+        if let Some(root) = &self.root {
+            fs::create_dir_all(root.join(LOGS_DIR))
+                .wrap_err_with(|| format!("Failed to create {}", root.display()))?;
+        }
+        Ok(())
+    }
+
+    pub fn views_dir(&self) -> PathBuf {
+        self.path(VIEWS_DIR)
+    }
+
+    pub fn cameras(&self) -> PathBuf {
+        self.path(CAMERAS_PATH)
+    }
+
+    pub fn run_manifest(&self) -> PathBuf {
+        self.path(RUN_MANIFEST_PATH)
+    }
+
+    pub fn run_state(&self) -> PathBuf {
+        self.path(RUN_STATE_PATH)
+    }
+
+    pub fn summary(&self) -> PathBuf {
+        self.path(SUMMARY_PATH)
+    }
+
+    pub fn dataset_dir(&self) -> PathBuf {
+        self.path(BRUSH_DATASET_DIR)
+    }
+
+    pub fn checkpoints_dir(&self) -> PathBuf {
+        self.path(CHECKPOINT_DIR)
+    }
+
+    /// The model of a project, which is otherwise named after the prompt.
+    pub fn model(&self) -> Option<PathBuf> {
+        self.root.as_ref().map(|root| root.join(MODEL_FILE))
+    }
+
+    /// Where the output of the tool `name` is logged, in a project.
+    pub fn log(
+        &self,
+        name: &str,
+    ) -> Option<PathBuf> {
+        let root = self.root.as_ref()?;
+        Some(root.join(LOGS_DIR).join(format!("{}.log", name)))
+    }
+
+    /// Records the prompt in a project.
+    pub fn write_prompt(
+        &self,
+        prompt: &str,
+    ) -> Result<()> {
+        match &self.root {
+            Some(root) if !prompt.is_empty() => {
+                let path = root.join(PROMPT_FILE);
+                fs::write(&path, format!("{}\n", prompt))
+                    .wrap_err_with(|| format!("Failed to write {}", path.display()))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Copies the images in `dir` into the views of a project, so that it holds every
+    /// input of the run.
+    pub fn import_views(
+        &self,
+        dir: &Path,
+    ) -> Result<PathBuf> {
+        let views = self.views_dir();
+        // The views of the project itself, as when reconstructing again from them.
+        if fs::canonicalize(dir).ok() == fs::canonicalize(&views).ok() && views.is_dir() {
+            return Ok(views);
+        }
+        fs::remove_dir_all(&views).ok();
+        fs::create_dir_all(&views)
+            .wrap_err_with(|| format!("Failed to create {}", views.display()))?;
+        let entries = fs::read_dir(dir)
+            .wrap_err_with(|| format!("Failed to read {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_file() {
+                fs::copy(&path, views.join(path.file_name().unwrap())).wrap_err_with(
+                    || format!("Failed to copy {} into the project", path.display()),
+                )?;
+            }
+        }
+        Ok(views)
+    }
+}
+
+/// Runs `command`, showing its output on stderr and also appending it to `log` when
+/// given. Once `cancel` is cancelled, the command is asked to stop and awaited.
+pub fn run_logged(
+    command: &mut Command,
+    log: Option<&Path>,
+    cancel: &CancellationToken,
+) -> io::Result<ExitStatus> {
+    let Some(log) = log else {
+        return wait(&mut command.spawn()?, cancel);
+    };
+    let log = Arc::new(Mutex::new(
+        File::options().create(true).append(true).open(log)?,
+    ));
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let tee = |stream: Box<dyn Read + Send>| {
+        let log = Arc::clone(&log);
+        thread::spawn(move || {
+            for line in BufReader::new(stream).split(b'\n') {
+                let Ok(mut line) = line else { break };
+                line.push(b'\n');
+                let _ = io::stderr().write_all(&line);
+                let _ = log.lock().unwrap().write_all(&line);
+            }
+        })
+    };
+    let threads = [
+        tee(Box::new(child.stdout.take().unwrap())),
+        tee(Box::new(child.stderr.take().unwrap())),
+    ];
+    let status = wait(&mut child, cancel)?;
+    for thread in threads {
+        let _ = thread.join();
+    }
+    Ok(status)
+}
+
+/// Waits for `child` to exit, asking it to stop once `cancel` is cancelled so that
+/// it can clean up after itself.
+fn wait(
+    child: &mut Child,
+    cancel: &CancellationToken,
+) -> io::Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if cancel.is_cancelled() {
+            #[cfg(unix)]
+            // SAFETY: the child has not been waited on, so its id is still its own.
+            unsafe {
+                libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+            }
+            #[cfg(not(unix))]
+            child.kill()?;
+            return child.wait();
+        }
+        thread::sleep(CHILD_POLL_INTERVAL);
+    }
+}
+
+/// Archives the project directory `root` as `<root>.zip`, with its entries under
+/// the name of the directory.
+pub fn package(root: &Path) -> Result<PathBuf> {
+    let root = root
+        .canonicalize()
+        .wrap_err_with(|| format!("Failed to find {}", root.display()))?;
+    let name = root
+        .file_name()
+        .ok_or_else(|| eyre!("Cannot package {}", root.display()))?;
+    let mut archive = root.clone().into_os_string();
+    archive.push(".zip");
+    let archive = PathBuf::from(archive);
+    let file = File::create(&archive)
+        .wrap_err_with(|| format!("Failed to create {}", archive.display()))?;
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default().large_file(true);
+
+    let mut pending = vec![root.clone()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = fs::read_dir(&dir)
+            .wrap_err_with(|| format!("Failed to read {}", dir.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        for path in entries {
+            let relative = Path::new(name).join(path.strip_prefix(&root).unwrap());
+            // Zip entry names always use forward slashes.
+            let entry = relative
+                .components()
+                .map(|part| part.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if path.is_dir() {
+                zip.add_directory(entry, options)?;
+                pending.push(path);
+            } else {
+                zip.start_file(entry, options)?;
+                let mut source = File::open(&path)
+                    .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+                io::copy(&mut source, &mut zip)?;
+            }
+        }
+    }
+    zip.finish()
+        .wrap_err_with(|| format!("Failed to write {}", archive.display()))?;
+    Ok(archive)
+}
@@ -0,0 +1,292 @@
+//! Removal of near-transparent and oversized gaussians from a reconstructed model,
+//! and of the least contributing ones past a gaussian budget.
+
+use crate::ply::{self, sigmoid, RetainSummary, VertexLayout};
+use clap::Args;
+use color_eyre::eyre::{eyre, Result};
+use std::cmp::Ordering;
+use std::path::Path;
+
+/// The histogram of contribution scores has a bucket for every value of the top 16
+/// bits of their `f32` keys, fine enough to place the cut within 1%.
+const SCORE_BUCKETS: usize = 1 << 16;
+
+/// Options for pruning the reconstructed model.
+#[derive(Args, Debug)]
+pub struct PruneArgs {
+    /// Remove gaussians whose opacity, after the sigmoid activation, is below THRESHOLD.
+    #[arg(long, value_name = "THRESHOLD")]
+    pub prune_opacity: Option<f64>,
+
+    /// Remove gaussians whose scale along any axis, after the exp activation, exceeds MAX.
+    #[arg(long, value_name = "MAX")]
+    pub prune_scale: Option<f64>,
+
+    /// Keep at most N gaussians, those contributing most by opacity times volume,
+    /// after the other pruning.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_gaussians: Option<u64>,
+}
+
+impl PruneArgs {
+    pub fn is_enabled(&self) -> bool {
+        self.prune_opacity.is_some()
+            || self.prune_scale.is_some()
+            || self.max_gaussians.is_some()
+    }
+}
+
+/// Admits the gaussians with the highest contribution scores, up to a budget.
+///
+/// Gaussians are admitted above the bucket the budget runs out in, and within that
+/// bucket in file order, so that the selection is deterministic.
+struct Budget {
+    opacity: usize,
+    scales: [usize; 3],
+    boundary: usize,
+    /// How many more gaussians of the boundary bucket are admitted.
+    boundary_left: u64,
+}
+
+impl Budget {
+    /// Scores the gaussians of the model at `path` that `eligible` accepts, returning
+    /// `None` if there are no more than `max` of them.
+    fn new(
+        path: &Path,
+        max: u64,
+        eligible: impl Fn(&VertexLayout, &[u8]) -> bool,
+    ) -> Result<Option<Self>> {
+        let (_, mut vertices) = ply::open(path)?;
+        let layout = vertices.layout().clone();
+        let opacity = layout.require("opacity")?;
+        let scales = [
+            layout.require("scale_0")?,
+            layout.require("scale_1")?,
+            layout.require("scale_2")?,
+        ];
+        let mut histogram = vec![0u64; SCORE_BUCKETS];
+        while let Some(record) = vertices.next_record()? {
+            if eligible(&layout, record) {
+                histogram[bucket(&layout, record, opacity, scales)] += 1;
+            }
+        }
+        if histogram.iter().sum::<u64>() <= max {
+            return Ok(None);
+        }
+
+        let mut above = 0;
+        for (boundary, &count) in histogram.iter().enumerate().rev() {
+            if above + count >= max {
+                return Ok(Some(Budget {
+                    opacity,
+                    scales,
+                    boundary,
+                    boundary_left: max - above,
+                }));
+            }
+            above += count;
+        }
+        unreachable!("the histogram holds more than the budget")
+    }
+
+    fn admits(
+        &mut self,
+        layout: &VertexLayout,
+        record: &[u8],
+    ) -> bool {
+        match bucket(layout, record, self.opacity, self.scales).cmp(&self.boundary) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal if self.boundary_left > 0 => {
+                self.boundary_left -= 1;
+                true
+            },
+            Ordering::Equal => false,
+        }
+    }
+}
+
+/// The histogram bucket of the contribution score of a gaussian, its opacity times
+/// its volume, with larger scores in higher buckets.
+fn bucket(
+    layout: &VertexLayout,
+    record: &[u8],
+    opacity: usize,
+    scales: [usize; 3],
+) -> usize {
+    // In log space, where the volume is the sum of the stored log scales, so that
+    // tiny gaussians do not underflow to the same score.
+    let score = sigmoid(layout.get(record, opacity)).ln()
+        + scales
+            .iter()
+            .map(|&index| layout.get(record, index))
+            .sum::<f64>();
+    if score.is_nan() {
+        return 0;
+    }
+    // Flipping the bits of negative floats makes their order that of the integers.
+    let bits = (score as f32).to_bits();
+    let key = if bits >> 31 == 1 {
+        !bits
+    } else {
+        bits | 1 << 31
+    };
+    (key >> 16) as usize
+}
+
+/// Prunes the model at `path` in place, returning `None` if no pruning was requested.
+pub fn prune_model(
+    path: &Path,
+    args: &PruneArgs,
+) -> Result<Option<RetainSummary>> {
+    if !args.is_enabled() {
+        return Ok(None);
+    }
+    if let Some(threshold) = args.prune_opacity {
+        if !(0.0..=1.0).contains(&threshold) {
+            return Err(eyre!(
+                "--prune-opacity must be between 0 and 1, got {}",
+                threshold
+            ));
+        }
+    }
+    if let Some(max) = args.prune_scale {
+        if max.is_nan() || max <= 0.0 {
+            return Err(eyre!("--prune-scale must be positive, got {}", max));
+        }
+    }
+
+    let (header, _) = ply::open(path)?;
+    let layout = header.vertex_layout()?;
+    let opacity = match args.prune_opacity {
+        Some(threshold) => Some((layout.require("opacity")?, threshold)),
+        None => None,
+    };
+    // Scales are stored in log space, so compare against the log of the maximum.
+    let scale = match args.prune_scale {
+        Some(max) => Some((
+            [
+                layout.require("scale_0")?,
+                layout.require("scale_1")?,
+                layout.require("scale_2")?,
+            ],
+            max.ln(),
+        )),
+        None => None,
+    };
+
+    let eligible = |layout: &VertexLayout, record: &[u8]| {
+        if let Some((index, threshold)) = opacity {
+            if sigmoid(layout.get(record, index)) < threshold {
+                return false;
+            }
+        }
+        if let Some((indices, max_log_scale)) = scale {
+            if indices
+                .iter()
+                .any(|&index| layout.get(record, index) > max_log_scale)
+            {
+                return false;
+            }
+        }
+        true
+    };
+    let mut budget = match args.max_gaussians {
+        Some(max) => Budget::new(path, max, eligible)?,
+        None => None,
+    };
+
+    eprintln!("Pruning gaussians from {}...", path.display());
+    let summary = ply::retain_vertices(path, |layout, record| {
+        eligible(layout, record)
+            && budget
+                .as_mut()
+                .is_none_or(|budget| budget.admits(layout, record))
+    })?;
+    if let (Some(max), Some(_)) = (args.max_gaussians, &budget) {
+        eprintln!(
+            "Kept the {} gaussians contributing most, by opacity times volume",
+            max
+        );
+    }
+
+    eprintln!(
+        "Pruned {} of {} gaussians, saving {:.2} MiB ({:.2} MiB -> {:.2} MiB)",
+        summary.removed,
+        summary.kept + summary.removed,
+        (summary.size_before - summary.size_after) as f64 / (1024.0 * 1024.0),
+        summary.size_before as f64 / (1024.0 * 1024.0),
+        summary.size_after as f64 / (1024.0 * 1024.0),
+    );
+    Ok(Some(summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Six gaussians in a big-endian model with extra attributes between the usual
+    /// ones, followed by a face element.
+    const FIXTURE: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/pruning.ply");
+
+    fn args(
+        prune_opacity: Option<f64>,
+        prune_scale: Option<f64>,
+    ) -> PruneArgs {
+        PruneArgs {
+            prune_opacity,
+            prune_scale,
+            max_gaussians: None,
+        }
+    }
+
+    #[test]
+    fn rejects_a_scale_that_is_not_positive() {
+        for max in [0.0, -1.0, f64::NAN] {
+            let error =
+                prune_model(Path::new(FIXTURE), &args(None, Some(max))).unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .starts_with("--prune-scale must be positive"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn copies_the_surviving_records_byte_for_byte() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.ply");
+        fs::copy(FIXTURE, &path).unwrap();
+
+        let summary = prune_model(&path, &args(Some(0.1), Some(1.0)))
+            .unwrap()
+            .unwrap();
+        assert_eq!((summary.kept, summary.removed), (3, 3));
+
+        // The header only changes in its vertex count, and the records of the first,
+        // fourth and last gaussians follow as they were, then the faces.
+        let original = fs::read(FIXTURE).unwrap();
+        let (_, vertices) = ply::open(Path::new(FIXTURE)).unwrap();
+        let stride = vertices.layout().stride;
+        let end = b"end_header\n";
+        let body = original
+            .windows(end.len())
+            .position(|window| window == end)
+            .unwrap()
+            + end.len();
+        let header = String::from_utf8(original[..body].to_vec()).unwrap();
+        let mut expected = header
+            .replace("element vertex 6\n", "element vertex 3\n")
+            .into_bytes();
+        for index in [0, 3, 5] {
+            expected.extend_from_slice(&original[body + index * stride..][..stride]);
+        }
+        expected.extend_from_slice(&original[body + 6 * stride..]);
+        assert_eq!(fs::read(&path).unwrap(), expected);
+    }
+}
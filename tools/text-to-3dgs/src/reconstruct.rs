@@ -0,0 +1,1936 @@
+//! Client for the view-to-3dgs (peropero) reconstruction server.
+
+use crate::cancel;
+use crate::checksum;
+use crate::credentials::Profile;
+use crate::manifest::RunState;
+use crate::model::ModelFormat;
+use crate::preflight::{preflight, Downscale, PreflightArgs};
+use crate::project::Layout;
+use crate::views::ViewsManifest;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use clap::{Args, ValueEnum};
+use color_eyre::eyre::{eyre, Report, Result, WrapErr};
+use futures::{StreamExt, TryStreamExt};
+use http_trace::{usage, SendTraced};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{multipart, Body, Client, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::fs::File;
+use tokio::time::sleep;
+use tokio_util::codec::{BytesCodec, FramedRead};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Version of the capabilities document understood by this client. Documents
+/// without a `schema` field are taken to be of this version.
+const CAPABILITIES_SCHEMA: u32 = 1;
+
+/// Fields of the upload carrying the temporal order and source clips of the views.
+const ORDER_FIELD: &str = "order";
+const GROUPS_FIELD: &str = "groups";
+
+/// Header and field carrying the client-generated id of the reconstruction.
+const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+const JOB_ID_FIELD: &str = "job_id";
+
+/// Extensions of the image files uploaded as views.
+const VIEW_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// How many times a batch of the chunked upload is attempted.
+const BATCH_ATTEMPTS: u32 = 3;
+
+/// Interval between job status polls when no heartbeat interval is given.
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The reconstruction server used when neither the options nor the credentials
+/// profile name one.
+pub const DEFAULT_SERVER: &str = "http://localhost:8888";
+
+/// Interval of TCP and HTTP/2 keep-alive probes on the reconstruction connection.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Options for talking to the reconstruction server.
+#[derive(Args, Debug)]
+pub struct ReconstructArgs {
+    /// How the model is reconstructed from the views.
+    #[arg(long, value_enum, default_value_t = Backend::Server)]
+    pub backend: Backend,
+
+    /// Base URL of the reconstruction server [default: the credentials profile's, or
+    /// http://localhost:8888].
+    #[arg(long, value_name = "URL")]
+    pub server: Option<String>,
+
+    /// Bearer token authenticating with the reconstruction server.
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        env = "T2G_SERVER_TOKEN",
+        hide_env_values = true
+    )]
+    pub server_token: Option<String>,
+
+    /// Send a heartbeat every SECS seconds while awaiting the reconstruction.
+    #[arg(long, value_name = "SECS")]
+    pub heartbeat: Option<u64>,
+
+    /// Path of the optional document describing what the server accepts, used to
+    /// adapt the upload to it.
+    #[arg(long, value_name = "PATH", default_value = "/capabilities")]
+    pub capabilities_path: String,
+
+    /// Lightweight endpoint hit by heartbeats while a synchronous request is pending.
+    #[arg(long, value_name = "PATH", default_value = "/")]
+    pub heartbeat_path: String,
+
+    /// Submit the reconstruction as a job and poll for its result. Implied when the
+    /// server's capabilities declare support for jobs.
+    #[arg(long)]
+    pub async_jobs: bool,
+
+    /// Speak HTTP/2 without negotiation, for cleartext servers that support it.
+    #[arg(long)]
+    pub http2_prior_knowledge: bool,
+
+    /// Path of the reconstruction endpoint. Jobs are submitted to `<PATH>/jobs`.
+    #[arg(long, value_name = "PATH", default_value = "/reconstruction")]
+    pub endpoint_path: String,
+
+    /// Name of the multipart part (or JSON field) carrying the views.
+    #[arg(long, value_name = "NAME", default_value = "images")]
+    pub field_name: String,
+
+    /// How the views are uploaded: in a single request, or in batches to an upload
+    /// session at `<PATH>/sessions`. Defaults to a single request, unless the views
+    /// exceed the limits in the server's capabilities.
+    #[arg(long, value_enum)]
+    pub upload_mode: Option<UploadMode>,
+
+    /// Number of views per batch in the chunked upload mode.
+    #[arg(long, value_name = "N", default_value_t = 8)]
+    pub upload_batch_size: usize,
+
+    /// Number of batches in flight at once in the chunked upload mode.
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    pub upload_concurrency: usize,
+
+    #[command(flatten)]
+    pub preflight: PreflightArgs,
+
+    /// Shape of the upload request. Either shape also carries an `order` field listing
+    /// the views in temporal order, and a `groups` field mapping each view to its
+    /// source clip when there are several, if the views manifest provides them.
+    #[arg(long, value_enum, default_value_t = RequestStyle::Multipart)]
+    pub request_style: RequestStyle,
+}
+
+/// Where the model is reconstructed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Upload the views to the reconstruction server.
+    Server,
+    /// Train the model locally with brush, which needs camera poses for the views.
+    Brush,
+}
+
+/// How the views are sent to the reconstruction server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum UploadMode {
+    /// All views in one request.
+    Single,
+    /// Batches of views to an upload session, finalized once all have arrived.
+    Chunked,
+}
+
+/// Shape of the upload request sent to the reconstruction server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RequestStyle {
+    /// A multipart form with one file part per view.
+    Multipart,
+    /// A JSON body holding an array of `{name, mime, data_b64}` objects.
+    JsonBase64,
+}
+
+impl RequestStyle {
+    fn as_str(self) -> &'static str {
+        match self {
+            RequestStyle::Multipart => "multipart",
+            RequestStyle::JsonBase64 => "json-base64",
+        }
+    }
+
+    /// Ratio of the request body size to the size of the views it carries.
+    fn overhead(self) -> f64 {
+        match self {
+            RequestStyle::Multipart => 1.0,
+            RequestStyle::JsonBase64 => 4.0 / 3.0,
+        }
+    }
+}
+
+/// The upload body, built anew for every attempt since it is consumed on send.
+enum Payload {
+    Multipart(multipart::Form),
+    Json(serde_json::Value),
+}
+
+impl Payload {
+    fn attach(
+        self,
+        request: RequestBuilder,
+    ) -> RequestBuilder {
+        match self {
+            Payload::Multipart(form) => request.multipart(form),
+            Payload::Json(body) => request.json(&body),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct EncodedImage {
+    name: String,
+    mime: &'static str,
+    data_b64: String,
+}
+
+/// The server's capabilities document. Every field is optional, and fields unknown
+/// to this client are ignored.
+#[derive(Deserialize, Debug)]
+struct Capabilities {
+    schema: Option<u32>,
+    /// Version of the server software, for logging only.
+    version: Option<String>,
+    request_styles: Option<Vec<String>>,
+    field_names: Option<Vec<String>>,
+    /// Maximum number of views in a single upload request.
+    max_images: Option<usize>,
+    /// Maximum size of a single upload request body.
+    #[serde(alias = "max_upload_size")]
+    max_body_bytes: Option<u64>,
+    /// Whether the server offers the submit-then-poll job protocol.
+    async_supported: Option<bool>,
+    /// Accepted image formats, as MIME types (`image/png`) or bare names (`png`).
+    accepted_formats: Option<Vec<String>>,
+}
+
+/// How the upload is carried out, settled from the options and the capabilities.
+struct Negotiated {
+    upload_mode: UploadMode,
+    async_jobs: bool,
+    batch_size: usize,
+}
+
+/// What the server returned for a reconstruction.
+struct Reconstruction {
+    model: Vec<u8>,
+    /// The `Content-Type` the model was labelled with, if any.
+    content_type: Option<String>,
+    /// Camera poses in the `cameras.json` layout, when the server publishes them.
+    cameras: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct JobSubmission {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct JobStatus {
+    status: String,
+    error: Option<String>,
+}
+
+impl ReconstructArgs {
+    /// Base URL of the reconstruction server.
+    pub fn server(&self) -> &str {
+        self.server.as_deref().unwrap_or(DEFAULT_SERVER)
+    }
+
+    /// Fills the server and its token from the credentials profile, where the
+    /// options and the environment left them unset.
+    pub fn apply_profile(
+        &mut self,
+        profile: &Profile,
+    ) {
+        if self.server.is_none() {
+            self.server.clone_from(&profile.server);
+        }
+        if self.server_token.is_none() {
+            self.server_token.clone_from(&profile.server_token);
+        }
+    }
+
+    fn url(
+        &self,
+        path: &str,
+    ) -> String {
+        format!("{}{}", self.server().trim_end_matches('/'), path)
+    }
+
+    fn endpoint_url(&self) -> String {
+        self.url(self.endpoint_path.trim_end_matches('/'))
+    }
+
+    fn jobs_url(&self) -> String {
+        format!("{}/jobs", self.endpoint_url())
+    }
+
+    fn sessions_url(&self) -> String {
+        format!("{}/sessions", self.endpoint_url())
+    }
+
+    /// The lightweight endpoint hit by heartbeats and health checks.
+    pub fn heartbeat_url(&self) -> String {
+        self.url(&self.heartbeat_path)
+    }
+
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        self.heartbeat
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)
+    }
+}
+
+/// A reconstructed model, saved to a file, or held in memory.
+#[derive(Debug)]
+pub enum Model {
+    Saved(PathBuf),
+    /// A PLY model bound for stdout as it is, validated but never written to disk.
+    Held(Vec<u8>),
+}
+
+/// Reconstructs the views into a model saved at `output`, along with the camera
+/// poses in the run's layout if the server provides them.
+///
+/// The model is asked for in the format `output` is named after. Returned in
+/// another format, it is saved next to `output` under the extension of that format.
+/// With `hold`, a PLY model is held in memory instead of saved.
+///
+/// Returns the model, and the downscales applied to fit the upload in the server's
+/// size limit. Cancelled through `cancel`, it stops waiting on the server and saves
+/// nothing.
+pub async fn run_view_to_3dgs(
+    args: &ReconstructArgs,
+    layout: &Layout,
+    views_dir: &Path,
+    output: &Path,
+    hold: bool,
+    cancel: &CancellationToken,
+) -> Result<(Model, Vec<Downscale>)> {
+    eprintln!("--- Step 2: Running view-to-3dgs (peropero) ---");
+
+    // For now, we assume the peropero server is already running locally.
+    // A more robust implementation would handle starting/stopping the server.
+
+    let requested = ModelFormat::from_path(output);
+    let client = build_client(args, requested)?;
+
+    let image_paths = list_views(views_dir)?;
+    if image_paths.is_empty() {
+        return Err(eyre!(
+            "No images found in the '{}' directory. Did text-to-view run correctly?",
+            views_dir.display()
+        ));
+    }
+
+    let capabilities = cancel::or_cancelled(cancel, async {
+        Ok(fetch_capabilities(&client, args).await)
+    })
+    .await?;
+    if let Some(capabilities) = &capabilities {
+        validate_request_shape(args, capabilities)?;
+        validate_formats(capabilities, &image_paths)?;
+    }
+    let negotiated = negotiate(args, capabilities.as_ref(), &image_paths)?;
+
+    // A chunked upload is split into requests well below any body limit.
+    let limit = body_limit(args, capabilities.as_ref())
+        .filter(|_| negotiated.upload_mode == UploadMode::Single);
+    let (image_paths, downscales) = match limit {
+        Some(limit) => preflight(
+            &args.preflight,
+            &image_paths,
+            limit,
+            args.request_style.overhead(),
+        )?,
+        None => (image_paths, Vec::new()),
+    };
+    // A body in JSON holds every view in memory twice, encoded and serialized.
+    if args.request_style == RequestStyle::JsonBase64
+        && negotiated.upload_mode == UploadMode::Single
+    {
+        let size: u64 = image_paths
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        usage::warn_if_short(
+            "uploading the views as JSON",
+            (size as f64 * RequestStyle::JsonBase64.overhead() * 2.0) as u64,
+        );
+    }
+
+    // Uploads of the same views to the same server share one id, across retries
+    // and across runs, until a reconstruction completes.
+    let state_path = &layout.run_state();
+    let fingerprint = fingerprint(&image_paths);
+    let mut state = RunState::load(state_path)
+        .filter(|state| state.server == args.server() && state.views == fingerprint)
+        .unwrap_or_else(|| RunState {
+            server: args.server().to_string(),
+            views: fingerprint,
+            idempotency_key: Uuid::new_v4().to_string(),
+            job_url: None,
+        });
+    state.save(state_path)?;
+    let views = Views {
+        metadata: frame_metadata(views_dir, &image_paths)?,
+        paths: image_paths,
+    };
+
+    let resumed = match state.job_url.clone() {
+        Some(job_url) => {
+            eprintln!("Resuming the reconstruction job at {}...", job_url);
+            match cancel::or_cancelled(cancel, await_job(&client, args, &job_url)).await {
+                Ok(reconstruction) => Some(reconstruction),
+                Err(error) if cancel.is_cancelled() => return Err(error),
+                Err(error) => {
+                    eprintln!(
+                        "Could not resume the job, uploading the views again: {}",
+                        error
+                    );
+                    None
+                },
+            }
+        },
+        None => None,
+    };
+
+    let reconstruction = if let Some(reconstruction) = resumed {
+        reconstruction
+    } else {
+        eprintln!(
+            "Uploading {} images to reconstruction server as {}...",
+            views.paths.len(),
+            args.request_style.as_str()
+        );
+        // The batches handle cancellation themselves, to delete their session.
+        if negotiated.upload_mode == UploadMode::Chunked {
+            let batch_size = negotiated.batch_size;
+            reconstruct_in_batches(
+                &client, args, &views, batch_size, &mut state, state_path, cancel,
+            )
+            .await?
+        } else if negotiated.async_jobs {
+            let job = reconstruct_with_job(&client, args, &views, &mut state, state_path);
+            cancel::or_cancelled(cancel, job).await?
+        } else {
+            let reconstruction = async {
+                match reconstruct(&client, args, &views, &state).await {
+                    Ok(reconstruction) => Ok(reconstruction),
+                    Err(error)
+                        if is_connection_error(&error)
+                            && jobs_supported(&client, args, capabilities.as_ref())
+                                .await =>
+                    {
+                        eprintln!(
+                            "Connection to the reconstruction server was lost: {}",
+                            error
+                        );
+                        eprintln!(
+                            "The server supports jobs, switching to submit-then-poll..."
+                        );
+                        reconstruct_with_job(
+                            &client, args, &views, &mut state, state_path,
+                        )
+                        .await
+                    },
+                    Err(error) => Err(error),
+                }
+            };
+            cancel::or_cancelled(cancel, reconstruction).await?
+        }
+    };
+    fs::remove_file(state_path).ok();
+
+    let format = ModelFormat::detect(
+        reconstruction.content_type.as_deref(),
+        &reconstruction.model,
+    )?;
+    let output = &crate::model::saved_path(output, format);
+    if format != requested {
+        eprintln!(
+            "Warning: the reconstruction server returned the model as {} rather \
+             than {}, saving it to {}",
+            format,
+            requested,
+            output.display()
+        );
+    }
+    let model = if hold && format == ModelFormat::Ply {
+        Model::Held(reconstruction.model)
+    } else {
+        usage::write(output, &reconstruction.model).wrap_err_with(|| {
+            format!("Failed to save the model to {}", output.display())
+        })?;
+        checksum::record(output, checksum::sha256(&reconstruction.model));
+        Model::Saved(output.clone())
+    };
+    // Never leave poses of a previous run next to the new model.
+    let cameras = &layout.cameras();
+    fs::remove_file(cameras).ok();
+    if let Some(poses) = &reconstruction.cameras {
+        fs::write(cameras, poses).wrap_err_with(|| {
+            format!("Failed to save the camera poses to {}", cameras.display())
+        })?;
+        eprintln!("Camera poses saved to {}", cameras.display());
+    }
+
+    match &model {
+        Model::Saved(output) => eprintln!(
+            "--- Step 2: Reconstruction successful! Model saved to {} ---\n",
+            output.display()
+        ),
+        Model::Held(_) => eprintln!(
+            "--- Step 2: Reconstruction successful! Model held for stdout ---\n"
+        ),
+    }
+    Ok((model, downscales))
+}
+
+/// A client asking for models in `format`, authenticated with the server token.
+fn build_client(
+    args: &ReconstructArgs,
+    format: ModelFormat,
+) -> Result<Client> {
+    let mut builder = Client::builder()
+        .tcp_keepalive(KEEP_ALIVE_INTERVAL)
+        .http2_keep_alive_interval(KEEP_ALIVE_INTERVAL)
+        .http2_keep_alive_while_idle(true);
+    if args.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+    let mut headers =
+        HeaderMap::from_iter([(ACCEPT, HeaderValue::from_str(&format.accept())?)]);
+    if let Some(token) = &args.server_token {
+        http_trace::redact(token);
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", token))
+            .wrap_err("The reconstruction server token is not a valid header value")?;
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+    builder
+        .default_headers(headers)
+        .build()
+        .wrap_err("Failed to build the reconstruction HTTP client")
+}
+
+/// The temporal order and source clips of the views, from the views manifest.
+#[derive(Default)]
+struct FrameMetadata {
+    order: Option<Vec<String>>,
+    groups: Option<BTreeMap<String, String>>,
+}
+
+impl FrameMetadata {
+    /// Adds the `order` and `groups` fields to `body`, leaving out those unknown.
+    fn insert_into(
+        &self,
+        body: &mut serde_json::Map<String, serde_json::Value>,
+    ) {
+        if let Some(order) = &self.order {
+            body.insert(ORDER_FIELD.to_string(), serde_json::json!(order));
+        }
+        if let Some(groups) = &self.groups {
+            body.insert(GROUPS_FIELD.to_string(), serde_json::json!(groups));
+        }
+    }
+}
+
+/// The views to upload.
+struct Views {
+    paths: Vec<PathBuf>,
+    metadata: FrameMetadata,
+}
+
+fn frame_metadata(
+    views_dir: &Path,
+    image_paths: &[PathBuf],
+) -> Result<FrameMetadata> {
+    let Some(manifest) = ViewsManifest::read(views_dir)? else {
+        return Ok(FrameMetadata::default());
+    };
+    let files: Vec<String> = image_paths.iter().map(|path| file_name(path)).collect();
+    let order = manifest.order(&files);
+    Ok(FrameMetadata {
+        order: (!order.is_empty()).then_some(order),
+        groups: manifest.groups(&files),
+    })
+}
+
+async fn build_payload(
+    args: &ReconstructArgs,
+    image_paths: &[PathBuf],
+    metadata: &FrameMetadata,
+    job_id: &str,
+) -> Result<Payload> {
+    match args.request_style {
+        RequestStyle::Multipart => {
+            let mut form = multipart::Form::new().text(JOB_ID_FIELD, job_id.to_string());
+            if let Some(order) = &metadata.order {
+                form = form.text(ORDER_FIELD, serde_json::to_string(order)?);
+            }
+            if let Some(groups) = &metadata.groups {
+                form = form.text(GROUPS_FIELD, serde_json::to_string(groups)?);
+            }
+            for path in image_paths {
+                let file = File::open(&path).await?;
+                let stream = FramedRead::new(file, BytesCodec::new());
+                let body = Body::wrap_stream(stream);
+                let part = multipart::Part::stream(body)
+                    .file_name(file_name(path))
+                    .mime_str(image_mime(path))?;
+                form = form.part(args.field_name.clone(), part);
+            }
+            Ok(Payload::Multipart(form))
+        },
+        RequestStyle::JsonBase64 => {
+            let mut images = Vec::with_capacity(image_paths.len());
+            for path in image_paths {
+                let data = tokio::fs::read(path)
+                    .await
+                    .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+                images.push(EncodedImage {
+                    name: file_name(path),
+                    mime: image_mime(path),
+                    data_b64: BASE64_STANDARD.encode(data),
+                });
+            }
+            let mut body = serde_json::Map::new();
+            body.insert(JOB_ID_FIELD.to_string(), job_id.into());
+            body.insert(args.field_name.clone(), serde_json::to_value(images)?);
+            metadata.insert_into(&mut body);
+            Ok(Payload::Json(body.into()))
+        },
+    }
+}
+
+/// Describes the upload in HTTP traces by its parts, leaving out the image data.
+fn describe_payload(
+    args: &ReconstructArgs,
+    image_paths: &[PathBuf],
+    metadata: &FrameMetadata,
+) -> serde_json::Value {
+    let parts: Vec<_> = image_paths
+        .iter()
+        .map(|path| {
+            serde_json::json!({
+                "name": args.field_name,
+                "file_name": file_name(path),
+                "mime": image_mime(path),
+                "size": fs::metadata(path).map(|metadata| metadata.len()).ok(),
+            })
+        })
+        .collect();
+    let mut description = serde_json::Map::new();
+    description.insert("style".to_string(), args.request_style.as_str().into());
+    description.insert("parts".to_string(), parts.into());
+    metadata.insert_into(&mut description);
+    description.into()
+}
+
+/// Identifies a set of views by their names and sizes.
+fn fingerprint(image_paths: &[PathBuf]) -> String {
+    let views: Vec<String> = image_paths
+        .iter()
+        .map(|path| {
+            let size = fs::metadata(path)
+                .map(|metadata| metadata.len())
+                .unwrap_or(0);
+            format!("{}:{}", file_name(path), size)
+        })
+        .collect();
+    views.join(",")
+}
+
+/// Lists the images in `dir`, in file name order.
+pub fn list_views(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in
+        fs::read_dir(dir).wrap_err_with(|| format!("Failed to read {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let is_image = extension.is_some_and(|extension| {
+            VIEW_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        });
+        if is_image {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_str().unwrap().to_string()
+}
+
+fn image_mime(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    match extension.map(str::to_ascii_lowercase).as_deref() {
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        _ => "image/jpeg",
+    }
+}
+
+/// Fetches the server's capabilities document, if it publishes one.
+async fn fetch_capabilities(
+    client: &Client,
+    args: &ReconstructArgs,
+) -> Option<Capabilities> {
+    let response = client
+        .get(args.url(&args.capabilities_path))
+        .send_traced()
+        .await
+        .ok()?;
+    if !response.status().is_success() {
+        eprintln!("The reconstruction server publishes no capabilities, using the options as given");
+        return None;
+    }
+    let capabilities: Capabilities = match response.json().await {
+        Ok(capabilities) => capabilities,
+        Err(error) => {
+            eprintln!(
+                "Warning: ignoring unparsable server capabilities: {}",
+                error
+            );
+            return None;
+        },
+    };
+    let schema = capabilities.schema.unwrap_or(CAPABILITIES_SCHEMA);
+    eprintln!(
+        "Server capabilities: schema {}, server version {}",
+        schema,
+        capabilities.version.as_deref().unwrap_or("unknown")
+    );
+    if schema > CAPABILITIES_SCHEMA {
+        eprintln!(
+            "Warning: the capabilities use schema {}, newer than the supported schema {}. \
+             Only the fields known to this version are used.",
+            schema, CAPABILITIES_SCHEMA
+        );
+    }
+    Some(capabilities)
+}
+
+/// The largest accepted request body, from the options or the capabilities.
+fn body_limit(
+    args: &ReconstructArgs,
+    capabilities: Option<&Capabilities>,
+) -> Option<u64> {
+    args.preflight
+        .max_upload_size
+        .or(capabilities.and_then(|capabilities| capabilities.max_body_bytes))
+}
+
+/// Chooses the upload mode, job protocol, and batch size, honoring explicit options
+/// and adapting the rest to the server's capabilities.
+fn negotiate(
+    args: &ReconstructArgs,
+    capabilities: Option<&Capabilities>,
+    image_paths: &[PathBuf],
+) -> Result<Negotiated> {
+    let Some(capabilities) = capabilities else {
+        return Ok(Negotiated {
+            upload_mode: args.upload_mode.unwrap_or(UploadMode::Single),
+            async_jobs: args.async_jobs,
+            batch_size: args.upload_batch_size,
+        });
+    };
+
+    let count = image_paths.len();
+    let batch_size = match capabilities.max_images {
+        Some(max_images) => args.upload_batch_size.min(max_images.max(1)),
+        None => args.upload_batch_size,
+    };
+    let too_many = capabilities
+        .max_images
+        .filter(|&max_images| count > max_images);
+    let too_large = body_limit(args, Some(capabilities)).filter(|&limit| {
+        let size: u64 = image_paths
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        size as f64 * args.request_style.overhead() > limit as f64
+    });
+
+    let upload_mode = match (args.upload_mode, too_many) {
+        (Some(UploadMode::Single), Some(max_images)) => {
+            return Err(eyre!(
+                "The reconstruction server accepts at most {} views per request, but \
+                 there are {}. Use --upload-mode chunked, or fewer views.",
+                max_images,
+                count
+            ));
+        },
+        (Some(mode), _) => mode,
+        (None, Some(max_images)) => {
+            eprintln!(
+                "Negotiated: chunked upload, as the {} views exceed the server's limit \
+                 of {} per request",
+                count, max_images
+            );
+            UploadMode::Chunked
+        },
+        (None, None) if too_large.is_some() && !args.preflight.auto_downscale => {
+            eprintln!(
+                "Negotiated: chunked upload, as the views exceed the server's limit of \
+                 {} bytes per request",
+                too_large.unwrap_or_default()
+            );
+            UploadMode::Chunked
+        },
+        (None, None) => UploadMode::Single,
+    };
+
+    let async_jobs = match (args.async_jobs, capabilities.async_supported) {
+        (true, Some(false)) => {
+            return Err(eyre!(
+                "The reconstruction server does not support jobs. Remove --async-jobs."
+            ));
+        },
+        (false, Some(true)) => {
+            eprintln!("Negotiated: submit-then-poll, as the server supports jobs");
+            true
+        },
+        (async_jobs, _) => async_jobs,
+    };
+
+    if upload_mode == UploadMode::Chunked {
+        eprintln!("Negotiated: batches of up to {} views", batch_size);
+    }
+    Ok(Negotiated {
+        upload_mode,
+        async_jobs,
+        batch_size,
+    })
+}
+
+/// Rejects views in formats the server has declared it does not accept.
+fn validate_formats(
+    capabilities: &Capabilities,
+    image_paths: &[PathBuf],
+) -> Result<()> {
+    let Some(formats) = &capabilities.accepted_formats else {
+        return Ok(());
+    };
+    let is_accepted = |mime: &str| {
+        let subtype = mime.trim_start_matches("image/");
+        formats.iter().any(|format| {
+            let format = format.to_ascii_lowercase();
+            format == mime || format == subtype || (subtype == "jpeg" && format == "jpg")
+        })
+    };
+    let rejected: Vec<String> = image_paths
+        .iter()
+        .filter(|path| !is_accepted(image_mime(path)))
+        .map(|path| file_name(path))
+        .collect();
+    if !rejected.is_empty() {
+        return Err(eyre!(
+            "The reconstruction server does not accept the format of {}. \
+             Accepted formats: {}.",
+            rejected.join(", "),
+            formats.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects request shapes the server has declared it does not accept.
+fn validate_request_shape(
+    args: &ReconstructArgs,
+    capabilities: &Capabilities,
+) -> Result<()> {
+    let style = args.request_style.as_str();
+    if let Some(styles) = &capabilities.request_styles {
+        if !styles.iter().any(|accepted| accepted == style) {
+            return Err(eyre!(
+                "The reconstruction server does not accept the '{}' request style. \
+                 Accepted styles: {}. Use --request-style to choose one.",
+                style,
+                styles.join(", ")
+            ));
+        }
+    }
+    if let Some(field_names) = &capabilities.field_names {
+        if !field_names.contains(&args.field_name) {
+            return Err(eyre!(
+                "The reconstruction server does not accept the field name '{}'. \
+                 Accepted names: {}. Use --field-name to choose one.",
+                args.field_name,
+                field_names.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Uploads the views in a single request and waits for the model in its response.
+async fn reconstruct(
+    client: &Client,
+    args: &ReconstructArgs,
+    views: &Views,
+    state: &RunState,
+) -> Result<Reconstruction> {
+    let payload =
+        build_payload(args, &views.paths, &views.metadata, &state.idempotency_key)
+            .await?;
+    let request = payload
+        .attach(client.post(args.endpoint_url()))
+        .header(IDEMPOTENCY_HEADER, &state.idempotency_key)
+        .send_traced_with(describe_payload(args, &views.paths, &views.metadata));
+    let response = with_heartbeat(client, args, request)
+        .await
+        .wrap_err_with(|| {
+            format!(
+                "Failed to send request to reconstruction server. Is it running at {}?",
+                args.server()
+            )
+        })?;
+
+    if !response.status().is_success() {
+        let error_body = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "Could not read error body".to_string());
+        return Err(eyre!(
+            "Reconstruction server returned an error: {}",
+            error_body
+        ));
+    }
+
+    Ok(Reconstruction {
+        content_type: content_type(&response),
+        model: usage::read_body(response).await?,
+        cameras: None,
+    })
+}
+
+/// Submits the views as a reconstruction job and polls until its model is ready.
+async fn reconstruct_with_job(
+    client: &Client,
+    args: &ReconstructArgs,
+    views: &Views,
+    state: &mut RunState,
+    state_path: &Path,
+) -> Result<Reconstruction> {
+    let payload =
+        build_payload(args, &views.paths, &views.metadata, &state.idempotency_key)
+            .await?;
+    let submission: JobSubmission = payload
+        .attach(client.post(args.jobs_url()))
+        .header(IDEMPOTENCY_HEADER, &state.idempotency_key)
+        .send_traced_with(describe_payload(args, &views.paths, &views.metadata))
+        .await
+        .wrap_err("Failed to submit reconstruction job")?
+        .error_for_status()?
+        .json()
+        .await
+        .wrap_err("Failed to parse reconstruction job submission")?;
+    eprintln!("Reconstruction job submitted. Job id: {}", submission.id);
+
+    let status_url = format!("{}/{}", args.jobs_url(), submission.id);
+    state.job_url = Some(status_url.clone());
+    state.save(state_path)?;
+    await_job(client, args, &status_url).await
+}
+
+/// Uploads the views in batches to an upload session, then finalizes it.
+///
+/// Finalizing answers either with the model, or with a job to poll for it. The
+/// session is deleted on the server if the upload fails or is interrupted.
+async fn reconstruct_in_batches(
+    client: &Client,
+    args: &ReconstructArgs,
+    views: &Views,
+    batch_size: usize,
+    state: &mut RunState,
+    state_path: &Path,
+    cancel: &CancellationToken,
+) -> Result<Reconstruction> {
+    let mut body = serde_json::Map::new();
+    body.insert(
+        JOB_ID_FIELD.to_string(),
+        state.idempotency_key.clone().into(),
+    );
+    views.metadata.insert_into(&mut body);
+    let session: JobSubmission = client
+        .post(args.sessions_url())
+        .header(IDEMPOTENCY_HEADER, &state.idempotency_key)
+        .json(&body)
+        .send_traced()
+        .await
+        .wrap_err("Failed to open an upload session")?
+        .error_for_status()
+        .wrap_err("The reconstruction server refused to open an upload session")?
+        .json()
+        .await
+        .wrap_err("Failed to parse the upload session")?;
+    let session_url = format!("{}/{}", args.sessions_url(), session.id);
+    eprintln!("Upload session opened. Session id: {}", session.id);
+
+    let upload = upload_batches(client, args, views, batch_size, state, &session_url);
+    let finalized = tokio::select! {
+        finalized = upload => finalized,
+        _ = cancel.cancelled() => Err(eyre!(cancel::CANCELLED)),
+    };
+    let response = match finalized {
+        Ok(response) => response,
+        Err(error) => {
+            eprintln!("Deleting the upload session {}...", session.id);
+            if let Err(delete_error) = client.delete(&session_url).send_traced().await {
+                eprintln!(
+                    "Warning: failed to delete the upload session: {}",
+                    delete_error
+                );
+            }
+            return Err(error);
+        },
+    };
+
+    let content_type = content_type(&response);
+    let is_json = content_type
+        .as_deref()
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_json {
+        return Ok(Reconstruction {
+            content_type,
+            model: usage::read_body(response).await?,
+            cameras: None,
+        });
+    }
+    let job: JobSubmission = response
+        .json()
+        .await
+        .wrap_err("Failed to parse the reconstruction job of the session")?;
+    eprintln!("Reconstruction job submitted. Job id: {}", job.id);
+    let status_url = format!("{}/{}", args.jobs_url(), job.id);
+    state.job_url = Some(status_url.clone());
+    state.save(state_path)?;
+    await_job(client, args, &status_url).await
+}
+
+/// Sends every batch of views to the session, then finalizes it.
+///
+/// Each batch, and the finalization, has an idempotency key of its own, derived
+/// from that of the run, so that a server replaying answers to retried requests
+/// never answers one batch with another.
+async fn upload_batches(
+    client: &Client,
+    args: &ReconstructArgs,
+    views: &Views,
+    batch_size: usize,
+    state: &RunState,
+    session_url: &str,
+) -> Result<reqwest::Response> {
+    let batches: Vec<&[PathBuf]> = views.paths.chunks(batch_size.max(1)).collect();
+    let total = batches.len();
+    futures::stream::iter(batches.into_iter().enumerate())
+        .map(|(index, batch)| async move {
+            let key = format!("{}-batch-{}", state.idempotency_key, index);
+            upload_batch(
+                client,
+                args,
+                batch,
+                &views.metadata,
+                state,
+                &key,
+                session_url,
+            )
+            .await
+            .wrap_err_with(|| {
+                format!("Failed to upload batch {}/{}", index + 1, total)
+            })?;
+            eprintln!(
+                "Uploaded batch {}/{} ({} views)",
+                index + 1,
+                total,
+                batch.len()
+            );
+            Ok::<_, Report>(())
+        })
+        .buffer_unordered(args.upload_concurrency.max(1))
+        .try_collect::<()>()
+        .await?;
+
+    let response = client
+        .post(format!("{}/finalize", session_url))
+        .header(
+            IDEMPOTENCY_HEADER,
+            format!("{}-finalize", state.idempotency_key),
+        )
+        .send_traced()
+        .await
+        .wrap_err("Failed to finalize the upload session")?;
+    if !response.status().is_success() {
+        let error_body = response.text().await.unwrap_or_default();
+        return Err(eyre!(
+            "The reconstruction server failed to finalize the upload session: {}",
+            error_body
+        ));
+    }
+    Ok(response)
+}
+
+/// Sends one batch of views, retrying it on failures that may pass, each time
+/// with the idempotency key `key` of the batch.
+async fn upload_batch(
+    client: &Client,
+    args: &ReconstructArgs,
+    batch: &[PathBuf],
+    metadata: &FrameMetadata,
+    state: &RunState,
+    key: &str,
+    session_url: &str,
+) -> Result<()> {
+    let url = format!("{}/images", session_url);
+    let mut attempt = 1;
+    loop {
+        let payload =
+            build_payload(args, batch, metadata, &state.idempotency_key).await?;
+        let result = payload
+            .attach(client.post(&url))
+            .header(IDEMPOTENCY_HEADER, key)
+            .send_traced_with(describe_payload(args, batch, metadata))
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => return Ok(()),
+            Err(error) if attempt < BATCH_ATTEMPTS && is_transient(&error) => {
+                eprintln!(
+                    "Batch upload failed (attempt {}/{}), retrying: {}",
+                    attempt, BATCH_ATTEMPTS, error
+                );
+                sleep(Duration::from_secs(1 << attempt)).await;
+                attempt += 1;
+            },
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// Polls the job at `status_url` until its model is ready, then downloads it.
+async fn await_job(
+    client: &Client,
+    args: &ReconstructArgs,
+    status_url: &str,
+) -> Result<Reconstruction> {
+    let job_id = status_url.rsplit('/').next().unwrap_or(status_url);
+    let poll_interval = args.heartbeat_interval().unwrap_or(DEFAULT_POLL_INTERVAL);
+    let started = Instant::now();
+    loop {
+        let status: JobStatus = client
+            .get(status_url)
+            .send_traced()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        match status.status.as_str() {
+            "done" => break,
+            "failed" => {
+                return Err(eyre!(
+                    "Reconstruction job {} failed: {}",
+                    job_id,
+                    status.error.as_deref().unwrap_or("no error message")
+                ));
+            },
+            state => {
+                eprintln!(
+                    "Heartbeat: job {} is {} ({}s elapsed)",
+                    job_id,
+                    state,
+                    started.elapsed().as_secs()
+                );
+                if !http_trace::recording::is_replaying() {
+                    sleep(poll_interval).await;
+                }
+            },
+        }
+    }
+
+    let response = client
+        .get(format!("{}/model", status_url))
+        .send_traced()
+        .await?
+        .error_for_status()?;
+    let content_type = content_type(&response);
+    let model = usage::read_body(response)
+        .await
+        .wrap_err("Failed to download the reconstructed model")?;
+
+    // Servers that estimated camera poses publish them next to the model.
+    let response = client
+        .get(format!("{}/cameras", status_url))
+        .send_traced()
+        .await?;
+    let cameras = if response.status().is_success() {
+        Some(usage::read_body(response).await?)
+    } else {
+        None
+    };
+
+    Ok(Reconstruction {
+        model,
+        content_type,
+        cameras,
+    })
+}
+
+/// The `Content-Type` of `response`, if it has a readable one.
+fn content_type(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Drives `future` to completion while periodically hitting the heartbeat endpoint,
+/// to report whether the server is still reachable during a long reconstruction.
+///
+/// The heartbeat is a request of its own, so it does not keep the connection of the
+/// reconstruction request alive; only the TCP and HTTP/2 keepalives set in
+/// [`build_client`] do.
+async fn with_heartbeat<F: Future>(
+    client: &Client,
+    args: &ReconstructArgs,
+    future: F,
+) -> F::Output {
+    // Replayed runs go faster than recorded ones, so their heartbeats cannot match.
+    let Some(interval) = args
+        .heartbeat_interval()
+        .filter(|_| !http_trace::recording::is_replaying())
+    else {
+        return future.await;
+    };
+    let url = args.heartbeat_url();
+    let heartbeat = async {
+        let started = Instant::now();
+        loop {
+            sleep(interval).await;
+            let elapsed = started.elapsed().as_secs();
+            match client.get(&url).send_traced().await {
+                Ok(response) => eprintln!(
+                    "Heartbeat: server answered {} ({}s elapsed)",
+                    response.status(),
+                    elapsed
+                ),
+                Err(error) => {
+                    eprintln!(
+                        "Heartbeat: server unreachable ({}s elapsed): {}",
+                        elapsed, error
+                    )
+                },
+            }
+        }
+    };
+
+    tokio::select! {
+        output = future => output,
+        _ = heartbeat => unreachable!("the heartbeat loop never completes"),
+    }
+}
+
+/// Checks whether the server exposes the job collection used by submit-then-poll,
+/// unless its capabilities already tell.
+async fn jobs_supported(
+    client: &Client,
+    args: &ReconstructArgs,
+    capabilities: Option<&Capabilities>,
+) -> bool {
+    if let Some(supported) =
+        capabilities.and_then(|capabilities| capabilities.async_supported)
+    {
+        return supported;
+    }
+    match client.get(args.jobs_url()).send_traced().await {
+        Ok(response) => response.status() != StatusCode::NOT_FOUND,
+        Err(_) => false,
+    }
+}
+
+/// Whether `error` is the connection to the server failing, timing out, closing
+/// before the response, or breaking off mid-body, rather than a request the server
+/// answered or one that could not be built.
+fn is_connection_error(error: &Report) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .any(is_broken_connection)
+}
+
+fn is_broken_connection(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout() || error.is_request() || error.is_body()
+}
+
+/// Whether a request failing with `error` may succeed when sent again: its
+/// connection broke, or the server was overloaded or failed on its side.
+fn is_transient(error: &reqwest::Error) -> bool {
+    let status = error.status();
+    is_broken_connection(error)
+        || status.is_some_and(|status| {
+            status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::collections::BTreeSet;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    const JOB_ID: &str = "3f2c8a7e-idempotency-key";
+    const MODEL: &[u8] = b"ply\nformat binary_little_endian 1.0\nend_header\n";
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        reconstruct: ReconstructArgs,
+    }
+
+    /// The options of a run against the server at `address`, followed by `options`.
+    fn args(
+        address: &str,
+        options: &[&str],
+    ) -> ReconstructArgs {
+        let server = format!("http://{}", address);
+        let command = ["text-to-3dgs", "--server", &server].into_iter();
+        Cli::parse_from(command.chain(options.iter().copied())).reconstruct
+    }
+
+    /// A request received by the mock server.
+    struct Request {
+        method: String,
+        path: String,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Request {
+        fn header(
+            &self,
+            name: &str,
+        ) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+
+        fn json(&self) -> serde_json::Value {
+            serde_json::from_slice(&self.body).unwrap()
+        }
+    }
+
+    /// What the mock server answers a request with, or `None` to hang up on it.
+    type Reply = Option<(u16, &'static str, Vec<u8>)>;
+
+    /// Answers every request with `handler`, returning the address served on and the
+    /// requests received so far.
+    fn serve(
+        handler: impl FnMut(&Request) -> Reply + Send + 'static
+    ) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(Mutex::new(handler));
+        let log = Arc::clone(&received);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (handler, log) = (Arc::clone(&handler), Arc::clone(&log));
+                thread::spawn(move || {
+                    let mut stream = stream.unwrap();
+                    let Some(request) = read_request(&stream) else {
+                        return;
+                    };
+                    let reply = handler.lock().unwrap()(&request);
+                    log.lock().unwrap().push(request);
+                    // Clients that gave up on the response have hung up already.
+                    if let Some((status, content_type, body)) = reply {
+                        let _ = write!(
+                            stream,
+                            "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\n\
+                             Content-Length: {}\r\nConnection: close\r\n\r\n",
+                            status,
+                            content_type,
+                            body.len()
+                        )
+                        .and_then(|()| stream.write_all(&body));
+                    }
+                });
+            }
+        });
+        (address, received)
+    }
+
+    fn read_request(stream: &TcpStream) -> Option<Request> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let mut words = line.split_whitespace();
+        let method = words.next()?.to_string();
+        let path = words.next()?.to_string();
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            reader.read_line(&mut line).ok()?;
+            let Some((name, value)) = line.trim_end().split_once(':') else {
+                break;
+            };
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+        let mut request = Request {
+            method,
+            path,
+            headers,
+            body: Vec::new(),
+        };
+        if let Some(length) = request.header("content-length") {
+            request.body = vec![0; length.parse().ok()?];
+            reader.read_exact(&mut request.body).ok()?;
+        } else if request.header("transfer-encoding") == Some("chunked") {
+            loop {
+                line.clear();
+                reader.read_line(&mut line).ok()?;
+                let size = usize::from_str_radix(line.trim(), 16).ok()?;
+                let mut chunk = vec![0; size + 2];
+                reader.read_exact(&mut chunk).ok()?;
+                if size == 0 {
+                    break;
+                }
+                request.body.extend_from_slice(&chunk[..size]);
+            }
+        }
+        Some(request)
+    }
+
+    /// A part of a multipart body: its name, file name, content type, and data.
+    struct Part {
+        name: String,
+        file_name: Option<String>,
+        mime: Option<String>,
+        data: Vec<u8>,
+    }
+
+    fn multipart_parts(request: &Request) -> Vec<Part> {
+        let content_type = request.header("content-type").unwrap();
+        let boundary = content_type.split_once("boundary=").unwrap().1;
+        let delimiter = format!("--{}", boundary).into_bytes();
+        let mut parts = Vec::new();
+        let mut rest = &request.body[..];
+        while let Some(start) = find(rest, &delimiter) {
+            rest = &rest[start + delimiter.len()..];
+            if rest.starts_with(b"--") {
+                break;
+            }
+            let head_end = find(rest, b"\r\n\r\n").unwrap();
+            let head = String::from_utf8_lossy(&rest[..head_end]).into_owned();
+            let data_end = find(rest, &delimiter).unwrap();
+            let data = rest[head_end + 4..data_end - 2].to_vec();
+            let attribute = |key: &str| {
+                let start = head.find(&format!("{}=\"", key))? + key.len() + 2;
+                let length = head[start..].find('"')?;
+                Some(head[start..start + length].to_string())
+            };
+            let mime = head.lines().find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("content-type")
+                    .then(|| value.trim().to_string())
+            });
+            parts.push(Part {
+                name: attribute(" name").unwrap(),
+                file_name: attribute("filename"),
+                mime,
+                data,
+            });
+        }
+        parts
+    }
+
+    fn find(
+        haystack: &[u8],
+        needle: &[u8],
+    ) -> Option<usize> {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+
+    /// Writes `count` views into `dir`, each with distinct binary contents.
+    fn write_views(
+        dir: &Path,
+        count: u8,
+    ) -> Vec<PathBuf> {
+        (0..count)
+            .map(|index| {
+                let path = dir.join(format!("{}.png", index));
+                fs::write(&path, view_data(index)).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn view_data(index: u8) -> Vec<u8> {
+        vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0xff, index]
+    }
+
+    fn state(args: &ReconstructArgs) -> RunState {
+        RunState {
+            server: args.server().to_string(),
+            views: String::new(),
+            idempotency_key: JOB_ID.to_string(),
+            job_url: None,
+        }
+    }
+
+    /// The views in reverse temporal order, each from a clip of its own.
+    fn metadata() -> FrameMetadata {
+        FrameMetadata {
+            order: Some(vec!["1.png".to_string(), "0.png".to_string()]),
+            groups: Some(BTreeMap::from([
+                ("0.png".to_string(), "0".to_string()),
+                ("1.png".to_string(), "1".to_string()),
+            ])),
+        }
+    }
+
+    /// Answers uploads to the reconstruction endpoint with the model.
+    fn reconstruction_server() -> (String, Arc<Mutex<Vec<Request>>>) {
+        serve(|request| {
+            assert_eq!(request.path, "/reconstruction");
+            Some((200, "application/octet-stream", MODEL.to_vec()))
+        })
+    }
+
+    /// Uploads `paths` with `metadata` in a single request, returning what the server
+    /// received.
+    async fn upload(
+        options: &[&str],
+        paths: Vec<PathBuf>,
+        metadata: FrameMetadata,
+    ) -> Request {
+        let (address, received) = reconstruction_server();
+        let args = args(&address, options);
+        let client = build_client(&args, ModelFormat::Ply).unwrap();
+        let views = Views { paths, metadata };
+        let reconstruction = reconstruct(&client, &args, &views, &state(&args))
+            .await
+            .unwrap();
+        assert_eq!(reconstruction.model, MODEL);
+
+        let mut received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let request = received.pop().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.header(IDEMPOTENCY_HEADER), Some(JOB_ID));
+        request
+    }
+
+    #[tokio::test]
+    async fn uploads_the_views_as_multipart_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = write_views(dir.path(), 2);
+        let request = upload(&[], paths, FrameMetadata::default()).await;
+
+        let content_type = request.header("content-type").unwrap();
+        assert!(
+            content_type.starts_with("multipart/form-data"),
+            "{}",
+            content_type
+        );
+        let parts = multipart_parts(&request);
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0].name, JOB_ID_FIELD);
+        assert_eq!(parts[0].data, JOB_ID.as_bytes());
+        for (index, part) in parts[1..].iter().enumerate() {
+            assert_eq!(part.name, "images");
+            assert_eq!(part.file_name.as_deref(), Some(&*format!("{}.png", index)));
+            assert_eq!(part.mime.as_deref(), Some("image/png"));
+            assert_eq!(part.data, view_data(index as u8));
+        }
+    }
+
+    #[tokio::test]
+    async fn uploads_the_views_as_base64_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = write_views(dir.path(), 2);
+        let options = ["--request-style", "json-base64", "--field-name", "frames"];
+        let request = upload(&options, paths, FrameMetadata::default()).await;
+
+        let content_type = request.header("content-type").unwrap();
+        assert!(
+            content_type.starts_with("application/json"),
+            "{}",
+            content_type
+        );
+        let body = request.json();
+        assert_eq!(body[JOB_ID_FIELD], JOB_ID);
+        let images = body["frames"].as_array().unwrap();
+        assert_eq!(images.len(), 2);
+        for (index, image) in images.iter().enumerate() {
+            assert_eq!(image["name"], format!("{}.png", index));
+            assert_eq!(image["mime"], "image/png");
+            let data = BASE64_STANDARD
+                .decode(image["data_b64"].as_str().unwrap())
+                .unwrap();
+            assert_eq!(data, view_data(index as u8));
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_the_order_and_groups_of_the_views() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = write_views(dir.path(), 2);
+        let order = serde_json::json!(["1.png", "0.png"]);
+        let groups = serde_json::json!({ "0.png": "0", "1.png": "1" });
+
+        let request = upload(&[], paths.clone(), metadata()).await;
+        let parts = multipart_parts(&request);
+        let field = |name: &str| -> serde_json::Value {
+            let part = parts.iter().find(|part| part.name == name).unwrap();
+            assert_eq!(part.file_name, None);
+            serde_json::from_slice(&part.data).unwrap()
+        };
+        assert_eq!(field(ORDER_FIELD), order);
+        assert_eq!(field(GROUPS_FIELD), groups);
+
+        let options = ["--request-style", "json-base64"];
+        let body = upload(&options, paths, metadata()).await.json();
+        assert_eq!(body[ORDER_FIELD], order);
+        assert_eq!(body[GROUPS_FIELD], groups);
+    }
+
+    #[tokio::test]
+    async fn omits_the_order_and_groups_without_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = write_views(dir.path(), 2);
+        let fields = [ORDER_FIELD, GROUPS_FIELD];
+
+        let request = upload(&[], paths.clone(), FrameMetadata::default()).await;
+        let parts = multipart_parts(&request);
+        assert!(parts
+            .iter()
+            .all(|part| !fields.contains(&part.name.as_str())));
+
+        let options = ["--request-style", "json-base64"];
+        let body = upload(&options, paths.clone(), FrameMetadata::default())
+            .await
+            .json();
+        let body = body.as_object().unwrap();
+        assert!(
+            fields.iter().all(|field| !body.contains_key(*field)),
+            "{:?}",
+            body
+        );
+
+        // Nor does the description of the upload in HTTP traces mention them.
+        let args = args("127.0.0.1:1", &[]);
+        let description = describe_payload(&args, &paths, &FrameMetadata::default());
+        let description = description.as_object().unwrap();
+        assert!(fields.iter().all(|field| !description.contains_key(*field)));
+        let description = describe_payload(&args, &paths, &metadata());
+        assert_eq!(
+            description[ORDER_FIELD],
+            serde_json::json!(["1.png", "0.png"])
+        );
+    }
+
+    /// Runs the session protocol, answering the attempts at each batch, known by its
+    /// first view, with `batch`.
+    fn session_server(
+        batch: impl Fn(&str, usize) -> Reply + Send + 'static
+    ) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let mut attempts = BTreeMap::<String, usize>::new();
+        serve(move |request| {
+            let ok = |content_type, body: &[u8]| Some((200, content_type, body.to_vec()));
+            match (request.method.as_str(), request.path.as_str()) {
+                ("POST", "/reconstruction/sessions") => {
+                    ok("application/json", br#"{"id": "s1"}"#)
+                },
+                ("POST", "/reconstruction/sessions/s1/images") => {
+                    let first = batch_views(request).remove(0);
+                    let attempt = attempts.entry(first.clone()).or_default();
+                    *attempt += 1;
+                    batch(&first, *attempt)
+                },
+                ("POST", "/reconstruction/sessions/s1/finalize") => {
+                    ok("application/octet-stream", MODEL)
+                },
+                ("DELETE", "/reconstruction/sessions/s1") => ok("text/plain", b""),
+                _ => Some((404, "text/plain", Vec::new())),
+            }
+        })
+    }
+
+    fn batch_views(request: &Request) -> Vec<String> {
+        multipart_parts(request)
+            .into_iter()
+            .filter_map(|part| part.file_name)
+            .collect()
+    }
+
+    /// Uploads six views in batches of two, returning the result and the requests the
+    /// server received, in the order it answered them.
+    async fn upload_in_batches(
+        address: &str,
+        cancel: &CancellationToken,
+        received: &Mutex<Vec<Request>>,
+    ) -> (Result<Reconstruction>, Vec<Request>) {
+        let dir = tempfile::tempdir().unwrap();
+        let args = args(address, &["--upload-mode", "chunked"]);
+        let client = build_client(&args, ModelFormat::Ply).unwrap();
+        let views = Views {
+            paths: write_views(dir.path(), 6),
+            metadata: FrameMetadata::default(),
+        };
+        let state_path = dir.path().join("state.json");
+        let result = reconstruct_in_batches(
+            &client,
+            &args,
+            &views,
+            2,
+            &mut state(&args),
+            &state_path,
+            cancel,
+        )
+        .await;
+        (result, std::mem::take(&mut *received.lock().unwrap()))
+    }
+
+    /// How many times the batch starting with `first` was sent.
+    fn attempts(
+        requests: &[Request],
+        first: &str,
+    ) -> usize {
+        requests
+            .iter()
+            .filter(|request| request.path.ends_with("/images"))
+            .filter(|request| batch_views(request)[0] == first)
+            .count()
+    }
+
+    fn requested(
+        requests: &[Request],
+        method: &str,
+        path: &str,
+    ) -> bool {
+        requests
+            .iter()
+            .any(|request| request.method == method && request.path == path)
+    }
+
+    #[tokio::test]
+    async fn resumes_the_upload_after_transient_batch_failures() {
+        let (address, received) =
+            session_server(|first, attempt| match (first, attempt) {
+                ("2.png", 1) => Some((503, "text/plain", b"overloaded".to_vec())),
+                ("4.png", 1) => Some((429, "text/plain", b"slow down".to_vec())),
+                _ => Some((200, "application/json", b"{}".to_vec())),
+            });
+        let cancel = CancellationToken::new();
+        let (result, requests) = upload_in_batches(&address, &cancel, &received).await;
+        assert_eq!(result.unwrap().model, MODEL);
+
+        // Only the failed batches were sent again, each with the same views.
+        assert_eq!(attempts(&requests, "0.png"), 1);
+        assert_eq!(attempts(&requests, "2.png"), 2);
+        assert_eq!(attempts(&requests, "4.png"), 2);
+        for request in requests
+            .iter()
+            .filter(|request| request.path.ends_with("/images"))
+        {
+            let views = batch_views(request);
+            let first: usize = views[0].trim_end_matches(".png").parse().unwrap();
+            assert_eq!(
+                views,
+                [format!("{}.png", first), format!("{}.png", first + 1)]
+            );
+            // A retried batch keeps its key, which no other batch shares.
+            assert_eq!(
+                request.header(IDEMPOTENCY_HEADER),
+                Some(format!("{}-batch-{}", JOB_ID, first / 2).as_str())
+            );
+        }
+        let batch_keys: BTreeSet<_> = requests
+            .iter()
+            .filter(|request| request.path.ends_with("/images"))
+            .filter_map(|request| request.header(IDEMPOTENCY_HEADER))
+            .collect();
+        assert_eq!(batch_keys.len(), 3);
+        let key = |path: &str| {
+            let request = requests.iter().find(|request| request.path == path);
+            request.and_then(|request| request.header(IDEMPOTENCY_HEADER))
+        };
+        assert_eq!(key("/reconstruction/sessions"), Some(JOB_ID));
+        let finalize = format!("{}-finalize", JOB_ID);
+        assert_eq!(
+            key("/reconstruction/sessions/s1/finalize"),
+            Some(finalize.as_str())
+        );
+        assert!(!requested(
+            &requests,
+            "DELETE",
+            "/reconstruction/sessions/s1"
+        ));
+    }
+
+    #[tokio::test]
+    async fn resumes_the_upload_after_a_dropped_connection() {
+        let (address, received) =
+            session_server(|first, attempt| match (first, attempt) {
+                ("0.png", 1) => None,
+                _ => Some((200, "application/json", b"{}".to_vec())),
+            });
+        let cancel = CancellationToken::new();
+        let (result, requests) = upload_in_batches(&address, &cancel, &received).await;
+        assert_eq!(result.unwrap().model, MODEL);
+        assert_eq!(attempts(&requests, "0.png"), 2);
+    }
+
+    #[tokio::test]
+    async fn deletes_the_session_when_a_batch_is_refused() {
+        let (address, received) = session_server(|first, _| match first {
+            "4.png" => Some((400, "text/plain", b"not an image".to_vec())),
+            _ => Some((200, "application/json", b"{}".to_vec())),
+        });
+        let cancel = CancellationToken::new();
+        let (result, requests) = upload_in_batches(&address, &cancel, &received).await;
+        let error = format!("{:#}", result.err().unwrap());
+        assert!(error.contains("Failed to upload batch 3/3"), "{}", error);
+
+        // A request the server refused is not sent again.
+        assert_eq!(attempts(&requests, "4.png"), 1);
+        assert!(!requested(
+            &requests,
+            "POST",
+            "/reconstruction/sessions/s1/finalize"
+        ));
+        assert!(requested(
+            &requests,
+            "DELETE",
+            "/reconstruction/sessions/s1"
+        ));
+    }
+
+    #[tokio::test]
+    async fn deletes_the_session_when_cancelled() {
+        let (address, received) =
+            session_server(|_, _| Some((200, "application/json", b"{}".to_vec())));
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let (result, requests) = upload_in_batches(&address, &cancel, &received).await;
+        assert!(result.is_err());
+        assert!(!requested(
+            &requests,
+            "POST",
+            "/reconstruction/sessions/s1/finalize"
+        ));
+        assert!(requested(
+            &requests,
+            "DELETE",
+            "/reconstruction/sessions/s1"
+        ));
+    }
+
+    /// Runs a reconstruction of two views in a project at `dir`, answering requests
+    /// to the reconstruction endpoint with `reconstruction`, and holding a PLY model
+    /// in memory with `hold`.
+    async fn reconstruct_project(
+        dir: &Path,
+        hold: bool,
+        cancel: &CancellationToken,
+        reconstruction: impl FnMut(&Request) -> Reply + Send + 'static,
+    ) -> (Result<(Model, Vec<Downscale>)>, Vec<Request>) {
+        let mut reconstruction = reconstruction;
+        let (address, received) = serve(move |request| match request.path.as_str() {
+            "/reconstruction" => reconstruction(request),
+            _ => Some((404, "text/plain", Vec::new())),
+        });
+        let args = args(&address, &[]);
+        let layout = Layout::at(Some(dir.to_path_buf()));
+        let views_dir = layout.views_dir();
+        fs::create_dir_all(&views_dir).unwrap();
+        write_views(&views_dir, 2);
+        let output = dir.join("model.ply");
+        let result =
+            run_view_to_3dgs(&args, &layout, &views_dir, &output, hold, cancel).await;
+        let requests = std::mem::take(&mut *received.lock().unwrap());
+        (result, requests)
+    }
+
+    #[tokio::test]
+    async fn holds_a_ply_model_bound_for_stdout_without_saving_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let cancel = CancellationToken::new();
+        let (result, _) = reconstruct_project(dir.path(), true, &cancel, |_| {
+            Some((200, "application/octet-stream", MODEL.to_vec()))
+        })
+        .await;
+        match result.unwrap().0 {
+            Model::Held(model) => assert_eq!(model, MODEL),
+            Model::Saved(path) => panic!("The model was saved to {}", path.display()),
+        }
+        assert!(!dir.path().join("model.ply").exists());
+    }
+
+    #[tokio::test]
+    async fn does_not_upload_once_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let (result, requests) = reconstruct_project(dir.path(), false, &cancel, |_| {
+            Some((200, "application/octet-stream", MODEL.to_vec()))
+        })
+        .await;
+        assert_eq!(result.unwrap_err().to_string(), cancel::CANCELLED);
+        assert!(!requested(&requests, "POST", "/reconstruction"));
+        assert!(!dir.path().join("model.ply").exists());
+    }
+
+    #[tokio::test]
+    async fn saves_nothing_when_cancelled_during_the_reconstruction() {
+        let dir = tempfile::tempdir().unwrap();
+        let cancel = CancellationToken::new();
+        let server_cancel = cancel.clone();
+        let (result, _) = reconstruct_project(dir.path(), false, &cancel, move |_| {
+            // The model comes too late, after the run gave up on it.
+            server_cancel.cancel();
+            thread::sleep(Duration::from_secs(1));
+            Some((200, "application/octet-stream", MODEL.to_vec()))
+        })
+        .await;
+        assert_eq!(result.unwrap_err().to_string(), cancel::CANCELLED);
+
+        let layout = Layout::at(Some(dir.path().to_path_buf()));
+        assert!(!dir.path().join("model.ply").exists());
+        assert!(!layout.cameras().exists());
+        // The views are the input of the stage, and stay for a later run.
+        assert_eq!(list_views(&layout.views_dir()).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,52 @@
+//! Recording the HTTP exchanges of a run, and replaying them without the network.
+//!
+//! text-to-view records into, and replays from, a `text-to-view` subdirectory, so
+//! that the numbering of each process stays its own.
+
+use clap::Args;
+use color_eyre::eyre::{Result, WrapErr};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// The subdirectory holding the exchanges of text-to-view.
+const TEXT_TO_VIEW_DIR: &str = "text-to-view";
+
+#[derive(Args, Clone, Debug)]
+pub struct RecordingArgs {
+    /// Save every API call, download and reconstruction request, with credentials
+    /// redacted, as numbered files in this directory, to replay them with --replay.
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Answer every request from the exchanges recorded in this directory, without
+    /// the network, failing on any request that was not recorded.
+    #[arg(long, value_name = "DIR")]
+    pub replay: Option<PathBuf>,
+}
+
+impl RecordingArgs {
+    /// Starts recording or replaying the requests of this process.
+    pub fn install(&self) -> Result<()> {
+        if let Some(dir) = &self.record {
+            http_trace::recording::record(dir).wrap_err_with(|| {
+                format!("Failed to start recording into {}", dir.display())
+            })?;
+        }
+        if let Some(dir) = &self.replay {
+            http_trace::recording::replay(dir).wrap_err_with(|| {
+                format!("Failed to read the recording in {}", dir.display())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The arguments recording or replaying the requests of text-to-view.
+    pub fn text_to_view_args(&self) -> Vec<OsString> {
+        let (flag, dir) = match (&self.record, &self.replay) {
+            (Some(dir), _) => ("--record", dir),
+            (None, Some(dir)) => ("--replay", dir),
+            (None, None) => return Vec::new(),
+        };
+        vec![flag.into(), dir.join(TEXT_TO_VIEW_DIR).into()]
+    }
+}
@@ -0,0 +1,208 @@
+//! Fetching views from remote URLs, as an alternative to generating them.
+
+use crate::cancel;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use futures::{StreamExt, TryStreamExt};
+use http_trace::SendTraced;
+use reqwest::{Client, StatusCode};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+/// Number of views fetched at once.
+const FETCH_CONCURRENCY: usize = 4;
+
+/// How many times each view is requested before the fetch fails.
+const FETCH_ATTEMPTS: u32 = 3;
+
+/// Where the fetched views are saved, unique to this process.
+pub fn fetch_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("text-to-3dgs-views-{}", std::process::id()))
+}
+
+/// Reads the URLs listed in `path`, one per line, skipping blank lines and `#`
+/// comments.
+pub fn read_url_list(path: &Path) -> Result<Vec<String>> {
+    let list = fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read the URL list {}", path.display()))?;
+    Ok(list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Fetches the views at `urls` into `dir`, failing before anything is uploaded if
+/// any of them cannot be fetched or is not an image.
+///
+/// Views are named after their position and the last segment of their URL path,
+/// with the extension of their actual format. Cancelled through `cancel`, it
+/// deletes `dir` rather than leave a partial set of views behind.
+pub async fn fetch_views(
+    urls: &[String],
+    dir: &Path,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    fs::remove_dir_all(dir).ok();
+    fs::create_dir_all(dir)
+        .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+    let client = Client::new();
+
+    eprintln!("Fetching {} views...", urls.len());
+    let fetched = futures::stream::iter(urls.iter().enumerate())
+        .map(|(index, url)| fetch_view(&client, index, url, dir))
+        .buffered(FETCH_CONCURRENCY)
+        .try_collect::<Vec<PathBuf>>();
+    let fetched = match cancel::or_cancelled(cancel, fetched).await {
+        Ok(fetched) => fetched,
+        Err(error) => {
+            if cancel.is_cancelled() {
+                fs::remove_dir_all(dir).ok();
+            }
+            return Err(error);
+        },
+    };
+    eprintln!("Fetched {} views into {}", fetched.len(), dir.display());
+    Ok(())
+}
+
+async fn fetch_view(
+    client: &Client,
+    index: usize,
+    url: &str,
+    dir: &Path,
+) -> Result<PathBuf> {
+    let parsed =
+        reqwest::Url::parse(url).wrap_err_with(|| format!("Invalid URL '{}'", url))?;
+    // Signed URLs carry their credentials in the query, so it is left out of messages.
+    let mut shown = parsed.clone();
+    shown.set_query(None);
+    let mut attempt = 1;
+    let bytes = loop {
+        let result = async {
+            let response = client
+                .get(parsed.clone())
+                .send_traced()
+                .await?
+                .error_for_status()?;
+            http_trace::usage::read_body(response).await
+        }
+        .await;
+        match result {
+            Ok(bytes) => break bytes,
+            Err(error) if attempt < FETCH_ATTEMPTS && is_transient(&error) => {
+                eprintln!(
+                    "Fetching {} failed (attempt {}/{}), retrying: {}",
+                    shown,
+                    attempt,
+                    FETCH_ATTEMPTS,
+                    error.without_url()
+                );
+                sleep(Duration::from_secs(1 << attempt)).await;
+                attempt += 1;
+            },
+            Err(error) => {
+                return Err(error.without_url())
+                    .wrap_err_with(|| format!("Failed to fetch {}", shown))
+            },
+        }
+    };
+
+    // Object stores often serve images with a generic content type or an unrelated
+    // extension, so the format is told by the content itself.
+    let format = image::guess_format(&bytes)
+        .map_err(|_| eyre!("{} is not a supported image", shown))?;
+    image::load_from_memory_with_format(&bytes, format)
+        .wrap_err_with(|| format!("{} is not a valid image", shown))?;
+    let stem = parsed
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .and_then(|name| Path::new(name).file_stem())
+        .and_then(|stem| stem.to_str())
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or("view");
+    let extension = format.extensions_str().first().copied().unwrap_or("img");
+    let path = dir.join(format!("{:03}-{}.{}", index, stem, extension));
+    http_trace::usage::write(&path, &bytes)
+        .wrap_err_with(|| format!("Failed to save {}", path.display()))?;
+    Ok(path)
+}
+
+/// Whether a failed fetch may succeed when retried, unlike a missing or forbidden
+/// object.
+fn is_transient(error: &reqwest::Error) -> bool {
+    error.status().is_none_or(|status| {
+        !status.is_client_error()
+            || status == StatusCode::REQUEST_TIMEOUT
+            || status == StatusCode::TOO_MANY_REQUESTS
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Cursor, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves a small PNG at `/ready.png`, and hangs on every other path.
+    fn serve() -> String {
+        let mut png = Vec::new();
+        image::RgbImage::new(4, 4)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let png = png.clone();
+                thread::spawn(move || {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    if !request_line.contains(" /ready.png ") {
+                        thread::sleep(Duration::from_secs(30));
+                        return;
+                    }
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        png.len()
+                    )
+                    .unwrap();
+                    stream.write_all(&png).unwrap();
+                });
+            }
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn deletes_the_views_when_cancelled() {
+        let address = serve();
+        let urls = [
+            format!("http://{}/ready.png", address),
+            format!("http://{}/pending.png", address),
+        ];
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("views");
+        let cancel = CancellationToken::new();
+
+        // Cancelled once the first view is saved, while the second is still awaited.
+        let fetch = fetch_views(&urls, &dir, &cancel);
+        let cancel_when_saved = async {
+            while !dir.join("000-ready.png").exists() {
+                sleep(Duration::from_millis(10)).await;
+            }
+            cancel.cancel();
+        };
+        let (fetched, _) = tokio::join!(fetch, cancel_when_saved);
+
+        assert_eq!(fetched.unwrap_err().to_string(), cancel::CANCELLED);
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,301 @@
+//! The quality report, relating statistics of the uploaded views to the model
+//! reconstructed from them, to tell bad views from a bad reconstruction.
+//!
+//! The report is a single HTML file with its thumbnails inlined, so it opens offline,
+//! next to a JSON file of the same numbers for scripted analysis.
+
+use crate::ply::{self, sigmoid, ModelStats};
+use crate::reconstruct::list_views;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use color_eyre::eyre::{Result, WrapErr};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// Longer side of the thumbnails shown in the report.
+const THUMBNAIL_SIZE: u32 = 192;
+
+/// Longer side of the views when scoring their sharpness, so that scores compare
+/// across resolutions.
+const SHARPNESS_SIZE: u32 = 512;
+
+/// Side of the grayscale thumbnails compared for the similarity of views.
+const SIMILARITY_SIZE: u32 = 32;
+
+/// Luminance below or above which a pixel counts as clipped.
+const CLIP_LEVELS: (f64, f64) = (2.0 / 255.0, 253.0 / 255.0);
+
+/// Number of bins of the opacity histogram.
+const OPACITY_BINS: usize = 10;
+
+/// The numbers of the report, as written to its JSON file.
+#[derive(Debug, Serialize)]
+pub struct QualityReport {
+    pub views: Vec<ViewQuality>,
+    /// Normalized cross-correlation of every pair of views, from -1 to 1.
+    pub similarity: Vec<Vec<f64>>,
+    pub model: ModelStats,
+    /// Counts of gaussians by activated opacity, in equal bins from 0 to 1.
+    pub opacity_histogram: Option<Vec<usize>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ViewQuality {
+    pub file: String,
+    pub width: u32,
+    pub height: u32,
+    /// Variance of the Laplacian of the luminance; blurry views score low.
+    pub sharpness: f64,
+    /// Mean luminance, from 0 to 1.
+    pub exposure: f64,
+    /// Fraction of pixels crushed to black or blown to white.
+    pub clipped: f64,
+    #[serde(skip)]
+    thumbnail: String,
+}
+
+/// Writes the quality report of the views in `views_dir` and the model at `model`
+/// to `path`, and its numbers to `path` with a `.json` extension.
+pub fn write_report(
+    path: &Path,
+    views_dir: &Path,
+    model: &Path,
+    stats: &ModelStats,
+) -> Result<()> {
+    eprintln!("Writing the quality report to {}...", path.display());
+    let mut views = Vec::new();
+    let mut samples = Vec::new();
+    for view in list_views(views_dir)? {
+        let image = image::open(&view)
+            .wrap_err_with(|| format!("Failed to decode {}", view.display()))?;
+        let file = view.file_name().unwrap().to_string_lossy().into_owned();
+        views.push(score_view(file, &image)?);
+        samples.push(
+            image
+                .resize_exact(SIMILARITY_SIZE, SIMILARITY_SIZE, FilterType::Triangle)
+                .to_luma8(),
+        );
+    }
+    let similarity = samples
+        .iter()
+        .map(|a| samples.iter().map(|b| correlation(a, b)).collect())
+        .collect();
+    let report = QualityReport {
+        views,
+        similarity,
+        model: stats.clone(),
+        opacity_histogram: opacity_histogram(model)?,
+    };
+
+    let json_path = path.with_extension("json");
+    fs::write(&json_path, serde_json::to_string_pretty(&report)?)
+        .wrap_err_with(|| format!("Failed to write {}", json_path.display()))?;
+    fs::write(path, render_html(&report))
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+    eprintln!(
+        "Quality report written to {} and {}",
+        path.display(),
+        json_path.display()
+    );
+    Ok(())
+}
+
+fn score_view(
+    file: String,
+    image: &DynamicImage,
+) -> Result<ViewQuality> {
+    let luma = image.to_luma8();
+    let pixels = luma.as_raw();
+    let exposure = pixels.iter().map(|&value| value as f64).sum::<f64>()
+        / (pixels.len().max(1) as f64 * 255.0);
+    let clipped = pixels
+        .iter()
+        .map(|&value| value as f64 / 255.0)
+        .filter(|&value| value < CLIP_LEVELS.0 || value > CLIP_LEVELS.1)
+        .count() as f64
+        / pixels.len().max(1) as f64;
+
+    Ok(ViewQuality {
+        file,
+        width: image.width(),
+        height: image.height(),
+        sharpness: laplacian_variance(
+            &image
+                .resize(SHARPNESS_SIZE, SHARPNESS_SIZE, FilterType::Triangle)
+                .to_luma8(),
+        ),
+        exposure,
+        clipped,
+        thumbnail: thumbnail(image)?,
+    })
+}
+
+/// A thumbnail of `image` as base64 JPEG, to be inlined in a report.
+pub fn thumbnail(image: &DynamicImage) -> Result<String> {
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, 80).encode_image(&thumbnail)?;
+    Ok(BASE64_STANDARD.encode(jpeg))
+}
+
+/// The variance of the 4-neighbor Laplacian over the interior of `image`.
+fn laplacian_variance(image: &GrayImage) -> f64 {
+    let (width, height) = image.dimensions();
+    let at = |x: u32, y: u32| image.get_pixel(x, y).0[0] as f64;
+    let mut responses = Vec::new();
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width.saturating_sub(1) {
+            responses.push(
+                at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1)
+                    - 4.0 * at(x, y),
+            );
+        }
+    }
+    variance(&responses)
+}
+
+fn variance(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / values.len() as f64
+}
+
+/// The normalized cross-correlation of two images of the same size.
+fn correlation(
+    a: &GrayImage,
+    b: &GrayImage,
+) -> f64 {
+    let mean = |image: &GrayImage| {
+        image
+            .as_raw()
+            .iter()
+            .map(|&value| value as f64)
+            .sum::<f64>()
+            / image.as_raw().len() as f64
+    };
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let (mut product, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+    for (&value_a, &value_b) in a.as_raw().iter().zip(b.as_raw()) {
+        let (da, db) = (value_a as f64 - mean_a, value_b as f64 - mean_b);
+        product += da * db;
+        norm_a += da * da;
+        norm_b += db * db;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        // A flat image correlates with nothing but an identical one.
+        return if a == b { 1.0 } else { 0.0 };
+    }
+    product / (norm_a * norm_b).sqrt()
+}
+
+/// Counts the gaussians of the model by activated opacity, if it has opacities.
+fn opacity_histogram(model: &Path) -> Result<Option<Vec<usize>>> {
+    let (_, mut vertices) = ply::open(model)?;
+    let layout = vertices.layout().clone();
+    let Some(opacity) = layout.index_of("opacity") else {
+        return Ok(None);
+    };
+    let mut bins = vec![0; OPACITY_BINS];
+    while let Some(record) = vertices.next_record()? {
+        let value = sigmoid(layout.get(record, opacity));
+        let bin = (value * OPACITY_BINS as f64) as usize;
+        bins[bin.min(OPACITY_BINS - 1)] += 1;
+    }
+    Ok(Some(bins))
+}
+
+fn render_html(report: &QualityReport) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Quality report</title>\n<style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; margin-bottom: 2em; }\n\
+         th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: right; }\n\
+         .bar { background: #4a7ab8; height: 1em; }\n\
+         </style>\n</head>\n<body>\n<h1>Quality report</h1>\n",
+    );
+
+    html.push_str(
+        "<h2>Views</h2>\n<table>\n<tr><th>View</th><th>File</th><th>Size</th>\
+         <th>Sharpness</th><th>Exposure</th><th>Clipped</th></tr>\n",
+    );
+    for view in &report.views {
+        let _ = writeln!(
+            html,
+            "<tr><td><img src=\"data:image/jpeg;base64,{}\"></td><td>{}</td>\
+             <td>{}x{}</td><td>{:.1}</td><td>{:.2}</td><td>{:.1}%</td></tr>",
+            view.thumbnail,
+            escape(&view.file),
+            view.width,
+            view.height,
+            view.sharpness,
+            view.exposure,
+            view.clipped * 100.0
+        );
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Similarity of views</h2>\n<table>\n<tr><th></th>");
+    for view in &report.views {
+        let _ = write!(html, "<th>{}</th>", escape(&view.file));
+    }
+    html.push_str("</tr>\n");
+    for (view, row) in report.views.iter().zip(&report.similarity) {
+        let _ = write!(html, "<tr><th>{}</th>", escape(&view.file));
+        for value in row {
+            // Near-duplicates stand out in red.
+            let lightness = 100.0 - 50.0 * value.clamp(0.0, 1.0);
+            let _ = write!(
+                html,
+                "<td style=\"background: hsl(0, 70%, {:.0}%)\">{:.2}</td>",
+                lightness, value
+            );
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+
+    let _ = writeln!(
+        html,
+        "<h2>Model</h2>\n<pre>{}</pre>",
+        escape(&report.model.to_string())
+    );
+    if let Some(bins) = &report.opacity_histogram {
+        html.push_str(
+            "<h3>Opacity</h3>\n<table>\n<tr><th>Opacity</th><th>Gaussians</th><th></th></tr>\n",
+        );
+        let most = bins.iter().copied().max().unwrap_or(0).max(1);
+        for (index, count) in bins.iter().enumerate() {
+            let _ = writeln!(
+                html,
+                "<tr><td>{:.1}-{:.1}</td><td>{}</td>\
+                 <td style=\"width: 300px; text-align: left\">\
+                 <div class=\"bar\" style=\"width: {:.0}%\"></div></td></tr>",
+                index as f64 / OPACITY_BINS as f64,
+                (index + 1) as f64 / OPACITY_BINS as f64,
+                count,
+                *count as f64 * 100.0 / most as f64
+            );
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,65 @@
+//! Revealing the output in the system file manager.
+
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::Command;
+
+/// Environment variables set by common CI services.
+const CI_VARS: [&str; 5] = [
+    "CI",
+    "GITHUB_ACTIONS",
+    "GITLAB_CI",
+    "BUILDKITE",
+    "JENKINS_URL",
+];
+
+/// Whether the tool runs attended, with a terminal and outside of CI.
+pub fn is_interactive() -> bool {
+    std::io::stderr().is_terminal()
+        && !CI_VARS.iter().any(|name| std::env::var_os(name).is_some())
+}
+
+/// Shows `path` in the platform's file manager, warning on failure.
+pub fn reveal(path: &Path) {
+    if !is_interactive() {
+        eprintln!(
+            "Not revealing {} in a non-interactive session.",
+            path.display()
+        );
+        return;
+    }
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("open");
+        command.arg("-R").arg(&path);
+        command
+    } else if cfg!(windows) {
+        let mut select = std::ffi::OsString::from("/select,");
+        select.push(&path);
+        let mut command = Command::new("explorer");
+        command.arg(select);
+        command
+    } else {
+        let mut command = Command::new("xdg-open");
+        command.arg(path.parent().unwrap_or(Path::new(".")));
+        command
+    };
+
+    // explorer exits with 1 even when it succeeds, so only a failed launch counts.
+    let result = command.status();
+    match result {
+        Ok(status) if status.success() || cfg!(windows) => {},
+        Ok(status) => eprintln!(
+            "Warning: failed to reveal {} ({:?} exited with {})",
+            path.display(),
+            command.get_program(),
+            status
+        ),
+        Err(error) => eprintln!(
+            "Warning: failed to reveal {} ({:?}: {})",
+            path.display(),
+            command.get_program(),
+            error
+        ),
+    }
+}
@@ -0,0 +1,770 @@
+//! The `serve` subcommand, exposing the pipeline as a REST API of jobs.
+//!
+//! Every job runs the pipeline as a child process in its own directory under the
+//! jobs directory, where its metadata is kept in `job.json` so that jobs survive a
+//! restart of the server. Jobs cancelled with `DELETE /jobs/{id}` stop their
+//! pipeline, which cleans up after itself, without stopping the server.
+
+use crate::limits::RateLimitArgs;
+use crate::model::{self, ModelFormat};
+use crate::summary::SUMMARY_PATH;
+use clap::Args;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use futures::TryStreamExt;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::process::Command;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Semaphore;
+use tokio_util::codec::{BytesCodec, FramedRead};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Environment variable holding the bearer token required by the API, if any.
+pub const TOKEN_VAR: &str = "TEXT_TO_3DGS_TOKEN";
+
+/// Number of events buffered for a subscriber that reads them slower than they come.
+/// Events past it are dropped for that subscriber only.
+const SUBSCRIBER_BUFFER: usize = 256;
+
+/// Metadata of a job, in its directory.
+const JOB_FILE: &str = "job.json";
+
+/// Output of the pipeline run of a job, in its directory.
+const LOG_FILE: &str = "pipeline.log";
+
+/// Where the pipeline saves the model, relative to the job directory, unless the
+/// reconstruction server returns it in another format.
+const MODEL_FILE: &str = "output.ply";
+
+/// Options of the pipeline that jobs may set. Options naming files are left out, as
+/// they would reach outside the job directory, and so are those choosing where the
+/// views are sent, which would send the server token along.
+const JOB_OPTIONS: [&str; 18] = [
+    "require-3dgs",
+    "normalize-model",
+    "convert",
+    "prune-opacity",
+    "prune-scale",
+    "max-gaussians",
+    "backend",
+    "heartbeat",
+    "async-jobs",
+    "http2-prior-knowledge",
+    "field-name",
+    "upload-mode",
+    "upload-batch-size",
+    "upload-concurrency",
+    "max-upload-size",
+    "auto-downscale",
+    "request-style",
+    "allow-duplicate",
+];
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// Where the jobs' directories and metadata are kept.
+    #[arg(long, value_name = "DIR", default_value = "jobs")]
+    jobs_dir: PathBuf,
+
+    /// Number of jobs running at once.
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    workers: usize,
+
+    /// Number of jobs waiting for a worker beyond which new jobs are refused.
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    max_queued: usize,
+
+    #[command(flatten)]
+    rate_limits: RateLimitArgs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Job {
+    id: String,
+    prompt: String,
+    options: Vec<String>,
+    status: JobStatus,
+    /// The pipeline step in progress, e.g. `Step 2: Running view-to-3dgs (peropero)`.
+    stage: Option<String>,
+    /// The latest line of the pipeline's output.
+    message: Option<String>,
+    error: Option<String>,
+    /// Submission time, in seconds since the Unix epoch.
+    created: u64,
+}
+
+/// A step of a job's progress, streamed to subscribers as server-sent events.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Event {
+    Status {
+        status: JobStatus,
+    },
+    /// A pipeline step started, e.g. `Step 2: Running view-to-3dgs (peropero)`.
+    Stage {
+        stage: String,
+    },
+    /// A line of the pipeline's output, e.g. a polling or upload progress report.
+    Log {
+        message: String,
+    },
+    /// Events missed by a subscriber too slow to keep up.
+    Dropped {
+        count: u64,
+    },
+    /// The summary of the run, with the commands to run next, as in its
+    /// `summary.json`.
+    Summary {
+        summary: serde_json::Value,
+    },
+    Done,
+    Error {
+        error: String,
+    },
+    Cancelled,
+}
+
+impl Event {
+    fn is_terminal(&self) -> bool {
+        matches!(self, Event::Done | Event::Error { .. } | Event::Cancelled)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Status { .. } => "status",
+            Event::Stage { .. } => "stage",
+            Event::Log { .. } => "log",
+            Event::Dropped { .. } => "dropped",
+            Event::Summary { .. } => "summary",
+            Event::Done => "done",
+            Event::Error { .. } => "error",
+            Event::Cancelled => "cancelled",
+        }
+    }
+
+    fn to_sse(&self) -> Bytes {
+        let data = serde_json::to_string(self).unwrap_or_default();
+        Bytes::from(format!("event: {}\ndata: {}\n\n", self.name(), data))
+    }
+}
+
+/// The events of a job so far, replayed to late subscribers, and the channel
+/// forwarding new ones.
+struct EventLog {
+    history: Vec<Event>,
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventLog {
+    fn new() -> Self {
+        EventLog {
+            history: Vec::new(),
+            sender: broadcast::channel(SUBSCRIBER_BUFFER).0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JobRequest {
+    prompt: String,
+    #[serde(default)]
+    options: Vec<String>,
+}
+
+struct Server {
+    jobs_dir: PathBuf,
+    jobs: Mutex<HashMap<String, Job>>,
+    events: Mutex<HashMap<String, EventLog>>,
+    /// Tokens cancelling the unfinished jobs.
+    cancels: Mutex<HashMap<String, CancellationToken>>,
+    workers: Semaphore,
+    /// Number of jobs running at once plus the number allowed to wait.
+    capacity: usize,
+    token: Option<String>,
+    /// Handed to every job, so that they share the limits of the server.
+    rate_limits: RateLimitArgs,
+}
+
+type Body = BoxBody<Bytes, std::io::Error>;
+
+/// Serves the API until interrupted.
+pub async fn run(args: &ServeArgs) -> Result<()> {
+    fs::create_dir_all(&args.jobs_dir)
+        .wrap_err_with(|| format!("Failed to create {}", args.jobs_dir.display()))?;
+    let workers = args.workers.max(1);
+    let server = Arc::new(Server {
+        jobs_dir: args.jobs_dir.clone(),
+        jobs: Mutex::new(HashMap::new()),
+        events: Mutex::new(HashMap::new()),
+        cancels: Mutex::new(HashMap::new()),
+        workers: Semaphore::new(workers),
+        capacity: workers + args.max_queued,
+        token: std::env::var(TOKEN_VAR)
+            .ok()
+            .filter(|token| !token.is_empty()),
+        rate_limits: args.rate_limits.clone(),
+    });
+
+    // Jobs cut short by a restart are run again from the start.
+    for mut job in load_jobs(&args.jobs_dir) {
+        let pending = matches!(job.status, JobStatus::Queued | JobStatus::Running);
+        if pending {
+            job.status = JobStatus::Queued;
+            job.stage = None;
+        }
+        let id = job.id.clone();
+        server.jobs.lock().unwrap().insert(id.clone(), job);
+        if pending {
+            eprintln!("Resuming job {}", id);
+            tokio::spawn(run_job(server.clone(), id));
+        }
+    }
+
+    let listener = TcpListener::bind(args.listen)
+        .await
+        .wrap_err_with(|| format!("Failed to listen on {}", args.listen))?;
+    eprintln!(
+        "Serving the pipeline on http://{}{}",
+        args.listen,
+        if server.token.is_some() {
+            ", requiring the bearer token"
+        } else {
+            ""
+        }
+    );
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        let server = server.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| handle(server.clone(), request));
+            if let Err(error) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                eprintln!("Warning: connection error: {}", error);
+            }
+        });
+    }
+    eprintln!("Stopped serving, unfinished jobs resume on the next start");
+    Ok(())
+}
+
+fn load_jobs(jobs_dir: &Path) -> Vec<Job> {
+    let Ok(entries) = fs::read_dir(jobs_dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| fs::read_to_string(entry.path().join(JOB_FILE)).ok())
+        .filter_map(|json| serde_json::from_str(&json).ok())
+        .collect()
+}
+
+async fn handle(
+    server: Arc<Server>,
+    request: Request<Incoming>,
+) -> Result<Response<Body>, Infallible> {
+    if let Some(token) = &server.token {
+        let expected = format!("Bearer {}", token);
+        let authorized = request
+            .headers()
+            .get(AUTHORIZATION)
+            .is_some_and(|value| value.as_bytes() == expected.as_bytes());
+        if !authorized {
+            return Ok(error(
+                StatusCode::UNAUTHORIZED,
+                "Missing or invalid bearer token",
+            ));
+        }
+    }
+
+    let path = request.uri().path().trim_matches('/').to_string();
+    let segments: Vec<&str> = path.split('/').collect();
+    let response = match (request.method(), segments.as_slice()) {
+        (&Method::POST, ["jobs"]) => submit(&server, request).await,
+        (&Method::GET, ["jobs", id]) => match server.jobs.lock().unwrap().get(*id) {
+            Some(job) => json(StatusCode::OK, job),
+            None => error(StatusCode::NOT_FOUND, "No such job"),
+        },
+        (&Method::DELETE, ["jobs", id]) => cancel(&server, id),
+        (&Method::GET, ["jobs", id, "model"]) => model(&server, id).await,
+        (&Method::GET, ["jobs", id, "events"]) => events(&server, id),
+        _ => error(StatusCode::NOT_FOUND, "No such endpoint"),
+    };
+    Ok(response)
+}
+
+async fn submit(
+    server: &Arc<Server>,
+    request: Request<Incoming>,
+) -> Response<Body> {
+    let body = match request.into_body().collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(_) => return error(StatusCode::BAD_REQUEST, "Failed to read the request"),
+    };
+    let request: JobRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(parse_error) => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid job request: {}", parse_error),
+            )
+        },
+    };
+    let prompt = request.prompt.trim().to_string();
+    if prompt.is_empty() {
+        return error(StatusCode::BAD_REQUEST, "The prompt is empty");
+    }
+    if let Err(option_error) = check_options(&request.options) {
+        return error(StatusCode::BAD_REQUEST, &option_error.to_string());
+    }
+
+    let job = Job {
+        id: Uuid::new_v4().simple().to_string(),
+        prompt,
+        options: request.options,
+        status: JobStatus::Queued,
+        stage: None,
+        message: None,
+        error: None,
+        created: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0),
+    };
+    {
+        let mut jobs = server.jobs.lock().unwrap();
+        let unfinished = jobs
+            .values()
+            .filter(|job| matches!(job.status, JobStatus::Queued | JobStatus::Running))
+            .count();
+        if unfinished >= server.capacity {
+            return error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Too many jobs are waiting, try again later",
+            );
+        }
+        jobs.insert(job.id.clone(), job.clone());
+    }
+    if let Err(save_error) = save_job(&server.jobs_dir, &job) {
+        server.jobs.lock().unwrap().remove(&job.id);
+        return error(StatusCode::INTERNAL_SERVER_ERROR, &save_error.to_string());
+    }
+    eprintln!("Job {} submitted: {}", job.id, job.prompt);
+    tokio::spawn(run_job(server.clone(), job.id.clone()));
+    json(StatusCode::ACCEPTED, &serde_json::json!({ "id": job.id }))
+}
+
+/// Accepts only the options in [`JOB_OPTIONS`], as `--name` or `--name=value`.
+fn check_options(options: &[String]) -> Result<()> {
+    for option in options.iter().filter(|option| option.starts_with('-')) {
+        let name = option.trim_start_matches('-');
+        let name = name.split_once('=').map_or(name, |(name, _)| name);
+        if !option.starts_with("--") || !JOB_OPTIONS.contains(&name) {
+            return Err(eyre!("The option '{}' is not allowed in jobs", option));
+        }
+    }
+    Ok(())
+}
+
+async fn model(
+    server: &Server,
+    id: &str,
+) -> Response<Body> {
+    let status = server.jobs.lock().unwrap().get(id).map(|job| job.status);
+    match status {
+        None => return error(StatusCode::NOT_FOUND, "No such job"),
+        Some(JobStatus::Done) => {},
+        Some(_) => return error(StatusCode::CONFLICT, "The job has no model yet"),
+    }
+    let Some(path) = model::find_saved(&server.jobs_dir.join(id).join(MODEL_FILE)) else {
+        return error(StatusCode::NOT_FOUND, "The model of the job is gone");
+    };
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(_) => return error(StatusCode::NOT_FOUND, "The model of the job is gone"),
+    };
+    let frames = FramedRead::new(file, BytesCodec::new())
+        .map_ok(|bytes| Frame::data(bytes.freeze()));
+    Response::builder()
+        .header(CONTENT_TYPE, ModelFormat::from_path(&path).media_type())
+        .body(BodyExt::boxed(StreamBody::new(frames)))
+        .unwrap()
+}
+
+/// Cancels an unfinished job, which ends once its pipeline has cleaned up.
+fn cancel(
+    server: &Server,
+    id: &str,
+) -> Response<Body> {
+    let Some(job) = server.jobs.lock().unwrap().get(id).cloned() else {
+        return error(StatusCode::NOT_FOUND, "No such job");
+    };
+    match server.cancels.lock().unwrap().get(id) {
+        Some(token) => token.cancel(),
+        None if job.status == JobStatus::Cancelled => {},
+        None => return error(StatusCode::CONFLICT, "The job has already finished"),
+    }
+    eprintln!("Job {} cancelled", id);
+    json(StatusCode::ACCEPTED, &job)
+}
+
+/// Streams the job's events, starting with those already emitted, until it ends.
+fn events(
+    server: &Server,
+    id: &str,
+) -> Response<Body> {
+    let Some(job) = server.jobs.lock().unwrap().get(id).cloned() else {
+        return error(StatusCode::NOT_FOUND, "No such job");
+    };
+    let (mut history, receiver) = subscribe(server, id);
+    // Jobs that ended before a restart have no events left but their outcome.
+    if history.is_empty() {
+        match job.status {
+            JobStatus::Done => history.push(Event::Done),
+            JobStatus::Failed => history.push(Event::Error {
+                error: job.error.unwrap_or_default(),
+            }),
+            status => history.push(Event::Status { status }),
+        }
+    }
+
+    // The stream ends right after the terminal event, without waiting for another.
+    let state = (history.into_iter(), receiver, false);
+    let frames =
+        futures::stream::unfold(state, |(mut history, mut receiver, ended)| async move {
+            if ended {
+                return None;
+            }
+            let event = match history.next() {
+                Some(event) => event,
+                None => match receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(count)) => Event::Dropped { count },
+                    Err(RecvError::Closed) => return None,
+                },
+            };
+            let ended = event.is_terminal();
+            Some((Ok(Frame::data(event.to_sse())), (history, receiver, ended)))
+        });
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(BodyExt::boxed(StreamBody::new(frames)))
+        .unwrap()
+}
+
+/// Returns the job's events so far, and a receiver of those to come.
+fn subscribe(
+    server: &Server,
+    id: &str,
+) -> (Vec<Event>, broadcast::Receiver<Event>) {
+    let mut events = server.events.lock().unwrap();
+    let log = events.entry(id.to_string()).or_insert_with(EventLog::new);
+    (log.history.clone(), log.sender.subscribe())
+}
+
+/// Records an event of the job and forwards it to its subscribers, never waiting on
+/// them.
+fn emit(
+    server: &Server,
+    id: &str,
+    event: Event,
+) {
+    let mut events = server.events.lock().unwrap();
+    let log = events.entry(id.to_string()).or_insert_with(EventLog::new);
+    log.history.push(event.clone());
+    let _ = log.sender.send(event);
+}
+
+/// Runs the pipeline for the job once a worker is free, tracking its progress.
+async fn run_job(
+    server: Arc<Server>,
+    id: String,
+) {
+    let cancel = CancellationToken::new();
+    server
+        .cancels
+        .lock()
+        .unwrap()
+        .insert(id.clone(), cancel.clone());
+    // Jobs cancelled while queued never take a worker.
+    let permit = tokio::select! {
+        permit = server.workers.acquire() => Some(permit.unwrap()),
+        _ = cancel.cancelled() => None,
+    };
+    let job = permit
+        .as_ref()
+        .and_then(|_| update_job(&server, &id, |job| job.status = JobStatus::Running));
+    let result = match job {
+        Some(job) => {
+            emit(
+                &server,
+                &id,
+                Event::Status {
+                    status: JobStatus::Running,
+                },
+            );
+            run_pipeline(&server, &job, &cancel).await
+        },
+        None if cancel.is_cancelled() => Ok(()),
+        None => return,
+    };
+    server.cancels.lock().unwrap().remove(&id);
+    let summary = std::fs::read_to_string(server.jobs_dir.join(&id).join(SUMMARY_PATH))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok());
+    if let Some(summary) = summary {
+        emit(&server, &id, Event::Summary { summary });
+    }
+    let model = model::find_saved(&server.jobs_dir.join(&id).join(MODEL_FILE));
+    let job = update_job(&server, &id, |job| match result {
+        _ if cancel.is_cancelled() => job.status = JobStatus::Cancelled,
+        Ok(()) if model.is_some() => job.status = JobStatus::Done,
+        Ok(()) => {
+            job.status = JobStatus::Failed;
+            job.error = Some("The pipeline produced no model".to_string());
+        },
+        Err(run_error) => {
+            job.status = JobStatus::Failed;
+            job.error = Some(run_error.to_string());
+        },
+    });
+    if let Some(job) = job {
+        let event = match job.error {
+            _ if job.status == JobStatus::Cancelled => Event::Cancelled,
+            Some(error) => Event::Error { error },
+            None => Event::Done,
+        };
+        emit(&server, &id, event);
+    }
+    eprintln!("Job {} finished", id);
+}
+
+async fn run_pipeline(
+    server: &Server,
+    job: &Job,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let job_dir = server.jobs_dir.join(&job.id);
+    let executable = std::env::current_exe()
+        .wrap_err("Failed to locate the text-to-3dgs executable")?;
+    let mut child = Command::new(executable)
+        .arg(&job.prompt)
+        .arg("--no-view")
+        .arg("--output")
+        .arg(MODEL_FILE)
+        .args(&job.options)
+        .current_dir(&job_dir)
+        .env("RUST_BACKTRACE", "0")
+        .env("RUST_LIB_BACKTRACE", "0")
+        .envs(server.rate_limits.envs())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .wrap_err("Failed to start the pipeline")?;
+
+    let mut log = tokio::fs::File::create(job_dir.join(LOG_FILE)).await?;
+    let mut lines = BufReader::new(child.stderr.take().unwrap()).lines();
+    let mut failure = None;
+    let mut in_error = false;
+    let mut stopping = false;
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => line?,
+            // The pipeline is asked to stop like on Ctrl-C, and its output read on
+            // until it has cleaned up and exited.
+            _ = cancel.cancelled(), if !stopping => {
+                stopping = true;
+                #[cfg(unix)]
+                if let Some(pid) = child.id() {
+                    // SAFETY: the child has not been waited on, so the id is still its own.
+                    unsafe {
+                        libc::kill(pid as libc::pid_t, libc::SIGTERM);
+                    }
+                }
+                #[cfg(not(unix))]
+                child.start_kill()?;
+                continue;
+            },
+        };
+        let Some(line) = line else {
+            break;
+        };
+        let line = strip_ansi(&line);
+        log.write_all(line.as_bytes()).await?;
+        log.write_all(b"\n").await?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        // color-eyre reports the error as `Error:`, followed by numbered causes.
+        if line.starts_with("Error:") {
+            in_error = true;
+        } else if in_error && failure.is_none() {
+            failure = line.split_once(": ").map(|(_, cause)| cause.to_string());
+        }
+        let stage = line
+            .strip_prefix("--- ")
+            .and_then(|line| line.strip_suffix(" ---"))
+            .filter(|line| line.starts_with("Step"));
+        update_job(server, &job.id, |job| match stage {
+            Some(stage) => job.stage = Some(stage.to_string()),
+            None => job.message = Some(line.to_string()),
+        });
+        let event = match stage {
+            Some(stage) => Event::Stage {
+                stage: stage.to_string(),
+            },
+            None => Event::Log {
+                message: line.to_string(),
+            },
+        };
+        emit(server, &job.id, event);
+    }
+    let status = child.wait().await?;
+    if !status.success() {
+        return Err(match failure {
+            Some(failure) => eyre!(failure),
+            None => eyre!("The pipeline exited with {}", status),
+        });
+    }
+    Ok(())
+}
+
+/// Applies `change` to the job and persists it, returning the updated job.
+fn update_job(
+    server: &Server,
+    id: &str,
+    change: impl FnOnce(&mut Job),
+) -> Option<Job> {
+    let job = {
+        let mut jobs = server.jobs.lock().unwrap();
+        let job = jobs.get_mut(id)?;
+        change(job);
+        job.clone()
+    };
+    if let Err(save_error) = save_job(&server.jobs_dir, &job) {
+        eprintln!("Warning: failed to save job {}: {}", id, save_error);
+    }
+    Some(job)
+}
+
+fn save_job(
+    jobs_dir: &Path,
+    job: &Job,
+) -> Result<()> {
+    let dir = jobs_dir.join(&job.id);
+    fs::create_dir_all(&dir)
+        .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+    let json = serde_json::to_string_pretty(job)?;
+    fs::write(dir.join(JOB_FILE), json)
+        .wrap_err_with(|| format!("Failed to save the job to {}", dir.display()))
+}
+
+/// Removes the terminal color codes from a line of the pipeline's output.
+fn strip_ansi(line: &str) -> String {
+    let mut plain = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+fn json(
+    status: StatusCode,
+    value: &impl Serialize,
+) -> Response<Body> {
+    let body = serde_json::to_vec(value).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(
+            Full::new(Bytes::from(body))
+                .map_err(|never| match never {})
+                .boxed(),
+        )
+        .unwrap()
+}
+
+fn error(
+    status: StatusCode,
+    message: &str,
+) -> Response<Body> {
+    json(status, &serde_json::json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(options: &[&str]) -> Vec<String> {
+        options.iter().map(|option| option.to_string()).collect()
+    }
+
+    #[test]
+    fn accepts_the_job_options() {
+        check_options(&options(&["--normalize-model", "--convert", "spz"])).unwrap();
+        check_options(&options(&["--upload-mode=chunked"])).unwrap();
+    }
+
+    #[test]
+    fn refuses_options_choosing_where_the_views_go() {
+        for option in [
+            "--server",
+            "--server=http://example.com",
+            "--endpoint-path",
+            "--heartbeat-path",
+            "--capabilities-path",
+        ] {
+            assert!(
+                check_options(&options(&[option, "/"])).is_err(),
+                "{}",
+                option
+            );
+        }
+        assert!(check_options(&options(&["--output", "/etc/passwd"])).is_err());
+        assert!(check_options(&options(&["-o", "model.ply"])).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Writer for Niantic's compressed SPZ gaussian-splat format.
+//!
+//! An SPZ file is a gzip stream holding a 16-byte header followed by the
+//! quantized attributes of all gaussians, one attribute after another:
+//! positions, alphas, colors, scales, rotations, and spherical harmonics.
+//! This writer produces version 2 of the format.
+
+use crate::ply::{self, sigmoid};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub const MAGIC: u32 = 0x5053_474e;
+const VERSION: u32 = 2;
+
+/// Bits after the binary point of the 24-bit fixed-point positions.
+const FRACTIONAL_BITS: u8 = 12;
+
+/// Scale applied to the DC color coefficients before quantization.
+const COLOR_SCALE: f64 = 0.15;
+
+/// Quantization bucket sizes of the degree-1 and higher-degree SH coefficients.
+const SH1_BUCKET: i32 = 1 << (8 - 5);
+const SH_REST_BUCKET: i32 = 1 << (8 - 4);
+
+/// Attributes a model needs to be converted, besides its SH coefficients.
+const REQUIRED_ATTRIBUTES: [&str; 14] = [
+    "x", "y", "z", "f_dc_0", "f_dc_1", "f_dc_2", "opacity", "scale_0", "scale_1",
+    "scale_2", "rot_0", "rot_1", "rot_2", "rot_3",
+];
+
+/// Converts the 3DGS PLY model at `ply_path` into an SPZ file at `spz_path`.
+pub fn write_spz(
+    ply_path: &Path,
+    spz_path: &Path,
+) -> Result<()> {
+    let (header, mut vertices) = ply::open(ply_path)?;
+    let layout = vertices.layout().clone();
+    let missing: Vec<&str> = REQUIRED_ATTRIBUTES
+        .into_iter()
+        .filter(|name| layout.index_of(name).is_none())
+        .collect();
+    if !missing.is_empty() {
+        return Err(eyre!(
+            "Cannot convert {} to SPZ, it lacks the attributes: {}",
+            ply_path.display(),
+            missing.join(", ")
+        ));
+    }
+    let index = |name: &str| layout.index_of(name).unwrap();
+    let position = ["x", "y", "z"].map(index);
+    let color = ["f_dc_0", "f_dc_1", "f_dc_2"].map(index);
+    let opacity = index("opacity");
+    let scale = ["scale_0", "scale_1", "scale_2"].map(index);
+    let rotation = ["rot_0", "rot_1", "rot_2", "rot_3"].map(index);
+
+    let sh_degree = header.sh_degree().unwrap_or(0).min(3);
+    let sh_per_channel = ((sh_degree + 1).pow(2) - 1) as usize;
+    let stored_per_channel = header
+        .vertex()?
+        .properties
+        .iter()
+        .filter(|property| property.name.starts_with("f_rest_"))
+        .count()
+        / 3;
+    let sh = (0..sh_per_channel)
+        .flat_map(|coefficient| {
+            (0..3).map(move |channel| channel * stored_per_channel + coefficient)
+        })
+        .map(|rest| layout.require(&format!("f_rest_{}", rest)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let count = header.vertex()?.count;
+    let capacity = ply::vertex_capacity(&header, fs::metadata(ply_path)?.len())?;
+    let mut positions = Vec::with_capacity(capacity * 9);
+    let mut alphas = Vec::with_capacity(capacity);
+    let mut colors = Vec::with_capacity(capacity * 3);
+    let mut scales = Vec::with_capacity(capacity * 3);
+    let mut rotations = Vec::with_capacity(capacity * 3);
+    let mut harmonics = Vec::with_capacity(capacity * sh.len());
+    let limit = (1 << (23 - FRACTIONAL_BITS)) as f64;
+    while let Some(record) = vertices.next_record()? {
+        for index in position {
+            let value = layout.get(record, index);
+            if value.abs() >= limit {
+                return Err(eyre!(
+                    "Cannot convert {} to SPZ, its positions exceed ±{}. \
+                     Try again with --normalize-model.",
+                    ply_path.display(),
+                    limit
+                ));
+            }
+            let fixed = (value * (1 << FRACTIONAL_BITS) as f64).round() as i32;
+            positions.extend_from_slice(&fixed.to_le_bytes()[..3]);
+        }
+        alphas.push(to_u8(sigmoid(layout.get(record, opacity)) * 255.0));
+        for index in color {
+            let value = layout.get(record, index);
+            colors.push(to_u8(value * (COLOR_SCALE * 255.0) + 0.5 * 255.0));
+        }
+        for index in scale {
+            scales.push(to_u8((layout.get(record, index) + 10.0) * 16.0));
+        }
+        let [w, x, y, z] = rotation.map(|index| layout.get(record, index));
+        let norm = (w * w + x * x + y * y + z * z).sqrt().max(f64::EPSILON);
+        // The real part is implied, so flip the sign to keep it non-negative.
+        let sign = if w < 0.0 { -1.0 } else { 1.0 };
+        for value in [x, y, z] {
+            rotations.push(to_u8(value / norm * sign * 127.5 + 127.5));
+        }
+        for (order, &index) in sh.iter().enumerate() {
+            let bucket = if order < 9 {
+                SH1_BUCKET
+            } else {
+                SH_REST_BUCKET
+            };
+            harmonics.push(quantize_sh(layout.get(record, index), bucket));
+        }
+    }
+
+    let file = File::create(spz_path)
+        .wrap_err_with(|| format!("Failed to create {}", spz_path.display()))?;
+    let mut writer = GzEncoder::new(BufWriter::new(file), Compression::default());
+    writer.write_all(&MAGIC.to_le_bytes())?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(count as u32).to_le_bytes())?;
+    writer.write_all(&[sh_degree as u8, FRACTIONAL_BITS, 0, 0])?;
+    for data in [positions, alphas, colors, scales, rotations, harmonics] {
+        writer.write_all(&data)?;
+    }
+    writer.finish()?.flush()?;
+    Ok(())
+}
+
+fn to_u8(value: f64) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+fn quantize_sh(
+    value: f64,
+    bucket: i32,
+) -> u8 {
+    let quantized = (value * 128.0).round() as i32 + 128;
+    let quantized = (quantized + bucket / 2) / bucket * bucket;
+    quantized.clamp(0, 255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    /// Three gaussians with SH degree 1, and their SPZ as quantized by hand from the
+    /// format description.
+    const PLY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sh1.ply");
+    const SPZ: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sh1.spz");
+
+    fn decompress(path: &Path) -> Vec<u8> {
+        let mut data = Vec::new();
+        GzDecoder::new(File::open(path).unwrap())
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn refuses_a_model_with_more_vertices_than_it_holds() {
+        let dir = tempfile::tempdir().unwrap();
+        let ply = dir.path().join("model.ply");
+        let data = fs::read(PLY).unwrap();
+        let at = data
+            .windows(17)
+            .position(|window| window == b"element vertex 3\n")
+            .unwrap();
+        let bogus = format!("element vertex {}\n", usize::MAX);
+        fs::write(
+            &ply,
+            [&data[..at], bogus.as_bytes(), &data[at + 17..]].concat(),
+        )
+        .unwrap();
+        let error = write_spz(&ply, &dir.path().join("model.spz")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The PLY model ends before all of its vertices"
+        );
+    }
+
+    #[test]
+    fn matches_the_reference_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.spz");
+        write_spz(Path::new(PLY), &path).unwrap();
+        let written = decompress(&path);
+        let expected = decompress(Path::new(SPZ));
+
+        let field = |data: &[u8], at: usize| {
+            u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+        };
+        assert_eq!(field(&written, 0), MAGIC);
+        assert_eq!(field(&written, 4), VERSION);
+        assert_eq!(field(&written, 8), 3, "gaussian count");
+        assert_eq!(
+            written[12..16],
+            [1, FRACTIONAL_BITS, 0, 0],
+            "SH degree and flags"
+        );
+
+        assert_eq!(written[..16], expected[..16], "header");
+
+        let count = 3;
+        let mut start = 16;
+        for (name, size) in [
+            ("positions", 9),
+            ("alphas", 1),
+            ("colors", 3),
+            ("scales", 3),
+            ("rotations", 3),
+            ("harmonics", 9),
+        ] {
+            let end = start + count * size;
+            assert_eq!(written[start..end], expected[start..end], "{}", name);
+            start = end;
+        }
+        assert_eq!(written.len(), expected.len());
+    }
+}
@@ -0,0 +1,502 @@
+//! The summary of a run, shown at its end and saved as `summary.json`: what it
+//! produced, what it skipped and why, where its records are, and what to run next.
+//!
+//! The next commands are worked out from where the run stopped, so that after a
+//! failed reconstruction, for instance, the views it already paid for are reused
+//! rather than generated again.
+
+use crate::brush::{self, Platform, SystemProbe};
+use crate::history::Status;
+use crate::manifest::RunState;
+use crate::model::ModelFormat;
+use crate::output;
+use color_eyre::Report;
+use http_trace::usage;
+use serde::Serialize;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+
+/// Where the summary of the current run is saved.
+pub const SUMMARY_PATH: &str = "summary.json";
+
+/// The options of a run that do not carry over to a run from its views: those
+/// giving the views, and the output, which is given again.
+const UNCARRIED_OPTIONS: [&str; 7] = [
+    "images",
+    "images-url",
+    "images-url-file",
+    "views-tar",
+    "seed-image",
+    "allow-duplicate",
+    "output",
+];
+
+/// A stage of the run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    Views,
+    Reconstruction,
+    PostProcessing,
+    Upload,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        let name = match self {
+            Stage::Views => "views",
+            Stage::Reconstruction => "reconstruction",
+            Stage::PostProcessing => "post-processing",
+            Stage::Upload => "upload",
+        };
+        f.pad(name)
+    }
+}
+
+/// The views the model was reconstructed from.
+#[derive(Debug, Serialize)]
+pub struct ViewsOutput {
+    pub dir: PathBuf,
+    pub count: usize,
+    /// Whether the views are still there, as fetched views are deleted at the end
+    /// of the run without `--keep-intermediates`.
+    pub kept: bool,
+}
+
+/// The model the run produced.
+#[derive(Debug, Serialize)]
+pub struct ModelOutput {
+    /// Where the model is, or `-` when it was written to stdout.
+    pub path: PathBuf,
+    pub format: ModelFormat,
+    pub size: u64,
+    pub gaussians: u64,
+}
+
+/// A step the run was asked for, or would take by default, but did not take.
+#[derive(Debug, Serialize)]
+pub struct Skipped {
+    pub step: String,
+    pub reason: String,
+}
+
+/// A command to run next.
+#[derive(Debug, Serialize)]
+pub struct NextCommand {
+    pub description: String,
+    /// The command line, quoted for POSIX shells.
+    pub command: String,
+}
+
+impl NextCommand {
+    fn new(
+        description: String,
+        program: &str,
+        args: &[String],
+    ) -> Self {
+        let words: Vec<String> = std::iter::once(program)
+            .chain(args.iter().map(String::as_str))
+            .map(quote)
+            .collect();
+        NextCommand {
+            description,
+            command: words.join(" "),
+        }
+    }
+}
+
+/// What the next commands are worked out from, besides where the run stopped.
+pub struct Context {
+    /// Whether text-to-view generates the views, rather than them being given.
+    pub generates_views: bool,
+    /// The options of the run carried over to a run from its views, see
+    /// [`carried_args`].
+    pub carried_args: Vec<String>,
+    /// The output given again to a run from the views, unless a project names it.
+    pub output: Option<PathBuf>,
+    /// The reconstruction server, checked by `doctor`.
+    pub server: Option<String>,
+    /// The state of the reconstruction, telling whether a job can be resumed.
+    pub run_state: PathBuf,
+    /// The brush executable given, to open the model with.
+    pub brush_path: Option<PathBuf>,
+    pub viewer_args: Vec<String>,
+}
+
+/// The summary of a run.
+#[derive(Serialize)]
+pub struct RunSummary {
+    pub outcome: Status,
+    /// The last stage the run started, where it stopped unless it completed.
+    pub stage: Stage,
+    /// Why the run failed or was cancelled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The views, once generated or fetched.
+    pub views: Option<ViewsOutput>,
+    /// The model, once checked.
+    pub model: Option<ModelOutput>,
+    pub skipped: Vec<Skipped>,
+    /// The run manifest, once written.
+    pub manifest: Option<PathBuf>,
+    /// The logs and traces the run wrote.
+    pub logs: Vec<PathBuf>,
+    pub next: Vec<NextCommand>,
+    /// Whether the model is opened in the brush viewer after the summary.
+    #[serde(skip)]
+    pub opens_viewer: bool,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    context: Context,
+    #[serde(skip)]
+    cancel: CancellationToken,
+}
+
+impl RunSummary {
+    /// The summary of a run starting, to be saved at `path`.
+    pub fn new(
+        path: PathBuf,
+        context: Context,
+        cancel: &CancellationToken,
+    ) -> Self {
+        RunSummary {
+            outcome: Status::Running,
+            stage: Stage::Views,
+            error: None,
+            views: None,
+            model: None,
+            skipped: Vec::new(),
+            manifest: None,
+            logs: Vec::new(),
+            next: Vec::new(),
+            opens_viewer: false,
+            path,
+            context,
+            cancel: cancel.clone(),
+        }
+    }
+
+    /// Records that `step` was not taken, because of `reason`.
+    pub fn skip(
+        &mut self,
+        step: impl Into<String>,
+        reason: impl Into<String>,
+    ) {
+        self.skipped.push(Skipped {
+            step: step.into(),
+            reason: reason.into(),
+        });
+    }
+
+    /// Records how the run ended, with `error` unless it completed, then shows the
+    /// summary on stderr and saves it. A summary that cannot be saved only warns,
+    /// as the run itself is over.
+    pub fn finish(
+        mut self,
+        error: Option<&Report>,
+    ) {
+        self.outcome = match error {
+            None => Status::Completed,
+            Some(_) if self.cancel.is_cancelled() => Status::Cancelled,
+            Some(_) => Status::Failed,
+        };
+        self.error = error.map(|error| format!("{:#}", error));
+        self.next = self.next_commands();
+        eprintln!("\n{}", self);
+        let saved = serde_json::to_string_pretty(&self)
+            .map_err(Report::from)
+            .and_then(|json| Ok(fs::write(&self.path, json)?));
+        match saved {
+            Ok(()) => eprintln!("The summary was saved to {}", self.path.display()),
+            Err(error) => eprintln!(
+                "Warning: could not save the run summary to {}: {:#}",
+                self.path.display(),
+                error
+            ),
+        }
+    }
+
+    fn next_commands(&self) -> Vec<NextCommand> {
+        let program = program();
+        let mut next = Vec::new();
+        match (self.outcome, self.stage) {
+            (Status::Completed, _) => {
+                let unopened = self.model.as_ref().filter(|model| {
+                    model.format == ModelFormat::Ply
+                        && !output::is_stdout(&model.path)
+                        && !self.opens_viewer
+                });
+                if let Some(model) = unopened {
+                    next.push(self.open_in_brush(&model.path));
+                }
+                if let Some(dir) = self.manifest.as_deref().and_then(Path::parent) {
+                    let dir = Some(dir).filter(|dir| !dir.as_os_str().is_empty());
+                    next.push(NextCommand::new(
+                        "Check the files of the run against its manifest later on"
+                            .to_string(),
+                        &program,
+                        &["verify".to_string(), display(dir.unwrap_or(Path::new(".")))],
+                    ));
+                }
+            },
+            // A model that was not uploaded is kept, but only a whole run uploads.
+            (_, Stage::Upload) => {},
+            (outcome, Stage::Views) => {
+                if outcome == Status::Failed && self.context.generates_views {
+                    next.push(self.doctor(
+                        "Diagnose the API key and the video decoder text-to-view needs"
+                            .to_string(),
+                    ));
+                }
+                next.push(NextCommand::new(
+                    "Run the same command again".to_string(),
+                    &program,
+                    &original_args(),
+                ));
+            },
+            (outcome, stage) => {
+                match self.reconstruct_from_views() {
+                    Some(command) => next.push(command),
+                    None => next.push(NextCommand::new(
+                        "Run the same command again".to_string(),
+                        &program,
+                        &original_args(),
+                    )),
+                }
+                if let Some(server) = self.context.server.as_ref().filter(|_| {
+                    outcome == Status::Failed && stage == Stage::Reconstruction
+                }) {
+                    next.push(self.doctor(format!(
+                        "Check that the reconstruction server at {} is reachable",
+                        server
+                    )));
+                }
+            },
+        }
+        next
+    }
+
+    /// Reconstructing again from the views of the run, when they are still there,
+    /// resuming the job the run left on the server, if any.
+    fn reconstruct_from_views(&self) -> Option<NextCommand> {
+        let views = self
+            .views
+            .as_ref()
+            .filter(|views| views.kept && views.count > 0 && views.dir.is_dir())?;
+        let mut description = format!(
+            "Reconstruct again from the {} views {}",
+            views.count,
+            if self.context.generates_views {
+                "already generated"
+            } else {
+                "of the run"
+            }
+        );
+        let job = RunState::load(&self.context.run_state).and_then(|state| state.job_url);
+        if let Some(job) = job {
+            description.push_str(&format!(", resuming the server's job at {}", job));
+        }
+        let mut args = vec!["--images".to_string(), display(&views.dir)];
+        if let Some(output) = &self.context.output {
+            args.extend(["--output".to_string(), display(output)]);
+        }
+        args.extend(self.context.carried_args.iter().cloned());
+        Some(NextCommand::new(description, &program(), &args))
+    }
+
+    fn doctor(
+        &self,
+        description: String,
+    ) -> NextCommand {
+        let mut args = vec!["doctor".to_string()];
+        if let Some(server) = &self.context.server {
+            args.extend(["--server".to_string(), server.clone()]);
+        }
+        NextCommand::new(description, &program(), &args)
+    }
+
+    fn open_in_brush(
+        &self,
+        model: &Path,
+    ) -> NextCommand {
+        let discovery = brush::discover_brush(
+            self.context.brush_path.as_deref(),
+            &Platform::CURRENT,
+            &SystemProbe,
+        );
+        let brush = discovery.found.unwrap_or_else(|| PathBuf::from("brush"));
+        let mut args = vec![
+            display(model),
+            "--with-viewer".to_string(),
+            "--sh-degree".to_string(),
+            "0".to_string(),
+        ];
+        args.extend(self.context.viewer_args.iter().cloned());
+        NextCommand::new(
+            "Open the model in the brush viewer".to_string(),
+            &display(&brush),
+            &args,
+        )
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        writeln!(f, "Run summary:")?;
+        match self.outcome {
+            Status::Completed | Status::Running => {
+                writeln!(f, "  Outcome:      {}", self.outcome.as_str())?
+            },
+            outcome => writeln!(
+                f,
+                "  Outcome:      {} during {}",
+                outcome.as_str(),
+                self.stage
+            )?,
+        }
+        if let Some(error) = &self.error {
+            writeln!(f, "  Reason:       {}", error)?;
+        }
+        if let Some(views) = &self.views {
+            write!(
+                f,
+                "  Views:        {} in {}",
+                views.count,
+                views.dir.display()
+            )?;
+            if !views.kept {
+                write!(f, ", deleted at the end of the run")?;
+            }
+            writeln!(f)?;
+        }
+        if let Some(model) = &self.model {
+            let path = if output::is_stdout(&model.path) {
+                "stdout".to_string()
+            } else {
+                model.path.display().to_string()
+            };
+            writeln!(
+                f,
+                "  Model:        {} ({}, {}, {} gaussians)",
+                path,
+                model.format,
+                usage::format_size(model.size),
+                model.gaussians
+            )?;
+        }
+        for (i, skipped) in self.skipped.iter().enumerate() {
+            let label = if i == 0 { "Skipped:" } else { "" };
+            writeln!(f, "  {:<14}{}: {}", label, skipped.step, skipped.reason)?;
+        }
+        if let Some(manifest) = &self.manifest {
+            writeln!(f, "  Manifest:     {}", manifest.display())?;
+        }
+        for (i, log) in self.logs.iter().enumerate() {
+            let label = if i == 0 { "Logs:" } else { "" };
+            writeln!(f, "  {:<14}{}", label, log.display())?;
+        }
+        if !self.next.is_empty() {
+            write!(f, "Next:")?;
+            for command in &self.next {
+                write!(f, "\n  {}:\n    {}", command.description, command.command)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The program the run was started as, for commands run from the same place.
+fn program() -> String {
+    std::env::args_os()
+        .next()
+        .map_or_else(|| "text-to-3dgs".to_string(), lossy)
+}
+
+/// The arguments the run was started with.
+fn original_args() -> Vec<String> {
+    std::env::args_os().skip(1).map(lossy).collect()
+}
+
+/// The arguments of the run that carry over to a run from its views: all but the
+/// words of the prompt and the options in [`UNCARRIED_OPTIONS`]. `command` tells
+/// the options that take a value.
+pub fn carried_args(mut command: clap::Command) -> Vec<String> {
+    command.build();
+    let find = |name: &str| {
+        command
+            .get_arguments()
+            .find(|arg| match name.strip_prefix("--") {
+                Some(long) => arg.get_long() == Some(long),
+                None => name
+                    .chars()
+                    .nth(1)
+                    .is_some_and(|short| arg.get_short() == Some(short)),
+            })
+    };
+    let mut args = original_args().into_iter();
+    let mut carried = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            carried.push(arg);
+            carried.extend(args.by_ref());
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            continue;
+        }
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, _)) => (name, true),
+            // A short option with its value attached, as `-omodel.ply`.
+            None if !arg.starts_with("--") && arg.chars().count() > 2 => {
+                (&arg[..arg.char_indices().nth(2).unwrap().0], true)
+            },
+            None => (arg.as_str(), false),
+        };
+        let Some(option) = find(name) else {
+            carried.push(arg);
+            continue;
+        };
+        let value = (option.get_action().takes_values() && !inline_value)
+            .then(|| args.next())
+            .flatten();
+        if !option
+            .get_long()
+            .is_some_and(|long| UNCARRIED_OPTIONS.contains(&long))
+        {
+            carried.push(arg);
+            carried.extend(value);
+        }
+    }
+    carried
+}
+
+fn lossy(arg: OsString) -> String {
+    arg.to_string_lossy().into_owned()
+}
+
+fn display(path: &Path) -> String {
+    path.display().to_string()
+}
+
+/// `word` quoted for POSIX shells, where it needs to be.
+fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
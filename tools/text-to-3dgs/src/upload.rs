@@ -0,0 +1,354 @@
+//! Upload of the finished model to object storage, for machines without a
+//! persistent disk.
+//!
+//! Each store is supported behind its cargo feature: `s3` for `s3://` URIs and
+//! `gcs` for `gs://` ones. Credentials come from the standard chain of each SDK.
+
+use clap::Args;
+use color_eyre::eyre::{eyre, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The exit code of a run whose model was reconstructed but could not be uploaded.
+pub const UPLOAD_FAILED_EXIT_CODE: i32 = 3;
+
+/// The context of an upload error, naming where the model is kept, so that the run
+/// exits with [`UPLOAD_FAILED_EXIT_CODE`].
+#[derive(Debug)]
+pub struct UploadFailed(pub PathBuf);
+
+impl fmt::Display for UploadFailed {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "Failed to upload the model, which is kept at {}",
+            self.0.display()
+        )
+    }
+}
+
+/// Models larger than this are uploaded in parts of this size, so that they are
+/// never read into memory whole. A multiple of 256 KiB, as GCS requires.
+#[cfg(any(feature = "s3", feature = "gcs"))]
+const PART_SIZE: u64 = 16 << 20;
+
+#[derive(Args, Debug)]
+pub struct UploadArgs {
+    /// Upload the model to object storage, at `s3://BUCKET/KEY` or `gs://BUCKET/KEY`.
+    #[arg(long, value_name = "URI", value_parser = ObjectUri::parse)]
+    pub upload_to: Option<ObjectUri>,
+
+    /// Also print a presigned URL to download the uploaded model, valid for this
+    /// many seconds.
+    #[arg(long, value_name = "SECONDS", requires = "upload_to")]
+    pub presign: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Store {
+    S3,
+    Gcs,
+}
+
+/// The location of an object in a store.
+#[derive(Clone, Debug)]
+pub struct ObjectUri {
+    pub store: Store,
+    pub bucket: String,
+    pub key: String,
+}
+
+impl ObjectUri {
+    /// Parses an object URI, rejecting stores this build does not support, so that a
+    /// run never reconstructs a model it cannot upload.
+    fn parse(uri: &str) -> Result<Self, String> {
+        let (store, rest) = if let Some(rest) = uri.strip_prefix("s3://") {
+            (Store::S3, rest)
+        } else if let Some(rest) = uri.strip_prefix("gs://") {
+            (Store::Gcs, rest)
+        } else {
+            return Err("expected an s3:// or gs:// URI".to_string());
+        };
+        let (bucket, key) = rest
+            .split_once('/')
+            .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+            .ok_or("expected a bucket and an object key, like s3://bucket/model.ply")?;
+        let feature = match store {
+            Store::S3 => (!cfg!(feature = "s3")).then_some("s3"),
+            Store::Gcs => (!cfg!(feature = "gcs")).then_some("gcs"),
+        };
+        if let Some(feature) = feature {
+            return Err(format!(
+                "this build cannot upload there, rebuild it with `--features {}`",
+                feature
+            ));
+        }
+        Ok(ObjectUri {
+            store,
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ObjectUri {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        let scheme = match self.store {
+            Store::S3 => "s3",
+            Store::Gcs => "gs",
+        };
+        write!(f, "{}://{}/{}", scheme, self.bucket, self.key)
+    }
+}
+
+/// Uploads the model at `path` as requested by `args`, printing where it landed.
+pub async fn upload(
+    args: &UploadArgs,
+    path: &Path,
+) -> Result<()> {
+    let Some(uri) = &args.upload_to else {
+        return Ok(());
+    };
+    let size = std::fs::metadata(path)
+        .map_err(|error| eyre!("Failed to read {}: {}", path.display(), error))?
+        .len();
+    eprintln!(
+        "Uploading the model ({:.2} MiB) to {}...",
+        size as f64 / (1024.0 * 1024.0),
+        uri
+    );
+    let presign = args.presign.map(Duration::from_secs);
+    let presigned = upload_to_store(uri, path, size, presign).await?;
+    eprintln!("Uploaded the model to {}", uri);
+    if let Some(url) = presigned {
+        eprintln!("Presigned download URL: {}", url);
+    }
+    Ok(())
+}
+
+/// Uploads to the store of `uri`, returning a presigned URL when asked for one.
+async fn upload_to_store(
+    uri: &ObjectUri,
+    path: &Path,
+    size: u64,
+    presign: Option<Duration>,
+) -> Result<Option<String>> {
+    match uri.store {
+        #[cfg(feature = "s3")]
+        Store::S3 => s3::upload(uri, path, size, presign).await,
+        #[cfg(feature = "gcs")]
+        Store::Gcs => gcs::upload(uri, path, size, presign).await,
+        // URIs of stores not built in are rejected when parsed.
+        #[allow(unreachable_patterns)]
+        _ => {
+            let _ = (path, size, presign);
+            Err(eyre!("This build cannot upload to {}", uri))
+        },
+    }
+}
+
+#[cfg(feature = "s3")]
+mod s3 {
+    use super::{ObjectUri, PART_SIZE};
+    use aws_sdk_s3::error::DisplayErrorContext;
+    use aws_sdk_s3::presigning::PresigningConfig;
+    use aws_sdk_s3::primitives::{ByteStream, Length};
+    use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+    use aws_sdk_s3::Client;
+    use color_eyre::eyre::{eyre, Result};
+    use std::path::Path;
+    use std::time::Duration;
+
+    fn sdk_error(error: impl std::error::Error) -> color_eyre::Report {
+        eyre!("{}", DisplayErrorContext(error))
+    }
+
+    pub async fn upload(
+        uri: &ObjectUri,
+        path: &Path,
+        size: u64,
+        presign: Option<Duration>,
+    ) -> Result<Option<String>> {
+        let config = aws_config::load_from_env().await;
+        let client = Client::new(&config);
+        if size <= PART_SIZE {
+            client
+                .put_object()
+                .bucket(&uri.bucket)
+                .key(&uri.key)
+                .body(ByteStream::from_path(path).await?)
+                .send()
+                .await
+                .map_err(sdk_error)?;
+        } else {
+            let upload_id = client
+                .create_multipart_upload()
+                .bucket(&uri.bucket)
+                .key(&uri.key)
+                .send()
+                .await
+                .map_err(sdk_error)?
+                .upload_id
+                .ok_or_else(|| eyre!("S3 returned no multipart upload id"))?;
+            let uploaded = upload_parts(&client, uri, &upload_id, path, size).await;
+            if uploaded.is_err() {
+                // Parts of an abandoned upload are billed until it is aborted.
+                let _ = client
+                    .abort_multipart_upload()
+                    .bucket(&uri.bucket)
+                    .key(&uri.key)
+                    .upload_id(&upload_id)
+                    .send()
+                    .await;
+            }
+            uploaded?;
+        }
+
+        let Some(expiry) = presign else {
+            return Ok(None);
+        };
+        let request = client
+            .get_object()
+            .bucket(&uri.bucket)
+            .key(&uri.key)
+            .presigned(PresigningConfig::expires_in(expiry)?)
+            .await
+            .map_err(sdk_error)?;
+        Ok(Some(request.uri().to_string()))
+    }
+
+    async fn upload_parts(
+        client: &Client,
+        uri: &ObjectUri,
+        upload_id: &str,
+        path: &Path,
+        size: u64,
+    ) -> Result<()> {
+        let mut parts = Vec::new();
+        for (index, offset) in (0..size).step_by(PART_SIZE as usize).enumerate() {
+            let number = index as i32 + 1;
+            let body = ByteStream::read_from()
+                .path(path)
+                .offset(offset)
+                .length(Length::Exact(PART_SIZE.min(size - offset)))
+                .build()
+                .await?;
+            let part = client
+                .upload_part()
+                .bucket(&uri.bucket)
+                .key(&uri.key)
+                .upload_id(upload_id)
+                .part_number(number)
+                .body(body)
+                .send()
+                .await
+                .map_err(sdk_error)?;
+            eprintln!(
+                "Uploaded {:.0}%",
+                (offset + PART_SIZE).min(size) as f64 * 100.0 / size as f64
+            );
+            parts.push(
+                CompletedPart::builder()
+                    .set_e_tag(part.e_tag)
+                    .part_number(number)
+                    .build(),
+            );
+        }
+        client
+            .complete_multipart_upload()
+            .bucket(&uri.bucket)
+            .key(&uri.key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(sdk_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "gcs")]
+mod gcs {
+    use super::{ObjectUri, PART_SIZE};
+    use color_eyre::eyre::{eyre, Result, WrapErr};
+    use google_cloud_storage::client::{Client, ClientConfig};
+    use google_cloud_storage::http::objects::upload::{
+        Media, UploadObjectRequest, UploadType,
+    };
+    use google_cloud_storage::http::resumable_upload_client::{ChunkSize, UploadStatus};
+    use google_cloud_storage::sign::{SignedURLMethod, SignedURLOptions};
+    use std::fs::File;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::path::Path;
+    use std::time::Duration;
+
+    pub async fn upload(
+        uri: &ObjectUri,
+        path: &Path,
+        size: u64,
+        presign: Option<Duration>,
+    ) -> Result<Option<String>> {
+        let config = ClientConfig::default()
+            .with_auth()
+            .await
+            .wrap_err("Failed to find Google Cloud credentials")?;
+        let client = Client::new(config);
+        let request = UploadObjectRequest {
+            bucket: uri.bucket.clone(),
+            ..Default::default()
+        };
+        let upload_type = UploadType::Simple(Media::new(uri.key.clone()));
+        if size <= PART_SIZE {
+            let model = http_trace::usage::read(path)?;
+            client.upload_object(&request, model, &upload_type).await?;
+        } else {
+            let session = client
+                .prepare_resumable_upload(&request, &upload_type)
+                .await?;
+            let mut file = File::open(path)?;
+            let mut offset = 0;
+            while offset < size {
+                let length = PART_SIZE.min(size - offset);
+                let mut chunk = vec![0; length as usize];
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(&mut chunk)?;
+                let chunk_size = ChunkSize::new(offset, offset + length - 1, Some(size));
+                let status = session.upload_multiple_chunk(chunk, &chunk_size).await?;
+                offset += length;
+                eprintln!("Uploaded {:.0}%", offset as f64 * 100.0 / size as f64);
+                if offset == size && !matches!(status, UploadStatus::Ok(_)) {
+                    return Err(eyre!("GCS did not finalize the upload: {:?}", status));
+                }
+            }
+        }
+
+        let Some(expiry) = presign else {
+            return Ok(None);
+        };
+        let url = client
+            .signed_url(
+                &uri.bucket,
+                &uri.key,
+                None,
+                None,
+                SignedURLOptions {
+                    method: SignedURLMethod::GET,
+                    expires: expiry,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        Ok(Some(url))
+    }
+}
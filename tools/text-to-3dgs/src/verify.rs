@@ -0,0 +1,102 @@
+//! The `verify` subcommand, checking the files of a run against the SHA-256
+//! checksums recorded in its manifests.
+
+use crate::checksum::sha256_files;
+use crate::manifest::{RunManifest, RUN_MANIFEST_PATH};
+use crate::views::ViewsManifest;
+use clap::Args;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Arguments of the `verify` subcommand.
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// The directory of the run, holding its `run.json`.
+    #[arg(default_value = ".")]
+    pub dir: PathBuf,
+}
+
+pub fn run(args: &VerifyArgs) -> Result<()> {
+    let manifest_path = args.dir.join(RUN_MANIFEST_PATH);
+    let json = fs::read_to_string(&manifest_path).wrap_err_with(|| {
+        format!(
+            "Failed to read the run manifest {}",
+            manifest_path.display()
+        )
+    })?;
+    let manifest: RunManifest = serde_json::from_str(&json).wrap_err_with(|| {
+        format!(
+            "Failed to parse the run manifest {}",
+            manifest_path.display()
+        )
+    })?;
+    if manifest.checksums.is_empty() {
+        return Err(eyre!(
+            "{} records no checksums. It was written by a version of text-to-3dgs \
+             that did not record them.",
+            manifest_path.display()
+        ));
+    }
+
+    // The run manifest records the views and the model, and the views manifests next
+    // to the views record the frames as text-to-view wrote them.
+    let mut expected = BTreeSet::new();
+    for (path, digest) in &manifest.checksums {
+        expected.insert((args.dir.join(path), digest.clone()));
+    }
+    let views_dirs: BTreeSet<PathBuf> = expected
+        .iter()
+        .filter_map(|(path, _)| path.parent().map(PathBuf::from))
+        .collect();
+    for dir in views_dirs {
+        let Ok(Some(views)) = ViewsManifest::read(&dir) else {
+            continue;
+        };
+        for frame in views.frames {
+            if let Some(digest) = frame.sha256 {
+                expected.insert((dir.join(&frame.file), digest));
+            }
+        }
+    }
+
+    let expected: Vec<(PathBuf, String)> = expected.into_iter().collect();
+    let paths: Vec<PathBuf> = expected.iter().map(|(path, _)| path.clone()).collect();
+    let mut failures = 0;
+    for ((path, digest), actual) in expected.iter().zip(sha256_files(&paths)) {
+        let path = path.strip_prefix(".").unwrap_or(path);
+        match actual {
+            Ok(actual) if actual == *digest => {
+                eprintln!("  ok        {}", path.display());
+            },
+            Ok(actual) => {
+                failures += 1;
+                eprintln!(
+                    "  MISMATCH  {} (expected {}, found {})",
+                    path.display(),
+                    digest,
+                    actual
+                );
+            },
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                failures += 1;
+                eprintln!("  MISSING   {}", path.display());
+            },
+            Err(error) => {
+                failures += 1;
+                eprintln!("  UNREADABLE  {} ({})", path.display(), error);
+            },
+        }
+    }
+    if failures > 0 {
+        return Err(eyre!(
+            "{} of {} files failed verification",
+            failures,
+            expected.len()
+        ));
+    }
+    eprintln!("All {} files match their checksums", expected.len());
+    Ok(())
+}
@@ -0,0 +1,331 @@
+//! The views extracted by text-to-view, and the manifest describing them.
+
+use crate::checksum::{self, HashingWriter};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use flate2::read::GzDecoder;
+use http_trace::usage::{self, Usage};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Component, Path};
+
+/// Where text-to-view saves the extracted frames.
+pub const VIEWS_DIR: &str = "views";
+
+/// The manifest text-to-view writes next to the views.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// The name of the seed image among the views.
+pub const SEED_FILE: &str = "seed.jpg";
+
+/// The views in temporal order, with the clip each was extracted from.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ViewsManifest {
+    pub frames: Vec<ViewFrame>,
+    /// The constant frame rate text-to-view re-encoded the video to before extracting
+    /// the views, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalized_fps: Option<u32>,
+    /// The clockwise rotation in degrees text-to-view turned the frames upright by,
+    /// if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<u32>,
+    /// The prompt the views were generated from, as finally sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<UsedPrompt>,
+    /// What text-to-view wrote to temporary files and held in memory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Each submission of the video generation, when text-to-view retried it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<GenerationAttempt>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsedPrompt {
+    pub text: String,
+    /// `optimized`, `original`, `edited` or `reused`.
+    pub source: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenerationAttempt {
+    pub prompt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u32>,
+    pub usable_frames: usize,
+    /// Why the generation was submitted again after this attempt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ViewFrame {
+    pub file: String,
+    /// Where the view comes from: `video` for extracted frames, `image` for generated
+    /// stills, `seed` for the seed image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<f64>,
+    /// The angle a generated still was asked for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub angle: Option<String>,
+    /// Why text-to-view found the frame unusable: `blurry` or `duplicate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unusable: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl ViewsManifest {
+    /// Reads the manifest in `dir`, if text-to-view wrote one.
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        let manifest = serde_json::from_str(&json)
+            .wrap_err_with(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Some(manifest))
+    }
+
+    pub fn write(
+        &self,
+        dir: &Path,
+    ) -> Result<()> {
+        let path = dir.join(MANIFEST_FILE);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .wrap_err_with(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The names of `files` in temporal order, leaving out those not in the manifest.
+    pub fn order(
+        &self,
+        files: &[String],
+    ) -> Vec<String> {
+        self.frames
+            .iter()
+            .filter(|frame| files.contains(&frame.file))
+            .map(|frame| frame.file.clone())
+            .collect()
+    }
+
+    /// The clip of each of `files`, when frames come from more than one clip.
+    pub fn groups(
+        &self,
+        files: &[String],
+    ) -> Option<BTreeMap<String, String>> {
+        let groups: BTreeMap<String, String> = self
+            .frames
+            .iter()
+            .filter(|frame| files.contains(&frame.file))
+            .filter_map(|frame| Some((frame.file.clone(), frame.clip.clone()?)))
+            .collect();
+        let mut clips: Vec<&String> = groups.values().collect();
+        clips.sort();
+        clips.dedup();
+        (clips.len() > 1).then_some(groups)
+    }
+}
+
+/// Adds the seed image at `seed` to the views extracted in `dir`, cropped to the
+/// aspect of the frames and resized to them.
+///
+/// The seed comes first in the manifest, since the video is animated from it.
+pub fn add_seed(
+    dir: &Path,
+    seed: &Path,
+) -> Result<()> {
+    let mut manifest = ViewsManifest::read(dir)?.ok_or_else(|| {
+        eyre!(
+            "No views manifest in {} to add the seed image to",
+            dir.display()
+        )
+    })?;
+    let first = manifest
+        .frames
+        .first()
+        .ok_or_else(|| eyre!("No views in {} to add the seed image to", dir.display()))?;
+    let (width, height) = image::image_dimensions(dir.join(&first.file))
+        .wrap_err_with(|| format!("Failed to read the view {}", first.file))?;
+    let image = image::open(seed).wrap_err_with(|| {
+        format!("Failed to decode the seed image {}", seed.display())
+    })?;
+
+    let path = dir.join(SEED_FILE);
+    let digest = if (image.width(), image.height()) == (width, height)
+        && image::ImageFormat::from_path(seed).ok() == Some(image::ImageFormat::Jpeg)
+    {
+        checksum::copy(seed, &path)
+            .wrap_err_with(|| format!("Failed to copy {}", seed.display()))?;
+        checksum::of(&path)?
+    } else {
+        // Crop the center to the aspect of the frames, so that resizing does not
+        // stretch the subject.
+        let (seed_width, seed_height) = (image.width() as u64, image.height() as u64);
+        let (crop_width, crop_height) =
+            if seed_width * height as u64 > width as u64 * seed_height {
+                (seed_height * width as u64 / height as u64, seed_height)
+            } else {
+                (seed_width, seed_width * height as u64 / width as u64)
+            };
+        let cropped = image.crop_imm(
+            ((seed_width - crop_width) / 2) as u32,
+            ((seed_height - crop_height) / 2) as u32,
+            crop_width.max(1) as u32,
+            crop_height.max(1) as u32,
+        );
+        let resized = cropped
+            .resize_exact(width, height, FilterType::Lanczos3)
+            .to_rgb8();
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 95).encode_image(&resized)?;
+        fs::write(&path, &jpeg).wrap_err_with(|| {
+            format!("Failed to save the seed image to {}", path.display())
+        })?;
+        checksum::sha256(&jpeg)
+    };
+
+    manifest.frames.retain(|frame| frame.file != SEED_FILE);
+    manifest.frames.insert(
+        0,
+        ViewFrame {
+            file: SEED_FILE.to_string(),
+            source: Some("seed".to_string()),
+            clip: None,
+            timestamp: None,
+            angle: None,
+            unusable: None,
+            sha256: Some(digest),
+        },
+    );
+    manifest.write(dir)?;
+    eprintln!("Added the seed image to the views as {}", path.display());
+    Ok(())
+}
+
+/// Unpacks the archive of views written by `text-to-view --tar` at `source`, or on
+/// stdin with `-`, into `dir`. Gzip-compressed archives are told by their content.
+///
+/// Archives cut short are rejected: every entry must be whole, and the manifest,
+/// written last, must list only views present with their checksums.
+pub fn unpack_tar(
+    source: &Path,
+    dir: &Path,
+) -> Result<()> {
+    fs::remove_dir_all(dir).ok();
+    fs::create_dir_all(dir)
+        .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+    let from_stdin = source == Path::new("-");
+    let reader: Box<dyn Read> = if from_stdin {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(
+            File::open(source)
+                .wrap_err_with(|| format!("Failed to open {}", source.display()))?,
+        )
+    };
+    let mut reader = BufReader::new(reader);
+    let gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    let reader: Box<dyn Read> = if gzip {
+        Box::new(GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+    let truncated = || "The views archive is truncated or corrupt";
+
+    eprintln!("Unpacking the views archive into {}...", dir.display());
+    let mut digests = BTreeMap::new();
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().wrap_err_with(truncated)? {
+        let mut entry = entry.wrap_err_with(truncated)?;
+        let path = entry.path().wrap_err_with(truncated)?.into_owned();
+        match entry.header().entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => {},
+            tar::EntryType::Directory => continue,
+            other => {
+                return Err(eyre!(
+                    "The views archive has an entry of an unsupported type {:?}: {}",
+                    other,
+                    path.display()
+                ))
+            },
+        }
+        // Only plain file names, so that no entry lands outside `dir`.
+        let mut components = path.components();
+        let name = match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) => name.to_owned(),
+            _ => {
+                return Err(eyre!(
+                    "The views archive has an entry with an unsafe name: {}",
+                    path.display()
+                ))
+            },
+        };
+        let size = entry.header().size()?;
+        let target = dir.join(&name);
+        if usage::is_temp(&target) {
+            usage::reserve_temp(size)?;
+        }
+        let mut writer = HashingWriter::new(
+            File::create(&target)
+                .wrap_err_with(|| format!("Failed to create {}", target.display()))?,
+        );
+        let copied = io::copy(&mut entry, &mut writer).wrap_err_with(truncated)?;
+        if copied != size {
+            return Err(eyre!(
+                "{}: {} has {} of its {} bytes",
+                truncated(),
+                path.display(),
+                copied,
+                size
+            ));
+        }
+        digests.insert(name.to_string_lossy().into_owned(), writer.finish().1);
+    }
+
+    let manifest = ViewsManifest::read(dir)?.ok_or_else(|| {
+        eyre!(
+            "The views archive has no {}. It is truncated, or was not written by \
+             text-to-view --tar.",
+            MANIFEST_FILE
+        )
+    })?;
+    for frame in &manifest.frames {
+        match (digests.get(&frame.file), &frame.sha256) {
+            (None, _) => {
+                return Err(eyre!(
+                    "{}: the view {} listed in its manifest is missing",
+                    truncated(),
+                    frame.file
+                ))
+            },
+            (Some(actual), Some(expected)) if actual != expected => {
+                return Err(eyre!(
+                    "{}: the view {} does not match its checksum",
+                    truncated(),
+                    frame.file
+                ))
+            },
+            _ => {},
+        }
+    }
+    eprintln!(
+        "Unpacked {} views from {}",
+        manifest.frames.len(),
+        if from_stdin {
+            "stdin".to_string()
+        } else {
+            source.display().to_string()
+        }
+    );
+    Ok(())
+}
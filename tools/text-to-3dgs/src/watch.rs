@@ -0,0 +1,271 @@
+//! Watch mode, running the pipeline for every prompt appended to a file.
+
+use crate::history::{self, PromptRun};
+use clap::Args;
+use color_eyre::eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::sleep;
+
+/// Where the run directories of watch mode are created by default.
+pub const RUNS_DIR: &str = "runs";
+
+/// Where the progress through the watched file is kept, in the output directory.
+pub const WATCH_STATE_FILE: &str = "watch-state.json";
+
+/// Interval between checks of the watched file for new prompts.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// Run the pipeline for every line appended to this file, each in its own run
+    /// directory, until interrupted.
+    #[arg(long, value_name = "FILE", conflicts_with = "prompt")]
+    pub watch: Option<PathBuf>,
+
+    /// Where the run directories of watch mode are created.
+    #[arg(long, value_name = "DIR", default_value = RUNS_DIR, requires = "watch")]
+    pub output_dir: PathBuf,
+}
+
+/// How far the watched file has been processed, persisted so that a restarted
+/// watcher only runs the prompts added since.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct WatchState {
+    file: PathBuf,
+    /// Byte offset just past the last processed line.
+    offset: u64,
+    /// Number of lines processed, numbering the run directories.
+    lines: u64,
+    last_prompt: Option<String>,
+    /// The prompts skipped for having been run before.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    skipped: Vec<SkippedPrompt>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SkippedPrompt {
+    line: u64,
+    prompt: String,
+    /// The directory of the earlier run of the prompt.
+    previous_dir: PathBuf,
+}
+
+impl WatchState {
+    fn load(
+        path: &Path,
+        file: &Path,
+    ) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<Self>(&json).ok())
+            .filter(|state| state.file == file)
+            .unwrap_or_else(|| WatchState {
+                file: file.to_path_buf(),
+                ..Default::default()
+            })
+    }
+
+    fn save(
+        &self,
+        path: &Path,
+    ) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).wrap_err_with(|| {
+            format!("Failed to write the watch state to {}", path.display())
+        })
+    }
+}
+
+/// How a pipeline run of the watcher ended.
+enum Outcome {
+    Finished(ExitStatus),
+    /// The run completed after a first interrupt, so the watcher stops.
+    FinishedAndStop(ExitStatus),
+    Aborted,
+}
+
+/// Tails `args.watch` and runs the pipeline for each new non-empty line, with the
+/// other command-line options, until interrupted.
+///
+/// Prompts run before are skipped and recorded in the watch state, unless
+/// `allow_duplicate`.
+pub async fn run(
+    args: &WatchArgs,
+    allow_duplicate: bool,
+) -> Result<()> {
+    let file = args.watch.as_deref().expect("watch mode needs a file");
+    let file = fs::canonicalize(file).wrap_err_with(|| {
+        format!("Failed to find the watched file {}", file.display())
+    })?;
+    fs::create_dir_all(&args.output_dir)
+        .wrap_err_with(|| format!("Failed to create {}", args.output_dir.display()))?;
+    let state_path = args.output_dir.join(WATCH_STATE_FILE);
+    let mut state = WatchState::load(&state_path, &file);
+    let forwarded = forwarded_args();
+
+    eprintln!(
+        "Watching {} for prompts, press Ctrl-C to stop...",
+        file.display()
+    );
+    loop {
+        let Some((prompt, next_offset)) = next_line(&file, &mut state)? else {
+            tokio::select! {
+                _ = sleep(POLL_INTERVAL) => continue,
+                _ = tokio::signal::ctrl_c() => break,
+            }
+        };
+        state.lines += 1;
+        let prompt = prompt.trim().to_string();
+        if prompt.is_empty() || state.last_prompt.as_ref() == Some(&prompt) {
+            state.offset = next_offset;
+            state.save(&state_path)?;
+            continue;
+        }
+        let previous = if allow_duplicate {
+            None
+        } else {
+            history::previous(&prompt)?.filter(PromptRun::is_duplicated_by_rerun)
+        };
+        if let Some(previous) = previous {
+            eprintln!(
+                "--- Watch: skipping '{}', already run in {} ---",
+                prompt,
+                previous.describe()
+            );
+            state.skipped.push(SkippedPrompt {
+                line: state.lines,
+                prompt: prompt.clone(),
+                previous_dir: previous.dir,
+            });
+            state.offset = next_offset;
+            state.last_prompt = Some(prompt);
+            state.save(&state_path)?;
+            continue;
+        }
+
+        let run_dir = args.output_dir.join(format!("{:04}", state.lines));
+        eprintln!(
+            "--- Watch: running '{}' in {} ---",
+            prompt,
+            run_dir.display()
+        );
+        let outcome = run_pipeline(&prompt, &forwarded, &run_dir).await?;
+        match &outcome {
+            Outcome::Finished(status) | Outcome::FinishedAndStop(status)
+                if status.success() =>
+            {
+                eprintln!("--- Watch: '{}' completed ---", prompt)
+            },
+            Outcome::Finished(status) | Outcome::FinishedAndStop(status) => {
+                eprintln!("--- Watch: '{}' failed ({}) ---", prompt, status)
+            },
+            Outcome::Aborted => {
+                // The prompt stays unprocessed, so a restarted watcher runs it again.
+                eprintln!("--- Watch: '{}' aborted ---", prompt);
+                return Ok(());
+            },
+        }
+        state.offset = next_offset;
+        state.last_prompt = Some(prompt);
+        state.save(&state_path)?;
+        if matches!(outcome, Outcome::FinishedAndStop(_)) {
+            break;
+        }
+    }
+    eprintln!("Stopped watching {}", file.display());
+    Ok(())
+}
+
+/// Reads the first complete line past the processed offset, along with the offset
+/// just past it.
+fn next_line(
+    file: &Path,
+    state: &mut WatchState,
+) -> Result<Option<(String, u64)>> {
+    let mut reader = fs::File::open(file).wrap_err_with(|| {
+        format!("Failed to open the watched file {}", file.display())
+    })?;
+    let len = reader.metadata()?.len();
+    if len < state.offset {
+        eprintln!(
+            "Warning: {} was truncated, watching it from the start",
+            file.display()
+        );
+        state.offset = 0;
+        state.last_prompt = None;
+    }
+    reader.seek(SeekFrom::Start(state.offset))?;
+    let mut pending = Vec::new();
+    reader.read_to_end(&mut pending)?;
+    // A line still being written has no newline yet.
+    let Some(end) = pending.iter().position(|&byte| byte == b'\n') else {
+        return Ok(None);
+    };
+    let line = String::from_utf8_lossy(&pending[..end]).into_owned();
+    Ok(Some((line, state.offset + end as u64 + 1)))
+}
+
+/// Runs the pipeline for `prompt` as a child process in `run_dir`.
+///
+/// The child runs in its own process group, so that an interrupt reaches only the
+/// watcher: the first lets the run finish, a second kills it.
+async fn run_pipeline(
+    prompt: &str,
+    forwarded: &[OsString],
+    run_dir: &Path,
+) -> Result<Outcome> {
+    fs::create_dir_all(run_dir)
+        .wrap_err_with(|| format!("Failed to create {}", run_dir.display()))?;
+    let executable = std::env::current_exe()
+        .wrap_err("Failed to locate the text-to-3dgs executable")?;
+    let mut command = Command::new(executable);
+    command.arg(prompt).args(forwarded).current_dir(run_dir);
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = command
+        .spawn()
+        .wrap_err("Failed to start the pipeline for the prompt")?;
+
+    tokio::select! {
+        status = child.wait() => return Ok(Outcome::Finished(status?)),
+        _ = tokio::signal::ctrl_c() => {},
+    }
+    eprintln!(
+        "Interrupted, finishing the current run before exiting. Press Ctrl-C again to abort it."
+    );
+    tokio::select! {
+        status = child.wait() => Ok(Outcome::FinishedAndStop(status?)),
+        _ = tokio::signal::ctrl_c() => {
+            child.kill().await.wrap_err("Failed to abort the current run")?;
+            Ok(Outcome::Aborted)
+        },
+    }
+}
+
+/// The command-line arguments of the watcher, without those of watch mode itself,
+/// passed on to every run.
+fn forwarded_args() -> Vec<OsString> {
+    let mut args = std::env::args_os().skip(1);
+    let mut forwarded = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            forwarded.push(arg);
+            forwarded.extend(args.by_ref());
+            break;
+        }
+        let name = arg.to_string_lossy();
+        if name == "--watch" || name == "--output-dir" {
+            args.next();
+        } else if !name.starts_with("--watch=") && !name.starts_with("--output-dir=") {
+            forwarded.push(arg);
+        }
+    }
+    forwarded
+}
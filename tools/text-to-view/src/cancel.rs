@@ -0,0 +1,64 @@
+//! Cancellation on Ctrl-C or SIGTERM, the latter sent by text-to-3dgs when its run
+//! is cancelled.
+//!
+//! A cancelled run stops at its next await point or between frames, removes its
+//! temporary videos and saves no views. A second signal ends the process at once.
+
+use color_eyre::eyre::{eyre, Result};
+use std::future::Future;
+use tokio_util::sync::CancellationToken;
+
+/// The error of cancelled runs.
+pub const CANCELLED: &str = "The run was cancelled, no views were saved";
+
+/// Exit code of a process ended by a second signal, as the shell reports Ctrl-C.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// A token cancelled on the first Ctrl-C or SIGTERM the process receives.
+pub fn on_signal() -> CancellationToken {
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    tokio::spawn(async move {
+        signal().await;
+        eprintln!("Cancelling, press Ctrl-C again to stop at once...");
+        cancel.cancel();
+        signal().await;
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    });
+    token
+}
+
+async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            },
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Runs `future` until it completes, or fails once `token` is cancelled, dropping
+/// it wherever it waits.
+pub async fn or_cancelled<T>(token: &CancellationToken, future: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::select! {
+        result = future => result,
+        _ = token.cancelled() => Err(eyre!(CANCELLED)),
+    }
+}
+
+/// Fails if `token` is cancelled, between two steps.
+pub fn check(token: &CancellationToken) -> Result<()> {
+    if token.is_cancelled() {
+        return Err(eyre!(CANCELLED));
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! Confirmation of the optimized prompt with `--confirm-prompt`, before any Veo quota
+//! is spent: the user accepts it, edits it in `$EDITOR`, or aborts.
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::Command;
+
+/// Width of the side-by-side comparison when the terminal's is unknown.
+const DEFAULT_WIDTH: usize = 100;
+
+/// Space between the two columns of the comparison.
+const GUTTER: usize = 3;
+
+/// The prompt the user settled on.
+pub struct Confirmed {
+    pub prompt: String,
+    /// Whether the user edited the prompt by hand.
+    pub edited: bool,
+}
+
+/// Fails unless the user can answer on a terminal, so that a run meant to wait for
+/// confirmation does not hang or go ahead unconfirmed.
+pub fn ensure_terminal() -> Result<()> {
+    if io::stdin().is_terminal() && io::stderr().is_terminal() {
+        return Ok(());
+    }
+    Err(eyre!("--confirm-prompt needs a terminal to answer on. In scripts, confirm the prompt once interactively and pass the manifest of that run to --reuse-prompt instead."))
+}
+
+/// Shows `original` and `optimized` side by side and asks the user what to do, until
+/// they accept a prompt or abort.
+pub fn confirm(
+    original: &str,
+    optimized: &str,
+) -> Result<Confirmed> {
+    let mut prompt = optimized.to_string();
+    let mut edited = false;
+    loop {
+        let right = if edited { "Edited" } else { "Optimized" };
+        eprintln!(
+            "\n{}",
+            side_by_side(("Original", original), (right, &prompt))
+        );
+        eprint!(
+            "Generate the video with this prompt? [Enter] accept, [e] edit, [a] abort: "
+        );
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Err(eyre!(
+                "Aborted at the prompt confirmation, no video was generated."
+            ));
+        }
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "" | "y" | "yes" => return Ok(Confirmed { prompt, edited }),
+            "e" | "edit" => match edit(&prompt)? {
+                Some(text) => {
+                    edited |= text != prompt;
+                    prompt = text;
+                },
+                None => {
+                    eprintln!("The edited prompt is empty, keeping the previous one.")
+                },
+            },
+            "a" | "abort" | "n" | "no" | "q" => {
+                return Err(eyre!(
+                    "Aborted at the prompt confirmation, no video was generated."
+                ))
+            },
+            other => eprintln!("Unknown answer '{}'.", other),
+        }
+    }
+}
+
+/// Opens `prompt` in the user's editor, returning the saved text unless it is empty.
+fn edit(prompt: &str) -> Result<Option<String>> {
+    let path =
+        env::temp_dir().join(format!("text-to-view-prompt-{}.txt", std::process::id()));
+    fs::write(&path, format!("{}\n", prompt))
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+    // Editors are often given with arguments, like `code --wait`.
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or_else(|| eyre!("$EDITOR is empty"))?;
+    let status = Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status()
+        .wrap_err_with(|| {
+            format!(
+                "Failed to start the editor '{}'. Set $EDITOR to one that exists.",
+                editor
+            )
+        });
+    let text = status.and_then(|status| {
+        if !status.success() {
+            return Err(eyre!("The editor '{}' exited with {}", editor, status));
+        }
+        fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))
+    });
+    fs::remove_file(&path).ok();
+    let text = text?.trim().to_string();
+    Ok((!text.is_empty()).then_some(text))
+}
+
+/// Lays out two titled texts in columns, wrapped to the width of the terminal.
+fn side_by_side(
+    left: (&str, &str),
+    right: (&str, &str),
+) -> String {
+    let width = env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH);
+    let column = (width.saturating_sub(GUTTER) / 2).max(20);
+    let wrap_titled = |(title, text): (&str, &str)| {
+        let mut lines = vec![title.to_string(), "-".repeat(title.len())];
+        lines.extend(wrap(text, column));
+        lines
+    };
+    let (left, right) = (wrap_titled(left), wrap_titled(right));
+    (0..left.len().max(right.len()))
+        .map(|i| {
+            let left = left.get(i).map_or("", String::as_str);
+            let right = right.get(i).map_or("", String::as_str);
+            format!(
+                "{:<column$}{:gutter$}{}",
+                left,
+                "",
+                right,
+                column = column,
+                gutter = GUTTER
+            )
+            .trim_end()
+            .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Wraps `text` at word boundaries into lines of at most `width` characters, cutting
+/// longer words.
+fn wrap(
+    text: &str,
+    width: usize,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            while !word.is_empty() {
+                let length = line.chars().count();
+                let room = if length == 0 {
+                    width
+                } else {
+                    width.saturating_sub(length + 1)
+                };
+                if word.len() <= room {
+                    if length > 0 {
+                        line.push(' ');
+                    }
+                    line.extend(word.drain(..));
+                } else if length > 0 {
+                    lines.push(std::mem::take(&mut line));
+                } else {
+                    lines.push(word.drain(..width).collect());
+                }
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
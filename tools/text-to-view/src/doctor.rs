@@ -0,0 +1,160 @@
+//! The `doctor` subcommand, diagnosing what text-to-view needs to run.
+//!
+//! Each check ends in a finding with a hint on how to fix it when it does not pass.
+//! text-to-3dgs runs `text-to-view doctor --json` and folds the findings into its
+//! own report.
+
+use clap::Args;
+use color_eyre::eyre::{eyre, Result};
+use http_trace::SendTraced;
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+
+/// How long the Gemini API has to answer the models list call.
+const API_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Args, Debug)]
+pub struct DoctorArgs {
+    /// Print the findings as JSON on stdout, for CI.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    /// A likely problem that does not stop a run.
+    Warn,
+    Fail,
+}
+
+/// The outcome of a check.
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// How to fix the problem, for findings that do not pass.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Finding {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Finding { name, status: Status::Pass, detail: detail.into(), hint: None }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Finding { name, status: Status::Warn, detail: detail.into(), hint: Some(hint.into()) }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Finding { name, status: Status::Fail, detail: detail.into(), hint: Some(hint.into()) }
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    ok: bool,
+    checks: &'a [Finding],
+}
+
+pub async fn run(args: &DoctorArgs) -> Result<()> {
+    let findings = vec![check_api_key().await, check_decoder(), check_temp_dir()];
+    let failed = findings.iter().filter(|finding| finding.status == Status::Fail).count();
+    if args.json {
+        let report = Report { ok: failed == 0, checks: &findings };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&findings);
+    }
+    if failed > 0 {
+        return Err(eyre!("{} of {} checks failed", failed, findings.len()));
+    }
+    Ok(())
+}
+
+/// Checks that the API key is set, and accepted by a models list call, which spends
+/// no quota.
+async fn check_api_key() -> Finding {
+    const NAME: &str = "api-key";
+    let Ok(api_key) = env::var("GEMINI_API_KEY") else {
+        return Finding::fail(NAME, "GEMINI_API_KEY is not set", "Create a key at https://aistudio.google.com/apikey and export it as GEMINI_API_KEY.");
+    };
+    http_trace::redact(&api_key);
+    let url = format!("https://generativelanguage.googleapis.com/v1beta/models?pageSize=1&key={}", api_key);
+    let client = match reqwest::Client::builder().timeout(API_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => return Finding::warn(NAME, format!("could not check the key: {}", e), "Check the TLS setup of this machine."),
+    };
+    match client.get(&url).send_traced().await {
+        Ok(response) if response.status().is_success() => Finding::pass(NAME, "GEMINI_API_KEY is set and accepted"),
+        Ok(response) if response.status().is_client_error() => Finding::fail(
+            NAME,
+            format!("the Gemini API rejected GEMINI_API_KEY ({})", response.status()),
+            "Check that the key is copied whole and that the Generative Language API is enabled for its project.",
+        ),
+        Ok(response) => Finding::warn(
+            NAME,
+            format!("the Gemini API answered {}", response.status()),
+            "The API may be having an outage; retry later.",
+        ),
+        Err(e) => Finding::warn(
+            NAME,
+            format!("could not reach the Gemini API: {}", e.without_url()),
+            "Check the network connection and any proxy settings.",
+        ),
+    }
+}
+
+fn check_decoder() -> Finding {
+    const NAME: &str = "decoder";
+    match video_rs::init() {
+        Ok(()) => Finding::pass(NAME, "the video decoder initializes"),
+        Err(e) => Finding::fail(
+            NAME,
+            format!("the video decoder failed to initialize: {}", e),
+            "Install the FFmpeg libraries, e.g. `brew install ffmpeg` or `apt install libavcodec-dev libavformat-dev libswscale-dev`, and rebuild.",
+        ),
+    }
+}
+
+/// Checks that the downloaded video can be written to the temporary directory.
+fn check_temp_dir() -> Finding {
+    const NAME: &str = "temp-dir";
+    let dir = env::temp_dir();
+    let probe = dir.join(format!("text-to-view-doctor-{}", std::process::id()));
+    match fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            Finding::pass(NAME, format!("{} is writable", dir.display()))
+        }
+        Err(e) => Finding::fail(
+            NAME,
+            format!("cannot write to {}: {}", dir.display(), e),
+            "Point TMPDIR at a writable directory.",
+        ),
+    }
+}
+
+/// Prints the findings as a table on stderr, colored on terminals.
+fn print_report(findings: &[Finding]) {
+    let color = io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+    eprintln!("text-to-view doctor:");
+    for finding in findings {
+        let (label, code) = match finding.status {
+            Status::Pass => ("PASS", "32"),
+            Status::Warn => ("WARN", "33"),
+            Status::Fail => ("FAIL", "31"),
+        };
+        let label = if color { format!("\x1b[{}m{}\x1b[0m", code, label) } else { label.to_string() };
+        eprintln!("  {}  {:<9} {}", label, finding.name, finding.detail);
+        if let Some(hint) = &finding.hint {
+            eprintln!("        {:<9} hint: {}", "", hint);
+        }
+    }
+}
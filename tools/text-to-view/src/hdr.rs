@@ -0,0 +1,445 @@
+//! Conversion of high-bit-depth and HDR video frames to views.
+//!
+//! The default decoding path converts frames straight to 8-bit RGB, which is right
+//! for 8-bit SDR video only: PQ and HLG frames come out washed out, and the BT.2020
+//! primaries of HDR video are taken for BT.709 ones. Such videos are decoded here to
+//! 16-bit YUV instead and converted with their own matrix, transfer function and
+//! primaries, tone-mapping HDR luminance into the range of SDR views.
+
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result};
+use image::{DynamicImage, ImageBuffer, ImageFormat, Rgb, RgbImage};
+use std::path::Path;
+use video_rs::ffmpeg::color::{Primaries, Range, Space, TransferCharacteristic};
+use video_rs::ffmpeg::format::Pixel;
+use video_rs::ffmpeg::frame::Video;
+use video_rs::ffmpeg::software::scaling;
+use video_rs::ffmpeg::{codec, format, media};
+
+/// A frame with 16 bits per channel.
+pub type Rgb16Image = ImageBuffer<Rgb<u16>, Vec<u16>>;
+
+/// The luminance of SDR white in HDR video, in nits (ITU-R BT.2408).
+const REFERENCE_WHITE: f64 = 203.0;
+
+/// The peak luminance of PQ, in nits.
+const PQ_PEAK: f64 = 10000.0;
+
+/// The nominal peak luminance of HLG displays, in nits.
+const HLG_PEAK: f64 = 1000.0;
+
+/// How HDR luminance is compressed into the range of SDR views.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ToneMap {
+    /// Compresses highlights smoothly, keeping midtones (extended Reinhard).
+    Reinhard,
+    /// A filmic curve with a soft toe and shoulder (Hable).
+    Hable,
+    /// Clips everything brighter than SDR white.
+    Clip,
+}
+
+/// How the samples of a video encode light.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transfer {
+    Sdr,
+    /// Perceptual quantizer (SMPTE ST 2084), as in HDR10 and Dolby Vision.
+    Pq,
+    /// Hybrid log-gamma (ARIB STD-B67).
+    Hlg,
+}
+
+/// The color encoding of a video, as read from its decoder.
+#[derive(Clone, Copy, Debug)]
+pub struct ColorInfo {
+    pub bit_depth: u32,
+    pub transfer: Transfer,
+    pub bt2020: bool,
+}
+
+impl ColorInfo {
+    /// Reads the color encoding of the video at `path`.
+    pub fn probe(path: &Path) -> Result<ColorInfo> {
+        let input = format::input(path)?;
+        let stream = input
+            .streams()
+            .best(media::Type::Video)
+            .ok_or_else(|| eyre!("{} has no video stream", path.display()))?;
+        let decoder = codec::context::Context::from_parameters(stream.parameters())?
+            .decoder()
+            .video()?;
+        let transfer = match decoder.color_transfer_characteristic() {
+            TransferCharacteristic::SMPTE2084 => Transfer::Pq,
+            TransferCharacteristic::ARIB_STD_B67 => Transfer::Hlg,
+            _ => Transfer::Sdr,
+        };
+        Ok(ColorInfo {
+            bit_depth: bit_depth(decoder.format()),
+            transfer,
+            bt2020: decoder.color_primaries() == Primaries::BT2020,
+        })
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        self.transfer != Transfer::Sdr
+    }
+
+    /// Whether the default 8-bit decoding would get the frames of the video wrong.
+    pub fn needs_conversion(&self) -> bool {
+        self.bit_depth > 8 || self.is_hdr() || self.bt2020
+    }
+}
+
+/// The bit depth of a pixel format, from the digits ending its name, e.g. 10 for
+/// `yuv420p10le` and `p010le`, and 16 for `rgb48le`.
+fn bit_depth(format: Pixel) -> u32 {
+    let name = format
+        .descriptor()
+        .map_or("", |descriptor| descriptor.name());
+    let Some(name) = name.strip_suffix("le").or_else(|| name.strip_suffix("be")) else {
+        return 8;
+    };
+    let digits = &name[name.trim_end_matches(|c: char| c.is_ascii_digit()).len()..];
+    match digits.parse() {
+        Ok(depth @ 9..=16) => depth,
+        Ok(48 | 64) => 16,
+        _ => 8,
+    }
+}
+
+/// Decodes the frames at the zero-based `indices`, in increasing order, of the video
+/// at `path` to 16-bit RGB in the color encoding of the video.
+pub fn decode_frames(
+    path: &Path,
+    indices: &[usize],
+) -> Result<Vec<Rgb16Image>> {
+    let mut input = format::input(path)?;
+    let stream = input
+        .streams()
+        .best(media::Type::Video)
+        .ok_or_else(|| eyre!("{} has no video stream", path.display()))?;
+    let stream_index = stream.index();
+    let mut decoder = codec::context::Context::from_parameters(stream.parameters())?
+        .decoder()
+        .video()?;
+    let mut scaler: Option<scaling::Context> = None;
+
+    let mut frames = Vec::with_capacity(indices.len());
+    let mut decoded = 0;
+    let mut frame = Video::empty();
+    let mut receive = |decoder: &mut codec::decoder::Video,
+                       frames: &mut Vec<Rgb16Image>|
+     -> Result<()> {
+        while frames.len() < indices.len() && decoder.receive_frame(&mut frame).is_ok() {
+            if indices[frames.len()] == decoded {
+                let scaler = match &mut scaler {
+                    Some(scaler) => scaler,
+                    None => scaler.insert(scaling::Context::get(
+                        frame.format(),
+                        frame.width(),
+                        frame.height(),
+                        Pixel::YUV444P16LE,
+                        frame.width(),
+                        frame.height(),
+                        scaling::Flags::BICUBIC,
+                    )?),
+                };
+                let mut yuv = Video::empty();
+                scaler.run(&frame, &mut yuv)?;
+                frames.push(yuv_to_rgb(&yuv, frame.color_space(), frame.color_range()));
+            }
+            decoded += 1;
+        }
+        Ok(())
+    };
+    for (stream, packet) in input.packets() {
+        if frames.len() == indices.len() {
+            break;
+        }
+        if stream.index() == stream_index {
+            decoder.send_packet(&packet)?;
+            receive(&mut decoder, &mut frames)?;
+        }
+    }
+    decoder.send_eof()?;
+    receive(&mut decoder, &mut frames)?;
+
+    if frames.len() < indices.len() {
+        return Err(eyre!(
+            "Failed to extract frame {}: it is past the {} frames of the video.",
+            indices[frames.len()],
+            decoded
+        ));
+    }
+    Ok(frames)
+}
+
+/// Converts a planar 16-bit YUV frame to R'G'B' with the matrix of `space`.
+fn yuv_to_rgb(
+    yuv: &Video,
+    space: Space,
+    range: Range,
+) -> Rgb16Image {
+    let (kr, kb) = match space {
+        Space::BT2020NCL | Space::BT2020CL => (0.2627, 0.0593),
+        Space::BT470BG | Space::SMPTE170M => (0.299, 0.114),
+        _ => (0.2126, 0.0722),
+    };
+    let kg = 1.0 - kr - kb;
+    // Limited range puts black at 16 and white at 235, or 240 for chroma, in 8 bits.
+    let (y_offset, y_scale, c_scale) = match range {
+        Range::JPEG => (0.0, 65535.0, 65535.0),
+        _ => (4096.0, 56064.0, 57344.0),
+    };
+    let sample = |plane: usize, x: u32, y: u32| {
+        let at = y as usize * yuv.stride(plane) + x as usize * 2;
+        u16::from_le_bytes([yuv.data(plane)[at], yuv.data(plane)[at + 1]]) as f64
+    };
+    ImageBuffer::from_fn(yuv.width(), yuv.height(), |x, y| {
+        let luma = (sample(0, x, y) - y_offset) / y_scale;
+        let cb = (sample(1, x, y) - 32768.0) / c_scale;
+        let cr = (sample(2, x, y) - 32768.0) / c_scale;
+        let r = luma + 2.0 * (1.0 - kr) * cr;
+        let b = luma + 2.0 * (1.0 - kb) * cb;
+        let g = (luma - kr * r - kb * b) / kg;
+        Rgb([r, g, b].map(|channel| (channel.clamp(0.0, 1.0) * 65535.0).round() as u16))
+    })
+}
+
+/// The view of a frame from [`decode_frames`], and the format it is saved in: the
+/// frame as a 16-bit PNG if `preserve_bit_depth`, or else converted to an 8-bit
+/// sRGB JPEG with `tone_map`.
+pub fn view(
+    frame: &Rgb16Image,
+    color: ColorInfo,
+    preserve_bit_depth: bool,
+    tone_map: ToneMap,
+) -> (DynamicImage, ImageFormat) {
+    if preserve_bit_depth {
+        (DynamicImage::ImageRgb16(frame.clone()), ImageFormat::Png)
+    } else {
+        (
+            DynamicImage::ImageRgb8(to_srgb(frame, color, tone_map)),
+            ImageFormat::Jpeg,
+        )
+    }
+}
+
+/// Converts a frame in the color encoding `color` to 8-bit sRGB, tone-mapping HDR
+/// luminance with `tone_map`.
+pub fn to_srgb(
+    frame: &Rgb16Image,
+    color: ColorInfo,
+    tone_map: ToneMap,
+) -> RgbImage {
+    ImageBuffer::from_fn(frame.width(), frame.height(), |x, y| {
+        let encoded = frame
+            .get_pixel(x, y)
+            .0
+            .map(|channel| channel as f64 / 65535.0);
+        Rgb(convert_pixel(encoded, color, tone_map)
+            .map(|channel| (channel * 255.0).round() as u8))
+    })
+}
+
+/// Converts one R'G'B' pixel in the color encoding `color` to sRGB, both in [0, 1].
+pub fn convert_pixel(
+    encoded: [f64; 3],
+    color: ColorInfo,
+    tone_map: ToneMap,
+) -> [f64; 3] {
+    if color.transfer == Transfer::Sdr && !color.bt2020 {
+        // BT.709 and sRGB differ too little to matter for views.
+        return encoded;
+    }
+    // Linear light, where 1 is SDR white.
+    let mut linear = match color.transfer {
+        Transfer::Pq => {
+            encoded.map(|channel| pq_eotf(channel) * PQ_PEAK / REFERENCE_WHITE)
+        },
+        Transfer::Hlg => {
+            hlg_to_display(encoded).map(|channel| channel * HLG_PEAK / REFERENCE_WHITE)
+        },
+        Transfer::Sdr => encoded.map(|channel| channel.powf(2.4)),
+    };
+    if color.bt2020 {
+        linear = bt2020_to_bt709(linear);
+    }
+    if color.is_hdr() {
+        linear = tone_map_luminance(linear, tone_map);
+    }
+    linear.map(|channel| srgb_oetf(channel.clamp(0.0, 1.0)))
+}
+
+/// The PQ EOTF, from a signal to a fraction of the PQ peak luminance.
+fn pq_eotf(signal: f64) -> f64 {
+    const M1: f64 = 2610.0 / 16384.0;
+    const M2: f64 = 2523.0 / 4096.0 * 128.0;
+    const C1: f64 = 3424.0 / 4096.0;
+    const C2: f64 = 2413.0 / 4096.0 * 32.0;
+    const C3: f64 = 2392.0 / 4096.0 * 32.0;
+    let power = signal.max(0.0).powf(1.0 / M2);
+    ((power - C1).max(0.0) / (C2 - C3 * power)).powf(1.0 / M1)
+}
+
+/// The HLG inverse OETF followed by the OOTF of a display at the nominal peak, from
+/// a signal to a fraction of the peak luminance.
+fn hlg_to_display(signal: [f64; 3]) -> [f64; 3] {
+    const A: f64 = 0.17883277;
+    const B: f64 = 0.28466892;
+    const C: f64 = 0.55991073;
+    const GAMMA: f64 = 1.2;
+    let scene = signal.map(|channel| {
+        let channel = channel.max(0.0);
+        if channel <= 0.5 {
+            channel * channel / 3.0
+        } else {
+            (((channel - C) / A).exp() + B) / 12.0
+        }
+    });
+    let luminance = 0.2627 * scene[0] + 0.6780 * scene[1] + 0.0593 * scene[2];
+    scene.map(|channel| channel * luminance.powf(GAMMA - 1.0))
+}
+
+fn bt2020_to_bt709([r, g, b]: [f64; 3]) -> [f64; 3] {
+    [
+        1.6605 * r - 0.5876 * g - 0.0728 * b,
+        -0.1246 * r + 1.1329 * g - 0.0083 * b,
+        -0.0182 * r - 0.1006 * g + 1.1187 * b,
+    ]
+}
+
+/// Compresses the luminance of a linear pixel into [0, 1], keeping its hue.
+fn tone_map_luminance(
+    linear: [f64; 3],
+    tone_map: ToneMap,
+) -> [f64; 3] {
+    let linear = linear.map(|channel| channel.max(0.0));
+    let luminance = 0.2126 * linear[0] + 0.7152 * linear[1] + 0.0722 * linear[2];
+    if luminance <= 0.0 {
+        return linear;
+    }
+    let mapped = match tone_map {
+        ToneMap::Reinhard => {
+            // The luminance mapped to white: the PQ peak.
+            let white = PQ_PEAK / REFERENCE_WHITE;
+            luminance * (1.0 + luminance / (white * white)) / (1.0 + luminance)
+        },
+        ToneMap::Hable => hable(luminance * 2.0) / hable(11.2),
+        ToneMap::Clip => luminance.min(1.0),
+    };
+    linear.map(|channel| channel * mapped / luminance)
+}
+
+fn hable(x: f64) -> f64 {
+    const A: f64 = 0.15;
+    const B: f64 = 0.50;
+    const C: f64 = 0.10;
+    const D: f64 = 0.20;
+    const E: f64 = 0.02;
+    const F: f64 = 0.30;
+    (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+}
+
+fn srgb_oetf(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// YUV samples of a gray ramp and random colors, stored as the R, G and B of a
+    /// 16-bit PNG, and their conversions computed apart from this module.
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/hdr");
+
+    fn fixture(name: &str) -> DynamicImage {
+        image::open(Path::new(FIXTURES).join(name)).unwrap()
+    }
+
+    /// The 10-bit YUV fixture as the scaler hands it on, in 16-bit planes.
+    fn yuv_frame() -> Video {
+        let samples = fixture("yuv444p10.png").into_rgb16();
+        let mut yuv = Video::new(Pixel::YUV444P16LE, samples.width(), samples.height());
+        for plane in 0..3 {
+            let stride = yuv.stride(plane);
+            for (x, y, pixel) in samples.enumerate_pixels() {
+                let at = y as usize * stride + x as usize * 2;
+                yuv.data_mut(plane)[at..at + 2]
+                    .copy_from_slice(&pixel.0[plane].to_le_bytes());
+            }
+        }
+        yuv
+    }
+
+    fn color(transfer: Transfer) -> ColorInfo {
+        ColorInfo {
+            bit_depth: 10,
+            transfer,
+            bt2020: transfer != Transfer::Sdr,
+        }
+    }
+
+    /// Checks that `actual` is `expected` within `tolerance` on every channel, to
+    /// allow for the rounding of floating-point math that differs in the last bit.
+    fn assert_close(
+        actual: &DynamicImage,
+        expected: &DynamicImage,
+        tolerance: u16,
+    ) {
+        assert_eq!(actual.color(), expected.color());
+        let (actual, expected) = (actual.to_rgb16(), expected.to_rgb16());
+        assert_eq!(actual.dimensions(), expected.dimensions());
+        for ((x, y, a), b) in actual.enumerate_pixels().zip(expected.pixels()) {
+            let off = a.0.iter().zip(b.0).any(|(&a, b)| a.abs_diff(b) > tolerance);
+            assert!(!off, "({}, {}) is {:?}, not {:?}", x, y, a.0, b.0);
+        }
+    }
+
+    #[test]
+    fn converts_yuv_with_the_matrix_of_the_video() {
+        let yuv = yuv_frame();
+        let bt2020 = yuv_to_rgb(&yuv, Space::BT2020NCL, Range::MPEG);
+        assert_close(&DynamicImage::ImageRgb16(bt2020), &fixture("bt2020.png"), 1);
+
+        // 10-bit SDR video keeps its tones, converted to 8 bits.
+        let bt709 = yuv_to_rgb(&yuv, Space::BT709, Range::MPEG);
+        let (view, format) = view(&bt709, color(Transfer::Sdr), false, ToneMap::Hable);
+        assert_eq!(format, ImageFormat::Jpeg);
+        assert_close(&view, &fixture("sdr-bt709.png"), 1);
+    }
+
+    #[test]
+    fn tone_maps_hdr_frames_to_srgb() {
+        let frame = fixture("bt2020.png").into_rgb16();
+        let cases = [
+            (Transfer::Pq, ToneMap::Hable, "pq-hable.png"),
+            (Transfer::Pq, ToneMap::Reinhard, "pq-reinhard.png"),
+            (Transfer::Pq, ToneMap::Clip, "pq-clip.png"),
+            (Transfer::Hlg, ToneMap::Hable, "hlg-hable.png"),
+        ];
+        for (transfer, tone_map, reference) in cases {
+            let (view, format) = view(&frame, color(transfer), false, tone_map);
+            assert_eq!(format, ImageFormat::Jpeg);
+            assert_close(&view, &fixture(reference), 1);
+        }
+    }
+
+    #[test]
+    fn preserves_the_bit_depth_in_png() {
+        let frame = fixture("bt2020.png").into_rgb16();
+        let (view, format) = view(&frame, color(Transfer::Pq), true, ToneMap::Hable);
+        assert_eq!(format, ImageFormat::Png);
+
+        // Saved and read back, every one of the 16 bits is kept.
+        let mut png = Vec::new();
+        view.write_to(&mut Cursor::new(&mut png), format).unwrap();
+        let saved = image::load_from_memory_with_format(&png, format).unwrap();
+        assert_close(&saved, &fixture("bt2020.png"), 0);
+    }
+}
@@ -11,19 +11,481 @@
 //!     ```
 //!     This will use Gemini to optimize the prompt before sending it to Veo.
 
+mod cancel;
+mod confirm;
+mod doctor;
+mod hdr;
+mod rotation;
+mod stills;
+mod synthetic;
+mod template;
+mod usable;
+mod validate;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use color_eyre::eyre::{eyre, Result, WrapErr};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use http_trace::{usage, SendTraced};
 use serde::{Deserialize, Serialize};
 use std::env;
 use std::fs;
-use std::io::Write;
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::time::sleep;
-use video_rs::Decoder;
-use image::save_buffer;
+use tokio_util::sync::CancellationToken;
+use video_rs::encode::Settings;
+use video_rs::frame::RawFrame;
+use video_rs::{Decoder, Encoder, Time};
+use hdr::ToneMap;
+use image::{DynamicImage, ImageFormat, RgbImage};
+use sha2::{Digest, Sha256};
 
 // --- Data Structures ---
 
+/// Generates a video from a text prompt with Veo and extracts views from it.
+#[derive(Debug, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// The text prompt describing the scene to generate.
+    #[arg(required_unless_present = "check")]
+    prompt: Vec<String>,
+
+    /// Only check that the API key is set and the video decoder initializes, printing
+    /// `ok` or the problem on stdout.
+    #[arg(long)]
+    check: bool,
+
+    /// Record every HTTP exchange, with credentials redacted, as JSON in this file.
+    #[arg(long, value_name = "PATH")]
+    debug_http: Option<PathBuf>,
+
+    /// Fail before writing more than this many bytes to temporary files, like the
+    /// downloaded and re-encoded videos.
+    #[arg(long, value_name = "BYTES")]
+    max_temp_bytes: Option<u64>,
+
+    /// Save every API call and download, with credentials redacted, as numbered files
+    /// in this directory, to replay them later with --replay.
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Answer every API call and download from the exchanges recorded in this
+    /// directory, without the network or an API key, failing on any other request.
+    #[arg(long, value_name = "DIR")]
+    replay: Option<PathBuf>,
+
+    /// Extract the views from a synthetic video of a turning cube instead of one
+    /// generated from the prompt, without the network or an API key, checking that
+    /// each view shows the frame at its timestamp.
+    #[arg(long, conflicts_with_all = ["record", "replay", "image"])]
+    mock: bool,
+
+    /// Where to save the extracted views.
+    #[arg(long, value_name = "DIR", default_value = "views")]
+    views_dir: PathBuf,
+
+    /// Show the original and optimized prompts side by side before generating the
+    /// video, to accept, edit in $EDITOR or abort. Needs a terminal.
+    #[arg(long, conflicts_with = "reuse_prompt")]
+    confirm_prompt: bool,
+
+    /// Skip the optimization and generate the video from the prompt recorded in this
+    /// views manifest, as confirmed or edited in an earlier run.
+    #[arg(long, value_name = "MANIFEST")]
+    reuse_prompt: Option<PathBuf>,
+
+    /// Ask Gemini to optimize the prompt with this template instead of the built-in
+    /// one. It gets the variables {prompt}, {style}, {duration_seconds} and
+    /// {aspect_ratio}, and those of --template-var.
+    #[arg(long, value_name = "PATH", conflicts_with = "reuse_prompt")]
+    prompt_template: Option<PathBuf>,
+
+    /// Set a variable of the prompt template, as `KEY=VALUE`. May be repeated.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_template_var, requires = "prompt_template")]
+    template_var: Vec<(String, String)>,
+
+    /// The look the optimized prompt asks for.
+    #[arg(long, value_enum, value_name = "PRESET", default_value = "cinematic")]
+    style: Style,
+
+    /// The length of the generated video, in seconds.
+    #[arg(long, value_name = "N", default_value_t = 5, value_parser = clap::value_parser!(u32).range(5..=8))]
+    duration_seconds: u32,
+
+    /// The aspect ratio of the generated video.
+    #[arg(long, value_enum, value_name = "RATIO", default_value = "16:9")]
+    aspect_ratio: AspectRatio,
+
+    /// Condition the video on this image, which Veo animates from its first frame.
+    #[arg(long, value_name = "PATH")]
+    image: Option<PathBuf>,
+
+    /// Whether the views are frames of a Veo video, or still images generated with
+    /// Imagen from different angles, for accounts without access to Veo.
+    #[arg(long, value_enum, value_name = "SOURCE", default_value = "video")]
+    view_source: ViewSource,
+
+    /// How many still images to generate with `--view-source images`.
+    #[arg(long, value_name = "N", default_value_t = 6, value_parser = clap::value_parser!(u32).range(2..=stills::ANGLES.len() as i64))]
+    image_views: u32,
+
+    /// Write the views and their manifest as a tar archive to this file, or to stdout
+    /// with `-`, instead of saving them in the views directory.
+    #[arg(long, value_name = "PATH|-", conflicts_with = "views_dir")]
+    tar: Option<PathBuf>,
+
+    /// Compress the tar archive with gzip.
+    #[arg(long, requires = "tar")]
+    gzip: bool,
+
+    #[command(flatten)]
+    depth: BitDepthArgs,
+
+    /// Re-encode the video to this constant frame rate before extracting the views,
+    /// unless it already has one close to it.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    normalize_fps: Option<u32>,
+
+    /// Fail, or retry with --auto-retry-generation, when fewer frames than this are
+    /// usable, neither blurry nor near-duplicates of the one before.
+    #[arg(long, value_name = "N", default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..=TIMESTAMPS.len() as i64))]
+    min_usable_frames: u32,
+
+    /// When too few frames are usable, submit the generation once more with a new seed
+    /// and a camera hint appended to the prompt, spending Veo quota on a second video.
+    #[arg(long)]
+    auto_retry_generation: bool,
+
+    /// Save the frames as they are stored, without turning them upright by the
+    /// display rotation of the video.
+    #[arg(long)]
+    no_auto_rotate: bool,
+
+    /// Cut prompts longer than Veo takes at their last sentence boundary that fits,
+    /// instead of failing.
+    #[arg(long)]
+    auto_truncate: bool,
+
+    /// Warn about prompts with the words in this file, one per line, instead of the
+    /// built-in list of words the safety filter of Veo is likely to refuse. An empty
+    /// file turns the warning off.
+    #[arg(long, value_name = "PATH")]
+    content_keywords: Option<PathBuf>,
+
+    /// The most Gemini prompt requests per minute, shared by all runs on this machine.
+    #[arg(long, value_name = "N", env = "TEXT_TO_VIEW_GEMINI_RPM", value_parser = clap::value_parser!(u32).range(1..))]
+    gemini_rpm: Option<u32>,
+
+    /// The most Veo generation requests per minute, shared by all runs on this machine.
+    #[arg(long, value_name = "N", env = "TEXT_TO_VIEW_VEO_RPM", value_parser = clap::value_parser!(u32).range(1..))]
+    veo_rpm: Option<u32>,
+
+    /// The most polls of Veo operations per minute, shared by all runs on this machine.
+    #[arg(long, value_name = "N", env = "TEXT_TO_VIEW_OPERATIONS_RPM", value_parser = clap::value_parser!(u32).range(1..))]
+    operations_rpm: Option<u32>,
+
+    /// The most Imagen image requests per minute, shared by all runs on this machine.
+    #[arg(long, value_name = "N", env = "TEXT_TO_VIEW_IMAGEN_RPM", value_parser = clap::value_parser!(u32).range(1..))]
+    imagen_rpm: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ViewSource {
+    Video,
+    Images,
+}
+
+/// Presets of the look of the video, described to Gemini as `{style}`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Style {
+    /// Dramatic lighting and smooth camera moves.
+    Cinematic,
+    /// Natural lighting and true-to-life materials.
+    Photorealistic,
+    /// Soft, even lighting on a plain backdrop, the easiest to reconstruct.
+    Studio,
+    /// Stylized 3D animation.
+    Animated,
+}
+
+impl Style {
+    fn description(self) -> &'static str {
+        match self {
+            Style::Cinematic => "cinematic, with dramatic lighting and smooth camera moves",
+            Style::Photorealistic => "photorealistic, with natural lighting and true-to-life materials",
+            Style::Studio => "a clean studio shot, with soft even lighting on a plain backdrop",
+            Style::Animated => "stylized 3D animation, with bold shapes and colors",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum AspectRatio {
+    #[value(name = "16:9")]
+    Landscape,
+    #[value(name = "9:16")]
+    Portrait,
+}
+
+impl AspectRatio {
+    fn as_str(self) -> &'static str {
+        match self {
+            AspectRatio::Landscape => "16:9",
+            AspectRatio::Portrait => "9:16",
+        }
+    }
+}
+
+/// Parses a `KEY=VALUE` variable of the prompt template.
+fn parse_template_var(text: &str) -> Result<(String, String), String> {
+    let (key, value) = text.split_once('=').ok_or("expected KEY=VALUE")?;
+    if !template::is_name(key) {
+        return Err(format!("'{}' is not a variable name, which takes only letters, digits and underscores", key));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Diagnose the API key, the video decoder and the temporary directory.
+    Doctor(doctor::DoctorArgs),
+}
+
+/// How views are saved from videos with more than 8 bits per sample or in HDR.
+#[derive(Args, Debug)]
+struct BitDepthArgs {
+    /// Save the views as 16-bit PNGs in the color encoding of the video, without
+    /// converting them to 8-bit sRGB.
+    #[arg(long)]
+    preserve_bit_depth: bool,
+
+    /// The curve compressing the luminance of HDR videos into the range of the views.
+    #[arg(long, value_enum, value_name = "CURVE", default_value = "hable", conflicts_with = "preserve_bit_depth")]
+    tone_map: ToneMap,
+}
+
+/// Where the extracted views go: a directory, or a tar archive written as they are
+/// extracted, along with its path unless it goes to stdout.
+enum ViewsSink {
+    Dir(PathBuf),
+    Tar(tar::Builder<TarStream>, Option<PathBuf>),
+}
+
+/// The stream a tar archive is written to, compressed or not.
+enum TarStream {
+    Plain(Box<dyn Write>),
+    Gzip(GzEncoder<Box<dyn Write>>),
+}
+
+impl Write for TarStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            TarStream::Plain(writer) => writer.write(buf),
+            TarStream::Gzip(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            TarStream::Plain(writer) => writer.flush(),
+            TarStream::Gzip(writer) => writer.flush(),
+        }
+    }
+}
+
+impl ViewsSink {
+    fn open(cli: &Cli) -> Result<Self> {
+        let Some(path) = &cli.tar else {
+            fs::remove_dir_all(&cli.views_dir).ok();
+            fs::create_dir_all(&cli.views_dir)?;
+            return Ok(ViewsSink::Dir(cli.views_dir.clone()));
+        };
+        let (writer, path): (Box<dyn Write>, _) = if path == Path::new("-") {
+            (Box::new(io::stdout()), None)
+        } else {
+            let file = fs::File::create(path)
+                .wrap_err_with(|| format!("Failed to create {}", path.display()))?;
+            (Box::new(file), Some(path.clone()))
+        };
+        let stream = if cli.gzip {
+            TarStream::Gzip(GzEncoder::new(writer, Compression::default()))
+        } else {
+            TarStream::Plain(writer)
+        };
+        Ok(ViewsSink::Tar(tar::Builder::new(stream), path))
+    }
+
+    /// A directory in the temporary directory, holding the views of an attempt until
+    /// they are counted.
+    fn staging() -> Result<Self> {
+        let dir = env::temp_dir().join(format!("text-to-view-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir)?;
+        Ok(ViewsSink::Dir(dir))
+    }
+
+    /// Saves a view or the manifest under `name`, returning where it went.
+    fn save(&mut self, name: &str, data: &[u8]) -> Result<String> {
+        match self {
+            ViewsSink::Dir(dir) => {
+                let path = dir.join(name);
+                usage::write(&path, data).with_context(|| format!("Failed to save {}", path.display()))?;
+                Ok(path.display().to_string())
+            }
+            ViewsSink::Tar(builder, _) => {
+                // Plain names and permissions, whoever runs the tool.
+                let mut header = tar::Header::new_ustar();
+                header.set_path(name)?;
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map_or(0, |elapsed| elapsed.as_secs()),
+                );
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_cksum();
+                builder.append(&header, data).wrap_err("Failed to write the tar archive")?;
+                // Send each view on its way as soon as it is saved.
+                builder.get_mut().flush()?;
+                Ok(format!("the tar archive as {}", name))
+            }
+        }
+    }
+
+    /// Opens the sink of `cli` and moves the views listed in `frames` into it one at a
+    /// time, out of the staging directory `dir`, which is then removed.
+    fn unstage(cli: &Cli, dir: &Path, frames: &[ViewFrame]) -> Result<Self> {
+        let moved = Self::open(cli).and_then(|mut sink| {
+            for frame in frames {
+                let saved = usage::read(&dir.join(&frame.file)).map_err(Into::into).and_then(|data| sink.save(&frame.file, &data));
+                match saved {
+                    Ok(saved_to) => eprintln!("Saved frame at {}s to {}", frame.timestamp.unwrap_or_default(), saved_to),
+                    Err(e) => {
+                        sink.discard();
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(sink)
+        });
+        fs::remove_dir_all(dir).ok();
+        moved
+    }
+
+    /// Removes the views saved so far, so that a failed attempt leaves no partial set
+    /// behind. An archive written to stdout cannot be taken back, but lacks the
+    /// manifest that marks it complete.
+    fn discard(self) {
+        match self {
+            ViewsSink::Dir(dir) => {
+                fs::remove_dir_all(dir).ok();
+            }
+            ViewsSink::Tar(builder, path) => {
+                drop(builder);
+                if let Some(path) = path {
+                    fs::remove_file(path).ok();
+                }
+            }
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        if let ViewsSink::Tar(builder, _) = self {
+            match builder.into_inner().wrap_err("Failed to finish the tar archive")? {
+                TarStream::Plain(mut writer) => writer.flush()?,
+                TarStream::Gzip(writer) => writer.finish()?.flush()?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The extracted views in temporal order, saved as `manifest.json` next to them.
+#[derive(Serialize)]
+struct ViewsManifest {
+    frames: Vec<ViewFrame>,
+    /// The constant frame rate the video was re-encoded to before extraction, if it
+    /// was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    normalized_fps: Option<u32>,
+    /// The clockwise rotation in degrees the frames were turned upright by, from the
+    /// display rotation of the video, if they were.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rotation: Option<u32>,
+    /// The prompt the views were generated from, as finally sent.
+    prompt: UsedPrompt,
+    /// What the run wrote to temporary files and held in memory.
+    usage: usage::Usage,
+    /// Each submission of the video generation, the last one making the views.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attempts: Vec<GenerationAttempt>,
+}
+
+#[derive(Serialize)]
+struct GenerationAttempt {
+    prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u32>,
+    usable_frames: usize,
+    /// Why the generation was submitted again after this attempt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UsedPrompt {
+    text: String,
+    /// `optimized`, `original` when used as given or the optimization failed,
+    /// `edited` at --confirm-prompt, or `reused` from --reuse-prompt.
+    source: String,
+}
+
+impl UsedPrompt {
+    fn new(text: &str, source: &str) -> Self {
+        UsedPrompt { text: text.to_string(), source: source.to_string() }
+    }
+
+    /// Reads the prompt recorded in a views manifest by an earlier run.
+    fn reuse(path: &Path) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Recorded {
+            prompt: Option<UsedPrompt>,
+        }
+        let json = fs::read_to_string(path).wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        let recorded: Recorded = serde_json::from_str(&json).wrap_err_with(|| format!("Failed to parse {}", path.display()))?;
+        let prompt = recorded.prompt.ok_or_else(|| eyre!("{} records no prompt, it was written before prompts were recorded", path.display()))?;
+        Ok(UsedPrompt::new(&prompt.text, "reused"))
+    }
+}
+
+#[derive(Serialize)]
+struct ViewFrame {
+    file: String,
+    /// Where the view comes from: `video` for extracted frames, `image` for
+    /// generated stills.
+    source: &'static str,
+    /// The video clip the frame was extracted from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<f64>,
+    /// The angle a still image was generated from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    angle: Option<&'static str>,
+    /// Why the frame is not usable for reconstruction: `blurry` or `duplicate`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unusable: Option<&'static str>,
+    /// SHA-256 of the saved file, to audit later that it was not altered.
+    sha256: String,
+}
+
 // --- Veo API Structures ---
 #[derive(Serialize)]
 struct VeoRequest<'a> {
@@ -34,6 +496,17 @@ struct VeoRequest<'a> {
 #[derive(Serialize)]
 struct Instance<'a> {
     prompt: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<&'a InlineImage>,
+}
+
+/// An image sent inline with a request.
+#[derive(Serialize)]
+struct InlineImage {
+    #[serde(rename = "bytesBase64Encoded")]
+    bytes_base64_encoded: String,
+    #[serde(rename = "mimeType")]
+    mime_type: &'static str,
 }
 
 #[derive(Serialize)]
@@ -46,6 +519,8 @@ struct Parameters<'a> {
     sample_count: u32,
     #[serde(rename = "durationSeconds")]
     duration_seconds: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u32>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -145,168 +620,445 @@ Add details about camera view movement, lighting, tracking, and composition whil
 
 Your output MUST be only the rewritten prompt text and nothing else.
 
+The video should look {style}. It lasts {duration_seconds} seconds, framed at {aspect_ratio}.
+
 **User's Base Prompt:**
-"{user_prompt_here}"
+"{prompt}"
 "#;
 
+/// The variables of the prompt template, from the options and --template-var.
+fn template_context(cli: &Cli, user_prompt: &str) -> Result<template::Context> {
+    let options = [
+        ("prompt", user_prompt.to_string()),
+        ("style", cli.style.description().to_string()),
+        ("duration_seconds", cli.duration_seconds.to_string()),
+        ("aspect_ratio", cli.aspect_ratio.as_str().to_string()),
+    ];
+    let mut context = template::Context::new();
+    for (key, value) in &cli.template_var {
+        if options.iter().any(|(name, _)| name == key) {
+            return Err(eyre!("--template-var cannot set {{{}}}, which comes from the options", key));
+        }
+        context.insert(key.clone(), value.clone());
+    }
+    context.extend(options.map(|(name, value)| (name.to_string(), value)));
+    Ok(context)
+}
+
 /// Rewrites the user's prompt using the Gemini API.
-async fn optimize_prompt(client: &reqwest::Client, api_key: &str, user_prompt: &str) -> Result<String> {
-    let model_id = "gemini-2.5-flash-preview-05-20";
-    let api_method = "streamGenerateContent";
-    let url = format!(
-        "https://generativelanguage.googleapis.com/v1beta/models/{}:{}?key={}",
-        model_id, api_method, api_key
-    );
-
-    let meta_prompt = META_PROMPT_TEMPLATE.replace("{user_prompt_here}", user_prompt);
-
-    let request_body = GeminiRequest {
-        contents: vec![Content {
-            role: "user",
-            parts: vec![Part { text: &meta_prompt }],
-        }],
-        generation_config: GenerationConfig {
-            response_mime_type: "text/plain",
-            temperature: 1.4,
-            top_p: 0.9,
-        },
+async fn optimize_prompt(client: &reqwest::Client, api_key: &str, meta_prompt: &str, cancel: &CancellationToken) -> Result<String> {
+    cancel::or_cancelled(cancel, async {
+        let model_id = "gemini-2.5-flash-preview-05-20";
+        let api_method = "streamGenerateContent";
+        let url = format!(
+            "https://generativelanguage.googleapis.com/v1beta/models/{}:{}?key={}",
+            model_id, api_method, api_key
+        );
+
+        let request_body = GeminiRequest {
+            contents: vec![Content {
+                role: "user",
+                parts: vec![Part { text: meta_prompt }],
+            }],
+            generation_config: GenerationConfig {
+                response_mime_type: "text/plain",
+                temperature: 1.4,
+                top_p: 0.9,
+            },
+        };
+
+        eprintln!("Asking Gemini to optimize prompt...");
+        let response = client
+            .post(&url)
+            .json(&request_body)
+            .send_traced()
+            .await?
+            .error_for_status()?;
+
+        let response_bytes = response.bytes().await?;
+        let response_text = std::str::from_utf8(&response_bytes)?;
+
+        // The streaming API returns chunks of JSON in an array. We need to parse them.
+        let gemini_responses: Vec<GeminiResponse> = serde_json::from_str(response_text)
+            .wrap_err_with(|| format!("Failed to parse Gemini's streaming response: {}", response_text))?;
+
+        let mut optimized_prompt = String::new();
+        for res in gemini_responses {
+            let Some(candidate) = res.candidates.first() else { continue };
+            let Some(part) = candidate.content.parts.first() else { continue };
+            optimized_prompt.push_str(&part.text);
+        }
+
+        if optimized_prompt.is_empty() {
+            return Err(eyre!("Gemini did not return an optimized prompt. Using original."));
+        }
+
+        eprintln!("Successfully optimized prompt: '{}'", optimized_prompt);
+        Ok(optimized_prompt)
+    }).await
+}
+
+
+/// Reads the image conditioning the video, converting it to PNG unless Veo takes it
+/// as it is.
+fn read_image(path: &Path) -> Result<InlineImage> {
+    let bytes = fs::read(path).wrap_err_with(|| format!("Failed to read the image {}", path.display()))?;
+    let format = image::guess_format(&bytes)
+        .wrap_err_with(|| format!("{} is not an image in a known format", path.display()))?;
+    let (bytes, mime_type) = match format {
+        image::ImageFormat::Jpeg => (bytes, "image/jpeg"),
+        image::ImageFormat::Png => (bytes, "image/png"),
+        _ => {
+            let decoded = image::load_from_memory_with_format(&bytes, format)
+                .wrap_err_with(|| format!("Failed to decode the image {}", path.display()))?;
+            let mut png = std::io::Cursor::new(Vec::new());
+            decoded.write_to(&mut png, image::ImageFormat::Png)?;
+            (png.into_inner(), "image/png")
+        }
     };
+    Ok(InlineImage { bytes_base64_encoded: BASE64_STANDARD.encode(bytes), mime_type })
+}
 
-    eprintln!("Asking Gemini to optimize prompt...");
-    let response = client
-        .post(&url)
-        .json(&request_body)
-        .send()
-        .await?
-        .error_for_status()?;
+/// The Veo model generating the videos.
+const VEO_MODEL: &str = "veo-2.0-generate-001";
 
-    let response_bytes = response.bytes().await?;
-    let response_text = std::str::from_utf8(&response_bytes)?;
-    
-    // The streaming API returns chunks of JSON in an array. We need to parse them.
-    let gemini_responses: Vec<GeminiResponse> = serde_json::from_str(response_text)
-        .wrap_err_with(|| format!("Failed to parse Gemini's streaming response: {}", response_text))?;
+/// Submits the video generation request and polls until the video URI is available.
+async fn submit_and_poll(
+    client: &reqwest::Client,
+    api_key: &str,
+    prompt: &str,
+    image: Option<&InlineImage>,
+    parameters: Parameters<'_>,
+    cancel: &CancellationToken,
+) -> Result<String> {
+    cancel::or_cancelled(cancel, async {
+        let url = format!(
+            "https://generativelanguage.googleapis.com/v1beta/models/{}:predictLongRunning?key={}",
+            VEO_MODEL, api_key
+        );
 
-    let mut optimized_prompt = String::new();
-    for res in gemini_responses {
-        let Some(candidate) = res.candidates.get(0) else { continue };
-        let Some(part) = candidate.content.parts.get(0) else { continue };
-        optimized_prompt.push_str(&part.text);
-    }
+        let request_body = VeoRequest {
+            instances: vec![Instance { prompt, image }],
+            parameters,
+        };
+
+        eprintln!("Submitting video generation job for the optimized prompt...");
+        let initial_response = client
+            .post(&url)
+            .json(&request_body)
+            .send_traced()
+            .await?
+            .error_for_status()?;
+
+        let operation: LongRunningOperation = initial_response.json().await?;
+        let op_name = operation.name;
+        eprintln!("Job submitted. Operation name: {}", op_name);
+
+        let status_url = format!("https://generativelanguage.googleapis.com/v1beta/{}?key={}", op_name, api_key);
+        loop {
+            let status_response = client.get(&status_url).send_traced().await?.error_for_status()?;
+            let status: OperationStatus = status_response.json().await?;
+
+            if !status.done.unwrap_or(false) {
+                eprintln!("Video not ready yet. Checking again in 6 seconds...");
+                if !http_trace::recording::is_replaying() {
+                    sleep(Duration::from_secs(6)).await;
+                }
+                continue;
+            }
+
+            eprintln!("Video generation complete!");
+            if let Some(error) = status.error {
+                return Err(eyre!("Operation finished with an error: (Code {}) {}", error.code, error.message));
+            }
+
+            let response = status.response.ok_or_else(|| eyre!("Operation is done, but no response field was found."))?;
+            let samples = response.generate_video_response.generated_samples.ok_or_else(|| eyre!("Response did not contain any generated video samples."))?;
+            let sample = samples.first().ok_or_else(|| eyre!("Response contained no video samples."))?;
+
+            return Ok(sample.video.uri.clone());
+        }
+    }).await
+}
+
+/// Downloads a video from a given URL and saves it to a temporary file.
+async fn download_video(client: &reqwest::Client, api_key: &str, video_url: &str, cancel: &CancellationToken) -> Result<TempVideo> {
+    cancel::or_cancelled(cancel, async {
+        let download_url = format!("{}&key={}", video_url, api_key);
+        let video_bytes = usage::read_body(client.get(&download_url).send_traced().await?).await?;
 
-    if optimized_prompt.is_empty() {
-        return Err(eyre!("Gemini did not return an optimized prompt. Using original."));
+        let temp_path = TempVideo::new("video.mp4")?;
+        usage::write(&temp_path, &video_bytes)?;
+
+        eprintln!("Successfully downloaded video to temporary path: {}", temp_path.display());
+        Ok(temp_path)
+    }).await
+}
+
+/// Frame rates within this fraction of the one asked for need no re-encoding.
+const FPS_TOLERANCE: f64 = 0.01;
+
+/// Intervals between frames further than this fraction from their mean make a
+/// variable frame rate.
+const INTERVAL_JITTER: f64 = 0.25;
+
+/// A video in the temporary directory, removed when dropped, so that a failed or
+/// cancelled run leaves it behind no more than a finished one does.
+struct TempVideo {
+    path: PathBuf,
+    /// The directory of the video, of its own so that concurrent runs never share
+    /// a video, removed after it.
+    _dir: tempfile::TempDir,
+}
+
+impl TempVideo {
+    /// A video to write named `name`, in a new directory under the temporary one.
+    fn new(name: &str) -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("text-to-view-")
+            .tempdir()
+            .wrap_err("Failed to create a temporary directory for the video")?;
+        Ok(TempVideo { path: dir.path().join(name), _dir: dir })
     }
-    
-    eprintln!("Successfully optimized prompt: '{}'", optimized_prompt);
-    Ok(optimized_prompt)
 }
 
+impl std::ops::Deref for TempVideo {
+    type Target = Path;
 
-/// Submits the video generation request and polls until the video URI is available.
-async fn submit_and_poll(client: &reqwest::Client, api_key: &str, prompt: &str) -> Result<String> {
-    let model_id = "veo-2.0-generate-001";
-    let url = format!(
-        "https://generativelanguage.googleapis.com/v1beta/models/{}:predictLongRunning?key={}",
-        model_id, api_key
-    );
-
-    let request_body = VeoRequest {
-        instances: vec![Instance { prompt }],
-        parameters: Parameters {
-            person_generation: "allow_all",
-            aspect_ratio: "16:9",
-            sample_count: 1,
-            duration_seconds: 5,
-        },
-    };
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempVideo {
+    fn drop(&mut self) {
+        if fs::remove_file(&self.path).is_ok() {
+            eprintln!("Cleaned up temporary video file: {}", self.path.display());
+        }
+    }
+}
 
-    eprintln!("Submitting video generation job for the optimized prompt...");
-    let initial_response = client
-        .post(&url)
-        .json(&request_body)
-        .send()
-        .await?
-        .error_for_status()?;
+/// Re-encodes the video at `video_path` to a constant `fps` in the temporary
+/// directory, unless it already has a constant frame rate close to it. Returns the
+/// re-encoded video, if any.
+fn normalize_frame_rate(video_path: &Path, fps: u32) -> Result<Option<TempVideo>> {
+    video_rs::init().map_err(|e| eyre!(e.to_string()))?;
 
-    let operation: LongRunningOperation = initial_response.json().await?;
-    let op_name = operation.name;
-    eprintln!("Job submitted. Operation name: {}", op_name);
+    let timestamps = frame_timestamps(video_path)?;
+    if is_constant_rate(&timestamps, fps as f64) {
+        eprintln!("The video already has a constant frame rate of {} fps", fps);
+        return Ok(None);
+    }
+    // Owned from the start, so that a partial re-encoding is removed as well.
+    let output = TempVideo::new(&format!("video-{}fps.mp4", fps))?;
+    eprintln!("Re-encoding the video to a constant frame rate of {} fps...", fps);
+    // The encoder writes the file itself, so it is counted once written.
+    transcode(video_path, fps, &output)
+        .and_then(|()| Ok(usage::reserve_temp(fs::metadata(&*output)?.len())?))
+        .wrap_err("Failed to re-encode the video to a constant frame rate")?;
+    Ok(Some(output))
+}
 
-    let status_url = format!("https://generativelanguage.googleapis.com/v1beta/{}?key={}", op_name, api_key);
+/// The presentation times of the frames of the video in seconds, read from its
+/// packets without decoding them, in order.
+fn frame_timestamps(video_path: &Path) -> Result<Vec<f64>> {
+    let (_, mut reader, stream) = Decoder::new(video_path)?.into_parts();
+    let mut timestamps = Vec::new();
     loop {
-        let status_response = client.get(&status_url).send().await?.error_for_status()?;
-        let status: OperationStatus = status_response.json().await?;
-
-        if !status.done.unwrap_or(false) {
-            eprintln!("Video not ready yet. Checking again in 6 seconds...");
-            sleep(Duration::from_secs(6)).await;
-            continue;
+        match reader.read(stream) {
+            Ok(packet) if packet.pts().has_value() => timestamps.push(packet.pts().as_secs_f64()),
+            Ok(_) => {}
+            Err(video_rs::Error::ReadExhausted) => break,
+            Err(e) => return Err(e.into()),
         }
+    }
+    // Packets come in decoding order, which differs when frames are predicted from
+    // later ones.
+    timestamps.sort_by(f64::total_cmp);
+    Ok(timestamps)
+}
+
+/// Whether the frames at `timestamps` are evenly spaced at close to `fps`.
+fn is_constant_rate(timestamps: &[f64], fps: f64) -> bool {
+    let (Some(first), Some(last)) = (timestamps.first(), timestamps.last()) else {
+        return false;
+    };
+    if last <= first {
+        return false;
+    }
+    let mean = (last - first) / (timestamps.len() - 1) as f64;
+    (1.0 / mean - fps).abs() <= fps * FPS_TOLERANCE
+        && timestamps
+            .windows(2)
+            .all(|pair| (pair[1] - pair[0] - mean).abs() <= mean * INTERVAL_JITTER)
+}
+
+/// Writes the video at `video_path` to `output` at a constant `fps`, each frame
+/// showing the last source frame presented by its time.
+fn transcode(video_path: &Path, fps: u32, output: &Path) -> Result<()> {
+    let mut decoder = Decoder::new(video_path)?;
+    let time_base = decoder.time_base();
+    let (width, height) = decoder.size_out();
+    let mut encoder = Encoder::new(output, Settings::preset_h264_yuv420p(width as usize, height as usize, false))?;
 
-        eprintln!("Video generation complete!");
-        if let Some(error) = status.error {
-            return Err(eyre!("Operation finished with an error: (Code {}) {}", error.code, error.message));
+    let mut start = None;
+    let mut previous: Option<(f64, RawFrame)> = None;
+    let mut written = 0;
+    loop {
+        let frame = match decoder.decode_raw() {
+            Ok(frame) => frame,
+            Err(video_rs::Error::DecodeExhausted) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let Some(pts) = frame.pts() else {
+            continue;
+        };
+        let time = Time::new(Some(pts), time_base).as_secs_f64();
+        let time = time - *start.get_or_insert(time);
+        if let Some((_, shown)) = &previous {
+            written = encode_until(&mut encoder, shown, written, fps, time)?;
         }
+        previous = Some((time, frame));
+    }
+    let Some((time, last)) = previous else {
+        return Err(eyre!("The video has no frames"));
+    };
+    // The last frame lasts one output frame.
+    encode_until(&mut encoder, &last, written, fps, time + 1.0 / fps as f64)?;
+    encoder.finish()?;
+    Ok(())
+}
 
-        let response = status.response.ok_or_else(|| eyre!("Operation is done, but no response field was found."))?;
-        let samples = response.generate_video_response.generated_samples.ok_or_else(|| eyre!("Response did not contain any generated video samples."))?;
-        let sample = samples.first().ok_or_else(|| eyre!("Response contained no video samples."))?;
-        
-        return Ok(sample.video.uri.clone());
+/// Encodes `frame` as every output frame from the `written`th up to `until` seconds,
+/// returning the number of frames written so far.
+fn encode_until(encoder: &mut Encoder, frame: &RawFrame, mut written: u64, fps: u32, until: f64) -> Result<u64> {
+    while (written as f64) / (fps as f64) < until {
+        let pts = Time::from_secs_f64(written as f64 / fps as f64).with_time_base(encoder.time_base());
+        let mut copy = frame.clone();
+        copy.set_pts(pts.into_value());
+        encoder.encode_raw(copy)?;
+        written += 1;
     }
+    Ok(written)
 }
 
-/// Downloads a video from a given URL and saves it to a temporary file.
-async fn download_video(client: &reqwest::Client, api_key: &str, video_url: &str) -> Result<PathBuf> {
-    let download_url = format!("{}&key={}", video_url, api_key);
-    let video_bytes = client.get(&download_url).send().await?.bytes().await?;
-    
-    let mut temp_path = env::temp_dir();
-    temp_path.push("video.mp4");
-
-    let mut file = fs::File::create(&temp_path)?;
-    file.write_all(&video_bytes)?;
-    
-    eprintln!("Successfully downloaded video to temporary path: {}", temp_path.display());
-    Ok(temp_path)
-}
-
-/// Extracts frames from a video file at specified timestamps.
-fn extract_frames(video_path: &Path) -> Result<()> {
-    video_rs::init().map_err(|e| eyre!(e.to_string()))?;
+/// The timestamps of the frames extracted as views, in seconds.
+const TIMESTAMPS: [f64; 6] = [0.0, 0.5, 1.5, 2.5, 3.5, 4.5];
 
-    let output_dir = Path::new("views");
-    fs::remove_dir_all(output_dir).ok();
-    fs::create_dir_all(output_dir)?;
+/// Appended to the prompt when the generation is retried for too few usable frames.
+const RETRY_HINT: &str = "slow orbiting camera, sharp focus";
+
+/// A still image generated as a view, encoded but not saved yet.
+struct ExtractedView {
+    frame: ViewFrame,
+    data: Vec<u8>,
+}
+
+/// Extracts frames from a video file at specified timestamps, turned clockwise by
+/// `rotation` degrees and marking those that are not usable. Each frame is passed
+/// to `check` and saved into `sink` as soon as it is encoded, so that only one is
+/// held in memory. Checks `cancel` between frames.
+fn extract_frames(
+    video_path: &Path,
+    clip: &str,
+    depth: &BitDepthArgs,
+    rotation: u32,
+    sink: &mut ViewsSink,
+    check: impl Fn(&ViewFrame, &DynamicImage) -> Result<()>,
+    cancel: &CancellationToken,
+) -> Result<Vec<ViewFrame>> {
+    video_rs::init().map_err(|e| eyre!(e.to_string()))?;
 
     let frame_rate = Decoder::new(video_path)?.frame_rate();
-    let timestamps = [0.0, 0.5, 1.5, 2.5, 3.5, 4.5];
+    let targets: Vec<usize> = TIMESTAMPS.iter().map(|&time_sec| (time_sec * frame_rate as f64) as usize).collect();
+    let mut frames = Vec::with_capacity(TIMESTAMPS.len());
+    let mut gate = usable::Gate::default();
 
-    for (i, &time_sec) in timestamps.iter().enumerate() {
-        let mut decoder = Decoder::new(video_path)
-            .with_context(|| format!("Failed to create decoder for timestamp {}s", time_sec))?;
+    // High-bit-depth and HDR frames are decoded apart, since the default decoding
+    // would squeeze them into 8-bit RGB as if they were SDR.
+    let color = hdr::ColorInfo::probe(video_path)?;
+    let converted = if depth.preserve_bit_depth || color.needs_conversion() {
+        if depth.preserve_bit_depth {
+            eprintln!("Saving the views as 16-bit PNGs in the color encoding of the video");
+        } else if color.is_hdr() {
+            eprintln!("The video is HDR ({:?}, {}-bit), tone-mapping the views to sRGB with {:?}", color.transfer, color.bit_depth, depth.tone_map);
+        } else {
+            eprintln!("The video is {}-bit, converting the views to 8-bit sRGB", color.bit_depth);
+        }
+        let frames = hdr::decode_frames(video_path, &targets)?;
+        usage::buffer(frames.iter().map(|frame| frame.as_raw().len() * 2).sum());
+        Some(frames)
+    } else {
+        None
+    };
 
-        let target_frame = (time_sec * frame_rate as f64) as usize;
-        let Some(frame_result) = decoder.decode_raw_iter().nth(target_frame) else {
-            return Err(eyre!("Failed to extract frame at {}s (frame {}): timestamp may be out of video duration.", time_sec, target_frame));
-        };
+    if rotation != 0 {
+        eprintln!("The video is displayed rotated by {} degrees, turning the views upright", rotation);
+    }
 
-        let frame = frame_result.with_context(|| format!("Failed to decode frame at position {}", target_frame))?;
-        let file_name = format!("{}.jpg", i);
-        let output_path = output_dir.join(file_name);
+    for (i, (&time_sec, &target_frame)) in TIMESTAMPS.iter().zip(&targets).enumerate() {
+        cancel::check(cancel)?;
+        let (image, format) = match &converted {
+            Some(frames) => hdr::view(&frames[i], color, depth.preserve_bit_depth, depth.tone_map),
+            None => {
+                let mut decoder = Decoder::new(video_path)
+                    .with_context(|| format!("Failed to create decoder for timestamp {}s", time_sec))?;
 
-        save_buffer(
-            &output_path,
-            frame.data(0),
-            frame.width(),
-            frame.height(),
-            image::ColorType::Rgb8,
-        ).with_context(|| format!("Failed to save frame to {}", output_path.display()))?;
-        
-        eprintln!("Saved frame at {}s (frame {}) to {}", time_sec, target_frame, output_path.display());
+                let Some(frame_result) = decoder.decode_raw_iter().nth(target_frame) else {
+                    return Err(eyre!("Failed to extract frame at {}s (frame {}): timestamp may be out of video duration.", time_sec, target_frame));
+                };
+
+                let frame = frame_result.with_context(|| format!("Failed to decode frame at position {}", target_frame))?;
+                let image = RgbImage::from_raw(frame.width(), frame.height(), frame.data(0).to_vec())
+                    .ok_or_else(|| eyre!("Failed to read the decoded frame at {}s", time_sec))?;
+                (DynamicImage::ImageRgb8(image), ImageFormat::Jpeg)
+            }
+        };
+        let image = rotation::apply(image, rotation);
+        // Encoded in memory, so that the file is hashed as it is written.
+        let mut data = Vec::new();
+        image.write_to(&mut io::Cursor::new(&mut data), format)
+            .with_context(|| format!("Failed to encode frame at {}s", time_sec))?;
+        let file_name = format!("{}.{}", i, format.extensions_str()[0]);
+        let unusable = gate.assess(&image).err();
+        let frame = ViewFrame {
+            file: file_name,
+            source: "video",
+            clip: Some(clip.to_string()),
+            timestamp: Some(time_sec),
+            angle: None,
+            unusable: unusable.map(usable::Unusable::as_str),
+            sha256: format!("{:x}", Sha256::digest(&data)),
+        };
+        check(&frame, &image)?;
+        let saved_to = sink.save(&frame.file, &data)?;
+        match unusable {
+            Some(reason) => eprintln!("Saved frame at {}s (frame {}) to {}, {}", time_sec, target_frame, saved_to, reason.as_str()),
+            None => eprintln!("Saved frame at {}s (frame {}) to {}", time_sec, target_frame, saved_to),
+        }
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+/// Saves the extracted views into `sink`, followed by `manifest` listing them.
+fn save_views(views: Vec<ExtractedView>, mut manifest: ViewsManifest, sink: &mut ViewsSink) -> Result<()> {
+    for view in views {
+        let saved_to = sink.save(&view.frame.file, &view.data)?;
+        match view.frame.angle {
+            Some(angle) => eprintln!("Saved the {} view to {}", angle, saved_to),
+            None => eprintln!("Saved frame at {}s to {}", view.frame.timestamp.unwrap_or_default(), saved_to),
+        }
+        manifest.frames.push(view.frame);
     }
+    save_manifest(&manifest, sink)
+}
 
+/// Saves `manifest` into `sink` after the views it lists.
+fn save_manifest(manifest: &ViewsManifest, sink: &mut ViewsSink) -> Result<()> {
+    // The manifest comes last, so that readers of an archive can tell it is complete.
+    sink.save("manifest.json", serde_json::to_string_pretty(manifest)?.as_bytes())
+        .wrap_err("Failed to write the views manifest")?;
     Ok(())
 }
 
@@ -316,39 +1068,297 @@ async fn main() -> Result<()> {
     color_eyre::install()?;
 
     // --- 1. Setup ---
-    let api_key = env::var("GEMINI_API_KEY").wrap_err("GEMINI_API_KEY environment variable not set")?;
+    let cli = Cli::parse();
+    if let Some(Commands::Doctor(args)) = &cli.command {
+        return doctor::run(args).await;
+    }
+    if cli.check {
+        // Nothing is sent to the API, so that checking spends no quota.
+        let result = env::var("GEMINI_API_KEY")
+            .wrap_err("GEMINI_API_KEY environment variable not set")
+            .and_then(|_| video_rs::init().map_err(|e| eyre!("The video decoder failed to initialize: {}", e)));
+        println!("{}", result.as_ref().map_or_else(|e| e.to_string(), |_| "ok".to_string()));
+        return result;
+    }
+    // Recorded URLs have their key redacted, so that any key matches on replay.
+    let api_key = match env::var("GEMINI_API_KEY") {
+        Err(_) if cli.replay.is_some() => "replay".to_string(),
+        Err(_) if cli.mock => "mock".to_string(),
+        api_key => api_key.wrap_err("GEMINI_API_KEY environment variable not set")?,
+    };
     let client = reqwest::Client::new();
+    let cancel = &cancel::on_signal();
+    http_trace::redact(&api_key);
+    if let Some(bytes) = cli.max_temp_bytes {
+        http_trace::usage::install(bytes)?;
+    }
+    if let Some(path) = &cli.debug_http {
+        http_trace::install(path, http_trace::DEFAULT_BODY_LIMIT)
+            .wrap_err_with(|| format!("Failed to create the HTTP trace at {}", path.display()))?;
+    }
+    if let Some(dir) = &cli.record {
+        http_trace::recording::record(dir).wrap_err_with(|| format!("Failed to start recording into {}", dir.display()))?;
+    }
+    if let Some(dir) = &cli.replay {
+        http_trace::recording::replay(dir).wrap_err_with(|| format!("Failed to read the recording in {}", dir.display()))?;
+    }
+    http_trace::limit::install(http_trace::limit::RateLimits {
+        gemini_rpm: cli.gemini_rpm,
+        veo_rpm: cli.veo_rpm,
+        operations_rpm: cli.operations_rpm,
+        imagen_rpm: cli.imagen_rpm,
+    })?;
 
-    let prompt_parts: Vec<String> = env::args().skip(1).collect();
-    if prompt_parts.is_empty() {
-        return Err(eyre!("Please provide a prompt as a command-line argument."));
+    if cli.tar.as_deref() == Some(Path::new("-")) && io::stdout().is_terminal() {
+        return Err(eyre!("Refusing to write a tar archive to the terminal. Redirect stdout to a file or a pipe."));
     }
-    let user_prompt = prompt_parts.join(" ");
+    // Checked before anything is sent, so that a run that cannot be confirmed spends no quota.
+    if cli.confirm_prompt {
+        confirm::ensure_terminal()?;
+    }
+    let user_prompt = cli.prompt.join(" ");
+    if cli.view_source == ViewSource::Images {
+        if cli.image.is_some() || cli.normalize_fps.is_some() || cli.depth.preserve_bit_depth || cli.no_auto_rotate || cli.mock {
+            return Err(eyre!("--image, --normalize-fps, --preserve-bit-depth, --no-auto-rotate and --mock apply to videos, not to --view-source images"));
+        }
+        if cli.confirm_prompt || cli.reuse_prompt.is_some() || cli.prompt_template.is_some() {
+            return Err(eyre!("--confirm-prompt, --reuse-prompt and --prompt-template apply to the optimized prompt of videos, not to --view-source images"));
+        }
+        // The prompt optimization is written for videos, so stills use the prompt as it is.
+        let views = stills::generate_views(&client, &api_key, &user_prompt, cli.image_views as usize, cancel).await?;
+        let manifest = ViewsManifest { frames: Vec::with_capacity(views.len()), normalized_fps: None, rotation: None, prompt: UsedPrompt::new(&user_prompt, "original"), usage: usage::take_stage(), attempts: Vec::new() };
+        let mut sink = ViewsSink::open(&cli)?;
+        save_views(views, manifest, &mut sink)?;
+        sink.finish()?;
+        eprintln!("Still image generation successful!");
+        return Ok(());
+    }
+    // Read before anything is sent, so that a bad image or template spends no quota.
+    let image = cli.image.as_deref().map(read_image).transpose()?;
+    let meta_prompt_template = match &cli.prompt_template {
+        Some(path) => fs::read_to_string(path).wrap_err_with(|| format!("Failed to read the prompt template {}", path.display()))?,
+        None => META_PROMPT_TEMPLATE.to_string(),
+    };
+    let meta_prompt = template::render(&meta_prompt_template, &template_context(&cli, &user_prompt)?)?;
+    let content_keywords: Vec<String> = match &cli.content_keywords {
+        Some(path) => fs::read_to_string(path).wrap_err_with(|| format!("Failed to read the content keywords {}", path.display()))?
+            .lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).map(String::from).collect(),
+        None => validate::CONTENT_KEYWORDS.iter().map(|keyword| keyword.to_string()).collect(),
+    };
 
     // --- 2. Prompt Alchemy ---
-    let optimized_prompt = match optimize_prompt(&client, &api_key, &user_prompt).await {
-        Ok(prompt) => prompt,
-        Err(e) => {
-            eprintln!("Could not optimize prompt, using original: {}", e);
-            user_prompt
+    let prompt = match &cli.reuse_prompt {
+        Some(path) => {
+            let prompt = UsedPrompt::reuse(path)?;
+            eprintln!("Reusing the prompt recorded in {}", path.display());
+            prompt
         }
+        // The synthetic video ignores the prompt, so it is not worth optimizing.
+        None if cli.mock => UsedPrompt::new(&user_prompt, "original"),
+        None => match optimize_prompt(&client, &api_key, &meta_prompt, cancel).await {
+            Ok(prompt) => UsedPrompt::new(&validate::clean(&prompt), "optimized"),
+            Err(e) if cancel.is_cancelled() => return Err(e),
+            Err(e) => {
+                eprintln!("Could not optimize prompt, using original: {}", e);
+                UsedPrompt::new(&user_prompt, "original")
+            }
+        },
+    };
+    let prompt = if cli.confirm_prompt {
+        let confirmed = confirm::confirm(&user_prompt, &prompt.text)?;
+        cancel::check(cancel)?;
+        if confirmed.edited { UsedPrompt::new(&confirmed.prompt, "edited") } else { prompt }
+    } else {
+        prompt
     };
 
-    // --- 3. Generate Video ---
-    let video_url = submit_and_poll(&client, &api_key, &optimized_prompt).await?;
-    eprintln!("Video is available at: {}", video_url);
+    // --- 3. Validate Prompt ---
+    // The cleaned prompt is the one sent and recorded.
+    let limit = validate::prompt_limit(VEO_MODEL);
+    let prompt = UsedPrompt { text: validate::fit(validate::clean(&prompt.text), limit, cli.auto_truncate)?, ..prompt };
+    let flagged = validate::flagged(&prompt.text, &content_keywords);
+    if !flagged.is_empty() {
+        eprintln!("Warning: the prompt mentions {}, which the safety filter of Veo may refuse. Reword it if the generation is blocked, or pass --content-keywords to change the words warned about.", flagged.join(", "));
+    }
 
-    // --- 4. Download Video ---
-    let video_path = download_video(&client, &api_key, &video_url).await?;
+    // --- 4. Generate Video ---
+    if image.is_some() {
+        eprintln!("Conditioning the video on the image {}", cli.image.as_ref().unwrap().display());
+    }
+    // The views are saved as they are extracted, and removed if the attempt fails.
+    // An archive cannot take back those of an attempt to be retried, so they are
+    // staged in the temporary directory until counted.
+    let staged = cli.tar.is_some() && cli.auto_retry_generation;
+    let mut generation_prompt = prompt.text.clone();
+    let mut seed = None;
+    let mut attempts: Vec<GenerationAttempt> = Vec::new();
+    let (sink, frames, normalized_fps, rotation) = loop {
+        let parameters = Parameters {
+            person_generation: "allow_all",
+            aspect_ratio: cli.aspect_ratio.as_str(),
+            sample_count: 1,
+            duration_seconds: cli.duration_seconds,
+            seed,
+        };
+        // Removed on every way out of the attempt, an error or cancellation included.
+        let video_path = if cli.mock {
+            let video = TempVideo::new("video-synthetic.mp4")?;
+            synthetic::write(&video, cli.duration_seconds, cli.aspect_ratio == AspectRatio::Portrait, cancel)?;
+            video
+        } else {
+            let video_url = submit_and_poll(&client, &api_key, &generation_prompt, image.as_ref(), parameters, cancel).await?;
+            eprintln!("Video is available at: {}", video_url);
 
-    // --- 5. Extract Frames ---
-    eprintln!("Starting frame extraction...");
-    extract_frames(&video_path)?;
-    eprintln!("Frame extraction successful!");
+            // --- 5. Download Video ---
+            download_video(&client, &api_key, &video_url, cancel).await?
+        };
 
-    // --- 6. Clean up ---
-    fs::remove_file(&video_path)?;
-    eprintln!("Cleaned up temporary video file: {}", video_path.display());
+        // --- 6. Normalize Frame Rate ---
+        let clip = video_path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let normalized_path = match cli.normalize_fps {
+            Some(fps) => normalize_frame_rate(&video_path, fps)?,
+            None => None,
+        };
+
+        // --- 7. Extract Frames ---
+        eprintln!("Starting frame extraction...");
+        // The rotation is read from the downloaded video, since re-encoding drops it.
+        let rotation = if cli.no_auto_rotate { Ok(0) } else { rotation::probe(&video_path) };
+        let normalized_fps = normalized_path.as_ref().and(cli.normalize_fps);
+        let check = |frame: &ViewFrame, image: &DynamicImage| if cli.mock { synthetic::check_view(frame, image, normalized_fps) } else { Ok(()) };
+        let mut sink = if staged { ViewsSink::staging()? } else { ViewsSink::open(&cli)? };
+        let extracted = rotation.and_then(|rotation| {
+            let frames = extract_frames(normalized_path.as_deref().unwrap_or(&video_path), &clip, &cli.depth, rotation, &mut sink, check, cancel)?;
+            Ok((frames, rotation))
+        });
+
+        // --- 8. Clean up ---
+        drop(normalized_path);
+        drop(video_path);
+        let (frames, rotation) = match extracted {
+            Ok(extracted) => extracted,
+            Err(e) => {
+                sink.discard();
+                return Err(e);
+            }
+        };
+        if cli.mock {
+            eprintln!("Every view shows the frame of the synthetic video at its timestamp");
+        }
+
+        // --- 9. Count Usable Frames ---
+        let unusable = |reason: usable::Unusable| frames.iter().filter(|frame| frame.unusable == Some(reason.as_str())).count();
+        let (blurry, duplicates) = (unusable(usable::Unusable::Blurry), unusable(usable::Unusable::Duplicate));
+        let usable_frames = frames.len() - blurry - duplicates;
+        attempts.push(GenerationAttempt { prompt: generation_prompt.clone(), seed, usable_frames, retry_reason: None });
+        if usable_frames >= cli.min_usable_frames as usize {
+            break (sink, frames, normalized_fps, rotation);
+        }
+        sink.discard();
+        let reason = format!("Only {} of {} frames are usable ({} blurry, {} near-duplicates), fewer than --min-usable-frames {}", usable_frames, frames.len(), blurry, duplicates, cli.min_usable_frames);
+        if !cli.auto_retry_generation {
+            return Err(eyre!("{}. Pass --auto-retry-generation to generate the video once more with adjusted parameters, or lower --min-usable-frames.", reason));
+        }
+        if attempts.len() > 1 {
+            return Err(eyre!("{}, even after retrying the generation. Try rewording the prompt to ask for a moving camera and a sharp subject.", reason));
+        }
+        eprintln!("{}, retrying the generation once with a new seed and a camera hint", reason);
+        attempts.last_mut().unwrap().retry_reason = Some(reason);
+        generation_prompt = validate::fit(format!("{}, {}", generation_prompt.trim_end_matches(['.', ' ']), RETRY_HINT), limit, true)?;
+        seed = Some(new_seed());
+    };
+
+    // --- 10. Save Views ---
+    if let Err(e) = cancel::check(cancel) {
+        sink.discard();
+        return Err(e);
+    }
+    // Past this point the views are saved whole, even if cancelled.
+    let mut sink = match sink {
+        ViewsSink::Dir(dir) if staged => ViewsSink::unstage(&cli, &dir, &frames)?,
+        sink => sink,
+    };
+    let prompt = UsedPrompt { text: generation_prompt, ..prompt };
+    let manifest = ViewsManifest { frames, normalized_fps, rotation: (rotation != 0).then_some(rotation), prompt, usage: usage::take_stage(), attempts };
+    save_manifest(&manifest, &mut sink).and_then(|()| sink.finish())?;
+    eprintln!("Frame extraction successful!");
 
     Ok(())
 }
+
+/// A seed for a retried generation, different from run to run.
+fn new_seed() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes the synthetic video into `dir`, long enough for every timestamp.
+    fn synthetic_video(dir: &Path) -> PathBuf {
+        video_rs::init().unwrap();
+        let path = dir.join("synthetic.mp4");
+        let frames = 5 * synthetic::FPS;
+        synthetic::encode(&path, 640, 360, frames, 0, &CancellationToken::new()).unwrap();
+        path
+    }
+
+    fn depth() -> BitDepthArgs {
+        BitDepthArgs { preserve_bit_depth: false, tone_map: ToneMap::Hable }
+    }
+
+    /// Extracts the views of `video` into `views`, checking each against the synthetic
+    /// video.
+    fn extract(video: &Path, views: &Path, cancel: &CancellationToken) -> Result<Vec<ViewFrame>> {
+        fs::create_dir_all(views).unwrap();
+        let mut sink = ViewsSink::Dir(views.to_path_buf());
+        let check = |frame: &ViewFrame, image: &DynamicImage| synthetic::check_view(frame, image, None);
+        extract_frames(video, "synthetic", &depth(), 0, &mut sink, check, cancel)
+    }
+
+    #[test]
+    fn extracts_the_frames_at_their_timestamps() {
+        let dir = tempfile::tempdir().unwrap();
+        let video = synthetic_video(dir.path());
+        let views = dir.path().join("views");
+        let frames = extract(&video, &views, &CancellationToken::new()).unwrap();
+
+        assert_eq!(frames.len(), TIMESTAMPS.len());
+        for (frame, timestamp) in frames.iter().zip(TIMESTAMPS) {
+            assert_eq!(frame.timestamp, Some(timestamp));
+            assert_eq!(frame.clip.as_deref(), Some("synthetic"));
+            // Each frame of the turning cube differs from the last, and is sharp.
+            assert_eq!(frame.unusable, None, "at {}s", timestamp);
+            // The view saved is the one checked and hashed.
+            let data = fs::read(views.join(&frame.file)).unwrap();
+            assert_eq!(frame.sha256, format!("{:x}", Sha256::digest(&data)));
+            let image = image::load_from_memory(&data).unwrap();
+            synthetic::check_view(frame, &image, None).unwrap();
+        }
+    }
+
+    #[test]
+    fn keeps_each_temporary_video_apart() {
+        let first = TempVideo::new("video.mp4").unwrap();
+        let second = TempVideo::new("video.mp4").unwrap();
+        assert_ne!(&*first, &*second);
+        assert_eq!(first.file_stem().unwrap(), "video");
+        fs::write(&*first, b"video").unwrap();
+        let dir = first.parent().unwrap().to_path_buf();
+        drop(first);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn extracts_nothing_once_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let video = synthetic_video(dir.path());
+        let views = dir.path().join("views");
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let error = extract(&video, &views, &cancel).err().unwrap();
+        assert_eq!(error.to_string(), cancel::CANCELLED);
+        assert_eq!(fs::read_dir(&views).unwrap().count(), 0);
+    }
+}
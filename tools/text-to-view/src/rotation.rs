@@ -0,0 +1,241 @@
+//! The display rotation of videos recorded on phones, which store their frames as
+//! the sensor saw them and tell players how to turn them instead.
+//!
+//! Decoders ignore it, so the views of a portrait video would come out on their
+//! side and be reconstructed as such. They are turned upright here, as players do.
+
+use color_eyre::eyre::{eyre, Result};
+use image::DynamicImage;
+use std::path::Path;
+use video_rs::ffmpeg::codec::packet::side_data;
+use video_rs::ffmpeg::{format, media};
+
+/// Reads the clockwise rotation, in degrees, that the video at `path` is displayed
+/// with: 0, 90, 180 or 270.
+///
+/// The display matrix side data of the stream is preferred to the `rotate` tag
+/// older muxers write, and rotations between right angles are rounded to the
+/// nearest one.
+pub fn probe(path: &Path) -> Result<u32> {
+    let input = format::input(path)?;
+    let stream = input
+        .streams()
+        .best(media::Type::Video)
+        .ok_or_else(|| eyre!("{} has no video stream", path.display()))?;
+    let from_matrix = stream
+        .side_data()
+        .find(|data| data.kind() == side_data::Type::DisplayMatrix)
+        .and_then(|data| matrix_rotation(data.data()));
+    let from_tag = || {
+        stream
+            .metadata()
+            .get("rotate")
+            .and_then(|tag| tag.trim().parse::<f64>().ok())
+    };
+    Ok(from_matrix.or_else(from_tag).map_or(0, right_angle))
+}
+
+/// The clockwise rotation of a 3x3 display matrix of native-endian 32-bit integers,
+/// whose 2x2 top-left part is in 16.16 fixed point (as `av_display_rotation_get`).
+fn matrix_rotation(data: &[u8]) -> Option<f64> {
+    if data.len() < 9 * 4 {
+        return None;
+    }
+    let entry = |i: usize| {
+        i32::from_ne_bytes([
+            data[i * 4],
+            data[i * 4 + 1],
+            data[i * 4 + 2],
+            data[i * 4 + 3],
+        ]) as f64
+            / 65536.0
+    };
+    let (a, b, c, d) = (entry(0), entry(1), entry(3), entry(4));
+    let (scale_x, scale_y) = (a.hypot(c), b.hypot(d));
+    if scale_x == 0.0 || scale_y == 0.0 {
+        return None;
+    }
+    Some((b / scale_y).atan2(a / scale_x).to_degrees())
+}
+
+/// `degrees` rounded to the nearest right angle in [0, 360).
+fn right_angle(degrees: f64) -> u32 {
+    ((degrees / 90.0).round() as i64).rem_euclid(4) as u32 * 90
+}
+
+/// Turns `image` clockwise by `degrees`, one of the right angles [`probe`] gives.
+pub fn apply(
+    image: DynamicImage,
+    degrees: u32,
+) -> DynamicImage {
+    match degrees {
+        90 => image.rotate90(),
+        180 => image.rotate180(),
+        270 => image.rotate270(),
+        _ => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extract_frames, synthetic, BitDepthArgs, ToneMap, ViewFrame, ViewsSink};
+    use std::fs;
+    use std::path::PathBuf;
+    use tokio_util::sync::CancellationToken;
+
+    /// The entries of a display matrix turning clockwise by `degrees`, as MP4 track
+    /// headers and FFmpeg hold them: 16.16 fixed point, but for the last column in
+    /// 2.30.
+    fn display_matrix(degrees: f64) -> [i32; 9] {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let fixed = |value: f64| (value * 65536.0).round() as i32;
+        [
+            fixed(cos),
+            fixed(sin),
+            0,
+            fixed(-sin),
+            fixed(cos),
+            0,
+            0,
+            0,
+            1 << 30,
+        ]
+    }
+
+    /// Tags the MP4 at `path` to be displayed turned clockwise by `degrees`, by
+    /// writing the display matrix into the header of its track, as phones do.
+    fn tag(
+        path: &Path,
+        degrees: u32,
+    ) {
+        let mut data = fs::read(path).unwrap();
+        let find = |from: usize, kind: &[u8]| {
+            from + data[from..]
+                .windows(4)
+                .position(|window| window == kind)
+                .unwrap()
+        };
+        let tkhd = find(find(0, b"moov"), b"tkhd");
+        // The matrix follows the version and flags, the times, track id and duration
+        // (with 64-bit times from version 1), then the layer, alternate group and
+        // volume, each with reserved fields.
+        let times = if data[tkhd + 4] == 1 { 32 } else { 20 };
+        let start = tkhd + 4 + 4 + times + 16;
+        let matrix = display_matrix(degrees as f64);
+        for (i, entry) in matrix.iter().enumerate() {
+            data[start + i * 4..start + i * 4 + 4].copy_from_slice(&entry.to_be_bytes());
+        }
+        fs::write(path, data).unwrap();
+    }
+
+    /// Encodes the synthetic video into `dir`, stored as turned counterclockwise by
+    /// `degrees` and tagged to be turned back.
+    fn tagged_video(
+        dir: &Path,
+        degrees: u32,
+    ) -> PathBuf {
+        video_rs::init().unwrap();
+        let path = dir.join(format!("turned-{}.mp4", degrees));
+        let frames = 5 * synthetic::FPS;
+        synthetic::encode(&path, 640, 360, frames, degrees, &CancellationToken::new())
+            .unwrap();
+        tag(&path, degrees);
+        path
+    }
+
+    /// Extracts the views of `video` turned clockwise by `degrees`, checking each
+    /// against the synthetic video, which only reads upright.
+    fn extract(
+        video: &Path,
+        degrees: u32,
+    ) -> Result<Vec<(ViewFrame, DynamicImage)>> {
+        let views = video.with_extension("views");
+        fs::create_dir_all(&views).unwrap();
+        let mut sink = ViewsSink::Dir(views.clone());
+        let depth = BitDepthArgs {
+            preserve_bit_depth: false,
+            tone_map: ToneMap::Hable,
+        };
+        let check = |frame: &ViewFrame, image: &DynamicImage| {
+            synthetic::check_view(frame, image, None)
+        };
+        let cancel = CancellationToken::new();
+        let frames =
+            extract_frames(video, "turned", &depth, degrees, &mut sink, check, &cancel)?;
+        Ok(frames
+            .into_iter()
+            .map(|frame| {
+                let image = image::open(views.join(&frame.file)).unwrap();
+                (frame, image)
+            })
+            .collect())
+    }
+
+    #[test]
+    fn reads_the_rotation_of_a_display_matrix() {
+        for degrees in [0, 90, 180, 270] {
+            let matrix = display_matrix(degrees as f64);
+            let data: Vec<u8> = matrix
+                .iter()
+                .flat_map(|entry| entry.to_ne_bytes())
+                .collect();
+            let rotation = matrix_rotation(&data).unwrap();
+            assert_eq!(right_angle(rotation), degrees);
+        }
+        // Scaled, and off a right angle.
+        let mut matrix = display_matrix(80.0);
+        matrix.iter_mut().take(5).for_each(|entry| *entry *= 2);
+        let data: Vec<u8> = matrix
+            .iter()
+            .flat_map(|entry| entry.to_ne_bytes())
+            .collect();
+        assert!((matrix_rotation(&data).unwrap() - 80.0).abs() < 0.01);
+        // Too short, or degenerate.
+        assert_eq!(matrix_rotation(&data[..32]), None);
+        assert_eq!(matrix_rotation(&[0; 36]), None);
+    }
+
+    #[test]
+    fn rounds_to_the_nearest_right_angle() {
+        assert_eq!(right_angle(0.0), 0);
+        assert_eq!(right_angle(44.0), 0);
+        assert_eq!(right_angle(46.0), 90);
+        assert_eq!(right_angle(-90.0), 270);
+        assert_eq!(right_angle(-179.0), 180);
+        assert_eq!(right_angle(359.0), 0);
+        assert_eq!(right_angle(630.0), 270);
+    }
+
+    #[test]
+    fn turns_the_views_of_tagged_videos_upright() {
+        let dir = tempfile::tempdir().unwrap();
+        for degrees in [0, 90, 180, 270] {
+            let video = tagged_video(dir.path(), degrees);
+            assert_eq!(probe(&video).unwrap(), degrees);
+            for (frame, image) in extract(&video, probe(&video).unwrap()).unwrap() {
+                assert_eq!(
+                    (image.width(), image.height()),
+                    (640, 360),
+                    "{}",
+                    frame.file
+                );
+                synthetic::check_view(&frame, &image, None).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_the_views_on_their_side_without_the_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let video = tagged_video(dir.path(), 90);
+        // As with `--no-auto-rotate`: the frames are saved as stored, and no longer
+        // read as the synthetic video.
+        let error = extract(&video, 0).err().unwrap();
+        assert!(
+            error.to_string().contains("shows no frame number"),
+            "{}",
+            error
+        );
+    }
+}
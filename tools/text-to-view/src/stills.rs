@@ -0,0 +1,145 @@
+//! Views generated as still images with Imagen, one per camera angle, for accounts
+//! without access to Veo.
+//!
+//! Each image is generated on its own from the prompt and an angle, so nothing ties
+//! the subject together across views the way the frames of a video do.
+
+use crate::cancel;
+use crate::{ExtractedView, ViewFrame};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use http_trace::SendTraced;
+use image::codecs::jpeg::JpegEncoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
+
+/// The angles around the subject, in orbit order; fewer views take them evenly
+/// spaced.
+pub const ANGLES: [&str; 8] = [
+    "front",
+    "three-quarter left",
+    "left side",
+    "three-quarter back left",
+    "back",
+    "three-quarter back right",
+    "right side",
+    "three-quarter right",
+];
+
+const ANGLE_PROMPT_TEMPLATE: &str = "{prompt}. Seen from the {angle}, at eye level. The subject is whole and centered, in the same pose, lighting and setting as in every other view, on an uncluttered background.";
+
+#[derive(Serialize)]
+struct ImagenRequest<'a> {
+    instances: Vec<ImagenInstance<'a>>,
+    parameters: ImagenParameters,
+}
+
+#[derive(Serialize)]
+struct ImagenInstance<'a> {
+    prompt: &'a str,
+}
+
+#[derive(Serialize)]
+struct ImagenParameters {
+    #[serde(rename = "sampleCount")]
+    sample_count: u32,
+    #[serde(rename = "aspectRatio")]
+    aspect_ratio: &'static str,
+}
+
+#[derive(Deserialize, Debug)]
+struct ImagenResponse {
+    #[serde(default)]
+    predictions: Vec<Prediction>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Prediction {
+    #[serde(rename = "bytesBase64Encoded")]
+    bytes_base64_encoded: Option<String>,
+}
+
+/// The angles of `count` views, evenly spaced around the subject.
+pub fn angles(count: usize) -> Vec<&'static str> {
+    (0..count)
+        .map(|i| ANGLES[i * ANGLES.len() / count])
+        .collect()
+}
+
+/// Generates a view of `prompt` from each of `count` angles, kept in memory until
+/// all are generated so that a cancelled or failed run saves none.
+pub async fn generate_views(
+    client: &reqwest::Client,
+    api_key: &str,
+    prompt: &str,
+    count: usize,
+    cancel: &CancellationToken,
+) -> Result<Vec<ExtractedView>> {
+    eprintln!("Generating {} still images as views instead of a video. Each is generated on its own, so the subject may change shape, details or lighting between them, and the model will be rougher than one made from a video.", count);
+    let mut views = Vec::with_capacity(count);
+    for (i, angle) in angles(count).into_iter().enumerate() {
+        eprintln!("Generating the {} view ({}/{})...", angle, i + 1, count);
+        let angle_prompt = ANGLE_PROMPT_TEMPLATE
+            .replace("{prompt}", prompt)
+            .replace("{angle}", angle);
+        let image =
+            cancel::or_cancelled(cancel, generate_image(client, api_key, &angle_prompt))
+                .await
+                .wrap_err_with(|| format!("Failed to generate the {} view", angle))?;
+
+        // Saved as JPEG like the frames of videos, whatever Imagen returned.
+        let mut data = Vec::new();
+        JpegEncoder::new(&mut data)
+            .encode_image(&image.to_rgb8())
+            .wrap_err_with(|| format!("Failed to encode the {} view", angle))?;
+        let file_name = format!("{}.jpg", i);
+        let frame = ViewFrame {
+            file: file_name,
+            source: "image",
+            clip: None,
+            timestamp: None,
+            angle: Some(angle),
+            unusable: None,
+            sha256: format!("{:x}", Sha256::digest(&data)),
+        };
+        views.push(ExtractedView { frame, data });
+    }
+    Ok(views)
+}
+
+async fn generate_image(
+    client: &reqwest::Client,
+    api_key: &str,
+    prompt: &str,
+) -> Result<image::DynamicImage> {
+    let model_id = "imagen-3.0-generate-002";
+    let url = format!(
+        "https://generativelanguage.googleapis.com/v1beta/models/{}:predict?key={}",
+        model_id, api_key
+    );
+    let request_body = ImagenRequest {
+        instances: vec![ImagenInstance { prompt }],
+        parameters: ImagenParameters {
+            sample_count: 1,
+            aspect_ratio: "16:9",
+        },
+    };
+    let response: ImagenResponse = client
+        .post(&url)
+        .json(&request_body)
+        .send_traced()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    // Images blocked by the safety filters come back as empty predictions.
+    let encoded = response.predictions.into_iter().find_map(|prediction| prediction.bytes_base64_encoded)
+        .ok_or_else(|| eyre!("Imagen returned no image, it may have been filtered out. Try rewording the prompt."))?;
+    let bytes = BASE64_STANDARD
+        .decode(encoded)
+        .wrap_err("Imagen returned an image that is not valid base64")?;
+    image::load_from_memory(&bytes)
+        .wrap_err("Imagen returned an image that cannot be decoded")
+}
@@ -0,0 +1,314 @@
+//! A video made up on the spot, for `--mock` runs that try the extraction without
+//! generating a video with Veo: a checkered cube turning against a gradient.
+//!
+//! Each frame carries its own number in a row of black and white blocks in its
+//! top-left corner, which survives compression, so that what was extracted from the
+//! video can be checked against the frames at the timestamps asked for.
+
+use crate::{cancel, rotation, ViewFrame};
+use color_eyre::eyre::{eyre, Result};
+use http_trace::usage;
+use image::{DynamicImage, Rgb, RgbImage};
+use std::f64::consts::TAU;
+use std::fs;
+use std::path::Path;
+use tokio_util::sync::CancellationToken;
+use video_rs::encode::Settings;
+use video_rs::frame::{PixelFormat, RawFrame};
+use video_rs::{Encoder, Time};
+
+/// The frame rate of the video, that of Veo.
+pub const FPS: u32 = 24;
+
+/// The length of the long side of the frames, in pixels.
+const LONG_SIDE: u32 = 640;
+
+/// How long the cube takes to turn once around, in seconds.
+const TURN_SECONDS: f64 = 8.0;
+
+/// How far the cube leans toward the camera, so that its top shows, in radians.
+const TILT: f64 = 0.45;
+
+/// The distance of the camera from the center of the cube, whose faces are 2 wide.
+const CAMERA_DISTANCE: f64 = 5.0;
+
+/// The number of blocks of the frame number, enough for 8 seconds of frames.
+const MARKER_BITS: u32 = 10;
+
+/// The size of a block of the frame number, and its distance from the corner.
+const MARKER_BLOCK: u32 = 16;
+
+/// The colors of the faces of the cube, in the order of [`face`].
+const FACE_COLORS: [[f64; 3]; 6] = [
+    [220.0, 60.0, 50.0],
+    [60.0, 180.0, 80.0],
+    [240.0, 200.0, 40.0],
+    [60.0, 90.0, 220.0],
+    [230.0, 120.0, 30.0],
+    [170.0, 70.0, 200.0],
+];
+
+/// Renders a video of `duration_seconds` at [`FPS`] to `path`, landscape or
+/// `portrait`. Checks `cancel` between frames.
+pub fn write(
+    path: &Path,
+    duration_seconds: u32,
+    portrait: bool,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    video_rs::init().map_err(|e| eyre!(e.to_string()))?;
+
+    let short_side = LONG_SIDE * 9 / 16;
+    let (width, height) = if portrait {
+        (short_side, LONG_SIDE)
+    } else {
+        (LONG_SIDE, short_side)
+    };
+    eprintln!(
+        "Rendering a synthetic video of {} seconds instead of generating one...",
+        duration_seconds
+    );
+    // The encoder writes the file itself, so it is counted once written.
+    let written = encode(path, width, height, duration_seconds * FPS, 0, cancel)
+        .and_then(|()| Ok(usage::reserve_temp(fs::metadata(path)?.len())?));
+    if let Err(e) = written {
+        let _ = fs::remove_file(path);
+        return Err(e.wrap_err("Failed to write the synthetic video"));
+    }
+    eprintln!(
+        "Wrote the synthetic video to temporary path: {}",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Encodes the first `frames` frames of the video, `width` by `height`, into an MP4
+/// at `path`. Checks `cancel` between frames.
+///
+/// The frames are stored turned counterclockwise by `rotation` degrees, a right
+/// angle, as phones store what they record on their side; the MP4 is not tagged to
+/// turn them back.
+pub fn encode(
+    path: &Path,
+    width: u32,
+    height: u32,
+    frames: u32,
+    rotation: u32,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let (stored_width, stored_height) = if rotation.is_multiple_of(180) {
+        (width, height)
+    } else {
+        (height, width)
+    };
+    let mut encoder = Encoder::new(
+        path,
+        Settings::preset_h264_yuv420p(
+            stored_width as usize,
+            stored_height as usize,
+            false,
+        ),
+    )?;
+    for number in 0..frames {
+        cancel::check(cancel)?;
+        let upright = DynamicImage::ImageRgb8(render(number, width, height));
+        let image = rotation::apply(upright, (360 - rotation) % 360).into_rgb8();
+        let mut frame = RawFrame::new(PixelFormat::RGB24, stored_width, stored_height);
+        // Rows of the frame may be padded past the width.
+        let stride = frame.stride(0);
+        let row = stored_width as usize * 3;
+        for (y, pixels) in image.as_raw().chunks_exact(row).enumerate() {
+            frame.data_mut(0)[y * stride..y * stride + row].copy_from_slice(pixels);
+        }
+        let pts = Time::from_secs_f64(number as f64 / FPS as f64)
+            .with_time_base(encoder.time_base());
+        frame.set_pts(pts.into_value());
+        encoder.encode_raw(frame)?;
+    }
+    encoder.finish()?;
+    Ok(())
+}
+
+/// Renders the frame `number` of the video, `width` by `height`.
+pub fn render(
+    number: u32,
+    width: u32,
+    height: u32,
+) -> RgbImage {
+    let turn = number as f64 / FPS as f64 * TAU / TURN_SECONDS;
+    let focal = width.min(height) as f64 * 1.2;
+    let (center_x, center_y) = (width as f64 / 2.0, height as f64 / 2.0);
+    // The camera ray in the frame of the cube: turned back by the tilt about x, then
+    // by the turn about y.
+    let to_cube = |[x, y, z]: [f64; 3]| {
+        let (y, z) = (
+            y * TILT.cos() + z * TILT.sin(),
+            z * TILT.cos() - y * TILT.sin(),
+        );
+        [
+            x * turn.cos() - z * turn.sin(),
+            y,
+            x * turn.sin() + z * turn.cos(),
+        ]
+    };
+    let origin = to_cube([0.0, 0.0, CAMERA_DISTANCE]);
+    let mut image = RgbImage::from_fn(width, height, |x, y| {
+        let direction = to_cube([
+            (x as f64 + 0.5 - center_x) / focal,
+            (center_y - y as f64 - 0.5) / focal,
+            -1.0,
+        ]);
+        match face(origin, direction) {
+            Some((face, u, v)) => {
+                // Four by four squares, every other one darker.
+                let checker = ((u + 1.0) * 2.0) as u32 + ((v + 1.0) * 2.0) as u32;
+                let shade = if checker.is_multiple_of(2) { 1.0 } else { 0.45 };
+                Rgb(FACE_COLORS[face].map(|channel| (channel * shade) as u8))
+            },
+            None => {
+                let t = y as f64 / height as f64;
+                Rgb([40.0 + 150.0 * t, 70.0 + 110.0 * t, 140.0 - 40.0 * t]
+                    .map(|channel| channel as u8))
+            },
+        }
+    });
+    for bit in 0..MARKER_BITS {
+        let value = if number >> bit & 1 == 1 { 255 } else { 0 };
+        let left = MARKER_BLOCK * (bit + 1);
+        for y in MARKER_BLOCK..MARKER_BLOCK * 2 {
+            for x in left..left + MARKER_BLOCK {
+                image.put_pixel(x, y, Rgb([value; 3]));
+            }
+        }
+    }
+    image
+}
+
+/// Where the ray from `origin` along `direction` first hits the cube spanning -1 to
+/// 1 on each axis: the index of the face, and the coordinates on it from -1 to 1.
+fn face(
+    origin: [f64; 3],
+    direction: [f64; 3],
+) -> Option<(usize, f64, f64)> {
+    let (mut near, mut far, mut axis) = (f64::NEG_INFINITY, f64::INFINITY, 0);
+    for i in 0..3 {
+        if direction[i] == 0.0 {
+            if origin[i].abs() > 1.0 {
+                return None;
+            }
+            continue;
+        }
+        let (a, b) = (
+            (-1.0 - origin[i]) / direction[i],
+            (1.0 - origin[i]) / direction[i],
+        );
+        if a.min(b) > near {
+            (near, axis) = (a.min(b), i);
+        }
+        far = far.min(a.max(b));
+    }
+    if near > far || near < 0.0 {
+        return None;
+    }
+    let hit = [0, 1, 2].map(|i| origin[i] + direction[i] * near);
+    let (u, v) = (
+        hit[(axis + 1) % 3].clamp(-1.0, 0.999),
+        hit[(axis + 2) % 3].clamp(-1.0, 0.999),
+    );
+    let side = if hit[axis] > 0.0 { 0 } else { 1 };
+    Some((axis * 2 + side, u, v))
+}
+
+/// Reads the frame number off a frame of the video, upright and at its size.
+pub fn frame_number(image: &RgbImage) -> Option<u32> {
+    let mut number = 0;
+    for bit in 0..MARKER_BITS {
+        let (left, top) = (MARKER_BLOCK * (bit + 1), MARKER_BLOCK);
+        // The middle of the block, clear of the blur of compression at its edges.
+        let inner = MARKER_BLOCK / 4..MARKER_BLOCK * 3 / 4;
+        let mut total = 0;
+        for y in inner.clone() {
+            for x in inner.clone() {
+                let pixel = image.get_pixel_checked(left + x, top + y)?;
+                total += pixel.0.iter().map(|&channel| channel as u32).sum::<u32>();
+            }
+        }
+        let mean = total / (inner.len() as u32).pow(2) / 3;
+        match mean {
+            0..64 => {},
+            192.. => number |= 1 << bit,
+            _ => return None,
+        }
+    }
+    Some(number)
+}
+
+/// Checks that `image`, the view `frame` extracted from the video, shows the frame at
+/// its timestamp, within a frame of the video and one of its re-encoding at
+/// `normalized_fps`.
+pub fn check_view(
+    frame: &ViewFrame,
+    image: &DynamicImage,
+    normalized_fps: Option<u32>,
+) -> Result<()> {
+    let tolerance =
+        1.0 / FPS as f64 + normalized_fps.map_or(0.0, |fps| 1.0 / fps as f64) + 1e-6;
+    let timestamp = frame.timestamp.unwrap_or_default();
+    let number = frame_number(&image.to_rgb8()).ok_or_else(|| {
+        eyre!(
+            "The view at {}s shows no frame number of the synthetic video",
+            timestamp
+        )
+    })?;
+    let shown = number as f64 / FPS as f64;
+    if (shown - timestamp).abs() > tolerance {
+        return Err(eyre!(
+            "The view at {}s shows frame {} of the synthetic video, at {:.3}s",
+            timestamp,
+            number,
+            shown
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view_at(timestamp: f64) -> ViewFrame {
+        ViewFrame {
+            file: "0.jpg".to_string(),
+            source: "video",
+            clip: None,
+            timestamp: Some(timestamp),
+            angle: None,
+            unusable: None,
+            sha256: String::new(),
+        }
+    }
+
+    #[test]
+    fn numbers_every_frame() {
+        for (width, height) in [(640, 360), (360, 640)] {
+            for number in [0, 1, 12, 59, 100, (1 << MARKER_BITS) - 1] {
+                let image = render(number, width, height);
+                assert_eq!(frame_number(&image), Some(number));
+            }
+        }
+        // A frame turned on its side shows no number.
+        let image = image::imageops::rotate90(&render(5, 640, 360));
+        assert_eq!(frame_number(&image), None);
+    }
+
+    #[test]
+    fn checks_views_against_their_timestamp() {
+        let image = DynamicImage::ImageRgb8(render(36, 640, 360));
+        check_view(&view_at(1.5), &image, None).unwrap();
+        // Within a frame of the video, and one more of its re-encoding.
+        check_view(&view_at(1.5 + 1.0 / FPS as f64), &image, None).unwrap();
+        check_view(&view_at(1.5 + 1.5 / FPS as f64), &image, Some(FPS)).unwrap();
+        assert!(check_view(&view_at(1.5 + 2.0 / FPS as f64), &image, None).is_err());
+        assert!(check_view(&view_at(2.5), &image, Some(FPS)).is_err());
+    }
+}
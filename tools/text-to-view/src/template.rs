@@ -0,0 +1,71 @@
+//! Rendering of the prompt optimization template, with `{name}` placeholders
+//! replaced by the variables of a context.
+//!
+//! Only braces around a name made of letters, digits and underscores are
+//! placeholders, so templates can hold other braces as they are, like examples of
+//! JSON.
+
+use color_eyre::eyre::{eyre, Result};
+use std::collections::BTreeMap;
+
+/// The variables of a template, by name.
+pub type Context = BTreeMap<String, String>;
+
+/// Renders `template` with the variables of `context`, failing on placeholders of
+/// unknown variables and on templates without any placeholders.
+pub fn render(
+    template: &str,
+    context: &Context,
+) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut unknown: Vec<&str> = Vec::new();
+    let mut placeholders = 0;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match placeholder(after) {
+            Some(name) => {
+                placeholders += 1;
+                match context.get(name) {
+                    Some(value) => rendered.push_str(value),
+                    None if !unknown.contains(&name) => unknown.push(name),
+                    None => {},
+                }
+                rest = &after[name.len() + 1..];
+            },
+            None => {
+                rendered.push('{');
+                rest = after;
+            },
+        }
+    }
+    rendered.push_str(rest);
+
+    if !unknown.is_empty() {
+        let names: Vec<String> =
+            unknown.iter().map(|name| format!("{{{}}}", name)).collect();
+        let available: Vec<&str> = context.keys().map(String::as_str).collect();
+        return Err(eyre!(
+            "The prompt template uses unknown variables {}. Available: {}",
+            names.join(", "),
+            available.join(", ")
+        ));
+    }
+    if placeholders == 0 {
+        return Err(eyre!("The prompt template has no placeholders, so Gemini would get the same text whatever the prompt. Use {{prompt}} where the prompt goes."));
+    }
+    Ok(rendered)
+}
+
+/// The name of the placeholder `text` starts with, just after its opening brace.
+fn placeholder(text: &str) -> Option<&str> {
+    let end = text.find('}')?;
+    let name = &text[..end];
+    is_name(name).then_some(name)
+}
+
+/// Whether `name` can be the name of a variable.
+pub fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
@@ -0,0 +1,153 @@
+//! Counting of the extracted frames that are usable for reconstruction.
+//!
+//! Veo sometimes returns a clip so static or blurry that the views are all but
+//! useless: frames barely different from the one before add nothing to the
+//! reconstruction, and blurry ones mislead it. Such frames are still saved, but
+//! marked, and a clip with too few usable frames is not worth reconstructing.
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+
+/// Width frames are scaled down to before measuring their sharpness, so that the
+/// measure does not depend on the resolution of the video.
+const SHARPNESS_WIDTH: u32 = 320;
+
+/// The variance of the Laplacian below which a frame is blurry. In-focus frames at
+/// `SHARPNESS_WIDTH` are well above it, even of smooth subjects.
+const MIN_SHARPNESS: f64 = 15.0;
+
+/// Size of the thumbnails frames are compared on.
+const THUMBNAIL_SIZE: (u32, u32) = (32, 18);
+
+/// The mean difference of thumbnails, out of 255, below which a frame is a
+/// near-duplicate of the last usable one.
+const MIN_DIFFERENCE: f64 = 2.0;
+
+/// Why a frame is not usable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unusable {
+    Blurry,
+    Duplicate,
+}
+
+impl Unusable {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Unusable::Blurry => "blurry",
+            Unusable::Duplicate => "duplicate",
+        }
+    }
+}
+
+/// Assesses frames in order, comparing each with the last usable one.
+#[derive(Default)]
+pub struct Gate {
+    last_usable: Option<GrayImage>,
+}
+
+impl Gate {
+    /// Whether `frame` is usable, following the frames assessed before it.
+    pub fn assess(
+        &mut self,
+        frame: &DynamicImage,
+    ) -> Result<(), Unusable> {
+        let height = (frame.height() as u64 * SHARPNESS_WIDTH as u64
+            / frame.width().max(1) as u64)
+            .max(3) as u32;
+        let gray = frame
+            .resize_exact(SHARPNESS_WIDTH, height, FilterType::Triangle)
+            .to_luma8();
+        if sharpness(&gray) < MIN_SHARPNESS {
+            return Err(Unusable::Blurry);
+        }
+        let thumbnail = image::imageops::resize(
+            &gray,
+            THUMBNAIL_SIZE.0,
+            THUMBNAIL_SIZE.1,
+            FilterType::Triangle,
+        );
+        if self
+            .last_usable
+            .as_ref()
+            .is_some_and(|last| difference(last, &thumbnail) < MIN_DIFFERENCE)
+        {
+            return Err(Unusable::Duplicate);
+        }
+        self.last_usable = Some(thumbnail);
+        Ok(())
+    }
+}
+
+/// The variance of the Laplacian of `image`, high for sharp edges and low for blur.
+fn sharpness(image: &GrayImage) -> f64 {
+    let (width, height) = image.dimensions();
+    let at = |x: u32, y: u32| image.get_pixel(x, y).0[0] as f64;
+    let mut sum = 0.0;
+    let mut sum_squares = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1)
+                - 4.0 * at(x, y);
+            sum += laplacian;
+            sum_squares += laplacian * laplacian;
+        }
+    }
+    let count = ((width - 2) * (height - 2)) as f64;
+    sum_squares / count - (sum / count).powi(2)
+}
+
+/// The mean absolute difference of two images of the same size.
+fn difference(
+    a: &GrayImage,
+    b: &GrayImage,
+) -> f64 {
+    let total: u64 = a
+        .pixels()
+        .zip(b.pixels())
+        .map(|(a, b)| a.0[0].abs_diff(b.0[0]) as u64)
+        .sum();
+    total as f64 / (a.width() * a.height()) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{synthetic, TIMESTAMPS};
+
+    /// The frame of the synthetic video at `timestamp`.
+    fn frame_at(timestamp: f64) -> DynamicImage {
+        let number = (timestamp * synthetic::FPS as f64) as u32;
+        DynamicImage::ImageRgb8(synthetic::render(number, 640, 360))
+    }
+
+    #[test]
+    fn keeps_the_frames_of_a_turning_cube() {
+        let mut gate = Gate::default();
+        for timestamp in TIMESTAMPS {
+            assert_eq!(
+                gate.assess(&frame_at(timestamp)),
+                Ok(()),
+                "at {}s",
+                timestamp
+            );
+        }
+    }
+
+    #[test]
+    fn marks_a_frame_like_the_last_usable_one_as_duplicate() {
+        let mut gate = Gate::default();
+        assert_eq!(gate.assess(&frame_at(1.5)), Ok(()));
+        assert_eq!(gate.assess(&frame_at(1.5)), Err(Unusable::Duplicate));
+        // Compared with the last usable frame, not the duplicate.
+        assert_eq!(gate.assess(&frame_at(2.5)), Ok(()));
+    }
+
+    #[test]
+    fn marks_a_blurred_frame_as_blurry() {
+        let mut gate = Gate::default();
+        let blurred = frame_at(1.5).blur(6.0);
+        assert_eq!(gate.assess(&blurred), Err(Unusable::Blurry));
+        // A blurry frame is not the one later frames are compared with.
+        assert_eq!(gate.assess(&frame_at(1.5)), Ok(()));
+    }
+}
@@ -0,0 +1,126 @@
+//! Checks of the prompt before it is sent to Veo, which answers prompts it cannot
+//! take with an unhelpful 400 or truncates them silently.
+
+use color_eyre::eyre::{eyre, Result};
+
+/// Words the safety filter of Veo is likely to refuse prompts for. Only a warning is
+/// printed, since many are harmless in context.
+pub const CONTENT_KEYWORDS: &[&str] = &[
+    "nude",
+    "naked",
+    "nsfw",
+    "sexual",
+    "erotic",
+    "gore",
+    "gory",
+    "blood",
+    "bloody",
+    "corpse",
+    "dead body",
+    "decapitated",
+    "murder",
+    "kill",
+    "killing",
+    "suicide",
+    "self-harm",
+    "torture",
+    "gun",
+    "rifle",
+    "bomb",
+    "terrorist",
+    "drugs",
+    "cocaine",
+    "heroin",
+];
+
+/// The most characters Veo takes in a prompt of the model `model_id`.
+pub fn prompt_limit(model_id: &str) -> usize {
+    match model_id {
+        // 1,024 tokens, of about 4 characters each in English prose.
+        "veo-2.0-generate-001" => 4000,
+        // A cautious guess for models whose limit is not known here.
+        _ => 2000,
+    }
+}
+
+/// Removes what Gemini sometimes wraps its output in: markdown code fences,
+/// surrounding quotes and control characters.
+pub fn clean(prompt: &str) -> String {
+    let text: Vec<&str> = prompt
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect();
+    let text: String = text
+        .join("\n")
+        .chars()
+        .map(|c| if c == '\t' { ' ' } else { c })
+        .filter(|&c| c == '\n' || !c.is_control())
+        .collect();
+    let text = text.trim();
+    let unquoted = ['"', '\'', '“'].iter().find_map(|&quote| {
+        let closing = if quote == '“' { '”' } else { quote };
+        text.strip_prefix(quote)?.strip_suffix(closing)
+    });
+    unquoted.unwrap_or(text).trim().to_string()
+}
+
+/// Fits `prompt` in `limit` characters, cutting it at the last sentence boundary
+/// under `auto_truncate`, or failing otherwise.
+pub fn fit(
+    prompt: String,
+    limit: usize,
+    auto_truncate: bool,
+) -> Result<String> {
+    let length = prompt.chars().count();
+    if length <= limit {
+        return Ok(prompt);
+    }
+    if !auto_truncate {
+        return Err(eyre!("The prompt is {} characters long, over the {} Veo takes. Pass --auto-truncate to cut it at a sentence boundary, or shorten it with --confirm-prompt.", length, limit));
+    }
+    let head: String = prompt.chars().take(limit).collect();
+    // A sentence ends with its punctuation, before a space or the end of the head.
+    let sentence_end = head
+        .char_indices()
+        .filter(|&(i, c)| {
+            matches!(c, '.' | '!' | '?')
+                && head[i + c.len_utf8()..]
+                    .chars()
+                    .next()
+                    .is_none_or(char::is_whitespace)
+        })
+        .map(|(i, c)| i + c.len_utf8())
+        .next_back();
+    let cut = sentence_end
+        .or_else(|| head.rfind(char::is_whitespace))
+        .unwrap_or(head.len());
+    let truncated = head[..cut].trim_end().to_string();
+    eprintln!(
+        "Truncated the prompt from {} to {} characters, under the {} Veo takes",
+        length,
+        truncated.chars().count(),
+        limit
+    );
+    Ok(truncated)
+}
+
+/// The `keywords` that appear in `prompt` as whole words, ignoring case.
+pub fn flagged<'a>(
+    prompt: &str,
+    keywords: &'a [String],
+) -> Vec<&'a str> {
+    let prompt = prompt.to_lowercase();
+    keywords
+        .iter()
+        .map(String::as_str)
+        .filter(|keyword| {
+            let keyword = keyword.to_lowercase();
+            prompt.match_indices(&keyword).any(|(i, _)| {
+                let before = prompt[..i].chars().next_back();
+                let after = prompt[i + keyword.len()..].chars().next();
+                !before.is_some_and(char::is_alphanumeric)
+                    && !after.is_some_and(char::is_alphanumeric)
+            })
+        })
+        .collect()
+}